@@ -0,0 +1,97 @@
+//! Benchmarks for endpoint lookup on large rule sets.
+//!
+//! Compares the linear scan in `ApiDeprecationConfig::find_endpoint_index`
+//! with the `EndpointIndex` used by the agent. The `find_glob` group uses 800
+//! glob rules spread over a few first segments, where the index only scans the
+//! globs sharing the request's first segment and rejects unknown first
+//! segments outright.
+
+use chrono::Utc;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use zentinel_agent_api_deprecation::matcher::EndpointIndex;
+use zentinel_agent_api_deprecation::ApiDeprecationConfig;
+
+/// Build a config with `n` literal rules and a handful of glob rules.
+fn config_with_rules(n: usize) -> ApiDeprecationConfig {
+    let mut yaml = String::from("endpoints:\n");
+    for i in 0..n {
+        yaml.push_str(&format!(
+            "  - id: rule-{i}\n    path: /api/v1/resource-{i}\n    methods: [GET]\n"
+        ));
+    }
+    for i in 0..10 {
+        yaml.push_str(&format!("  - id: glob-{i}\n    path: /legacy/{i}/*\n"));
+    }
+
+    let mut config: ApiDeprecationConfig = serde_yaml::from_str(&yaml).unwrap();
+    config.compile();
+    config
+}
+
+/// Build a config with `n` glob rules spread over eight first segments.
+fn config_with_globs(n: usize) -> ApiDeprecationConfig {
+    let mut yaml = String::from("endpoints:\n");
+    for i in 0..n {
+        yaml.push_str(&format!(
+            "  - id: glob-{i}\n    path: /service-{}/v{i}/*/items\n",
+            i % 8
+        ));
+    }
+
+    let mut config: ApiDeprecationConfig = serde_yaml::from_str(&yaml).unwrap();
+    config.compile();
+    config
+}
+
+fn bench_find_endpoint(c: &mut Criterion) {
+    let now = Utc::now();
+    let mut group = c.benchmark_group("find_endpoint");
+
+    for n in [100, 1_000, 5_000] {
+        let config = config_with_rules(n);
+        let index = EndpointIndex::new(&config.endpoints);
+        let hit = format!("/api/v1/resource-{}/items", n - 1);
+        let miss = "/api/v2/unrelated/path";
+
+        group.bench_with_input(BenchmarkId::new("linear_hit", n), &hit, |b, path| {
+            b.iter(|| config.find_endpoint_index_at(black_box(path), "GET", now))
+        });
+        group.bench_with_input(BenchmarkId::new("indexed_hit", n), &hit, |b, path| {
+            b.iter(|| index.find_at(&config.endpoints, black_box(path), "GET", now))
+        });
+        group.bench_with_input(BenchmarkId::new("linear_miss", n), &miss, |b, path| {
+            b.iter(|| config.find_endpoint_index_at(black_box(path), "GET", now))
+        });
+        group.bench_with_input(BenchmarkId::new("indexed_miss", n), &miss, |b, path| {
+            b.iter(|| index.find_at(&config.endpoints, black_box(path), "GET", now))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_find_glob(c: &mut Criterion) {
+    let now = Utc::now();
+    let mut group = c.benchmark_group("find_glob");
+    let config = config_with_globs(800);
+    let index = EndpointIndex::new(&config.endpoints);
+
+    let cases = [
+        ("hit", "/service-7/v799/42/items"),
+        ("known_segment_miss", "/service-3/v2/42/orders"),
+        ("unknown_segment_miss", "/static/app.js"),
+    ];
+    for (name, path) in cases {
+        group.bench_with_input(BenchmarkId::new("linear", name), &path, |b, path| {
+            b.iter(|| config.find_endpoint_index_at(black_box(path), "GET", now))
+        });
+        group.bench_with_input(BenchmarkId::new("indexed", name), &path, |b, path| {
+            b.iter(|| index.find_at(&config.endpoints, black_box(path), "GET", now))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_endpoint, bench_find_glob);
+criterion_main!(benches);
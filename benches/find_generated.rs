@@ -0,0 +1,56 @@
+//! Benchmarks for endpoint lookup on generated rule sets.
+//!
+//! Compares the linear scan in `ApiDeprecationConfig::find_endpoint_index`
+//! with the `EndpointIndex` used by the agent on rule sets from the seeded
+//! `fixtures::generate_config`, with mixed paths and methods.
+//!
+//! Run with `cargo bench --features fixtures --bench find_generated`.
+
+use chrono::Utc;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use zentinel_agent_api_deprecation::fixtures::generate_config;
+use zentinel_agent_api_deprecation::matcher::EndpointIndex;
+
+/// Seed of the generated rule sets; change it to benchmark other shapes.
+const SEED: u64 = 0x5eed;
+
+fn bench_find_generated(c: &mut Criterion) {
+    let now = Utc::now();
+    let mut group = c.benchmark_group("find_generated");
+
+    for n in [1_000, 5_000] {
+        let mut config = generate_config(SEED, n);
+        config.compile();
+        let index = EndpointIndex::new(&config.endpoints);
+        // Requests for every 97th rule, globs filled in
+        let requests: Vec<(String, &str)> = config
+            .endpoints
+            .iter()
+            .step_by(97)
+            .map(|endpoint| {
+                let method = endpoint.methods.first().map_or("GET", String::as_str);
+                (endpoint.path.primary().replace('*', "42"), method)
+            })
+            .collect();
+
+        group.bench_with_input(BenchmarkId::new("linear", n), &requests, |b, requests| {
+            b.iter(|| {
+                for (path, method) in requests {
+                    black_box(config.find_endpoint_index_at(black_box(path), method, now));
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("indexed", n), &requests, |b, requests| {
+            b.iter(|| {
+                for (path, method) in requests {
+                    black_box(index.find_at(&config.endpoints, black_box(path), method, now));
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_find_generated);
+criterion_main!(benches);
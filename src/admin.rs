@@ -0,0 +1,720 @@
+//! Admin routes served on the metrics listener, and the `healthcheck` client.
+//!
+//! Besides the metrics themselves, the listener answers:
+//!
+//! - `GET /health`: the agent's [`AgentHealth`] as JSON, for liveness checks.
+//! - `POST /reload`: re-read the configuration file and swap it in, like
+//!   SIGHUP. Requires `Authorization: Bearer <metrics.admin_token>` and is
+//!   disabled while no token is configured. An invalid configuration is
+//!   answered with `400` and the validation error.
+//! - `GET /admin/enforcement`: whether blocks and redirects are enforced.
+//!   `POST /admin/enforcement?enabled=false` switches enforcement off at
+//!   runtime (and `true` back on), with the same token as `/reload`.
+//! - `GET /export.csv`: the usage snapshot as CSV, optionally over a trailing
+//!   `?window=30d` (see [`export`](crate::export)). It names owners and
+//!   carries operator notes, so it takes the same token as `/reload`.
+//!
+//! The listener is a TCP port, or with `--metrics-socket` a Unix domain
+//! socket ([`serve_unix`]) that keeps metrics off the network; both answer
+//! each connection through [`AdminServer::serve_connection`].
+//!
+//! The `healthcheck` CLI subcommand calls `/health` through
+//! [`fetch_health`] and judges the answer with [`check_health`]; the `export`
+//! subcommand fetches `/export.csv` through [`fetch_export`].
+
+use crate::agent::AgentHandle;
+use crate::config::ConfigError;
+use crate::export;
+use crate::history::SunsetChange;
+use crate::metrics::{MetricsFilter, MetricsSink};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// How long a connection may take to send its request before it is dropped.
+pub const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Health reported at `GET /health`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentHealth {
+    /// `healthy` or `degraded`
+    pub status: String,
+    /// Capability reported as degraded (`new_requests`, `config_reload`,
+    /// `shared_store`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub degraded: Option<String>,
+    /// Rules in the active configuration
+    pub endpoints: usize,
+    /// When the active configuration was loaded
+    pub config_loaded_at: DateTime<Utc>,
+    /// Hash of the active configuration (`ApiDeprecationConfig::hash`)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub config_hash: String,
+    /// Recent sunset date changes made by reloads, by rule id
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sunset_changes: BTreeMap<String, Vec<SunsetChange>>,
+}
+
+/// Enforcement state reported at `/admin/enforcement`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnforcementStatus {
+    /// Whether blocks and redirects are enforced: `switch` and `setting`
+    pub enabled: bool,
+    /// Runtime switch, toggled with `POST /admin/enforcement`
+    pub switch: bool,
+    /// `settings.enforcement_enabled` of the active configuration
+    pub setting: bool,
+}
+
+/// A response from the metrics listener.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl AdminResponse {
+    fn json(status: u16, body: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    /// Serialize as an HTTP/1.1 response.
+    pub fn to_http(&self) -> String {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            _ => "Internal Server Error",
+        };
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+/// Routes of the metrics listener.
+pub struct AdminServer {
+    handle: AgentHandle,
+    metrics: Arc<dyn MetricsSink>,
+    /// File re-read by `/reload` (`None` when started without one)
+    config_path: Option<PathBuf>,
+    /// `metrics.admin_token`
+    token: Option<String>,
+}
+
+impl AdminServer {
+    pub fn new(
+        handle: AgentHandle,
+        metrics: Arc<dyn MetricsSink>,
+        config_path: Option<PathBuf>,
+        token: Option<String>,
+    ) -> Self {
+        Self {
+            handle,
+            metrics,
+            config_path,
+            token,
+        }
+    }
+
+    /// Answer a raw HTTP request (request line and headers).
+    pub fn respond(&self, request: &str) -> AdminResponse {
+        let mut lines = request.lines();
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let method = request_line.next().unwrap_or("GET");
+        let target = request_line.next().unwrap_or("/");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let authorization = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("authorization"))
+            .map(|(_, value)| value.trim());
+
+        match (method, path) {
+            ("GET" | "HEAD", "/health") => AdminResponse::json(
+                200,
+                serde_json::to_value(self.handle.health()).unwrap_or_default(),
+            ),
+            ("POST", "/reload") => self.reload(authorization),
+            ("GET" | "HEAD", "/admin/enforcement") => AdminResponse::json(
+                200,
+                serde_json::to_value(self.handle.enforcement()).unwrap_or_default(),
+            ),
+            ("POST", "/admin/enforcement") => self.set_enforcement(query, authorization),
+            ("GET" | "HEAD", "/export.csv") => self.export(query, authorization),
+            (_, "/health" | "/reload" | "/admin/enforcement" | "/export.csv") => {
+                AdminResponse::json(405, serde_json::json!({ "error": "method_not_allowed" }))
+            }
+            // Everything else is a (possibly filtered) scrape
+            _ => AdminResponse {
+                status: 200,
+                content_type: "text/plain; charset=utf-8",
+                body: self
+                    .metrics
+                    .encode_filtered(&MetricsFilter::from_query(query)),
+            },
+        }
+    }
+
+    /// Answer the single request of a connection, dropping connections that
+    /// send nothing within [`REQUEST_READ_TIMEOUT`].
+    pub async fn serve_connection<S>(self: Arc<Self>, mut socket: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // The request line and headers select the route: /health, POST
+        // /reload, or a (filtered) scrape of everything else
+        let mut request = [0u8; 4096];
+        let read = match tokio::time::timeout(REQUEST_READ_TIMEOUT, socket.read(&mut request)).await
+        {
+            Ok(read) => read.unwrap_or(0),
+            Err(_) => return,
+        };
+        // A reload reads the configuration file; keep it off the accept loop
+        let response = tokio::task::spawn_blocking(move || {
+            self.respond(&String::from_utf8_lossy(&request[..read]))
+        })
+        .await;
+        if let Ok(response) = response {
+            let _ = socket.write_all(response.to_http().as_bytes()).await;
+        }
+    }
+
+    /// Check the bearer token of a state-changing or sensitive route,
+    /// answering `404` while no token is configured.
+    fn authorize(&self, route: &str, authorization: Option<&str>) -> Result<(), AdminResponse> {
+        let Some(token) = &self.token else {
+            return Err(AdminResponse::json(
+                404,
+                serde_json::json!({
+                    "error": "admin_disabled",
+                    "message": format!("set metrics.admin_token to enable {}", route),
+                }),
+            ));
+        };
+        let presented = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        if !presented.is_some_and(|presented| constant_time_eq(presented, token)) {
+            return Err(AdminResponse::json(
+                401,
+                serde_json::json!({ "error": "unauthorized" }),
+            ));
+        }
+        Ok(())
+    }
+
+    fn set_enforcement(&self, query: &str, authorization: Option<&str>) -> AdminResponse {
+        if let Err(response) = self.authorize("POST /admin/enforcement", authorization) {
+            return response;
+        }
+        let enabled = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("enabled="))
+            .and_then(|value| value.parse::<bool>().ok());
+        let Some(enabled) = enabled else {
+            return AdminResponse::json(
+                400,
+                serde_json::json!({
+                    "error": "invalid_request",
+                    "message": "expected ?enabled=true or ?enabled=false",
+                }),
+            );
+        };
+        self.handle.set_enforcement(enabled);
+        AdminResponse::json(
+            200,
+            serde_json::to_value(self.handle.enforcement()).unwrap_or_default(),
+        )
+    }
+
+    fn export(&self, query: &str, authorization: Option<&str>) -> AdminResponse {
+        if let Err(response) = self.authorize("GET /export.csv", authorization) {
+            return response;
+        }
+        let window = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("window="));
+        let recent = match window.map(export::parse_window).transpose() {
+            Ok(hours) => hours.map(|hours| self.handle.recent_requests(hours)),
+            Err(message) => {
+                return AdminResponse::json(
+                    400,
+                    serde_json::json!({ "error": "invalid_request", "message": message }),
+                )
+            }
+        };
+        AdminResponse {
+            status: 200,
+            content_type: "text/csv; charset=utf-8; header=present",
+            body: export::usage_csv(&self.handle.usage_snapshot(), recent.as_ref()),
+        }
+    }
+
+    fn reload(&self, authorization: Option<&str>) -> AdminResponse {
+        if let Err(response) = self.authorize("POST /reload", authorization) {
+            return response;
+        }
+        let Some(path) = &self.config_path else {
+            return AdminResponse::json(
+                409,
+                serde_json::json!({
+                    "error": "no_config_file",
+                    "message": "the agent was started without a configuration file",
+                }),
+            );
+        };
+
+        match self.handle.reload_from_file(path) {
+            Ok(endpoints) => AdminResponse::json(
+                200,
+                serde_json::json!({ "status": "reloaded", "endpoints": endpoints }),
+            ),
+            Err(err @ ConfigError::Io { .. }) => AdminResponse::json(
+                500,
+                serde_json::json!({ "error": "config_unreadable", "message": err.to_string() }),
+            ),
+            Err(err) => AdminResponse::json(
+                400,
+                serde_json::json!({ "error": "invalid_config", "message": err.to_string() }),
+            ),
+        }
+    }
+}
+
+/// Serve the metrics listener on a Unix domain socket, each connection on
+/// its own task so a slow client can't hold up the others.
+#[cfg(unix)]
+pub async fn serve_unix(server: Arc<AdminServer>, listener: tokio::net::UnixListener) {
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                tokio::spawn(Arc::clone(&server).serve_connection(socket));
+            }
+            Err(e) => tracing::warn!(error = %e, "Failed to accept metrics connection"),
+        }
+    }
+}
+
+/// Compare a presented token without returning early on the first mismatch.
+fn constant_time_eq(presented: &str, expected: &str) -> bool {
+    presented.len() == expected.len()
+        && presented
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Fetch `GET <target>` from a running agent's metrics listener, returning
+/// the body of a `200` answer.
+async fn fetch(
+    address: SocketAddr,
+    target: &str,
+    token: Option<&str>,
+    timeout: Duration,
+) -> Result<String, String> {
+    let exchange = async {
+        let mut stream = tokio::net::TcpStream::connect(address)
+            .await
+            .map_err(|e| format!("cannot connect to {}: {}", address, e))?;
+        let authorization = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        stream
+            .write_all(
+                format!(
+                    "GET {} HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n",
+                    target, address, authorization
+                )
+                .as_bytes(),
+            )
+            .await
+            .map_err(|e| format!("failed to send request: {}", e))?;
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .map_err(|e| format!("failed to read response: {}", e))?;
+        Ok::<_, String>(response)
+    };
+    let response = tokio::time::timeout(timeout, exchange)
+        .await
+        .map_err(|_| format!("no response from {} within {:?}", address, timeout))??;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| "malformed HTTP response".to_string())?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        let path = target.split('?').next().unwrap_or(target);
+        return Err(format!("{} answered with status {}", path, status));
+    }
+    Ok(body.to_string())
+}
+
+/// Fetch `GET /health` from a running agent's metrics listener.
+pub async fn fetch_health(address: SocketAddr, timeout: Duration) -> Result<AgentHealth, String> {
+    let body = fetch(address, "/health", None, timeout).await?;
+    serde_json::from_str(&body).map_err(|e| format!("unexpected /health body: {}", e))
+}
+
+/// Fetch `GET /export.csv` from a running agent's metrics listener with its
+/// admin `token`, over a trailing `window` (e.g. `30d`) if given.
+pub async fn fetch_export(
+    address: SocketAddr,
+    window: Option<&str>,
+    token: Option<&str>,
+    timeout: Duration,
+) -> Result<String, String> {
+    let target = match window {
+        Some(window) => format!("/export.csv?window={}", window),
+        None => "/export.csv".to_string(),
+    };
+    fetch(address, &target, token, timeout).await
+}
+
+/// Judge a health report: the agent must have rules loaded and a load time
+/// that isn't in the future; with `strict`, it must also not be degraded.
+pub fn check_health(health: &AgentHealth, strict: bool, now: DateTime<Utc>) -> Result<(), String> {
+    if health.endpoints == 0 {
+        return Err("no endpoints configured".to_string());
+    }
+    if health.config_loaded_at > now + chrono::Duration::minutes(1) {
+        return Err(format!(
+            "configuration load time {} is in the future",
+            health.config_loaded_at
+        ));
+    }
+    if strict {
+        if let Some(capability) = &health.degraded {
+            return Err(format!("degraded: {}", capability));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiDeprecationConfig;
+    use crate::metrics::NoopMetrics;
+    use crate::ApiDeprecationAgent;
+    use std::io::Write;
+
+    const VALID: &str = "endpoints:\n  - id: users-v1\n    path: /api/v1/users\n  - id: orders-v1\n    path: /api/v1/orders\n";
+
+    fn server(
+        file: &tempfile::NamedTempFile,
+        token: Option<&str>,
+    ) -> (ApiDeprecationAgent, AdminServer) {
+        let agent = ApiDeprecationAgent::new(ApiDeprecationConfig::from_yaml(VALID).unwrap());
+        let server = AdminServer::new(
+            agent.handle(),
+            Arc::new(NoopMetrics),
+            Some(file.path().to_path_buf()),
+            token.map(str::to_string),
+        );
+        (agent, server)
+    }
+
+    fn config_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    const RELOAD: &str =
+        "POST /reload HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer s3cret\r\n\r\n";
+
+    #[test]
+    fn test_reload_rejects_invalid_config_with_400() {
+        let file = config_file("endpoints:\n  - id: users-v1\n    path: \"\"\n");
+        let (agent, server) = server(&file, Some("s3cret"));
+
+        let response = server.respond(RELOAD);
+        assert_eq!(response.status, 400);
+        let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(body["error"], "invalid_config");
+        assert_eq!(
+            body["message"],
+            "invalid endpoint 'users-v1': path cannot be empty"
+        );
+        // The last-good configuration stays active
+        assert_eq!(agent.handle().health().endpoints, 2);
+
+        std::fs::write(
+            file.path(),
+            "endpoints:\n  - id: users-v1\n    path: /api/v1/users\n",
+        )
+        .unwrap();
+        let response = server.respond(RELOAD);
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert_eq!(agent.handle().health().endpoints, 1);
+    }
+
+    #[test]
+    fn test_reload_requires_token() {
+        let file = config_file(VALID);
+
+        let (_agent, protected) = server(&file, Some("s3cret"));
+        let unauthenticated = "POST /reload HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert_eq!(protected.respond(unauthenticated).status, 401);
+        let wrong = RELOAD.replace("s3cret", "guess!");
+        assert_eq!(protected.respond(&wrong).status, 401);
+        assert_eq!(
+            protected.respond("GET /reload HTTP/1.1\r\n\r\n").status,
+            405
+        );
+
+        let (_agent, disabled) = server(&file, None);
+        assert_eq!(disabled.respond(RELOAD).status, 404);
+    }
+
+    #[test]
+    fn test_enforcement_route() {
+        let file = config_file(VALID);
+        let (agent, admin) = server(&file, Some("s3cret"));
+        let status = |response: AdminResponse| -> EnforcementStatus {
+            assert_eq!(response.status, 200, "{}", response.body);
+            serde_json::from_str(&response.body).unwrap()
+        };
+
+        let get = "GET /admin/enforcement HTTP/1.1\r\n\r\n";
+        assert!(status(admin.respond(get)).enabled);
+
+        let off = "POST /admin/enforcement?enabled=false HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n";
+        assert_eq!(admin.respond(&off.replace("s3cret", "guess!")).status, 401);
+        assert!(!status(admin.respond(off)).enabled);
+        assert!(!agent.handle().enforcement().enabled);
+        assert!(!status(admin.respond(get)).switch);
+
+        assert!(status(admin.respond(&off.replace("false", "true"))).enabled);
+        assert_eq!(admin.respond(&off.replace("false", "maybe")).status, 400);
+
+        let (_agent, disabled) = server(&file, None);
+        assert_eq!(disabled.respond(off).status, 404);
+    }
+
+    #[test]
+    fn test_export_route() {
+        let file = config_file(VALID);
+        let (agent, admin) = server(&file, Some("s3cret"));
+        for path in ["/api/v1/users", "/api/v1/users", "/api/v1/orders"] {
+            agent.process_and_record(path, "GET");
+        }
+        let get = |target: &str| {
+            admin.respond(&format!(
+                "GET {target} HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n"
+            ))
+        };
+
+        // Owners and notes are only shown with the admin token
+        assert_eq!(
+            admin.respond("GET /export.csv HTTP/1.1\r\n\r\n").status,
+            401
+        );
+        let (_agent, disabled) = server(&file, None);
+        assert_eq!(
+            disabled.respond("GET /export.csv HTTP/1.1\r\n\r\n").status,
+            404
+        );
+
+        let response = get("/export.csv");
+        assert_eq!(response.status, 200, "{}", response.body);
+        assert!(response.content_type.starts_with("text/csv"));
+        let rows: Vec<Vec<&str>> = response
+            .body
+            .split_terminator("\r\n")
+            .map(|line| line.split(',').collect())
+            .collect();
+        assert_eq!(rows[0], export::COLUMNS);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            &rows[1][..8],
+            [
+                "users-v1",
+                "/api/v1/users",
+                "deprecated",
+                "",
+                "",
+                "2",
+                "0",
+                "0"
+            ]
+        );
+        assert_eq!(
+            &rows[2][..6],
+            ["orders-v1", "/api/v1/orders", "deprecated", "", "", "1"]
+        );
+
+        // Windowed exports leave out the counters without history
+        let windowed = get("/export.csv?window=1d");
+        assert_eq!(windowed.status, 200, "{}", windowed.body);
+        let users = windowed.body.split_terminator("\r\n").nth(1).unwrap();
+        assert!(
+            users.starts_with("users-v1,/api/v1/users,deprecated,,,2,,,"),
+            "{}",
+            users
+        );
+
+        // Longer windows are counted by the day
+        let month = get("/export.csv?window=30d");
+        assert_eq!(month.status, 200, "{}", month.body);
+        let users = month.body.split_terminator("\r\n").nth(1).unwrap();
+        assert!(users.starts_with("users-v1,/api/v1/users,deprecated,,,2,,,"));
+
+        assert_eq!(get("/export.csv?window=90d").status, 400);
+        assert_eq!(
+            admin.respond("POST /export.csv HTTP/1.1\r\n\r\n").status,
+            405
+        );
+    }
+
+    async fn serve_once(server: AdminServer) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let read = socket.read(&mut request).await.unwrap();
+            let response = server.respond(&String::from_utf8_lossy(&request[..read]));
+            socket
+                .write_all(response.to_http().as_bytes())
+                .await
+                .unwrap();
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn test_healthcheck_exit_codes() {
+        let timeout = Duration::from_secs(2);
+        let file = config_file(VALID);
+
+        // Healthy agent with rules loaded: exit 0
+        let (_agent, healthy) = server(&file, None);
+        let health = fetch_health(serve_once(healthy).await, timeout)
+            .await
+            .unwrap();
+        assert_eq!(health.status, "healthy");
+        assert_eq!(health.endpoints, 2);
+        assert!(check_health(&health, false, Utc::now()).is_ok());
+
+        // No rules loaded: exit 1
+        let empty = AdminServer::new(
+            ApiDeprecationAgent::new(ApiDeprecationConfig::default()).handle(),
+            Arc::new(NoopMetrics),
+            None,
+            None,
+        );
+        let health = fetch_health(serve_once(empty).await, timeout)
+            .await
+            .unwrap();
+        assert_eq!(
+            check_health(&health, false, Utc::now()).unwrap_err(),
+            "no endpoints configured"
+        );
+
+        // Degraded after a rejected reload: exit 1 only with --strict
+        let (agent, degraded) = server(&file, None);
+        let mut config = ApiDeprecationConfig::from_yaml(VALID).unwrap();
+        config.settings.reload_failure_mode = crate::config::ReloadFailureMode::Degrade;
+        agent.update_config(config).unwrap();
+        let _ = agent.update_config(
+            ApiDeprecationConfig::from_yaml("endpoints:\n  - id: x\n    path: \"\"\n").unwrap(),
+        );
+        let health = fetch_health(serve_once(degraded).await, timeout)
+            .await
+            .unwrap();
+        assert!(check_health(&health, false, Utc::now()).is_ok());
+        assert_eq!(
+            check_health(&health, true, Utc::now()).unwrap_err(),
+            "degraded: config_reload"
+        );
+
+        // Nothing listening: exit 1
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = listener.local_addr().unwrap();
+        drop(listener);
+        assert!(fetch_health(closed, timeout).await.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_metrics_over_unix_socket() {
+        let agent = ApiDeprecationAgent::new(ApiDeprecationConfig::from_yaml(VALID).unwrap());
+        agent.process_and_record("/api/v1/users", "GET");
+        agent.process_and_record("/api/v1/users/42", "GET");
+        let server = AdminServer::new(agent.handle(), agent.metrics().clone(), None, None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("metrics.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(serve_unix(Arc::new(server), listener));
+
+        let scrape = |request: &'static str| {
+            let path = path.clone();
+            async move {
+                let mut socket = tokio::net::UnixStream::connect(&path).await.unwrap();
+                socket.write_all(request.as_bytes()).await.unwrap();
+                let mut response = String::new();
+                socket.read_to_string(&mut response).await.unwrap();
+                response
+            }
+        };
+
+        // A client that connects and sends nothing doesn't hold up others
+        let _idle = tokio::net::UnixStream::connect(&path).await.unwrap();
+
+        let response = scrape("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"), "{}", head);
+        assert!(head.contains("Content-Type: text/plain"), "{}", head);
+
+        #[cfg(feature = "prometheus")]
+        {
+            let samples: BTreeMap<&str, f64> = body
+                .lines()
+                .filter(|line| !line.starts_with('#') && !line.is_empty())
+                .map(|line| {
+                    let (series, value) = line.rsplit_once(' ').unwrap();
+                    (series, value.parse().unwrap())
+                })
+                .collect();
+            let requests: f64 = samples
+                .iter()
+                .filter(|(series, _)| {
+                    series.starts_with("zentinel_api_deprecation_requests_total{")
+                        && series.contains(r#"endpoint_id="users-v1""#)
+                })
+                .map(|(_, value)| value)
+                .sum();
+            assert_eq!(requests, 2.0, "{}", body);
+        }
+        #[cfg(not(feature = "prometheus"))]
+        assert!(body.is_empty());
+
+        // The admin routes are served on the socket too
+        let response = scrape("GET /health HTTP/1.1\r\n\r\n").await;
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let health: AgentHealth = serde_json::from_str(body).unwrap();
+        assert_eq!(health.endpoints, 2);
+    }
+}
@@ -1,22 +1,46 @@
 //! Main API Deprecation agent implementation.
 
+use crate::admin::{AgentHealth, EnforcementStatus};
+use crate::client_state::ClientStateConfig;
+use crate::clock::{Clock, SystemClock};
 use crate::config::{
-    ApiDeprecationConfig, DeprecatedEndpoint, DeprecationAction, DeprecationStatus,
-    PastSunsetAction,
+    ApiDeprecationConfig, BodyFieldAction, ConfigError, CustomBody, DeprecatedEndpoint,
+    DeprecatedHeader, DeprecatedParam, DeprecationStatus, ExampleAction, GlobalSettings,
+    MatchExample, ParamAction, PastSunsetAction, ReloadFailureMode, Severity, WarnOnce,
+    HITS_PLACEHOLDER,
 };
-use crate::headers::{gone_response_body, DeprecationHeaders};
+use crate::diagnostics::{DiagnosticSample, DiagnosticsBuffer, TombstoneTracker};
+use crate::headers::{
+    allowed_on_not_modified, deprecated_body_fields_body_with, deprecated_header_body_with,
+    deprecated_param_body_with, header_bytes, render_computed_header, trim_headers,
+};
+use crate::history::{sunset_moves, SunsetHistory, SunsetMove};
+use crate::manifest::ManifestResponse;
+#[cfg(feature = "prometheus")]
 use crate::metrics::DeprecationMetrics;
+use crate::metrics::{MetricsError, MetricsSink, NoopMetrics};
+use crate::notify;
+use crate::processed::{DeprecationActionResult, ProcessedConfig, ProcessedEndpoint};
+use crate::trace::{MatchTrace, TRACE_MATCH_HEADER};
+use crate::usage::{
+    ClusterUsage, DailyHits, EndpointUsage, LifetimeSummary, Sessions, UsageTracker,
+};
+use arc_swap::{ArcSwap, ArcSwapOption};
 use async_trait::async_trait;
-use chrono::Utc;
-use std::collections::HashMap;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use tracing::{debug, info, warn};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Once, PoisonError};
+use tracing::{debug, error, info, warn};
 use zentinel_agent_protocol::v2::{
-    AgentCapabilities, AgentFeatures, AgentHandlerV2, DrainReason, GaugeMetric, HealthStatus,
-    MetricsReport, ShutdownReason,
+    AgentCapabilities, AgentFeatures, AgentHandlerV2, CounterMetric, DrainReason, GaugeMetric,
+    HealthStatus, MetricsReport, ShutdownReason,
+};
+use zentinel_agent_protocol::{
+    Decision as ProtocolDecision, EventType, RequestHeadersEvent, RequestMetadata,
 };
-use zentinel_agent_protocol::EventType;
 use zentinel_agent_sdk::{Agent, Decision, Request, Response};
 
 /// API Deprecation Agent
@@ -24,57 +48,612 @@ use zentinel_agent_sdk::{Agent, Decision, Request, Response};
 /// Manages API lifecycle by adding deprecation headers, tracking usage,
 /// and handling sunset policies for deprecated endpoints.
 pub struct ApiDeprecationAgent {
-    config: ApiDeprecationConfig,
-    metrics: Arc<DeprecationMetrics>,
+    /// Active configuration, swapped atomically on updates
+    state: Arc<ArcSwap<ProcessedConfig>>,
+    metrics: Arc<dyn MetricsSink>,
+    /// In-process usage counters backing `usage_snapshot`
+    usage: Arc<UsageTracker>,
+    /// Cluster-wide totals read from the shared store, if configured
+    cluster: Arc<ClusterUsage>,
+    /// Today's requests per endpoint and client, for `{{hits}}` notices;
+    /// rebuilt when `settings.client_state` changes
+    hits: Arc<ArcSwap<DailyHits>>,
+    /// Client sessions per endpoint, for `metrics.session_window_secs`;
+    /// rebuilt when `settings.client_state` changes
+    sessions: Arc<ArcSwap<Sessions>>,
+    /// Time source for sunset decisions
+    clock: Arc<dyn Clock>,
     /// Whether the agent is draining (not accepting new requests)
-    draining: AtomicBool,
+    draining: Arc<AtomicBool>,
+    /// Last rejected reload, reported as degraded health under `ReloadFailureMode::Degrade`
+    reload_error: Arc<ArcSwapOption<String>>,
+    /// Sunset dates moved by reloads
+    sunset_history: Arc<Mutex<SunsetHistory>>,
+    /// Runtime enforcement switch, see [`set_enforcement`](Self::set_enforcement)
+    enforcement: Arc<AtomicBool>,
+    /// Requests allowed unprocessed because processing panicked
+    internal_errors: AtomicU64,
+    /// Panic messages already logged
+    logged_panics: Mutex<HashSet<String>>,
+    /// Callers of tombstoned endpoints
+    tombstones: Mutex<TombstoneTracker>,
+    /// Requests sampled for diagnostics
+    diagnostics: DiagnosticsBuffer,
+}
+
+/// Distinct panic messages logged before further ones are only counted.
+const MAX_LOGGED_PANICS: usize = 32;
+
+thread_local! {
+    /// Whether this thread is running request processing in
+    /// [`ApiDeprecationAgent::isolate_panics`]
+    static ISOLATING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Install (once per process) a panic hook that keeps panics caught by
+/// `isolate_panics` quiet, so they are only reported through its
+/// deduplicated log. Panics anywhere else go to the previous hook.
+fn install_panic_hook() {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if !ISOLATING_PANICS.with(Cell::get) {
+                previous(info);
+            }
+        }));
+    });
 }
 
 impl ApiDeprecationAgent {
     /// Create a new API deprecation agent with the given configuration.
+    ///
+    /// Metrics are collected with Prometheus when the `prometheus` feature is
+    /// enabled, and discarded otherwise or when `metrics.enabled` is false.
+    /// If the Prometheus metrics can't be created (see [`Self::try_new`]), the
+    /// error is logged and metrics are discarded.
     pub fn new(config: ApiDeprecationConfig) -> Self {
-        let metrics = Arc::new(DeprecationMetrics::new(&config.metrics.prefix));
-
-        // Initialize days_until_sunset metrics for all endpoints
-        for endpoint in &config.endpoints {
-            if let Some(sunset) = &endpoint.sunset_at {
-                let days = (*sunset - Utc::now()).num_days();
-                metrics.set_days_until_sunset(&endpoint.id, &endpoint.path, days);
+        match Self::metrics_for(&config) {
+            Ok(metrics) => Self::with_metrics(config, metrics),
+            Err(err) => {
+                error!(error = %err, "Failed to create metrics; metrics are disabled");
+                Self::with_metrics(config, Arc::new(NoopMetrics))
             }
         }
+    }
+
+    /// Create a new API deprecation agent, failing if the Prometheus metrics
+    /// can't be created (e.g. an invalid `metrics.prefix`).
+    pub fn try_new(config: ApiDeprecationConfig) -> Result<Self, MetricsError> {
+        let metrics = Self::metrics_for(&config)?;
+        Ok(Self::with_metrics(config, metrics))
+    }
+
+    fn metrics_for(config: &ApiDeprecationConfig) -> Result<Arc<dyn MetricsSink>, MetricsError> {
+        if !config.metrics.enabled {
+            return Ok(Arc::new(NoopMetrics));
+        }
+        #[cfg(feature = "prometheus")]
+        let metrics: Arc<dyn MetricsSink> =
+            Arc::new(DeprecationMetrics::new(&config.metrics.prefix)?);
+        #[cfg(not(feature = "prometheus"))]
+        let metrics: Arc<dyn MetricsSink> = Arc::new(NoopMetrics);
+        Ok(metrics)
+    }
 
+    /// Create a new agent that records usage into the given metrics sink.
+    pub fn with_metrics(config: ApiDeprecationConfig, metrics: Arc<dyn MetricsSink>) -> Self {
         info!(
             endpoints = config.endpoints.len(),
             "API deprecation agent initialized"
         );
 
-        Self {
-            config,
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let agent = Self {
+            hits: Arc::new(ArcSwap::from_pointee(DailyHits::new(
+                &config.settings.client_state,
+                Arc::clone(&clock),
+            ))),
+            sessions: Arc::new(ArcSwap::from_pointee(Sessions::new(
+                &config.settings.client_state,
+                Arc::clone(&clock),
+            ))),
+            diagnostics: DiagnosticsBuffer::new(config.settings.diagnostics_buffer_size),
+            state: Arc::new(ArcSwap::from_pointee(ProcessedConfig::new(
+                config,
+                clock.now(),
+            ))),
             metrics,
-            draining: AtomicBool::new(false),
+            usage: Arc::default(),
+            cluster: Arc::default(),
+            clock,
+            draining: Arc::default(),
+            reload_error: Arc::default(),
+            sunset_history: Arc::default(),
+            enforcement: Arc::new(AtomicBool::new(true)),
+            internal_errors: AtomicU64::new(0),
+            logged_panics: Mutex::default(),
+            tombstones: Mutex::default(),
+        };
+
+        // Initialize days_until_sunset metrics for all endpoints
+        let state = agent.state.load_full();
+        agent.refresh_sunset_gauges(&state.config);
+        agent.report_unmatchable(&state);
+        agent.handle().report_unconfirmed(&state.config);
+        agent.handle().report_severities(&state.config);
+        agent.handle().report_config_info(&state);
+        agent.handle().report_trimmed_headers(&state);
+        agent
+    }
+
+    /// Use the given clock instead of the system time.
+    ///
+    /// The configuration is reprocessed so its load time (the manifest's
+    /// `updated_at`) comes from the new clock too.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        let state = Arc::new(ProcessedConfig::new(
+            self.state.load().config.clone(),
+            clock.now(),
+        ));
+        self.state.store(Arc::clone(&state));
+        let client_state = &state.config.settings.client_state;
+        self.hits = Arc::new(ArcSwap::from_pointee(DailyHits::new(
+            client_state,
+            Arc::clone(&clock),
+        )));
+        self.sessions = Arc::new(ArcSwap::from_pointee(Sessions::new(
+            client_state,
+            Arc::clone(&clock),
+        )));
+        self.clock = clock;
+        self.refresh_sunset_gauges(&state.config);
+        self.handle().report_config_info(&state);
+        self
+    }
+
+    /// Replace the active configuration.
+    ///
+    /// The new configuration is validated and compiled before being swapped
+    /// in atomically; on error the active configuration is left untouched.
+    /// Requests already in flight finish against the configuration they
+    /// started with. The metrics prefix is fixed at construction and is not
+    /// affected by updates.
+    ///
+    /// A rejected update is handled according to the active configuration's
+    /// `settings.reload_failure_mode`; a later successful update clears it.
+    pub fn update_config(&self, config: ApiDeprecationConfig) -> Result<(), ConfigError> {
+        self.handle().update_config(config)
+    }
+
+    /// A handle to this agent's configuration and health that stays usable
+    /// after the agent is moved into the runner.
+    pub fn handle(&self) -> AgentHandle {
+        AgentHandle {
+            state: Arc::clone(&self.state),
+            metrics: Arc::clone(&self.metrics),
+            clock: Arc::clone(&self.clock),
+            draining: Arc::clone(&self.draining),
+            reload_error: Arc::clone(&self.reload_error),
+            sunset_history: Arc::clone(&self.sunset_history),
+            usage: Arc::clone(&self.usage),
+            cluster: Arc::clone(&self.cluster),
+            enforcement: Arc::clone(&self.enforcement),
+            hits: Arc::clone(&self.hits),
+            sessions: Arc::clone(&self.sessions),
+        }
+    }
+
+    /// Switch enforcement on or off at runtime, e.g. during an incident.
+    ///
+    /// While off, blocks and redirects degrade to warnings, as with
+    /// `settings.enforcement_enabled: false`; metrics still count the
+    /// intended actions. Enforcement needs both the switch and the setting,
+    /// and the switch survives configuration reloads.
+    pub fn set_enforcement(&self, enabled: bool) {
+        self.handle().set_enforcement(enabled);
+    }
+
+    /// Degrade a blocking or redirecting decision to a warning while
+    /// enforcement is off, keeping the intended action for metrics.
+    fn unenforced(&self, mut decision: DeprecationDecision) -> DeprecationDecision {
+        let enforcing = decision.state.config.settings.enforcement_enabled
+            && self.enforcement.load(Ordering::Relaxed);
+        if !enforcing && !matches!(decision.action, DeprecationActionResult::Warn) {
+            debug!(
+                endpoint_id = %decision.endpoint_id,
+                action = ?decision.action,
+                "Enforcement is off; allowing request"
+            );
+            decision.intended = Some(std::mem::replace(
+                &mut decision.action,
+                DeprecationActionResult::Warn,
+            ));
+            decision.allow_reason = Some(AllowReason::EnforcementDisabled);
+        }
+        decision
+    }
+
+    /// Report rules whose pattern failed to compile; they fail open (never match).
+    fn report_unmatchable(&self, state: &ProcessedConfig) {
+        for endpoint in state.unmatchable_endpoints() {
+            error!(
+                endpoint_id = %endpoint.id,
+                path = %endpoint.path,
+                "Invalid path pattern, rule will never match"
+            );
+            if state.config.metrics.enabled {
+                self.metrics.record_matcher_error(endpoint.metrics_id());
+            }
+        }
+    }
+
+    /// Reset the days_until_sunset gauges to match a configuration.
+    fn refresh_sunset_gauges(&self, config: &ApiDeprecationConfig) {
+        refresh_sunset_gauges(config, self.metrics.as_ref(), self.clock.now());
+    }
+
+    /// Start refreshing the computed gauges every `metrics.refresh_interval_secs`.
+    ///
+    /// Each refresh recomputes days until sunset and the upstream error ratio
+    /// of every endpoint over the elapsed interval, logging a warning for
+    /// endpoints above `metrics.error_ratio_warn_threshold`, and warns once
+    /// per spike about endpoints whose request rate or last day of requests
+    /// jumps above their spike threshold (`spike_threshold`, or
+    /// `metrics.usage_spike_factor`) times its average. The unique
+    /// consumer gauges move on to a new day at the first refresh after
+    /// midnight UTC. Refreshes are skipped while `metrics.enabled` is off.
+    pub fn start_gauge_refresh(&self) -> tokio::task::JoinHandle<()> {
+        let state = Arc::clone(&self.state);
+        let usage = Arc::clone(&self.usage);
+        let metrics = Arc::clone(&self.metrics);
+        let clock = Arc::clone(&self.clock);
+
+        tokio::spawn(async move {
+            loop {
+                let interval = state.load().config.metrics.refresh_interval_secs.max(1);
+                tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+
+                // Metrics can be enabled or disabled by a reload, so the
+                // task always runs and only refreshes while they are on
+                let state = state.load();
+                if !state.config.metrics.enabled {
+                    continue;
+                }
+                refresh_sunset_gauges(&state.config, metrics.as_ref(), clock.now());
+                refresh_error_ratios(&state.config, &usage, metrics.as_ref());
+                refresh_spikes(
+                    &state.config,
+                    &usage,
+                    metrics.as_ref(),
+                    interval,
+                    clock.now(),
+                );
+                refresh_unique_consumers(&state.config, &usage, metrics.as_ref(), clock.now());
+            }
+        })
+    }
+
+    /// Ping the systemd watchdog while `WatchdogSec=` is set (see
+    /// [`notify`]), reporting degraded health in the service status. The
+    /// agent never reports itself unhealthy, so pings only stop when the
+    /// runtime does, which is what the watchdog is there to catch. Returns
+    /// `None` when there is no watchdog to ping.
+    pub fn start_watchdog(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let interval = notify::watchdog_interval()?;
+        let draining = Arc::clone(&self.draining);
+        let reload_error = Arc::clone(&self.reload_error);
+        let cluster = Arc::clone(&self.cluster);
+
+        Some(tokio::spawn(async move {
+            let mut reported = None;
+            loop {
+                let degraded = degraded_capability(&draining, &reload_error, &cluster);
+                if degraded != reported {
+                    match degraded {
+                        Some(capability) => notify::status(&format!("Degraded: {}", capability)),
+                        None => notify::status("Healthy"),
+                    }
+                    reported = degraded;
+                }
+                notify::notify("WATCHDOG=1");
+                tokio::time::sleep(interval).await;
+            }
+        }))
+    }
+
+    /// Record the upstream response to a request let through to a deprecated
+    /// endpoint.
+    fn record_response(&self, path: &str, method: &str, status: u16) {
+        let state = self.state.load();
+        let Some((endpoint, processed)) = state.find(path, method, self.clock.now()) else {
+            return;
+        };
+        // Same gating as request counting; preflights aren't real usage
+        let preflight =
+            !state.config.settings.block_options && method.eq_ignore_ascii_case("OPTIONS");
+        if !endpoint.track_usage || !state.config.metrics.enabled || preflight {
+            return;
+        }
+
+        if self.usage.record_response(&processed.metrics_id, status) {
+            self.metrics.record_error(&processed.metrics_id);
         }
     }
 
+    /// Headers the agent adds to responses from a deprecated endpoint that
+    /// come off the upstream response, given its status: all of them from
+    /// error responses (`settings.skip_headers_on_error`), and those a 304
+    /// (Not Modified) can't carry (`settings.strip_headers_on_not_modified`).
+    /// A `warn_once` marker cookie goes with them, so a session isn't marked
+    /// as told by a response that didn't carry the notice.
+    ///
+    /// Headers are added in the request phase, before the upstream status is
+    /// known, so the response phase takes them back.
+    fn response_removals(&self, path: &str, method: &str, status: u16) -> Vec<String> {
+        let state = self.state.load();
+        let settings = &state.config.settings;
+        let error = settings.skips_headers_on_status(status);
+        let not_modified = status == 304 && settings.strip_headers_on_not_modified;
+        if !error && !not_modified {
+            return Vec::new();
+        }
+        let Some((endpoint, processed)) = state.find(path, method, self.clock.now()) else {
+            return Vec::new();
+        };
+
+        let cookie = endpoint
+            .warn_once
+            .as_ref()
+            .map(|_| "Set-Cookie".to_string());
+        let mut names: Vec<String> = processed
+            .headers
+            .keys()
+            .chain(processed.scheduled_headers.iter().flat_map(|h| h.keys()))
+            .chain(processed.computed_headers.iter().map(|(name, _)| name))
+            .chain([
+                &settings.sunset_reminder_header,
+                &settings.warning_level_header,
+                &settings.deprecated_header_used_header,
+                &settings.deprecated_body_fields_header,
+            ])
+            .chain(cookie.as_ref())
+            .filter(|name| error || !allowed_on_not_modified(name))
+            .cloned()
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Take the headers in [`response_removals`](Self::response_removals)
+    /// back off an upstream response.
+    ///
+    /// Header operations remove every value of a name, so the upstream's own
+    /// values of the same headers (its own `Link`, its session cookie) are
+    /// put back: only what the agent added comes off.
+    fn response_decision(&self, path: &str, method: &str, response: &Response) -> Decision {
+        let removals = self.response_removals(path, method, response.status_code());
+        let mut d = Decision::allow();
+        for name in removals {
+            d = d.remove_response_header(name.clone());
+            for (upstream_name, values) in response.headers() {
+                if upstream_name.eq_ignore_ascii_case(&name) {
+                    for value in values {
+                        d = d.add_response_header(upstream_name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        d
+    }
+
     /// Create from a YAML configuration string.
     pub fn from_yaml(yaml: &str) -> Result<Self, serde_yaml::Error> {
         let config: ApiDeprecationConfig = serde_yaml::from_str(yaml)?;
         Ok(Self::new(config))
     }
 
-    /// Get the metrics collector.
-    pub fn metrics(&self) -> &DeprecationMetrics {
+    /// Headers a warned request to the endpoint with id `endpoint_id` would
+    /// get now under the active settings, sorted by name; `None` if no rule
+    /// has that id.
+    ///
+    /// Nothing is counted, so a `{{hits}}` notice previews as the generated
+    /// notice. Rules outside their active window are previewed as if active.
+    pub fn preview_headers(&self, endpoint_id: &str) -> Option<Vec<(String, String)>> {
+        let state = self.state.load();
+        let position = state
+            .config
+            .endpoints
+            .iter()
+            .position(|endpoint| endpoint.id == endpoint_id)?;
+        let endpoint = &state.config.endpoints[position];
+        let settings = &state.config.settings;
+        let now = self.clock.now();
+
+        let headers = endpoint_headers(endpoint, &state.endpoints[position], settings, None, now);
+        let mut preview: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+
+        if settings.within_sunset_header_window(endpoint.sunset_at, now) {
+            let days = endpoint.days_until_sunset(now);
+            if let Some(days) = days.filter(|_| settings.sunset_reminder) {
+                preview.push((settings.sunset_reminder_header.clone(), days.to_string()));
+            }
+            if settings.warning_level {
+                preview.push((
+                    settings.warning_level_header.clone(),
+                    settings.warning_level(days).to_string(),
+                ));
+            }
+        }
+        preview.sort();
+        Some(preview)
+    }
+
+    /// Rollout bucket (0-99) of a client under the active `settings.bucket_seed`.
+    pub fn client_bucket(&self, client_id: &str) -> u8 {
+        self.state.load().config.settings.client_bucket(client_id)
+    }
+
+    /// Get the metrics sink.
+    pub fn metrics(&self) -> &Arc<dyn MetricsSink> {
         &self.metrics
     }
 
-    /// Process a request and determine the appropriate action.
+    /// Current usage of every configured endpoint, in configuration order.
+    ///
+    /// Counts are kept per `metrics_id` and survive configuration reloads;
+    /// they are only collected while `metrics.enabled` is true and the
+    /// endpoint has `track_usage` set.
+    pub fn usage_snapshot(&self) -> Vec<EndpointUsage> {
+        self.handle().usage_snapshot()
+    }
+
+    /// Totals over the lifetime of the process, from the usage counters.
+    pub fn lifetime_summary(&self) -> LifetimeSummary {
+        LifetimeSummary::from_usage(&self.usage_snapshot())
+    }
+
+    /// Start syncing usage with the shared store from `metrics.shared_store`.
+    ///
+    /// Local counts are pushed and cluster totals read back every
+    /// `flush_interval_secs`. While the store is unreachable the agent keeps
+    /// counting locally and reports degraded health. Returns `None` if no
+    /// shared store is configured or its URL is invalid.
+    #[cfg(feature = "redis-store")]
+    pub fn start_shared_store(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let config = self.state.load().config.metrics.shared_store.clone()?;
+        let mut store = match crate::shared_store::SharedStore::new(&config) {
+            Ok(store) => store,
+            Err(err) => {
+                error!(error = %err, "Invalid shared store configuration, counting locally only");
+                self.cluster.set_error(err.to_string());
+                return None;
+            }
+        };
+
+        let state = Arc::clone(&self.state);
+        let usage = Arc::clone(&self.usage);
+        let cluster = Arc::clone(&self.cluster);
+        let metrics = Arc::clone(&self.metrics);
+        let interval = std::time::Duration::from_secs(config.flush_interval_secs.max(1));
+
+        info!(
+            interval_secs = interval.as_secs(),
+            "Starting shared usage store sync"
+        );
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let state = state.load_full();
+                let metrics_ids: Vec<String> = state
+                    .config
+                    .endpoints
+                    .iter()
+                    .map(|endpoint| endpoint.metrics_id().to_string())
+                    .collect();
+
+                match store.sync(&usage, &metrics_ids).await {
+                    Ok(totals) => {
+                        if state.config.metrics.enabled {
+                            for (metrics_id, totals) in &totals {
+                                metrics.set_cluster_totals(metrics_id, totals);
+                            }
+                        }
+                        if cluster.error().is_some() {
+                            info!("Shared usage store reachable again");
+                        }
+                        cluster.update(totals);
+                    }
+                    Err(err) => {
+                        warn!(error = %err, "Shared usage store unavailable, counting locally only");
+                        cluster.set_error(err.to_string());
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Answer a request for the deprecation manifest, if it is one.
+    fn manifest_response(
+        &self,
+        path: &str,
+        method: &str,
+        if_none_match: Option<&str>,
+    ) -> Option<ManifestResponse> {
+        self.state
+            .load()
+            .manifest
+            .as_ref()?
+            .respond(path, method, if_none_match)
+    }
+
+    /// Trace rule matching for a request that opted in, when
+    /// `settings.trace_match` is enabled and debug logging is on.
+    fn match_trace<'a>(
+        &self,
+        path: &str,
+        method: &str,
+        opted_in: bool,
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Option<MatchTrace> {
+        if !opted_in || !tracing::enabled!(tracing::Level::DEBUG) {
+            return None;
+        }
+        let state = self.state.load();
+        if !state.config.settings.trace_match {
+            return None;
+        }
+        Some(
+            state
+                .trace(path, method, self.clock.now())
+                .with_headers(headers, &state.config.settings.redact_headers),
+        )
+    }
+
+    /// Process a request from an unidentified client.
+    #[cfg(test)]
     fn process_request(
         &self,
         path: &str,
         method: &str,
         query_string: Option<&str>,
     ) -> Option<DeprecationDecision> {
+        self.process_client_request(path, method, query_string, None)
+    }
+
+    /// Process a request from an unidentified client and record its
+    /// outcome, as `on_request` does. Returns whether a rule matched.
+    #[cfg(test)]
+    pub(crate) fn process_and_record(&self, path: &str, method: &str) -> bool {
+        let Some(decision) = self.process_request(path, method, None) else {
+            return false;
+        };
+        self.record_outcome(&decision, path);
+        true
+    }
+
+    /// Process a request and determine the appropriate action.
+    ///
+    /// `client` is the client's identity (see `settings.client_identity`), if
+    /// known.
+    fn process_client_request(
+        &self,
+        path: &str,
+        method: &str,
+        query_string: Option<&str>,
+        client: Option<&str>,
+    ) -> Option<DeprecationDecision> {
+        // Load the configuration once so a concurrent swap can't be observed mid-request
+        let state = self.state.load_full();
+
         // Find matching deprecated endpoint
-        let endpoint = self.config.find_endpoint(path, method)?;
+        let now = self.clock.now();
+        let (endpoint, processed) = state.find(path, method, now)?;
 
         debug!(
             endpoint_id = %endpoint.id,
@@ -83,19 +662,31 @@ impl ApiDeprecationAgent {
             "Request matches deprecated endpoint"
         );
 
-        // Track usage
-        if endpoint.track_usage {
-            let status = match endpoint.status {
-                DeprecationStatus::Deprecated => "deprecated",
-                DeprecationStatus::Removed => "removed",
-                DeprecationStatus::Scheduled => "scheduled",
+        // CORS preflight is only answered with headers and isn't counted as real usage
+        let preflight =
+            !state.config.settings.block_options && method.eq_ignore_ascii_case("OPTIONS");
+
+        // Track usage (subordinate to the global metrics switch)
+        if endpoint.track_usage && state.config.metrics.enabled {
+            let status = if preflight {
+                "preflight"
+            } else {
+                self.usage.record_request(&processed.metrics_id, now);
+                if let Some(client) = client {
+                    self.usage
+                        .record_consumer(&processed.metrics_id, client, now);
+                }
+                processed.status_label
             };
-            self.metrics
-                .record_request(&endpoint.id, path, method, status);
+            self.record_request_metric(&state, &processed.metrics_id, path, method, status, client);
+        }
+
+        if endpoint.tombstone.is_some() && !preflight {
+            self.record_tombstone_hit(&state, endpoint, processed, path, method, client);
         }
 
         // Check if past sunset
-        let past_sunset = endpoint.is_past_sunset();
+        let past_sunset = endpoint.is_past_sunset_at(now);
         if past_sunset {
             warn!(
                 endpoint_id = %endpoint.id,
@@ -105,15 +696,30 @@ impl ApiDeprecationAgent {
         }
 
         // Determine action
-        let action = self.determine_action(endpoint, past_sunset);
+        let mut action = if preflight {
+            DeprecationActionResult::Warn
+        } else {
+            self.determine_action(&state, endpoint, processed, past_sunset)
+        };
 
-        // Build deprecation headers
-        let headers = DeprecationHeaders::for_endpoint(endpoint, &self.config.settings).build();
+        // Unconfirmed rules never block; they only report what they would have done
+        let would_block = !endpoint.is_confirmed(&state.config.settings) && action.blocks();
+        if would_block && state.first_shadowed(&endpoint.id) {
+            warn!(
+                endpoint_id = %endpoint.id,
+                path = %path,
+                action = ?action,
+                "Unconfirmed rule would block request; allowing (set confirmed: true to enforce; logged once per rule)"
+            );
+        }
+        if would_block {
+            action = DeprecationActionResult::Warn;
+        }
 
         // Build redirect URL if needed
         let redirect_url = if matches!(action, DeprecationActionResult::Redirect { .. }) {
             endpoint.replacement.as_ref().map(|r| {
-                let mut url = r.path.clone();
+                let mut url = r.target_for(path);
                 if r.preserve_query {
                     if let Some(qs) = query_string {
                         if !qs.is_empty() {
@@ -128,20 +734,420 @@ impl ApiDeprecationAgent {
             None
         };
 
-        Some(DeprecationDecision {
+        let settings = &state.config.settings;
+
+        // Sunset-related headers are held back until the sunset is near
+        let sunset_near = settings.within_sunset_header_window(endpoint.sunset_at, now);
+
+        // Countdown for the sunset reminder header
+        let days_until_sunset = if settings.sunset_reminder && sunset_near {
+            endpoint.days_until_sunset(now)
+        } else {
+            None
+        };
+
+        // Escalating level for clients that ignore the standard headers
+        let warning_level = if settings.warning_level && sunset_near {
+            Some(settings.warning_level(endpoint.days_until_sunset(now)))
+        } else {
+            None
+        };
+
+        // Without counting there is no count to show, so the notice is generated
+        let counted = endpoint.track_usage && state.config.metrics.enabled;
+        let hits = (processed.hits_template && counted).then(|| {
+            if preflight {
+                self.hits.load().get(&processed.metrics_id, client, now)
+            } else {
+                self.hits.load().record(&processed.metrics_id, client, now)
+            }
+        });
+        let headers = endpoint_headers(endpoint, processed, settings, hits, now);
+
+        let allow_reason = allow_reason(&action, preflight, would_block);
+
+        // Blocks and redirects without a target (410) may have a body of their own
+        let block_status = match action {
+            DeprecationActionResult::Block { status_code } => Some(status_code),
+            DeprecationActionResult::Redirect { .. } if redirect_url.is_none() => Some(410),
+            _ => None,
+        };
+        let custom_body =
+            block_status.and_then(|status_code| CustomBody::for_status(endpoint, status_code));
+
+        let decision = DeprecationDecision {
             endpoint_id: endpoint.id.clone(),
+            metrics_id: Arc::clone(&processed.metrics_id),
             action,
             headers,
             redirect_url,
-            message: endpoint.deprecation_message(),
-            documentation_url: endpoint.documentation_url.clone(),
-        })
+            gone_body: Arc::clone(&processed.gone_body),
+            custom_body,
+            days_until_sunset,
+            warning_level,
+            allow_reason,
+            would_block,
+            severity: endpoint.severity,
+            notes: endpoint.notes.clone(),
+            warn_once: endpoint.warn_once.clone(),
+            intended: None,
+            sunset_at: endpoint.sunset_at,
+            migration_deadline: endpoint
+                .migration_deadline(state.config.settings.migration_grace_days),
+            state: Arc::clone(&state),
+        };
+        // Deprecated query parameters add to the rule's own decision
+        let decision =
+            self.apply_param_uses(decision, endpoint, processed, path, query_string, preflight);
+        let mut decision = self.unenforced(decision);
+        fit_header_budget(&mut decision, endpoint);
+        Some(decision)
+    }
+
+    /// Decide on a buffered request body for rules with deprecated body fields.
+    ///
+    /// The endpoint's own deprecation was already decided on the headers, so
+    /// this only adds to it: requests whose body contains none of the fields
+    /// are allowed unchanged, as are bodies larger than
+    /// `settings.max_body_inspection_bytes` or not JSON unless
+    /// `body_inspection_fail_open` is off. Otherwise the fields are listed in
+    /// a response header, or the request is rejected with 400 if one of them
+    /// blocks.
+    pub fn request_body_decision(&self, path: &str, method: &str, body: &[u8]) -> Decision {
+        let Some(decision) = self.process_request_body(path, method, body) else {
+            return Decision::allow();
+        };
+
+        let severity = decision.severity;
+        let migration_deadline = decision.migration_deadline;
+        let d = match decision.action {
+            DeprecationActionResult::Custom {
+                status_code,
+                body,
+                content_type,
+            } => {
+                let (body, content_length) = response_body(method, body.as_ref());
+                let mut d = with_content_length(Decision::block(status_code), content_length)
+                    .with_body(body)
+                    .with_block_header("Content-Type", content_type.as_ref())
+                    .with_tag("deprecated")
+                    .with_tag("blocked")
+                    .with_metadata(
+                        "deprecated_endpoint",
+                        serde_json::json!(decision.endpoint_id),
+                    );
+                for (name, value) in decision.headers.iter() {
+                    d = d.with_block_header(name.clone(), value.clone());
+                }
+                with_close_connection(d, &decision.state.config.settings)
+            }
+            _ => {
+                let mut d = Decision::allow().with_tag("deprecated").with_metadata(
+                    "deprecated_endpoint",
+                    serde_json::json!(decision.endpoint_id),
+                );
+                if decision.would_block {
+                    d = d.with_tag("would_block");
+                }
+                if let Some(reason) = decision.allow_reason {
+                    d = d.with_metadata(ALLOW_REASON_METADATA, serde_json::json!(reason.as_str()));
+                }
+                // The endpoint's own headers were added in the headers phase
+                let fields_header = &decision.state.config.settings.deprecated_body_fields_header;
+                match decision.headers.get(fields_header) {
+                    Some(fields) => d.add_response_header(fields_header.clone(), fields.clone()),
+                    None => d,
+                }
+            }
+        };
+        with_deadline(
+            d.with_metadata(SEVERITY_METADATA, serde_json::json!(severity)),
+            migration_deadline,
+        )
+    }
+
+    /// Check a request body against the matching rule's deprecated fields.
+    fn process_request_body(
+        &self,
+        path: &str,
+        method: &str,
+        body: &[u8],
+    ) -> Option<DeprecationDecision> {
+        let state = self.state.load_full();
+        let (endpoint, processed) = state.find(path, method, self.clock.now())?;
+        // Requests without a body have no fields to check
+        if !endpoint.inspects_body() || body.is_empty() {
+            return None;
+        }
+
+        let settings = &state.config.settings;
+        let json = if body.len() > settings.max_body_inspection_bytes {
+            debug!(
+                endpoint_id = %endpoint.id,
+                size = body.len(),
+                "Request body too large to inspect for deprecated fields"
+            );
+            None
+        } else {
+            serde_json::from_slice::<serde_json::Value>(body).ok()
+        };
+
+        // Without a body to inspect, fail open or reject as if a field blocked
+        let (fields, notice, blocks) = match json {
+            Some(json) => {
+                let fields: Vec<_> = endpoint
+                    .body_fields()
+                    .into_iter()
+                    .filter(|field| json.pointer(&field.pointer).is_some())
+                    .collect();
+                if fields.is_empty() {
+                    return None;
+                }
+                let notice = match &endpoint.message {
+                    Some(_) => endpoint.uncounted_deprecation_message(),
+                    None => fields
+                        .iter()
+                        .map(|field| field.message(endpoint.sunset_at))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                };
+                let blocks = fields
+                    .iter()
+                    .any(|field| field.action == BodyFieldAction::Block);
+                (fields, notice, blocks)
+            }
+            None if settings.body_inspection_fail_open => {
+                if state.config.metrics.enabled {
+                    self.metrics.record_allowed(
+                        endpoint.metrics_id(),
+                        AllowReason::BodyInspectionFailOpen.as_str(),
+                    );
+                }
+                return None;
+            }
+            None => (
+                Vec::new(),
+                "The request body could not be inspected for deprecated fields.".to_string(),
+                true,
+            ),
+        };
+
+        // The request itself was counted when its headers were decided
+        if endpoint.track_usage && state.config.metrics.enabled {
+            for field in &fields {
+                self.metrics.record_deprecated_body_field(
+                    &processed.metrics_id,
+                    &field.pointer,
+                    field.action.as_str(),
+                );
+            }
+        }
+
+        let mut headers = HashMap::clone(&processed.headers);
+        headers.insert(settings.notice_header.clone(), notice.clone());
+        if !fields.is_empty() {
+            headers.insert(
+                settings.deprecated_body_fields_header.clone(),
+                fields
+                    .iter()
+                    .map(|field| field.pointer.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+        }
+
+        let mut would_block = false;
+        let action = if !blocks {
+            DeprecationActionResult::Warn
+        } else if endpoint.is_confirmed(settings) {
+            let fields: Vec<_> = fields
+                .iter()
+                .filter(|field| field.action == BodyFieldAction::Block)
+                .collect();
+            let envelope = &settings.error_envelope;
+            DeprecationActionResult::Custom {
+                status_code: 400,
+                body: Arc::from(deprecated_body_fields_body_with(&fields, &notice, envelope)),
+                content_type: Arc::from(envelope.body_fields_content_type()),
+            }
+        } else {
+            if state.first_shadowed(&endpoint.id) {
+                warn!(
+                    endpoint_id = %endpoint.id,
+                    "Unconfirmed rule would block request body with deprecated fields; allowing (set confirmed: true to enforce; logged once per rule)"
+                );
+            }
+            would_block = true;
+            DeprecationActionResult::Warn
+        };
+
+        let allow_reason = allow_reason(&action, false, would_block);
+        Some(self.unenforced(DeprecationDecision {
+            endpoint_id: endpoint.id.clone(),
+            metrics_id: Arc::clone(&processed.metrics_id),
+            action,
+            headers: Arc::new(headers),
+            redirect_url: None,
+            gone_body: Arc::clone(&processed.gone_body),
+            custom_body: None,
+            days_until_sunset: None,
+            warning_level: None,
+            allow_reason,
+            would_block,
+            severity: endpoint.severity,
+            notes: endpoint.notes.clone(),
+            warn_once: endpoint.warn_once.clone(),
+            intended: None,
+            sunset_at: endpoint.sunset_at,
+            migration_deadline:
+                endpoint.migration_deadline(state.config.settings.migration_grace_days),
+            state: Arc::clone(&state),
+        }))
+    }
+
+    /// Record the caller of a tombstoned endpoint and sample the request.
+    fn record_tombstone_hit(
+        &self,
+        state: &ProcessedConfig,
+        endpoint: &DeprecatedEndpoint,
+        processed: &ProcessedEndpoint,
+        path: &str,
+        method: &str,
+        client: Option<&str>,
+    ) {
+        let sample_every = endpoint
+            .tombstone
+            .as_ref()
+            .map_or(1, |tombstone| tombstone.sample_every);
+        let mut tombstones = self
+            .tombstones
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.config.metrics.enabled {
+            let (path_label, client_label) =
+                tombstones.labels(&processed.metrics_id, method, path, client);
+            self.metrics.record_tombstone_hit(
+                &processed.metrics_id,
+                method,
+                &path_label,
+                &client_label,
+            );
+        }
+        if tombstones.should_sample(&endpoint.id, sample_every) {
+            self.diagnostics.push(DiagnosticSample {
+                at: self.clock.now(),
+                endpoint_id: endpoint.id.clone(),
+                method: method.to_string(),
+                path: path.to_string(),
+                client: client.map(str::to_string),
+            });
+        }
+    }
+
+    /// Requests sampled for diagnostics (from tombstoned endpoints), oldest
+    /// first.
+    pub fn diagnostic_samples(&self) -> Vec<DiagnosticSample> {
+        self.diagnostics.snapshot()
+    }
+
+    /// Add the deprecated query parameters a request uses to the rule's own
+    /// decision: their notice is appended to the rule's, a `block` entry
+    /// rejects the request with 400 and a `strip` entry redirects to the URL
+    /// without it. A rule that already blocks or redirects keeps its action.
+    fn apply_param_uses(
+        &self,
+        mut decision: DeprecationDecision,
+        endpoint: &DeprecatedEndpoint,
+        processed: &ProcessedEndpoint,
+        path: &str,
+        query_string: Option<&str>,
+        preflight: bool,
+    ) -> DeprecationDecision {
+        let query_string = query_string.unwrap_or_default();
+        let used: Vec<&DeprecatedParam> = endpoint
+            .deprecated_params
+            .iter()
+            .filter(|param| query_param_names(query_string).any(|name| name == param.name))
+            .collect();
+        if used.is_empty() {
+            return decision;
+        }
+
+        let state = Arc::clone(&decision.state);
+        if endpoint.track_usage && state.config.metrics.enabled && !preflight {
+            for param in &used {
+                self.metrics.record_deprecated_param(
+                    &processed.metrics_id,
+                    &param.name,
+                    param.action.as_str(),
+                );
+            }
+        }
+
+        let notice = used
+            .iter()
+            .map(|param| param.message())
+            .collect::<Vec<_>>()
+            .join(" ");
+        let notice_header = &state.config.settings.notice_header;
+        let mut headers = HashMap::clone(&decision.headers);
+        let notice = match headers.get(notice_header) {
+            Some(rule_notice) => format!("{} {}", rule_notice, notice),
+            None => notice,
+        };
+        headers.insert(notice_header.clone(), notice);
+        decision.headers = Arc::new(headers);
+
+        if preflight || !matches!(decision.action, DeprecationActionResult::Warn) {
+            return decision;
+        }
+
+        let blocking = used.iter().find(|param| param.action == ParamAction::Block);
+        if let Some(param) = blocking {
+            if endpoint.is_confirmed(&state.config.settings) {
+                decision.action = DeprecationActionResult::Custom {
+                    status_code: 400,
+                    body: Arc::from(deprecated_param_body_with(
+                        param,
+                        &state.config.settings.error_envelope,
+                    )),
+                    content_type: Arc::from("application/json"),
+                };
+                decision.allow_reason = None;
+            } else {
+                if state.first_shadowed(&endpoint.id) {
+                    warn!(
+                        endpoint_id = %endpoint.id,
+                        param = %param.name,
+                        "Unconfirmed rule would block request using deprecated parameter; allowing (set confirmed: true to enforce; logged once per rule)"
+                    );
+                }
+                decision.would_block = true;
+                decision.allow_reason = Some(AllowReason::Shadowed);
+            }
+        } else if used.iter().any(|param| param.action == ParamAction::Strip) {
+            let stripped = strip_query_params(query_string, |name| {
+                used.iter()
+                    .any(|param| param.action == ParamAction::Strip && param.name == name)
+            });
+            decision.redirect_url = Some(if stripped.is_empty() {
+                path.to_string()
+            } else {
+                format!("{}?{}", path, stripped)
+            });
+            // 307 keeps the method and body
+            decision.action = DeprecationActionResult::Redirect { status_code: 307 };
+            decision.allow_reason = None;
+        }
+        decision
     }
 
     /// Determine the action to take based on endpoint config and sunset status.
     fn determine_action(
         &self,
+        state: &ProcessedConfig,
         endpoint: &DeprecatedEndpoint,
+        processed: &ProcessedEndpoint,
         past_sunset: bool,
     ) -> DeprecationActionResult {
         // If removed, always block
@@ -151,7 +1157,7 @@ impl ApiDeprecationAgent {
 
         // If past sunset, apply global policy
         if past_sunset {
-            return match self.config.settings.past_sunset_action {
+            return match state.config.settings.past_sunset_action {
                 PastSunsetAction::Warn => DeprecationActionResult::Warn,
                 PastSunsetAction::Block => DeprecationActionResult::Block { status_code: 410 },
                 PastSunsetAction::Redirect => {
@@ -165,96 +1171,286 @@ impl ApiDeprecationAgent {
         }
 
         // Otherwise, use endpoint-specific action
-        match &endpoint.action {
-            DeprecationAction::Warn => DeprecationActionResult::Warn,
-            DeprecationAction::Redirect { status_code } => DeprecationActionResult::Redirect {
-                status_code: *status_code,
-            },
-            DeprecationAction::Block { status_code } => DeprecationActionResult::Block {
-                status_code: *status_code,
-            },
-            DeprecationAction::Custom {
-                status_code,
-                body,
-                content_type,
-            } => DeprecationActionResult::Custom {
-                status_code: *status_code,
-                body: body.clone(),
-                content_type: content_type.clone(),
-            },
+        processed.action.clone()
+    }
+
+    /// Record redirect and block outcomes for a decision.
+    fn record_outcome(&self, decision: &DeprecationDecision, path: &str) {
+        if !decision.state.config.metrics.enabled {
+            return;
+        }
+
+        if let Some(reason) = decision.allow_reason {
+            self.metrics
+                .record_allowed(&decision.metrics_id, reason.as_str());
+        }
+
+        // Intended actions are counted even with enforcement off
+        let action = decision.intended.as_ref().unwrap_or(&decision.action);
+        match (action, &decision.redirect_url) {
+            (DeprecationActionResult::Redirect { .. }, Some(redirect_url)) => {
+                self.metrics
+                    .record_redirect(&decision.metrics_id, path, redirect_url);
+                self.usage.record_redirect(&decision.metrics_id);
+            }
+            (DeprecationActionResult::Redirect { .. }, None) => {
+                self.metrics
+                    .record_blocked(&decision.metrics_id, path, "no_replacement");
+                self.usage.record_blocked(&decision.metrics_id);
+            }
+            (DeprecationActionResult::Block { .. }, _) => {
+                self.metrics
+                    .record_blocked(&decision.metrics_id, path, "removed");
+                self.usage.record_blocked(&decision.metrics_id);
+            }
+            _ => {}
         }
     }
 
-    /// Apply deprecation headers to an allow decision.
-    fn apply_headers(&self, decision: Decision, headers: HashMap<String, String>) -> Decision {
-        let mut d = decision;
-        for (name, value) in headers {
-            d = d.add_response_header(name, value);
+    /// Count a request in `requests_total`. With `metrics.session_window_secs`
+    /// set, every request goes to `requests_raw_total` instead, and only the
+    /// first of an identified client's session (and preflights) to
+    /// `requests_total`.
+    fn record_request_metric(
+        &self,
+        state: &ProcessedConfig,
+        metrics_id: &str,
+        path: &str,
+        method: &str,
+        status: &str,
+        client: Option<&str>,
+    ) {
+        let Some(window) = state.config.metrics.session_window_secs else {
+            self.metrics
+                .record_request(metrics_id, path, method, status);
+            return;
+        };
+        self.metrics
+            .record_raw_request(metrics_id, path, method, status);
+        let counted = match client {
+            Some(client) if status != "preflight" => {
+                self.sessions
+                    .load()
+                    .start(metrics_id, client, window, self.clock.now())
+            }
+            _ => true,
+        };
+        if counted {
+            self.metrics
+                .record_request(metrics_id, path, method, status);
         }
-        d
     }
-}
 
-/// Result of processing a deprecated endpoint.
-struct DeprecationDecision {
-    endpoint_id: String,
-    action: DeprecationActionResult,
-    headers: HashMap<String, String>,
-    redirect_url: Option<String>,
-    message: String,
-    documentation_url: Option<String>,
-}
-
-/// Action result after processing.
-#[derive(Debug, Clone)]
-enum DeprecationActionResult {
-    Warn,
-    Redirect {
-        status_code: u16,
-    },
-    Block {
-        status_code: u16,
-    },
-    Custom {
-        status_code: u16,
-        body: String,
-        content_type: String,
-    },
-}
+    /// Path of a request rules are matched against (see
+    /// `settings.original_path_header`).
+    ///
+    /// Clients can send the header themselves, so it is only consulted when
+    /// the request's own path matches no rule: a spoofed value can point a
+    /// rewritten request at a rule, but never away from one.
+    fn match_path<'a>(&self, request: &'a Request) -> &'a str {
+        let state = self.state.load();
+        let settings = &state.config.settings;
+        if settings.original_path_header.is_none()
+            || state
+                .find(request.path(), request.method(), self.clock.now())
+                .is_some()
+        {
+            return request.path();
+        }
+        settings.match_path(request.path(), |name| request.header(name))
+    }
 
-// The agent needs to be Send + Sync for the SDK
-unsafe impl Send for ApiDeprecationAgent {}
-unsafe impl Sync for ApiDeprecationAgent {}
+    /// Decide on a request (everything `on_request` does once admitted).
+    fn request_decision(&self, request: &Request) -> Decision {
+        let method = request.method();
+        let path = self.match_path(request);
 
-#[async_trait]
-impl Agent for ApiDeprecationAgent {
-    async fn on_request(&self, request: &Request) -> Decision {
+        // Serve the deprecation manifest directly
+        if let Some(response) =
+            self.manifest_response(path, method, request.header("If-None-Match"))
+        {
+            let body = match &response.body {
+                Some(body) if !method.eq_ignore_ascii_case("HEAD") => body.as_ref(),
+                _ => "",
+            };
+            let mut d = Decision::block(response.status)
+                .with_body(body)
+                .with_tag("deprecation_manifest");
+            for (name, value) in response.headers {
+                d = d.with_block_header(name, value);
+            }
+            return d;
+        }
+
+        let headers = request.headers().iter().flat_map(|(name, values)| {
+            values
+                .iter()
+                .map(move |value| (name.as_str(), value.as_str()))
+        });
+        if let Some(trace) = self.match_trace(
+            path,
+            method,
+            request.header(TRACE_MATCH_HEADER).is_some(),
+            headers,
+        ) {
+            debug!(
+                trace = %serde_json::to_string(&trace).unwrap_or_default(),
+                "Match trace"
+            );
+        }
+
+        // Deprecated request headers are checked on every request
+        let header_uses =
+            self.deprecated_headers_used(path, method, |name| request.header(name).is_some());
+        if let Some(blocked) = header_uses
+            .iter()
+            .find(|used| used.action == ParamAction::Block)
+        {
+            let state = self.state.load();
+            let settings = &state.config.settings;
+            let body = deprecated_header_body_with(&blocked.header, &settings.error_envelope);
+            let (body, content_length) = response_body(method, &body);
+            let d = with_content_length(Decision::block(400), content_length)
+                .with_body(body)
+                .with_block_header("Content-Type", "application/json")
+                .with_block_header(
+                    settings.deprecated_header_used_header.clone(),
+                    blocked.header.name.clone(),
+                )
+                .with_tag("deprecated_header")
+                .with_tag("blocked");
+            return with_close_connection(d, settings);
+        }
+
+        let decision = self.endpoint_decision(request);
+        self.apply_header_uses(decision, &header_uses)
+    }
+
+    /// Run request processing, falling back to `fallback` if it panics.
+    ///
+    /// Failing open keeps one bad request (or a bug in one rule) from taking
+    /// the stream down. The state request processing updates under a lock
+    /// (usage counters, trackers, caches) only holds counts and samples, so
+    /// locks a panic poisoned are taken as they are rather than failing
+    /// every later request. Each distinct panic message is logged once, up
+    /// to [`MAX_LOGGED_PANICS`] of them; the default panic hook's output is
+    /// suppressed, so a hot panic can't flood stderr.
+    fn isolate_panics<T>(&self, processing: impl FnOnce() -> T, fallback: impl FnOnce() -> T) -> T {
+        install_panic_hook();
+        let nested = ISOLATING_PANICS.with(|isolating| isolating.replace(true));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(processing));
+        ISOLATING_PANICS.with(|isolating| isolating.set(nested));
+
+        match result {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+
+                let mut logged = self
+                    .logged_panics
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                if logged.len() < MAX_LOGGED_PANICS && logged.insert(message.clone()) {
+                    error!(panic = %message, "Request processing panicked; allowing request");
+                }
+                drop(logged);
+
+                self.internal_errors.fetch_add(1, Ordering::Relaxed);
+                self.metrics.record_internal_error();
+                fallback()
+            }
+        }
+    }
+
+    /// Rule an endpoint example matches and what the request gets, from the
+    /// same handling as requests sent by the proxy.
+    pub(crate) fn example_outcome(
+        &self,
+        example: &MatchExample,
+    ) -> (Option<String>, ExampleAction) {
+        let headers = example
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), vec![value.clone()]))
+            .collect();
+        let request = Request::from_headers_event(RequestHeadersEvent {
+            metadata: RequestMetadata {
+                correlation_id: "example".to_string(),
+                request_id: "example".to_string(),
+                client_ip: "127.0.0.1".to_string(),
+                client_port: 0,
+                server_name: None,
+                protocol: "HTTP/1.1".to_string(),
+                tls_version: None,
+                tls_cipher: None,
+                route_id: None,
+                upstream_id: None,
+                timestamp: self.clock.now().to_rfc3339(),
+                traceparent: None,
+            },
+            method: example.method.to_uppercase(),
+            uri: example.path.clone(),
+            headers,
+        });
+
+        let matched = self
+            .state
+            .load()
+            .find(
+                self.match_path(&request),
+                request.method(),
+                self.clock.now(),
+            )
+            .map(|(endpoint, _)| endpoint.id.clone());
+        let action = match self.request_decision(&request).build().decision {
+            ProtocolDecision::Redirect { .. } => ExampleAction::Redirect,
+            // 307 and 308 redirects are sent as blocks with a Location header
+            ProtocolDecision::Block { status, .. } if (300..400).contains(&status) => {
+                ExampleAction::Redirect
+            }
+            ProtocolDecision::Block { .. } => ExampleAction::Block,
+            _ => ExampleAction::Allow,
+        };
+        (matched, action)
+    }
+
+    /// Decision for the deprecated endpoint a request matches, if any.
+    fn endpoint_decision(&self, request: &Request) -> Decision {
         let method = request.method();
-        let path = request.path();
+        let path = self.match_path(request);
         let query_string = request.query_string();
 
         // Process the request
-        let decision = match self.process_request(path, method, query_string) {
-            Some(d) => d,
-            None => {
-                // Not a deprecated endpoint, allow
-                return Decision::allow();
-            }
-        };
+        let client = self
+            .state
+            .load()
+            .config
+            .settings
+            .client_id(|name| request.header(name));
+        let decision =
+            match self.process_client_request(path, method, query_string, client.as_deref()) {
+                Some(d) => d,
+                None => {
+                    // Not a deprecated endpoint, allow
+                    return self.without_upstream_header(Decision::allow());
+                }
+            };
 
         // Log the access
-        if self.config.settings.log_access {
-            info!(
-                endpoint_id = %decision.endpoint_id,
-                path = %path,
-                method = %method,
-                action = ?decision.action,
-                "Deprecated endpoint accessed"
-            );
+        if decision.state.config.settings.log_access {
+            log_access(&decision, client.as_deref(), path, method);
         }
 
+        self.record_outcome(&decision, path);
+
         // Apply the action
-        match decision.action {
+        let severity = decision.severity;
+        let migration_deadline = decision.migration_deadline;
+        let d = match decision.action {
             DeprecationActionResult::Warn => {
                 // Allow but add deprecation headers
                 let mut d = Decision::allow().with_tag("deprecated").with_metadata(
@@ -262,15 +1458,58 @@ impl Agent for ApiDeprecationAgent {
                     serde_json::json!(decision.endpoint_id),
                 );
 
-                d = self.apply_headers(d, decision.headers);
+                if decision.would_block {
+                    d = d.with_tag("would_block");
+                }
+                if decision.intended.is_some() {
+                    d = d.with_tag("enforcement_disabled");
+                }
+                if let Some(reason) = decision.allow_reason {
+                    d = d.with_metadata(ALLOW_REASON_METADATA, serde_json::json!(reason.as_str()));
+                }
+                d = self.without_upstream_header(d);
+                if let Some((name, value)) = upstream_header(&decision) {
+                    d = d.add_request_header(name, value);
+                }
+
+                // warn_once sessions get the headers on their first request only
+                let delivery =
+                    notice_delivery(&decision, request.header("Cookie"), self.clock.now());
+                if delivery == NoticeDelivery::Suppressed {
+                    return with_deadline(
+                        d.with_tag("notice_suppressed")
+                            .with_metadata(SEVERITY_METADATA, serde_json::json!(severity)),
+                        migration_deadline,
+                    );
+                }
+                if let NoticeDelivery::Mark(cookie) = delivery {
+                    d = d.add_response_header("Set-Cookie", cookie);
+                }
+
+                d = self.apply_headers(d, &decision.headers);
+
+                if let Some(days) = decision.days_until_sunset {
+                    d = d.add_response_header(
+                        decision
+                            .state
+                            .config
+                            .settings
+                            .sunset_reminder_header
+                            .clone(),
+                        days.to_string(),
+                    );
+                }
+                if let Some(level) = decision.warning_level {
+                    d = d.add_response_header(
+                        decision.state.config.settings.warning_level_header.clone(),
+                        level.to_string(),
+                    );
+                }
                 d
             }
 
             DeprecationActionResult::Redirect { status_code } => {
                 if let Some(redirect_url) = decision.redirect_url {
-                    self.metrics
-                        .record_redirect(&decision.endpoint_id, path, &redirect_url);
-
                     // Use permanent redirect for 301, regular for others
                     // Note: SDK supports 301 and 302; for 308 we use block with Location header
                     let mut d = if status_code == 301 {
@@ -294,61 +1533,30 @@ impl Agent for ApiDeprecationAgent {
                         .with_metadata("redirect_target", serde_json::json!(redirect_url));
 
                     // Add deprecation headers to the redirect response
-                    for (name, value) in decision.headers {
-                        d = d.with_block_header(name, value);
+                    for (name, value) in decision.headers.iter() {
+                        d = d.with_block_header(name.clone(), value.clone());
                     }
 
                     d
                 } else {
                     // No replacement URL, block instead
-                    self.metrics
-                        .record_blocked(&decision.endpoint_id, path, "no_replacement");
-
-                    Decision::block(410)
-                        .with_body(gone_response_body(&DeprecatedEndpoint {
-                            id: decision.endpoint_id.clone(),
-                            path: path.to_string(),
-                            methods: vec![],
-                            status: DeprecationStatus::Removed,
-                            deprecated_at: None,
-                            sunset_at: None,
-                            replacement: None,
-                            documentation_url: decision.documentation_url,
-                            message: Some(decision.message),
-                            action: DeprecationAction::Block { status_code: 410 },
-                            headers: HashMap::new(),
-                            track_usage: false,
-                            path_matcher: None,
-                        }))
-                        .with_block_header("Content-Type", "application/json")
+                    let (body, content_type) = decision.block_body();
+                    let (body, content_length) = response_body(method, body);
+                    let d = with_content_length(Decision::block(410), content_length)
+                        .with_body(body)
+                        .with_block_header("Content-Type", content_type)
                         .with_tag("deprecated")
-                        .with_tag("blocked")
+                        .with_tag("blocked");
+                    with_close_connection(d, &decision.state.config.settings)
                 }
             }
 
             DeprecationActionResult::Block { status_code } => {
-                self.metrics
-                    .record_blocked(&decision.endpoint_id, path, "removed");
-
-                let body = gone_response_body(&DeprecatedEndpoint {
-                    id: decision.endpoint_id.clone(),
-                    path: path.to_string(),
-                    methods: vec![],
-                    status: DeprecationStatus::Removed,
-                    deprecated_at: None,
-                    sunset_at: None,
-                    replacement: None,
-                    documentation_url: decision.documentation_url,
-                    message: Some(decision.message),
-                    action: DeprecationAction::Block { status_code },
-                    headers: HashMap::new(),
-                    track_usage: false,
-                    path_matcher: None,
-                });
-
-                let mut d = Decision::block(status_code)
+                let (body, content_type) = decision.block_body();
+                let (body, content_length) = response_body(method, body);
+                let mut d = with_content_length(Decision::block(status_code), content_length)
                     .with_body(body)
-                    .with_block_header("Content-Type", "application/json")
+                    .with_block_header("Content-Type", content_type)
                     .with_tag("deprecated")
                     .with_tag("blocked")
                     .with_metadata(
@@ -357,235 +1565,3568 @@ impl Agent for ApiDeprecationAgent {
                     );
 
                 // Add deprecation headers
-                for (name, value) in decision.headers {
-                    d = d.with_block_header(name, value);
+                for (name, value) in decision.headers.iter() {
+                    d = d.with_block_header(name.clone(), value.clone());
                 }
 
-                d
+                with_close_connection(d, &decision.state.config.settings)
             }
 
             DeprecationActionResult::Custom {
                 status_code,
                 body,
                 content_type,
-            } => Decision::block(status_code)
-                .with_body(body)
-                .with_block_header("Content-Type", content_type)
-                .with_tag("deprecated")
-                .with_tag("custom_response")
-                .with_metadata(
-                    "deprecated_endpoint",
-                    serde_json::json!(decision.endpoint_id),
-                ),
-        }
-    }
-
-    async fn on_response(&self, _request: &Request, _response: &Response) -> Decision {
-        // Response phase - nothing to do for deprecation
-        // Headers are already added in on_request for allowed requests
-        Decision::allow()
-    }
-}
-
-/// Protocol v2 implementation for API Deprecation Agent.
-///
-/// Provides capability negotiation, health reporting, metrics export,
-/// and lifecycle management.
-#[async_trait]
-impl AgentHandlerV2 for ApiDeprecationAgent {
-    fn capabilities(&self) -> AgentCapabilities {
-        AgentCapabilities::new(
-            "api-deprecation",
-            "API Deprecation Agent",
-            env!("CARGO_PKG_VERSION"),
+            } => {
+                let (body, content_length) = response_body(method, body.as_ref());
+                let d = with_content_length(Decision::block(status_code), content_length)
+                    .with_body(body)
+                    .with_block_header("Content-Type", content_type.as_ref())
+                    .with_tag("deprecated")
+                    .with_tag("custom_response")
+                    .with_metadata(
+                        "deprecated_endpoint",
+                        serde_json::json!(decision.endpoint_id),
+                    );
+                with_close_connection(d, &decision.state.config.settings)
+            }
+        };
+        with_deadline(
+            d.with_metadata(SEVERITY_METADATA, serde_json::json!(severity)),
+            migration_deadline,
         )
-        .with_event(EventType::RequestHeaders)
-        .with_event(EventType::ResponseHeaders)
-        .with_features(AgentFeatures {
-            streaming_body: false,
-            websocket: false,
-            guardrails: false,
-            config_push: true,
-            health_reporting: true,
-            metrics_export: true,
-            concurrent_requests: 100,
-            cancellation: false,
-            flow_control: false,
-        })
     }
 
-    fn health_status(&self) -> HealthStatus {
-        if self.draining.load(Ordering::Relaxed) {
-            HealthStatus::degraded("api-deprecation", vec!["new_requests".to_string()], 1.0)
-        } else {
-            HealthStatus::healthy("api-deprecation")
-        }
-    }
+    /// Deprecated request headers a request sends, from
+    /// `settings.deprecated_request_headers` and the matching rule's own list
+    /// (which wins for the same name). Uses are counted per header and action.
+    ///
+    /// `block` entries of a rule that isn't `confirmed` only warn.
+    fn deprecated_headers_used(
+        &self,
+        path: &str,
+        method: &str,
+        has_header: impl Fn(&str) -> bool,
+    ) -> Vec<HeaderUse> {
+        let state = self.state.load();
+        let settings = &state.config.settings;
 
-    fn metrics_report(&self) -> Option<MetricsReport> {
-        let mut report = MetricsReport::new("api-deprecation", 10000);
+        // CORS preflight carries no application headers
+        if !settings.block_options && method.eq_ignore_ascii_case("OPTIONS") {
+            return Vec::new();
+        }
 
-        // Add endpoint count gauge
-        report.gauges.push(GaugeMetric::new(
-            "api_deprecation_endpoints_total",
-            self.config.endpoints.len() as f64,
-        ));
+        let endpoint = state
+            .find(path, method, self.clock.now())
+            .map(|(endpoint, _)| endpoint);
+        let endpoint_headers = endpoint
+            .map(|endpoint| endpoint.deprecated_request_headers.as_slice())
+            .unwrap_or_default();
+        let global_headers = settings.deprecated_request_headers.iter().filter(|global| {
+            !endpoint_headers
+                .iter()
+                .any(|header| header.name.eq_ignore_ascii_case(&global.name))
+        });
 
-        // Add counters for each endpoint's days until sunset
-        for endpoint in &self.config.endpoints {
-            if let Some(sunset) = &endpoint.sunset_at {
-                let days = (*sunset - Utc::now()).num_days();
-                let mut metric = GaugeMetric::new("api_deprecation_days_until_sunset", days as f64);
-                metric
-                    .labels
-                    .insert("endpoint_id".to_string(), endpoint.id.clone());
-                metric
-                    .labels
-                    .insert("path".to_string(), endpoint.path.clone());
-                report.gauges.push(metric);
+        // With enforcement off blocks only warn, and are counted as warnings
+        let enforced = settings.enforcement_enabled && self.enforcement.load(Ordering::Relaxed);
+        let mut uses = Vec::new();
+        let rule_confirmed = endpoint.is_some_and(|endpoint| endpoint.is_confirmed(settings));
+        for (header, confirmed) in endpoint_headers
+            .iter()
+            .map(|header| (header, header.confirmed.unwrap_or(rule_confirmed)))
+            .chain(global_headers.map(|header| {
+                (
+                    header,
+                    header.confirmed.unwrap_or(!settings.require_confirmation),
+                )
+            }))
+        {
+            if !has_header(&header.name) {
+                continue;
             }
-        }
 
-        // Add request counters from our Prometheus metrics (if we have any recorded)
-        // Note: In a real implementation, we'd aggregate from self.metrics
-        // For now, we just report the endpoint configuration
+            let would_block = header.action == ParamAction::Block && !confirmed;
+            if would_block
+                && state.first_shadowed(
+                    endpoint.map_or(header.name.as_str(), |endpoint| endpoint.id.as_str()),
+                )
+            {
+                warn!(
+                    endpoint_id = %endpoint.map(|endpoint| endpoint.id.as_str()).unwrap_or_default(),
+                    header = %header.name,
+                    "Unconfirmed rule would block request sending deprecated header; allowing (set confirmed: true to enforce; logged once per rule)"
+                );
+            }
+            let action = if would_block || (header.action == ParamAction::Block && !enforced) {
+                ParamAction::Warn
+            } else {
+                header.action
+            };
+            if state.config.metrics.enabled {
+                self.metrics
+                    .record_deprecated_header(&header.name, action.as_str());
+            }
 
-        if report.is_empty() {
-            None
-        } else {
-            Some(report)
+            uses.push(HeaderUse {
+                header: header.clone(),
+                action,
+                would_block,
+            });
         }
+        uses
     }
 
-    async fn on_shutdown(&self, reason: ShutdownReason, grace_period_ms: u64) {
-        info!(
-            ?reason,
-            grace_period_ms, "API deprecation agent shutting down"
+    /// Name the deprecated headers a request sent in a response header and
+    /// strip those with `action: strip` before the request is forwarded.
+    fn apply_header_uses(&self, decision: Decision, uses: &[HeaderUse]) -> Decision {
+        if uses.is_empty() {
+            return decision;
+        }
+
+        let names = uses
+            .iter()
+            .map(|used| used.header.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut d = decision.add_response_header(
+            self.state
+                .load()
+                .config
+                .settings
+                .deprecated_header_used_header
+                .clone(),
+            names,
         );
-        self.draining.store(true, Ordering::Relaxed);
+        for used in uses {
+            if used.action == ParamAction::Strip {
+                d = d.remove_request_header(used.header.name.clone());
+            }
+        }
+        if uses.iter().any(|used| used.would_block) {
+            d = d.with_tag("would_block");
+        }
+        d
     }
 
-    async fn on_drain(&self, duration_ms: u64, reason: DrainReason) {
-        info!(?reason, duration_ms, "API deprecation agent draining");
-        self.draining.store(true, Ordering::Relaxed);
+    /// Remove `settings.upstream_header` from a request the upstream will
+    /// see, so a value sent by the client never passes for the agent's.
+    fn without_upstream_header(&self, decision: Decision) -> Decision {
+        match &self.state.load().config.settings.upstream_header {
+            Some(name) => decision.remove_request_header(name.clone()),
+            None => decision,
+        }
     }
 
-    async fn on_stream_closed(&self) {
-        debug!("API deprecation agent stream closed");
+    /// Apply deprecation headers to an allow decision.
+    fn apply_headers(&self, decision: Decision, headers: &HashMap<String, String>) -> Decision {
+        let mut d = decision;
+        for (name, value) in headers {
+            d = d.add_response_header(name.clone(), value.clone());
+        }
+        d
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Names of the parameters in a query string, in order.
+fn query_param_names(query_string: &str) -> impl Iterator<Item = &str> {
+    query_string
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').map_or(pair, |(name, _)| name))
+}
 
-    fn test_config() -> ApiDeprecationConfig {
-        let yaml = r#"
-endpoints:
-  - id: legacy-users
-    path: /api/v1/users
-    methods: [GET, POST]
-    status: deprecated
-    sunset_at: "2030-06-01T00:00:00Z"
-    replacement:
-      path: /api/v2/users
-    documentation_url: https://docs.example.com/migration
-    action:
-      type: warn
+/// Remove the parameters selected by `strip` from a query string.
+fn strip_query_params(query_string: &str, strip: impl Fn(&str) -> bool) -> String {
+    query_string
+        .split('&')
+        .filter(|pair| {
+            let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+            !pair.is_empty() && !strip(name)
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
 
-  - id: removed-posts
-    path: /api/v1/posts
-    status: removed
-    action:
-      type: block
-      status_code: 410
+/// Deprecation headers of a request to an endpoint at `now`: the scheduled
+/// set until `deprecated_at` and the regular set otherwise, with `{{hits}}` filled in from `hits`, the
+/// Sunset headers held back outside the sunset header window and computed
+/// headers rendered.
+fn endpoint_headers(
+    endpoint: &DeprecatedEndpoint,
+    processed: &ProcessedEndpoint,
+    settings: &GlobalSettings,
+    hits: Option<u64>,
+    now: DateTime<Utc>,
+) -> Arc<HashMap<String, String>> {
+    let pending = endpoint.is_pending_deprecation(now);
+    let mut headers = match &processed.scheduled_headers {
+        Some(scheduled) if pending => Arc::clone(scheduled),
+        _ => Arc::clone(&processed.headers),
+    };
 
-  - id: redirect-orders
-    path: /api/v1/orders
-    status: deprecated
-    replacement:
-      path: /api/v2/orders
-    action:
-      type: redirect
-      status_code: 308
-"#;
-        serde_yaml::from_str(yaml).unwrap()
+    if processed.hits_template {
+        headers = render_hits(headers, endpoint, settings, pending, hits);
     }
 
-    #[test]
-    fn test_agent_creation() {
-        let config = test_config();
-        let agent = ApiDeprecationAgent::new(config);
-        assert_eq!(agent.config.endpoints.len(), 3);
+    let sunset_near = settings.within_sunset_header_window(endpoint.sunset_at, now);
+    if !sunset_near && headers.contains_key(&settings.sunset_header) {
+        let mut early = HashMap::clone(&headers);
+        early.remove(&settings.sunset_header);
+        early.remove(&settings.iso_sunset_header);
+        headers = Arc::new(early);
     }
 
-    #[test]
-    fn test_process_deprecated_endpoint() {
-        let config = test_config();
-        let agent = ApiDeprecationAgent::new(config);
+    if !processed.computed_headers.is_empty() {
+        let mut computed = HashMap::clone(&headers);
+        for (name, template) in &processed.computed_headers {
+            computed.insert(
+                name.clone(),
+                render_computed_header(template, endpoint, processed.status_label, now),
+            );
+        }
+        headers = Arc::new(computed);
+    }
+    headers
+}
 
-        let decision = agent.process_request("/api/v1/users", "GET", None);
-        assert!(decision.is_some());
+/// Keep the headers a request gets within `settings.max_added_header_bytes`,
+/// counting what is only known per request: the `{{hits}}` count, computed
+/// headers, parameter notices and, on warnings, the countdown and warning
+/// level headers sent alongside. Each rule's first trimmed request per
+/// configuration is logged.
+fn fit_header_budget(decision: &mut DeprecationDecision, endpoint: &DeprecatedEndpoint) {
+    let state = Arc::clone(&decision.state);
+    let settings = &state.config.settings;
+    if settings.max_added_header_bytes == 0 {
+        return;
+    }
 
-        let d = decision.unwrap();
-        assert_eq!(d.endpoint_id, "legacy-users");
-        assert!(matches!(d.action, DeprecationActionResult::Warn));
+    let mut reserved = 0;
+    if matches!(decision.action, DeprecationActionResult::Warn) {
+        if let Some(days) = decision.days_until_sunset {
+            reserved += header_bytes(&HashMap::from([(
+                settings.sunset_reminder_header.clone(),
+                days.to_string(),
+            )]));
+        }
+        if let Some(level) = decision.warning_level {
+            reserved += header_bytes(&HashMap::from([(
+                settings.warning_level_header.clone(),
+                level.to_string(),
+            )]));
+        }
+    }
+    if header_bytes(&decision.headers) + reserved <= settings.max_added_header_bytes {
+        return;
     }
 
-    #[test]
-    fn test_process_removed_endpoint() {
-        let config = test_config();
+    let mut headers = HashMap::clone(&decision.headers);
+    let dropped = trim_headers(&mut headers, endpoint, settings, reserved);
+    decision.headers = Arc::new(headers);
+    if !dropped.is_empty() && state.first_trimmed(&endpoint.id) {
+        warn!(
+            endpoint_id = %endpoint.id,
+            dropped = ?dropped,
+            limit = settings.max_added_header_bytes,
+            "Request headers exceed max_added_header_bytes; dropped lowest-priority headers (logged once per rule)"
+        );
+    }
+}
+
+/// Fill in the `{{hits}}` placeholder of the notice header, or fall back to
+/// the generated notice when requests aren't counted.
+fn render_hits(
+    headers: Arc<HashMap<String, String>>,
+    endpoint: &DeprecatedEndpoint,
+    settings: &GlobalSettings,
+    pending: bool,
+    hits: Option<u64>,
+) -> Arc<HashMap<String, String>> {
+    let Some(notice) = headers.get(&settings.notice_header) else {
+        return headers;
+    };
+    if !notice.contains(HITS_PLACEHOLDER) {
+        return headers;
+    }
+
+    let notice = match hits {
+        Some(hits) => notice.replace(HITS_PLACEHOLDER, &hits.to_string()),
+        None if pending => endpoint.default_scheduled_message(),
+        None => endpoint.default_deprecation_message(),
+    };
+    let mut headers = HashMap::clone(&headers);
+    headers.insert(settings.notice_header.clone(), notice);
+    Arc::new(headers)
+}
+
+/// Reset the days_until_sunset gauges to match a configuration.
+fn refresh_sunset_gauges(
+    config: &ApiDeprecationConfig,
+    metrics: &dyn MetricsSink,
+    now: DateTime<Utc>,
+) {
+    metrics.clear_days_until_sunset();
+    if !config.metrics.enabled {
+        return;
+    }
+
+    for endpoint in &config.endpoints {
+        if let Some(days) = endpoint.days_until_sunset(now) {
+            metrics.set_days_until_sunset(endpoint.metrics_id(), endpoint.path.primary(), days);
+        }
+    }
+}
+
+/// Responses an endpoint needs within one interval before its error ratio is
+/// warned about, so a single failure doesn't read as a 100% error rate.
+const ERROR_RATIO_MIN_RESPONSES: u64 = 10;
+
+/// Compute each endpoint's error ratio since the previous refresh.
+fn refresh_error_ratios(
+    config: &ApiDeprecationConfig,
+    usage: &UsageTracker,
+    metrics: &dyn MetricsSink,
+) {
+    if !config.metrics.enabled {
+        return;
+    }
+
+    let mut refreshed = HashSet::new();
+    for endpoint in &config.endpoints {
+        let metrics_id = endpoint.metrics_id();
+        if !refreshed.insert(metrics_id) {
+            continue;
+        }
+        let Some(counters) = usage.get(metrics_id) else {
+            continue;
+        };
+
+        let window = counters.roll_error_window();
+        metrics.set_error_ratio(metrics_id, window.ratio);
+
+        if let Some(threshold) = config.metrics.error_ratio_warn_threshold {
+            if window.responses >= ERROR_RATIO_MIN_RESPONSES && window.ratio > threshold {
+                warn!(
+                    endpoint_id = %metrics_id,
+                    error_ratio = window.ratio,
+                    responses = window.responses,
+                    threshold,
+                    "Upstream error ratio of deprecated endpoint above threshold"
+                );
+            }
+        }
+    }
+}
+
+/// Requests an endpoint needs within one interval before its rate counts as a
+/// spike, so a handful of requests to a quiet endpoint don't.
+const SPIKE_MIN_REQUESTS: u64 = 10;
+
+/// Export each endpoint's distinct consumers today and yesterday, starting a
+/// new day once `now` has passed midnight UTC.
+fn refresh_unique_consumers(
+    config: &ApiDeprecationConfig,
+    usage: &UsageTracker,
+    metrics: &dyn MetricsSink,
+    now: DateTime<Utc>,
+) {
+    if !config.metrics.enabled {
+        return;
+    }
+
+    let mut refreshed = HashSet::new();
+    for endpoint in &config.endpoints {
+        let metrics_id = endpoint.metrics_id();
+        if !refreshed.insert(metrics_id) {
+            continue;
+        }
+        if let Some(counters) = usage.get(metrics_id) {
+            metrics.set_unique_consumers(metrics_id, &counters.unique_consumers(now));
+        }
+    }
+}
+
+/// Check each endpoint for usage spikes above its
+/// [`spike_factor`](DeprecatedEndpoint::spike_factor), warning once per
+/// spike: of the request rate since the previous refresh against its moving
+/// average, and of the last 24 hours of requests against the average day of
+/// the week before. The daily average is exported for dashboards.
+fn refresh_spikes(
+    config: &ApiDeprecationConfig,
+    usage: &UsageTracker,
+    metrics: &dyn MetricsSink,
+    interval_secs: u64,
+    now: DateTime<Utc>,
+) {
+    let min_daily_average = config.metrics.usage_spike_min_daily_average;
+    let mut refreshed = HashSet::new();
+    for endpoint in &config.endpoints {
+        let metrics_id = endpoint.metrics_id();
+        if !refreshed.insert(metrics_id) {
+            continue;
+        }
+        let Some(counters) = usage.get(metrics_id) else {
+            continue;
+        };
+        let threshold = endpoint.spike_factor(&config.metrics);
+
+        let rate = counters.roll_rate_window(interval_secs, threshold, SPIKE_MIN_REQUESTS);
+        if rate.spike_started {
+            warn!(
+                endpoint_id = %metrics_id,
+                rate = rate.rate,
+                average = rate.average,
+                requests = rate.requests,
+                threshold,
+                "Deprecated endpoint traffic spiked above its moving average"
+            );
+            if config.metrics.enabled {
+                metrics.record_traffic_spike(metrics_id);
+            }
+        }
+
+        if !config.metrics.enabled {
+            continue;
+        }
+        let trend = counters.usage_trend(now, threshold, min_daily_average);
+        if let Some(average) = trend.daily_average {
+            metrics.set_usage_daily_average(metrics_id, average);
+        }
+        if trend.spike_started {
+            warn!(
+                endpoint_id = %metrics_id,
+                last_day = trend.last_day,
+                daily_average = trend.daily_average,
+                threshold,
+                "Deprecated endpoint usage over the last day spiked above its 7-day average"
+            );
+            metrics.record_usage_spike(metrics_id);
+        }
+    }
+}
+
+/// Result of processing a deprecated endpoint.
+struct DeprecationDecision {
+    endpoint_id: String,
+    /// Identifier used to label metrics
+    metrics_id: Arc<str>,
+    action: DeprecationActionResult,
+    headers: Arc<HashMap<String, String>>,
+    redirect_url: Option<String>,
+    gone_body: Arc<str>,
+    /// Body configured in the endpoint's `bodies` for the status of a block,
+    /// replacing `gone_body`
+    custom_body: Option<CustomBody>,
+    /// Days left until sunset, when the sunset reminder is enabled
+    days_until_sunset: Option<i64>,
+    /// Escalating warning level, when enabled
+    warning_level: Option<u8>,
+    /// Why the request is allowed, when the action is a warning
+    allow_reason: Option<AllowReason>,
+    /// Whether an unconfirmed rule would have blocked the request
+    would_block: bool,
+    severity: Severity,
+    /// Operator notes, logged with accesses
+    notes: Option<String>,
+    warn_once: Option<WarnOnce>,
+    /// The blocking or redirecting action enforcement being off turned
+    /// into a warning
+    intended: Option<DeprecationActionResult>,
+    sunset_at: Option<DateTime<Utc>>,
+    /// Sunset plus `settings.migration_grace_days`, if the endpoint has a sunset
+    migration_deadline: Option<DateTime<Utc>>,
+    /// Configuration the decision was made against
+    state: Arc<ProcessedConfig>,
+}
+
+/// Why a request matching a rule was allowed through, recorded so bypasses
+/// of enforcement can be audited in metrics, logs and decision metadata.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllowReason {
+    /// The rule only warns
+    WarnAction,
+    /// An unconfirmed rule would have blocked or redirected (shadow mode)
+    Shadowed,
+    /// Enforcement is switched off (`settings.enforcement_enabled` or the
+    /// runtime switch)
+    EnforcementDisabled,
+    /// CORS preflight, answered with headers only
+    PreflightSkip,
+    /// The body couldn't be inspected for deprecated fields and
+    /// `body_inspection_fail_open` let it through
+    BodyInspectionFailOpen,
+}
+
+impl AllowReason {
+    /// Name of the reason, as used in metric labels, logs and metadata.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::WarnAction => "warn_action",
+            Self::Shadowed => "shadowed",
+            Self::EnforcementDisabled => "enforcement_disabled",
+            Self::PreflightSkip => "preflight_skip",
+            Self::BodyInspectionFailOpen => "body_inspection_fail_open",
+        }
+    }
+}
+
+/// Why a decision with `action` allows the request; `None` for blocks and
+/// redirects. Enforcement being off is applied later, in `unenforced`.
+fn allow_reason(
+    action: &DeprecationActionResult,
+    preflight: bool,
+    would_block: bool,
+) -> Option<AllowReason> {
+    if !matches!(action, DeprecationActionResult::Warn) {
+        None
+    } else if preflight {
+        Some(AllowReason::PreflightSkip)
+    } else if would_block {
+        Some(AllowReason::Shadowed)
+    } else {
+        Some(AllowReason::WarnAction)
+    }
+}
+
+impl DeprecationDecision {
+    /// Body and content type of a blocked response: the endpoint's body for
+    /// its status if one is configured, else the generated JSON body.
+    fn block_body(&self) -> (&str, &str) {
+        match &self.custom_body {
+            Some(custom) => (&custom.body, &custom.content_type),
+            None => (&self.gone_body, "application/json"),
+        }
+    }
+}
+
+/// How the notice headers of a request reach the client.
+#[derive(Debug, PartialEq, Eq)]
+enum NoticeDelivery {
+    /// Sent, as on every request
+    Always,
+    /// Sent along with this `Set-Cookie` marking the session as told
+    Mark(String),
+    /// Left out: the session carries the marker already
+    Suppressed,
+}
+
+/// Notice delivery for a decision, given the request's `Cookie` header.
+/// Only warned requests to `warn_once` endpoints are ever suppressed; blocks
+/// and redirects always carry their headers, also while not enforced.
+fn notice_delivery(
+    decision: &DeprecationDecision,
+    cookies: Option<&str>,
+    now: DateTime<Utc>,
+) -> NoticeDelivery {
+    match &decision.warn_once {
+        Some(warn_once)
+            if matches!(decision.action, DeprecationActionResult::Warn)
+                && decision.intended.is_none() =>
+        {
+            if warn_once.is_marked(cookies, now) {
+                NoticeDelivery::Suppressed
+            } else {
+                NoticeDelivery::Mark(warn_once.set_cookie(now))
+            }
+        }
+        _ => NoticeDelivery::Always,
+    }
+}
+
+/// A deprecated request header sent with a request.
+struct HeaderUse {
+    header: DeprecatedHeader,
+    /// Action taken: the header's own, except that an unconfirmed `block` warns
+    action: ParamAction,
+    would_block: bool,
+}
+
+/// Decision metadata key carrying the matched endpoint's `severity`.
+pub const SEVERITY_METADATA: &str = "deprecation_severity";
+
+/// Decision metadata key carrying the [`AllowReason`] of an allowed request.
+pub const ALLOW_REASON_METADATA: &str = "allow_reason";
+
+/// Decision metadata key carrying the RFC 3339 time clients must have
+/// migrated by: the endpoint's sunset plus `settings.migration_grace_days`.
+pub const MIGRATION_DEADLINE_METADATA: &str = "migration_deadline";
+
+/// Add the migration deadline to a decision's metadata, if there is one.
+fn with_deadline(decision: Decision, deadline: Option<DateTime<Utc>>) -> Decision {
+    match deadline {
+        Some(deadline) => decision.with_metadata(
+            MIGRATION_DEADLINE_METADATA,
+            serde_json::json!(deadline.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        ),
+        None => decision,
+    }
+}
+
+/// Decision metadata key asking the proxy to close the client connection
+/// after a blocked response, with `settings.close_connection_on_block` set.
+pub const CLOSE_CONNECTION_METADATA: &str = "close_connection";
+
+/// Flag a blocked response for [`CLOSE_CONNECTION_METADATA`]. The proxy
+/// closes the connection the way the client's protocol allows (e.g. a GOAWAY
+/// on HTTP/2), so no hop-by-hop header is sent from here.
+fn with_close_connection(decision: Decision, settings: &GlobalSettings) -> Decision {
+    if settings.close_connection_on_block {
+        decision
+            .with_tag("close_connection")
+            .with_metadata(CLOSE_CONNECTION_METADATA, serde_json::json!(true))
+    } else {
+        decision
+    }
+}
+
+/// Body of a blocking response to `method`, with the `Content-Length` to
+/// send: HEAD responses leave the body out but announce the length the GET
+/// response would have had.
+fn response_body<'a>(method: &str, body: &'a str) -> (&'a str, Option<String>) {
+    if method.eq_ignore_ascii_case("HEAD") {
+        ("", Some(body.len().to_string()))
+    } else {
+        (body, None)
+    }
+}
+
+/// Add the `Content-Length` from [`response_body`], if there is one.
+fn with_content_length(decision: Decision, content_length: Option<String>) -> Decision {
+    match content_length {
+        Some(length) => decision.with_block_header("Content-Length", length),
+        None => decision,
+    }
+}
+
+/// Request header naming the matched rule for the upstream, with
+/// `settings.upstream_header` set: `<endpoint-id>[; sunset=<RFC 3339 date>]`.
+///
+/// Only allowed (warn) requests reach the upstream, so blocked and
+/// redirected ones never get it.
+fn upstream_header(decision: &DeprecationDecision) -> Option<(String, String)> {
+    if !matches!(decision.action, DeprecationActionResult::Warn) {
+        return None;
+    }
+    let name = decision.state.config.settings.upstream_header.clone()?;
+    let value = match decision.sunset_at {
+        Some(sunset) => format!(
+            "{}; sunset={}",
+            decision.endpoint_id,
+            sunset.to_rfc3339_opts(SecondsFormat::Secs, true)
+        ),
+        None => decision.endpoint_id.clone(),
+    };
+    Some((name, value))
+}
+
+/// Log an access to a deprecated endpoint at its severity's level.
+fn log_access(decision: &DeprecationDecision, client: Option<&str>, path: &str, method: &str) {
+    let severity = decision.severity;
+    let notes = decision.notes.as_deref();
+    let allow_reason = decision.allow_reason.map(AllowReason::as_str);
+    macro_rules! log {
+        ($level:ident) => {
+            $level!(
+                endpoint_id = %decision.endpoint_id,
+                severity = %severity,
+                notes = notes,
+                client = client,
+                path = %path,
+                method = %method,
+                action = ?decision.action,
+                allow_reason = allow_reason,
+                "Deprecated endpoint accessed"
+            )
+        };
+    }
+    match severity.access_log_level() {
+        tracing::Level::DEBUG => log!(debug),
+        tracing::Level::INFO => log!(info),
+        _ => log!(warn),
+    }
+}
+
+// The agent needs to be Send + Sync for the SDK
+unsafe impl Send for ApiDeprecationAgent {}
+unsafe impl Sync for ApiDeprecationAgent {}
+
+/// Shared handle to a running agent, for control paths that outlive the
+/// agent value handed to the runner: the admin `/reload`, `/health` and
+/// `/export.csv` routes and SIGHUP reloads.
+#[derive(Clone)]
+pub struct AgentHandle {
+    state: Arc<ArcSwap<ProcessedConfig>>,
+    metrics: Arc<dyn MetricsSink>,
+    clock: Arc<dyn Clock>,
+    draining: Arc<AtomicBool>,
+    reload_error: Arc<ArcSwapOption<String>>,
+    sunset_history: Arc<Mutex<SunsetHistory>>,
+    usage: Arc<UsageTracker>,
+    cluster: Arc<ClusterUsage>,
+    enforcement: Arc<AtomicBool>,
+    hits: Arc<ArcSwap<DailyHits>>,
+    sessions: Arc<ArcSwap<Sessions>>,
+}
+
+impl AgentHandle {
+    /// Replace the active configuration; see [`ApiDeprecationAgent::update_config`].
+    pub fn update_config(&self, config: ApiDeprecationConfig) -> Result<(), ConfigError> {
+        if let Err(err) = config.validate() {
+            self.reload_failed(&err);
+            return Err(err);
+        }
+
+        let processed = Arc::new(ProcessedConfig::new(config, self.clock.now()));
+        let previous = self.state.swap(Arc::clone(&processed));
+        self.reload_error.store(None);
+        self.resize_client_state(
+            &previous.config.settings.client_state,
+            &processed.config.settings.client_state,
+        );
+        self.track_sunset_moves(&previous.config, &processed.config);
+        refresh_sunset_gauges(&processed.config, self.metrics.as_ref(), self.clock.now());
+        self.report_unconfirmed(&processed.config);
+        self.report_severities(&processed.config);
+        self.report_config_info(&processed);
+        self.report_trimmed_headers(&processed);
+
+        info!(
+            endpoints = processed.config.endpoints.len(),
+            config_hash = %processed.hash,
+            "API deprecation configuration updated"
+        );
+        notify::status(&format!(
+            "Configuration updated: {} endpoints",
+            processed.config.endpoints.len()
+        ));
+        Ok(())
+    }
+
+    /// Re-read a configuration file and swap it in, returning the number of
+    /// endpoints loaded. A file that can't be read or parsed is rejected like
+    /// an invalid configuration.
+    pub fn reload_from_file(&self, path: &Path) -> Result<usize, ConfigError> {
+        let config =
+            ApiDeprecationConfig::from_file(path).inspect_err(|err| self.reload_failed(err))?;
+        let endpoints = config.endpoints.len();
+        self.update_config(config)?;
+        Ok(endpoints)
+    }
+
+    /// Switch enforcement on or off; see [`ApiDeprecationAgent::set_enforcement`].
+    pub fn set_enforcement(&self, enabled: bool) {
+        let previous = self.enforcement.swap(enabled, Ordering::Relaxed);
+        if previous != enabled {
+            warn!(enabled, "Enforcement switched at runtime");
+            notify::status(if enabled {
+                "Enforcement switched on"
+            } else {
+                "Enforcement switched off: blocks and redirects only warn"
+            });
+        }
+    }
+
+    /// Whether blocks and redirects are enforced, and the runtime switch
+    /// and setting that decide it.
+    pub fn enforcement(&self) -> EnforcementStatus {
+        let switch = self.enforcement.load(Ordering::Relaxed);
+        let setting = self.state.load().config.settings.enforcement_enabled;
+        EnforcementStatus {
+            enabled: switch && setting,
+            switch,
+            setting,
+        }
+    }
+
+    /// Current usage of every configured endpoint; see
+    /// [`ApiDeprecationAgent::usage_snapshot`].
+    pub fn usage_snapshot(&self) -> Vec<EndpointUsage> {
+        let state = self.state.load();
+        let now = self.clock.now();
+
+        state
+            .config
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let counters = self.usage.get(endpoint.metrics_id());
+                let count = |read: fn(&crate::usage::UsageCounters) -> u64| {
+                    counters.as_deref().map(read).unwrap_or(0)
+                };
+                let consumers = counters
+                    .as_deref()
+                    .map(|c| c.unique_consumers(now))
+                    .unwrap_or_default();
+
+                EndpointUsage {
+                    id: endpoint.id.clone(),
+                    metrics_id: endpoint.metrics_id().to_string(),
+                    pattern: endpoint.path.primary().to_string(),
+                    aliases: endpoint.path.aliases().to_vec(),
+                    status: endpoint.status.clone(),
+                    sunset_at: endpoint.sunset_at,
+                    active: endpoint.is_active_at(now),
+                    severity: endpoint.severity,
+                    notes: endpoint.notes.clone(),
+                    owner: endpoint.owner.clone(),
+                    contact: endpoint.contact.clone(),
+                    days_until_sunset: endpoint.days_until_sunset(now),
+                    requests: count(|c| c.requests.load(Ordering::Relaxed)),
+                    redirects: count(|c| c.redirects.load(Ordering::Relaxed)),
+                    blocked: count(|c| c.blocked.load(Ordering::Relaxed)),
+                    errors: count(|c| c.errors.load(Ordering::Relaxed)),
+                    error_ratio: counters.as_deref().and_then(|c| c.error_ratio()),
+                    last_access: counters.as_deref().and_then(|c| c.last_access()),
+                    unique_consumers_today: consumers.today,
+                    unique_consumers_yesterday: consumers.yesterday,
+                    cluster: self.cluster.get(endpoint.metrics_id()),
+                }
+            })
+            .collect()
+    }
+
+    /// Requests per `metrics_id` over the last `hours` hours (including the
+    /// current one), as far as the hourly usage history reaches.
+    pub fn recent_requests(&self, hours: usize) -> HashMap<String, u64> {
+        let state = self.state.load();
+        let now = self.clock.now();
+        state
+            .config
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let requests = self
+                    .usage
+                    .get(endpoint.metrics_id())
+                    .map_or(0, |counters| counters.recent_requests(hours, now));
+                (endpoint.metrics_id().to_string(), requests)
+            })
+            .collect()
+    }
+
+    /// Health of the agent and its active configuration.
+    pub fn health(&self) -> AgentHealth {
+        let state = self.state.load();
+        let degraded = degraded_capability(&self.draining, &self.reload_error, &self.cluster);
+        AgentHealth {
+            status: if degraded.is_some() {
+                "degraded"
+            } else {
+                "healthy"
+            }
+            .to_string(),
+            degraded: degraded.map(str::to_string),
+            endpoints: state.config.endpoints.len(),
+            config_loaded_at: state.loaded_at,
+            config_hash: state.hash.clone(),
+            sunset_changes: self
+                .sunset_history
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .snapshot(),
+        }
+    }
+
+    /// Rebuild the per-client stores with a reload's `settings.client_state`
+    /// limits; counts are kept when the limits are unchanged.
+    fn resize_client_state(&self, old: &ClientStateConfig, new: &ClientStateConfig) {
+        if old == new {
+            return;
+        }
+        self.hits
+            .store(Arc::new(DailyHits::new(new, Arc::clone(&self.clock))));
+        self.sessions
+            .store(Arc::new(Sessions::new(new, Arc::clone(&self.clock))));
+        info!(
+            max_entries = new.max_entries,
+            idle_ttl_secs = new.idle_ttl_secs,
+            "Per-client state reset for new limits"
+        );
+    }
+
+    /// Log, count and record rules whose sunset date a reload moved or
+    /// withdrew; extensions and withdrawals are warned about, accelerations
+    /// only logged.
+    fn track_sunset_moves(&self, old: &ApiDeprecationConfig, new: &ApiDeprecationConfig) {
+        for (endpoint, change) in sunset_moves(old, new, self.clock.now()) {
+            match (change.kind, change.to) {
+                (SunsetMove::Extension, Some(to)) => {
+                    warn!(
+                        endpoint_id = %endpoint.id,
+                        from = %change.from,
+                        to = %to,
+                        days = (to - change.from).num_days(),
+                        "Sunset date extended"
+                    );
+                }
+                (SunsetMove::Acceleration, Some(to)) => {
+                    info!(
+                        endpoint_id = %endpoint.id,
+                        from = %change.from,
+                        to = %to,
+                        days = (change.from - to).num_days(),
+                        "Sunset date brought forward"
+                    );
+                }
+                _ => {
+                    warn!(
+                        endpoint_id = %endpoint.id,
+                        from = %change.from,
+                        "Sunset date withdrawn"
+                    );
+                }
+            }
+            if change.kind != SunsetMove::Acceleration && new.metrics.enabled {
+                self.metrics.record_sunset_extension(endpoint.metrics_id());
+            }
+            self.sunset_history
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .record(&endpoint.id, change);
+        }
+    }
+
+    /// Handle a rejected configuration; the last-good configuration stays active.
+    fn reload_failed(&self, err: &ConfigError) {
+        notify::status(&format!("Configuration reload rejected: {}", err));
+        match self.state.load().config.settings.reload_failure_mode {
+            ReloadFailureMode::FailOpen => {
+                warn!(error = %err, "Configuration reload rejected, keeping last-good configuration");
+            }
+            ReloadFailureMode::Degrade => {
+                error!(
+                    error = %err,
+                    "Configuration reload rejected, reporting degraded health until a reload succeeds"
+                );
+                self.reload_error.store(Some(Arc::new(err.to_string())));
+            }
+        }
+    }
+
+    /// Warn about rules held in shadow mode until they are confirmed.
+    fn report_unconfirmed(&self, config: &ApiDeprecationConfig) {
+        let unconfirmed: Vec<&str> = config
+            .unconfirmed_endpoints()
+            .map(|endpoint| endpoint.id.as_str())
+            .collect();
+        if !unconfirmed.is_empty() {
+            warn!(
+                endpoints = ?unconfirmed,
+                "Rules that can block traffic are not confirmed and only warn until confirmed: true is set"
+            );
+        }
+        if config.metrics.enabled {
+            self.metrics.set_unconfirmed_rules(unconfirmed.len());
+        }
+    }
+
+    /// Warn about endpoints whose headers were trimmed to fit in
+    /// `settings.max_added_header_bytes`, once per endpoint and load.
+    fn report_trimmed_headers(&self, state: &ProcessedConfig) {
+        for (endpoint, processed) in state.config.endpoints.iter().zip(&state.endpoints) {
+            if processed.trimmed_headers.is_empty() {
+                continue;
+            }
+            warn!(
+                endpoint_id = %endpoint.id,
+                dropped = ?processed.trimmed_headers,
+                limit = state.config.settings.max_added_header_bytes,
+                "Deprecation headers exceed max_added_header_bytes; dropped lowest-priority headers"
+            );
+            if state.config.metrics.enabled {
+                self.metrics.record_header_truncation(&processed.metrics_id);
+            }
+        }
+    }
+
+    /// Export the active configuration's hash and load time as `config_info`.
+    fn report_config_info(&self, state: &ProcessedConfig) {
+        if state.config.metrics.enabled {
+            self.metrics.set_config_info(&state.hash, state.loaded_at);
+        }
+    }
+
+    /// Export each endpoint's severity as an `endpoint_severity` series.
+    fn report_severities(&self, config: &ApiDeprecationConfig) {
+        self.metrics.clear_endpoint_severity();
+        if !config.metrics.enabled {
+            return;
+        }
+        for endpoint in &config.endpoints {
+            self.metrics
+                .set_endpoint_severity(endpoint.metrics_id(), endpoint.severity);
+        }
+    }
+}
+
+/// The capability reported as degraded, if any: `new_requests` while
+/// draining, `config_reload` after a rejected reload under
+/// `ReloadFailureMode::Degrade`, `shared_store` while the store is unreachable.
+fn degraded_capability(
+    draining: &AtomicBool,
+    reload_error: &ArcSwapOption<String>,
+    cluster: &ClusterUsage,
+) -> Option<&'static str> {
+    if draining.load(Ordering::Relaxed) {
+        Some("new_requests")
+    } else if reload_error.load().is_some() {
+        Some("config_reload")
+    } else if cluster.error().is_some() {
+        Some("shared_store")
+    } else {
+        None
+    }
+}
+
+#[async_trait]
+impl Agent for ApiDeprecationAgent {
+    async fn on_configure(&self, config: serde_json::Value) -> Result<(), String> {
+        let config = match ApiDeprecationConfig::from_json(&config) {
+            Ok(config) => config,
+            Err(err) => {
+                self.handle().reload_failed(&err);
+                return Err(err.to_string());
+            }
+        };
+
+        self.update_config(config).map_err(|err| err.to_string())
+    }
+
+    async fn on_request(&self, request: &Request) -> Decision {
+        self.isolate_panics(
+            || self.request_decision(request),
+            || self.without_upstream_header(Decision::allow().with_tag("agent_error")),
+        )
+    }
+
+    async fn on_request_body(&self, request: &Request) -> Decision {
+        let path = self.match_path(request);
+        let body = request.body().unwrap_or_default();
+        self.isolate_panics(
+            || self.request_body_decision(path, request.method(), body),
+            || Decision::allow().with_tag("agent_error"),
+        )
+    }
+
+    async fn on_response(&self, request: &Request, response: &Response) -> Decision {
+        // Headers are already added in on_request for allowed requests; the
+        // response phase tracks upstream errors and takes them back from
+        // error responses and 304s
+        let path = self.match_path(request);
+        self.record_response(path, request.method(), response.status_code());
+        self.response_decision(path, request.method(), response)
+    }
+}
+
+/// Protocol v2 implementation for API Deprecation Agent.
+///
+/// Provides capability negotiation, health reporting, metrics export,
+/// and lifecycle management.
+#[async_trait]
+impl AgentHandlerV2 for ApiDeprecationAgent {
+    fn capabilities(&self) -> AgentCapabilities {
+        // Bodies are only needed for rules on deprecated body fields
+        let inspects_body = self
+            .state
+            .load()
+            .config
+            .endpoints
+            .iter()
+            .any(DeprecatedEndpoint::inspects_body);
+        let capabilities = AgentCapabilities::new(
+            "api-deprecation",
+            "API Deprecation Agent",
+            env!("CARGO_PKG_VERSION"),
+        )
+        .with_event(EventType::RequestHeaders)
+        .with_event(EventType::ResponseHeaders);
+        let capabilities = if inspects_body {
+            capabilities.with_event(EventType::RequestBodyChunk)
+        } else {
+            capabilities
+        };
+        capabilities.with_features(AgentFeatures {
+            streaming_body: inspects_body,
+            websocket: false,
+            guardrails: false,
+            config_push: true,
+            health_reporting: true,
+            metrics_export: true,
+            concurrent_requests: self.state.load().config.settings.max_concurrent_requests,
+            cancellation: false,
+            flow_control: false,
+        })
+    }
+
+    fn health_status(&self) -> HealthStatus {
+        match degraded_capability(&self.draining, &self.reload_error, &self.cluster) {
+            Some(capability) => {
+                HealthStatus::degraded("api-deprecation", vec![capability.to_string()], 1.0)
+            }
+            None => HealthStatus::healthy("api-deprecation"),
+        }
+    }
+
+    fn metrics_report(&self) -> Option<MetricsReport> {
+        let state = self.state.load();
+        if !state.config.metrics.enabled {
+            return None;
+        }
+
+        let mut report = MetricsReport::new("api-deprecation", 10000);
+
+        // Add endpoint count gauge
+        report.gauges.push(GaugeMetric::new(
+            "api_deprecation_endpoints_total",
+            state.config.endpoints.len() as f64,
+        ));
+        report.gauges.push(GaugeMetric::new(
+            "api_deprecation_unconfirmed_rules",
+            state.config.unconfirmed_endpoints().count() as f64,
+        ));
+        let mut config_info = GaugeMetric::new("api_deprecation_config_info", 1.0);
+        config_info
+            .labels
+            .insert("hash".to_string(), state.hash.clone());
+        config_info.labels.insert(
+            "loaded_at".to_string(),
+            state.loaded_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+        report.gauges.push(config_info);
+        report.counters.push(CounterMetric::new(
+            "api_deprecation_internal_errors_total",
+            self.internal_errors.load(Ordering::Relaxed),
+        ));
+
+        // Add counters for each endpoint's days until sunset
+        let now = self.clock.now();
+        for endpoint in &state.config.endpoints {
+            if let Some(days) = endpoint.days_until_sunset(now) {
+                let mut metric = GaugeMetric::new("api_deprecation_days_until_sunset", days as f64);
+                metric
+                    .labels
+                    .insert("endpoint_id".to_string(), endpoint.metrics_id().to_string());
+                metric
+                    .labels
+                    .insert("path".to_string(), endpoint.path.primary().to_string());
+                report.gauges.push(metric);
+            }
+        }
+
+        // Upstream errors and the last computed error ratio, once per series
+        let mut reported = HashSet::new();
+        for endpoint in &state.config.endpoints {
+            let metrics_id = endpoint.metrics_id();
+            if !reported.insert(metrics_id) {
+                continue;
+            }
+            let Some(counters) = self.usage.get(metrics_id) else {
+                continue;
+            };
+
+            let mut errors = CounterMetric::new(
+                "api_deprecation_errors_total",
+                counters.errors.load(Ordering::Relaxed),
+            );
+            errors
+                .labels
+                .insert("endpoint_id".to_string(), metrics_id.to_string());
+            report.counters.push(errors);
+
+            if let Some(ratio) = counters.error_ratio() {
+                let mut metric = GaugeMetric::new("api_deprecation_error_ratio", ratio);
+                metric
+                    .labels
+                    .insert("endpoint_id".to_string(), metrics_id.to_string());
+                report.gauges.push(metric);
+            }
+        }
+
+        if report.is_empty() {
+            None
+        } else {
+            Some(report)
+        }
+    }
+
+    async fn on_shutdown(&self, reason: ShutdownReason, grace_period_ms: u64) {
+        info!(
+            ?reason,
+            grace_period_ms, "API deprecation agent shutting down"
+        );
+        self.draining.store(true, Ordering::Relaxed);
+        notify::notify("STOPPING=1\nSTATUS=Shutting down");
+
+        let summary = self.lifetime_summary();
+        info!(
+            config_hash = %self.state.load().hash,
+            requests = summary.requests,
+            redirects = summary.redirects,
+            blocked = summary.blocked,
+            endpoints = %serde_json::to_string(&summary.endpoints).unwrap_or_default(),
+            "API deprecation lifetime summary"
+        );
+    }
+
+    async fn on_drain(&self, duration_ms: u64, reason: DrainReason) {
+        info!(?reason, duration_ms, "API deprecation agent draining");
+        self.draining.store(true, Ordering::Relaxed);
+        notify::status("Draining");
+    }
+
+    async fn on_stream_closed(&self) {
+        debug!("API deprecation agent stream closed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::config::{DeprecationAction, PathPatterns};
+    use crate::headers::{deprecation_response_body_with, gone_response_body_with};
+    use chrono::Duration;
+
+    fn test_config() -> ApiDeprecationConfig {
+        let yaml = r#"
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    methods: [GET, POST]
+    status: deprecated
+    sunset_at: "2030-06-01T00:00:00Z"
+    replacement:
+      path: /api/v2/users
+    documentation_url: https://docs.example.com/migration
+    action:
+      type: warn
+
+  - id: removed-posts
+    path: /api/v1/posts
+    status: removed
+    confirmed: true
+    action:
+      type: block
+      status_code: 410
+
+  - id: redirect-orders
+    path: /api/v1/orders
+    status: deprecated
+    replacement:
+      path: /api/v2/orders
+    action:
+      type: redirect
+      status_code: 308
+"#;
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_agent_creation() {
+        let config = test_config();
+        let agent = ApiDeprecationAgent::new(config);
+        assert_eq!(agent.state.load().config.endpoints.len(), 3);
+    }
+
+    #[test]
+    fn test_process_deprecated_endpoint() {
+        let config = test_config();
+        let agent = ApiDeprecationAgent::new(config);
+
+        let decision = agent.process_request("/api/v1/users", "GET", None);
+        assert!(decision.is_some());
+
+        let d = decision.unwrap();
+        assert_eq!(d.endpoint_id, "legacy-users");
+        assert!(matches!(d.action, DeprecationActionResult::Warn));
+    }
+
+    #[test]
+    fn test_process_removed_endpoint() {
+        let config = test_config();
+        let agent = ApiDeprecationAgent::new(config);
+
+        let decision = agent.process_request("/api/v1/posts", "GET", None);
+        assert!(decision.is_some());
+
+        let d = decision.unwrap();
+        assert_eq!(d.endpoint_id, "removed-posts");
+        assert!(matches!(
+            d.action,
+            DeprecationActionResult::Block { status_code: 410 }
+        ));
+    }
+
+    #[test]
+    fn test_head_matches_get_rules() {
+        let mut config = test_config();
+        let agent = ApiDeprecationAgent::new(config.clone());
+
+        // legacy-users lists GET and POST only
+        let head = agent
+            .process_request("/api/v1/users", "HEAD", None)
+            .unwrap();
+        assert_eq!(head.endpoint_id, "legacy-users");
+        assert!(head.headers.contains_key("Sunset"));
+
+        // Metrics keep the method the request was made with
+        #[cfg(feature = "prometheus")]
+        assert!(agent.metrics().encode().contains("method=\"HEAD\""));
+
+        config.settings.treat_head_as_get = false;
+        agent.update_config(config).unwrap();
+        assert!(agent
+            .process_request("/api/v1/users", "HEAD", None)
+            .is_none());
+        assert!(agent
+            .process_request("/api/v1/users", "GET", None)
+            .is_some());
+    }
+
+    #[test]
+    fn test_head_response_body() {
+        let body = r#"{"error":"gone"}"#;
+        assert_eq!(response_body("GET", body), (body, None));
+        assert_eq!(
+            response_body("HEAD", body),
+            ("", Some(body.len().to_string()))
+        );
+        assert_eq!(response_body("head", ""), ("", Some("0".to_string())));
+    }
+
+    #[test]
+    fn test_process_redirect_endpoint() {
+        let config = test_config();
+        let agent = ApiDeprecationAgent::new(config);
+
+        let decision = agent.process_request("/api/v1/orders", "GET", Some("page=1"));
+        assert!(decision.is_some());
+
+        let d = decision.unwrap();
+        assert_eq!(d.endpoint_id, "redirect-orders");
+        assert!(matches!(
+            d.action,
+            DeprecationActionResult::Redirect { status_code: 308 }
+        ));
+        assert_eq!(d.redirect_url, Some("/api/v2/orders?page=1".to_string()));
+    }
+
+    #[test]
+    fn test_redirect_from_path_alias() {
+        let yaml = r#"
+endpoints:
+  - id: legacy-orders
+    path: [/api/v1/orders, /v1/orders, /legacy/orders/*]
+    replacement:
+      path: /api/v2/orders
+    action:
+      type: redirect
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+
+        for path in ["/api/v1/orders", "/v1/orders", "/legacy/orders/42"] {
+            let d = agent.process_request(path, "GET", Some("page=2")).unwrap();
+            assert_eq!(d.endpoint_id, "legacy-orders");
+            assert_eq!(d.redirect_url, Some("/api/v2/orders?page=2".to_string()));
+        }
+        assert!(agent.process_request("/v2/orders", "GET", None).is_none());
+
+        let usage = agent.usage_snapshot();
+        assert_eq!(usage[0].pattern, "/api/v1/orders");
+        assert_eq!(usage[0].aliases, vec!["/v1/orders", "/legacy/orders/*"]);
+        assert_eq!(usage[0].requests, 3, "aliases share one metric series");
+    }
+
+    #[test]
+    fn test_version_deprecation_redirect() {
+        let yaml = r#"
+version_deprecations:
+  - id: api-v1
+    prefix: /api/v1
+    successor: /api/v2
+    exclude: [/api/v1/status]
+    action:
+      type: redirect
+      status_code: 308
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+
+        let d = agent
+            .process_request("/api/v1/users/5", "GET", None)
+            .unwrap();
+        assert_eq!(d.endpoint_id, "api-v1");
+        assert!(matches!(
+            d.action,
+            DeprecationActionResult::Redirect { status_code: 308 }
+        ));
+        assert_eq!(d.redirect_url, Some("/api/v2/users/5".to_string()));
+
+        let d = agent
+            .process_request("/api/v1/users/5/orders", "GET", Some("page=3"))
+            .unwrap();
+        assert_eq!(
+            d.redirect_url,
+            Some("/api/v2/users/5/orders?page=3".to_string())
+        );
+
+        assert!(agent
+            .process_request("/api/v1/status", "GET", None)
+            .is_none());
+        assert!(agent
+            .process_request("/api/v2/users/5", "GET", None)
+            .is_none());
+    }
+
+    #[test]
+    fn test_non_deprecated_endpoint() {
+        let config = test_config();
+        let agent = ApiDeprecationAgent::new(config);
+
+        let decision = agent.process_request("/api/v2/users", "GET", None);
+        assert!(decision.is_none());
+    }
+
+    #[test]
+    fn test_method_filtering() {
+        let config = test_config();
+        let agent = ApiDeprecationAgent::new(config);
+
+        // GET should match
+        let decision = agent.process_request("/api/v1/users", "GET", None);
+        assert!(decision.is_some());
+
+        // DELETE should not match (only GET, POST configured)
+        let decision = agent.process_request("/api/v1/users", "DELETE", None);
+        assert!(decision.is_none());
+    }
+
+    #[test]
+    fn test_options_preflight_warns() {
+        let agent = ApiDeprecationAgent::new(test_config());
+
+        // A preflight to a removed endpoint gets headers, not a block
+        let d = agent
+            .process_request("/api/v1/posts", "OPTIONS", None)
+            .unwrap();
+        assert!(matches!(d.action, DeprecationActionResult::Warn));
+        assert!(d.headers.contains_key("Deprecation"));
+
+        // Other methods are still blocked
+        let d = agent.process_request("/api/v1/posts", "GET", None).unwrap();
+        assert!(matches!(
+            d.action,
+            DeprecationActionResult::Block { status_code: 410 }
+        ));
+    }
+
+    #[test]
+    fn test_options_blocked_when_configured() {
+        let mut config = test_config();
+        config.settings.block_options = true;
+        let agent = ApiDeprecationAgent::new(config);
+
+        let d = agent
+            .process_request("/api/v1/posts", "OPTIONS", None)
+            .unwrap();
+        assert!(matches!(
+            d.action,
+            DeprecationActionResult::Block { status_code: 410 }
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
+    fn test_options_preflight_not_counted_as_usage() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        let _ = agent.process_request("/api/v1/posts", "OPTIONS", None);
+
+        let output = agent.metrics().encode();
+        assert!(output.contains("status=\"preflight\""));
+        assert!(!output.contains("status=\"removed\""));
+    }
+
+    #[test]
+    fn test_metrics_disabled() {
+        let mut config = test_config();
+        config.metrics.enabled = false;
+        let agent = ApiDeprecationAgent::new(config);
+
+        let _ = agent.process_request("/api/v1/users", "GET", None);
+        let _ = agent.process_request("/api/v1/posts", "GET", None);
+
+        assert!(agent.metrics().encode().is_empty());
+        assert!(agent.metrics_report().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
+    fn test_metrics_disabled_by_update() {
+        let agent = ApiDeprecationAgent::new(test_config());
+
+        let mut config = test_config();
+        config.metrics.enabled = false;
+        agent.update_config(config).unwrap();
+
+        let _ = agent.process_request("/api/v1/users", "GET", None);
+        let output = agent.metrics().encode();
+        assert!(!output.contains("requests_total"));
+        assert!(!output.contains("days_until_sunset{"));
+        assert!(agent.metrics_report().is_none());
+    }
+
+    #[test]
+    fn test_invalid_pattern_fails_open() {
+        let yaml = r#"
+endpoints:
+  - id: broken
+    path: /api/v1/*/[unclosed
+"#;
+        // Building from an unvalidated config still works, but the rule never matches
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+        assert!(agent
+            .process_request("/api/v1/users/[unclosed", "GET", None)
+            .is_none());
+        assert!(agent
+            .process_request("/api/v1/users", "GET", None)
+            .is_none());
+
+        #[cfg(feature = "prometheus")]
+        assert!(agent
+            .metrics()
+            .encode()
+            .contains("matcher_errors_total{endpoint_id=\"broken\"} 1"));
+
+        // Updates with invalid patterns are rejected
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        assert!(matches!(
+            agent.update_config(config),
+            Err(ConfigError::Validation { field: "path", .. })
+        ));
+    }
+
+    #[test]
+    fn test_sunset_reminder_countdown() {
+        let yaml = r#"
+settings:
+  sunset_reminder: true
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    sunset_at: "2030-06-01T00:00:00Z"
+"#;
+        let sunset: DateTime<Utc> = "2030-06-01T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(FixedClock::new(sunset - Duration::days(10)));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+
+        let days = |agent: &ApiDeprecationAgent| {
+            agent
+                .process_request("/api/v1/users", "GET", None)
+                .unwrap()
+                .days_until_sunset
+        };
+
+        assert_eq!(days(&agent), Some(10));
+
+        clock.set(sunset);
+        assert_eq!(days(&agent), Some(0));
+
+        // Past sunset the default policy still warns, with a negative count
+        clock.advance(Duration::days(3));
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert!(matches!(decision.action, DeprecationActionResult::Warn));
+        assert_eq!(decision.days_until_sunset, Some(-3));
+    }
+
+    #[test]
+    fn test_preview_headers() {
+        let clock = Arc::new(FixedClock::new(
+            "2030-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        ));
+        let agent = ApiDeprecationAgent::new(test_config()).with_clock(clock);
+
+        let preview = agent.preview_headers("legacy-users").unwrap();
+        let names: Vec<&str> = preview.iter().map(|(name, _)| name.as_str()).collect();
+        for name in ["Deprecation", "Sunset", "Link"] {
+            assert!(names.contains(&name), "{} missing from {:?}", name, names);
+        }
+        assert!(names.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        let sunset = preview.iter().find(|(name, _)| name == "Sunset").unwrap();
+        assert_eq!(sunset.1, "Sat, 01 Jun 2030 00:00:00 GMT");
+
+        // Same headers a request gets, without counting it
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        let mut sent: Vec<(String, String)> = decision
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect();
+        sent.sort();
+        assert_eq!(preview, sent);
+        assert_eq!(agent.usage_snapshot()[0].requests, 1);
+
+        assert!(agent.preview_headers("no-such-rule").is_none());
+    }
+
+    #[test]
+    fn test_computed_headers() {
+        let yaml = r#"
+settings:
+  computed_headers:
+    Tk: "N"
+    X-Audit: "global"
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    sunset_at: "2030-06-01T00:00:00Z"
+    computed_headers:
+      X-Audit: "{{status}}; sunset={{sunset_iso}}; days={{days}}"
+"#;
+        let sunset: DateTime<Utc> = "2030-06-01T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(FixedClock::new(sunset - Duration::days(10)));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+
+        let audit = |agent: &ApiDeprecationAgent| {
+            let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+            assert_eq!(decision.headers["Tk"], "N");
+            decision.headers["X-Audit"].clone()
+        };
+
+        assert_eq!(
+            audit(&agent),
+            "deprecated; sunset=2030-06-01T00:00:00Z; days=10"
+        );
+
+        // Rendered on every request, not at load
+        clock.advance(Duration::days(3));
+        assert_eq!(
+            audit(&agent),
+            "deprecated; sunset=2030-06-01T00:00:00Z; days=7"
+        );
+
+        let invalid = yaml.replace("{{days}}", "{{hits}}");
+        let config: ApiDeprecationConfig = serde_yaml::from_str(&invalid).unwrap();
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("unknown placeholder '{{hits}}'"));
+    }
+
+    #[test]
+    fn test_scheduled_deprecation_announced_at_deprecated_at() {
+        let yaml = r#"
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    status: scheduled
+    deprecated_at: "2030-01-01T00:00:00Z"
+    sunset_at: "2030-06-01T00:00:00Z"
+  - id: scheduled-undated
+    path: /api/v1/posts
+    status: scheduled
+"#;
+        let deprecated_at: DateTime<Utc> = "2030-01-01T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(FixedClock::new(deprecated_at - Duration::seconds(1)));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+
+        let headers = |path: &str| agent.process_request(path, "GET", None).unwrap().headers;
+
+        // Before the date: only the softer notice, no Deprecation header
+        let before = headers("/api/v1/users");
+        assert!(!before.contains_key("Deprecation"));
+        assert!(before["X-Deprecation-Notice"].contains("scheduled for deprecation on 2030-01-01"));
+        assert_eq!(before["Sunset"], "Sat, 01 Jun 2030 00:00:00 GMT");
+
+        // From the date on: the full deprecation headers
+        clock.set(deprecated_at);
+        let after = headers("/api/v1/users");
+        assert_eq!(
+            after["Deprecation"],
+            format!("@{}", deprecated_at.timestamp())
+        );
+        assert!(after["X-Deprecation-Notice"].contains("is deprecated"));
+
+        // Without a deprecated_at there is nothing to wait for
+        assert_eq!(headers("/api/v1/posts")["Deprecation"], "true");
+    }
+
+    #[test]
+    fn test_hits_in_notice() {
+        let yaml = r#"
+settings:
+  client_id_header: X-Client-Id
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    message: "Deprecated; you have called it {{hits}} times today."
+"#;
+        let clock = Arc::new(FixedClock::new(
+            "2030-01-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        ));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+
+        let notice = |client: Option<&str>| {
+            agent
+                .process_client_request("/api/v1/users", "GET", None, client)
+                .unwrap()
+                .headers["X-Deprecation-Notice"]
+                .clone()
+        };
+
+        notice(Some("alice"));
+        assert_eq!(
+            notice(Some("alice")),
+            "Deprecated; you have called it 2 times today."
+        );
+        assert_eq!(
+            notice(Some("bob")),
+            "Deprecated; you have called it 1 times today."
+        );
+        // Without a client id the endpoint's total is shown
+        assert_eq!(
+            notice(None),
+            "Deprecated; you have called it 4 times today."
+        );
+
+        // Counts start over at midnight UTC
+        clock.advance(Duration::hours(12));
+        assert_eq!(
+            notice(Some("alice")),
+            "Deprecated; you have called it 1 times today."
+        );
+
+        // And when a reload changes the client_state limits
+        let reloaded = yaml.replace(
+            "settings:\n",
+            "settings:\n  client_state: {max_entries: 10}\n",
+        );
+        agent
+            .update_config(ApiDeprecationConfig::from_yaml(&reloaded).unwrap())
+            .unwrap();
+        assert_eq!(
+            notice(Some("alice")),
+            "Deprecated; you have called it 1 times today."
+        );
+    }
+
+    #[test]
+    fn test_hits_in_notice_without_counting() {
+        let yaml = r#"
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    message: "Deprecated; you have called it {{hits}} times today."
+    track_usage: false
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(
+            decision.headers["X-Deprecation-Notice"],
+            "This endpoint (/api/v1/users) is deprecated."
+        );
+    }
+
+    #[test]
+    fn test_default_sunset_at() {
+        let yaml = r#"
+settings:
+  default_sunset_at: 2030-06-01
+  past_sunset_action: block
+  require_confirmation: true
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+  - id: legacy-posts
+    path: /api/v1/posts
+    sunset_at: 2031-01-01
+"#;
+        let clock = Arc::new(FixedClock::new(
+            "2030-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        ));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(decision.headers["Sunset"], "Sat, 01 Jun 2030 00:00:00 GMT");
+        // Per-endpoint dates win
+        let decision = agent.process_request("/api/v1/posts", "GET", None).unwrap();
+        assert_eq!(decision.headers["Sunset"], "Wed, 01 Jan 2031 00:00:00 GMT");
+
+        // Past the default sunset the policy applies (shadowed until confirmed)
+        clock.set("2030-07-01T00:00:00Z".parse().unwrap());
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert!(decision.would_block);
+        assert!(
+            !agent
+                .process_request("/api/v1/posts", "GET", None)
+                .unwrap()
+                .would_block
+        );
+    }
+
+    #[test]
+    fn test_sunset_header_window() {
+        let yaml = r#"
+settings:
+  sunset_reminder: true
+  warning_level: true
+  sunset_header_window_days: 30
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    sunset_at: "2030-06-01T00:00:00Z"
+    documentation_url: https://docs.example.com/migration
+  - id: legacy-posts
+    path: /api/v1/posts
+"#;
+        let sunset: DateTime<Utc> = "2030-06-01T00:00:00Z".parse().unwrap();
+        let window_start = sunset - Duration::days(30);
+        let clock = Arc::new(FixedClock::new(window_start - Duration::seconds(1)));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+
+        // Just outside the window: Deprecation and Link only
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert!(!decision.headers.contains_key("Sunset"));
+        assert!(decision.headers.contains_key("Deprecation"));
+        assert!(decision.headers.contains_key("Link"));
+        assert_eq!(decision.days_until_sunset, None);
+        assert_eq!(decision.warning_level, None);
+
+        // Endpoints without a sunset date are unaffected
+        let decision = agent.process_request("/api/v1/posts", "GET", None).unwrap();
+        assert_eq!(decision.warning_level, Some(0));
+
+        // Just inside the window: everything, decided per request
+        clock.set(window_start);
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(decision.headers["Sunset"], "Sat, 01 Jun 2030 00:00:00 GMT");
+        assert_eq!(decision.days_until_sunset, Some(30));
+        assert_eq!(decision.warning_level, Some(2));
+    }
+
+    #[test]
+    fn test_unconfirmed_rules_only_warn() {
+        let yaml = r#"
+settings:
+  past_sunset_action: block
+  require_confirmation: true
+endpoints:
+  - id: removed-posts
+    path: /api/v1/posts
+    status: removed
+  - id: sunset-users
+    path: /api/v1/users
+    sunset_at: "2020-01-01T00:00:00Z"
+  - id: redirect-orders
+    path: /api/v1/orders
+    replacement:
+      path: /api/v2/orders
+    action:
+      type: redirect
+"#;
+        let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        let agent = ApiDeprecationAgent::new(config.clone());
+
+        let unconfirmed: Vec<_> = config.unconfirmed_endpoints().map(|e| &e.id).collect();
+        assert_eq!(unconfirmed, ["removed-posts", "sunset-users"]);
+
+        // Removed status and the past-sunset policy are both held back
+        for path in ["/api/v1/posts", "/api/v1/users"] {
+            let decision = agent.process_request(path, "GET", None).unwrap();
+            assert!(matches!(decision.action, DeprecationActionResult::Warn));
+            assert!(decision.would_block);
+        }
+
+        // Redirects don't need confirming
+        let decision = agent
+            .process_request("/api/v1/orders", "GET", None)
+            .unwrap();
+        assert!(matches!(
+            decision.action,
+            DeprecationActionResult::Redirect { status_code: 308 }
+        ));
+        assert!(!decision.would_block);
+
+        #[cfg(feature = "prometheus")]
+        assert!(agent.metrics().encode().contains("unconfirmed_rules 2"));
+
+        // Confirming a rule enforces it
+        config.endpoints[0].confirmed = Some(true);
+        agent.update_config(config).unwrap();
+        let decision = agent.process_request("/api/v1/posts", "GET", None).unwrap();
+        assert!(matches!(
+            decision.action,
+            DeprecationActionResult::Block { status_code: 410 }
+        ));
+        assert!(!decision.would_block);
+
+        #[cfg(feature = "prometheus")]
+        assert!(agent.metrics().encode().contains("unconfirmed_rules 1"));
+    }
+
+    #[test]
+    fn test_client_bucket_stable_across_agents() {
+        let mut config = test_config();
+        config.settings.bucket_seed = Some(0x5eed);
+        let first = ApiDeprecationAgent::new(config.clone());
+        let second = ApiDeprecationAgent::new(config);
+
+        for i in 0..50 {
+            let client = format!("client-{i}");
+            assert_eq!(first.client_bucket(&client), second.client_bucket(&client));
+            assert!(first.client_bucket(&client) < crate::bucket::BUCKETS);
+        }
+
+        // A different seed reshuffles clients
+        let other = ApiDeprecationAgent::new(test_config());
+        assert!((0..50)
+            .map(|i| format!("client-{i}"))
+            .any(|client| first.client_bucket(&client) != other.client_bucket(&client)));
+    }
+
+    #[test]
+    fn test_deprecated_query_params() {
+        let yaml = r#"
+endpoints:
+  - id: users-params
+    path: /api/v2/users
+    confirmed: true
+    deprecated_params:
+      - name: include_deleted
+        sunset_at: 2030-01-01
+        replacement: with_deleted
+      - name: fields
+        action: strip
+      - name: legacy_auth
+        action: block
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+
+        // Requests without a listed parameter get the rule's own deprecation
+        for query_string in [None, Some("page=2&include=all")] {
+            let decision = agent
+                .process_request("/api/v2/users", "GET", query_string)
+                .unwrap();
+            assert!(matches!(decision.action, DeprecationActionResult::Warn));
+            assert_eq!(
+                decision.headers["X-Deprecation-Notice"],
+                "This endpoint (/api/v2/users) is deprecated."
+            );
+        }
+
+        // A listed parameter's notice is added to it
+        let decision = agent
+            .process_request("/api/v2/users", "GET", Some("page=2&include_deleted=true"))
+            .unwrap();
+        assert!(matches!(decision.action, DeprecationActionResult::Warn));
+        assert_eq!(
+            decision.headers["X-Deprecation-Notice"],
+            "This endpoint (/api/v2/users) is deprecated. \
+             The query parameter 'include_deleted' is deprecated and will be removed on 2030-01-01. Please use 'with_deleted' instead."
+        );
+        assert!(decision.headers.contains_key("Deprecation"));
+
+        let decision = agent
+            .process_request("/api/v2/users", "GET", Some("fields=id&page=2"))
+            .unwrap();
+        assert!(matches!(
+            decision.action,
+            DeprecationActionResult::Redirect { status_code: 307 }
+        ));
+        assert_eq!(decision.redirect_url.unwrap(), "/api/v2/users?page=2");
+
+        let decision = agent
+            .process_request("/api/v2/users", "GET", Some("fields=id&legacy_auth"))
+            .unwrap();
+        match decision.action {
+            DeprecationActionResult::Custom {
+                status_code, body, ..
+            } => {
+                assert_eq!(status_code, 400);
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                assert_eq!(body["parameter"], "legacy_auth");
+            }
+            other => panic!("Expected a 400 naming the parameter, got {:?}", other),
+        }
+
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains(
+                "deprecated_params_total{action=\"strip\",endpoint_id=\"users-params\",param=\"fields\"} 2"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_deprecated_body_field_actions() {
+        let yaml = r#"
+settings:
+  max_body_inspection_bytes: 64
+  body_inspection_fail_open: false
+endpoints:
+  - id: users-fields
+    path: /api/v2/users
+    confirmed: true
+    deprecated_body_fields:
+      - pointer: /user_name
+        replacement: /username
+      - pointer: /legacy_role
+        action: block
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+        let body =
+            |body: &str| agent.process_request_body("/api/v2/users", "POST", body.as_bytes());
+
+        assert!(body(r#"{"username": "ada"}"#).is_none());
+
+        let decision = body(r#"{"user_name": "ada"}"#).unwrap();
+        assert!(matches!(decision.action, DeprecationActionResult::Warn));
+        assert_eq!(decision.headers["X-Deprecated-Body-Fields"], "/user_name");
+        assert_eq!(
+            decision.headers["X-Deprecation-Notice"],
+            "The request body field '/user_name' is deprecated. Please use '/username' instead."
+        );
+
+        let decision = body(r#"{"user_name": "ada", "legacy_role": "admin"}"#).unwrap();
+        assert_eq!(
+            decision.headers["X-Deprecated-Body-Fields"],
+            "/user_name, /legacy_role"
+        );
+        match decision.action {
+            DeprecationActionResult::Custom {
+                status_code,
+                body,
+                content_type,
+            } => {
+                assert_eq!(status_code, 400);
+                assert_eq!(content_type.as_ref(), "application/problem+json");
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                assert_eq!(body["fields"][0]["pointer"], "/legacy_role");
+            }
+            other => panic!("Expected a 400 naming the field, got {:?}", other),
+        }
+
+        // Failing closed, bodies that can't be inspected are rejected too
+        let decision = body("user_name=ada").unwrap();
+        assert!(matches!(
+            decision.action,
+            DeprecationActionResult::Custom {
+                status_code: 400,
+                ..
+            }
+        ));
+
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains(
+                "deprecated_body_fields_total{action=\"warn\",endpoint_id=\"users-fields\",pointer=\"/user_name\"} 2"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_deprecated_request_headers() {
+        let yaml = r#"
+settings:
+  deprecated_request_headers:
+    - name: X-Auth-Token
+      replacement: Authorization
+    - name: X-Legacy-Tenant
+      action: block
+    - name: X-Session-Key
+      action: block
+      confirmed: false
+endpoints:
+  - id: users-headers
+    path: /api/v2/users
+    confirmed: false
+    deprecated_request_headers:
+      - name: X-Auth-Token
+        action: strip
+      - name: X-Api-Version
+        action: block
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+        let used = |path: &str, sent: &[&str]| {
+            agent
+                .deprecated_headers_used(path, "GET", |name| {
+                    sent.iter().any(|header| header.eq_ignore_ascii_case(name))
+                })
+                .into_iter()
+                .map(|used| (used.header.name, used.action, used.would_block))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(used("/api/v1/orders", &["Authorization"]).is_empty());
+        assert_eq!(
+            used("/api/v1/orders", &["x-auth-token", "X-Legacy-Tenant"]),
+            vec![
+                ("X-Auth-Token".to_string(), ParamAction::Warn, false),
+                ("X-Legacy-Tenant".to_string(), ParamAction::Block, false),
+            ]
+        );
+
+        // Global entries carry their own confirmation
+        assert_eq!(
+            used("/api/v1/orders", &["X-Session-Key"]),
+            vec![("X-Session-Key".to_string(), ParamAction::Warn, true)]
+        );
+
+        // The rule's own entries win, and its blocks wait for confirmation
+        assert_eq!(
+            used("/api/v2/users", &["X-Auth-Token", "X-Api-Version"]),
+            vec![
+                ("X-Auth-Token".to_string(), ParamAction::Strip, false),
+                ("X-Api-Version".to_string(), ParamAction::Warn, true),
+            ]
+        );
+
+        // The rule's own deprecation still applies
+        let decision = agent.process_request("/api/v2/users", "GET", None).unwrap();
+        assert_eq!(decision.endpoint_id, "users-headers");
+        assert!(decision.headers.contains_key("Deprecation"));
+
+        let state = agent.state.load();
+        let body: serde_json::Value = serde_json::from_str(&deprecated_header_body_with(
+            &state.config.settings.deprecated_request_headers[0],
+            &state.config.settings.error_envelope,
+        ))
+        .unwrap();
+        assert_eq!(body["header"], "X-Auth-Token");
+        assert_eq!(body["replacement"], "Authorization");
+
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output
+                .contains("deprecated_headers_total{action=\"warn\",header=\"X-Auth-Token\"} 1"));
+            assert!(output
+                .contains("deprecated_headers_total{action=\"strip\",header=\"X-Auth-Token\"} 1"));
+            assert!(output.contains(
+                "deprecated_headers_total{action=\"block\",header=\"X-Legacy-Tenant\"} 1"
+            ));
+
+            // Blocks held back are counted as the warnings they were
+            assert!(output
+                .contains("deprecated_headers_total{action=\"warn\",header=\"X-Api-Version\"} 1"));
+            assert!(output
+                .contains("deprecated_headers_total{action=\"warn\",header=\"X-Session-Key\"} 1"));
+            assert!(!output.contains("action=\"block\",header=\"X-Api-Version\""));
+        }
+    }
+
+    #[test]
+    fn test_deprecated_body_fields() {
+        let yaml = r#"
+settings:
+  max_body_inspection_bytes: 64
+endpoints:
+  - id: orders-legacy-fields
+    path: /api/v2/orders
+    sunset_at: 2030-01-01
+    body_field_deprecated: [/legacy_id, /shipping/method_code]
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+        let body =
+            |body: &str| agent.process_request_body("/api/v2/orders", "POST", body.as_bytes());
+
+        // The endpoint's own deprecation is decided on the headers, and the
+        // request counted there
+        let decision = agent
+            .process_request("/api/v2/orders", "POST", None)
+            .unwrap();
+        assert!(matches!(decision.action, DeprecationActionResult::Warn));
+        assert_eq!(agent.usage_snapshot()[0].requests, 1);
+
+        let decision = body(r#"{"legacy_id": 7, "shipping": {"method_code": "x"}}"#).unwrap();
+        assert_eq!(
+            decision.headers["X-Deprecation-Notice"],
+            "The request body field '/legacy_id' is deprecated and will be removed on 2030-01-01. \
+             The request body field '/shipping/method_code' is deprecated and will be removed on 2030-01-01."
+        );
+        assert!(decision.headers.contains_key("Deprecation"));
+
+        // Bodies without the fields, non-JSON and oversized bodies pass
+        assert!(body(r#"{"id": 7, "shipping": {}}"#).is_none());
+        assert!(body("legacy_id=7").is_none());
+        assert!(body(&format!(r#"{{"legacy_id": "{}"}}"#, "x".repeat(64))).is_none());
+        assert_eq!(agent.usage_snapshot()[0].requests, 1);
+
+        // Only the uninspected ones count as failing open
+        #[cfg(feature = "prometheus")]
+        assert!(agent.metrics().encode().contains(
+            "allowed_total{endpoint_id=\"orders-legacy-fields\",reason=\"body_inspection_fail_open\"} 2"
+        ));
+    }
+
+    #[test]
+    fn test_warning_level_escalates_towards_sunset() {
+        let yaml = r#"
+settings:
+  warning_level: true
+  warning_level_thresholds: [60, 14, 3]
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    sunset_at: "2030-06-01T00:00:00Z"
+  - id: legacy-posts
+    path: /api/v1/posts
+"#;
+        let sunset: DateTime<Utc> = "2030-06-01T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(FixedClock::new(sunset));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+
+        let level = |path| {
+            agent
+                .process_request(path, "GET", None)
+                .unwrap()
+                .warning_level
+        };
+
+        for (days_before, expected) in [
+            (61, 0),
+            (60, 1),
+            (15, 1),
+            (14, 2),
+            (4, 2),
+            (3, 3),
+            (0, 3),
+            (-1, 3),
+        ] {
+            clock.set(sunset - Duration::days(days_before));
+            assert_eq!(
+                level("/api/v1/users"),
+                Some(expected),
+                "{} days before sunset",
+                days_before
+            );
+        }
+
+        // No sunset date, nothing to escalate
+        assert_eq!(level("/api/v1/posts"), Some(0));
+    }
+
+    #[test]
+    fn test_warning_level_disabled_by_default() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(decision.warning_level, None);
+
+        let settings = &decision.state.config.settings;
+        assert_eq!(settings.warning_level_header, "X-Deprecation-Warning-Level");
+        assert_eq!(settings.warning_level(Some(100)), 0);
+        assert_eq!(settings.warning_level(Some(30)), 2);
+    }
+
+    #[test]
+    fn test_match_trace_gating() {
+        let mut config = test_config();
+        let debug = || {
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_writer(std::io::sink)
+                .finish()
+        };
+
+        // Disabled in the configuration
+        let agent = ApiDeprecationAgent::new(config.clone());
+        tracing::subscriber::with_default(debug(), || {
+            assert!(agent
+                .match_trace("/api/v1/users", "GET", true, [])
+                .is_none());
+        });
+
+        config.settings.trace_match = true;
+        let agent = ApiDeprecationAgent::new(config);
+
+        // Without debug logging or the opt-in header
+        assert!(agent
+            .match_trace("/api/v1/users", "GET", true, [])
+            .is_none());
+        tracing::subscriber::with_default(debug(), || {
+            assert!(agent
+                .match_trace("/api/v1/users", "GET", false, [])
+                .is_none());
+
+            let trace = agent
+                .match_trace(
+                    "/api/v1/users",
+                    "GET",
+                    true,
+                    [
+                        ("Authorization", "Bearer secret"),
+                        (TRACE_MATCH_HEADER, "1"),
+                    ],
+                )
+                .unwrap();
+            assert_eq!(trace.selected.as_deref(), Some("legacy-users"));
+            assert_eq!(trace.rules.len(), 3);
+            // The logged sample never carries credentials
+            assert_eq!(trace.headers["authorization"], "***");
+            assert_eq!(trace.headers["x-deprecation-trace"], "1");
+        });
+    }
+
+    #[test]
+    fn test_sunset_reminder_disabled_by_default() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(decision.days_until_sunset, None);
+        assert!(!decision.state.config.settings.sunset_reminder);
+        assert_eq!(
+            decision.state.config.settings.sunset_reminder_header,
+            "X-Days-Until-Sunset"
+        );
+    }
+
+    #[test]
+    fn test_reload_failure_fail_open() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        let mut bad = test_config();
+        bad.endpoints[0].path = PathPatterns::default();
+
+        assert!(agent.update_config(bad).is_err());
+        assert!(agent.health_status().is_healthy());
+        assert_eq!(agent.state.load().config.endpoints[0].id, "legacy-users");
+    }
+
+    #[test]
+    fn test_reload_failure_degrade() {
+        let mut config = test_config();
+        config.settings.reload_failure_mode = ReloadFailureMode::Degrade;
+        let agent = ApiDeprecationAgent::new(config.clone());
+
+        let mut bad = config.clone();
+        bad.endpoints[0].path = PathPatterns::default();
+        assert!(agent.update_config(bad).is_err());
+        assert!(!agent.health_status().is_healthy());
+
+        // The last-good configuration keeps serving
+        assert!(agent
+            .process_request("/api/v1/users", "GET", None)
+            .is_some());
+
+        // A successful reload restores health
+        agent.update_config(config).unwrap();
+        assert!(agent.health_status().is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_pushed_config_parse_failure_degrades() {
+        let mut config = test_config();
+        config.settings.reload_failure_mode = ReloadFailureMode::Degrade;
+        let agent = ApiDeprecationAgent::new(config);
+
+        let result =
+            Agent::on_configure(&agent, serde_json::json!({ "endpoints": "not-a-list" })).await;
+        assert!(result.is_err());
+        assert!(!agent.health_status().is_healthy());
+    }
+
+    #[tokio::test]
+    async fn test_pushed_config_invalid_dates() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        let pushed = |on_invalid_date: &str| {
+            serde_json::json!({
+                "settings": { "on_invalid_date": on_invalid_date },
+                "endpoints": [{
+                    "id": "users-v1",
+                    "path": "/api/v1/users",
+                    "sunset_at": "${SUNSET_DATE}",
+                }],
+            })
+        };
+
+        // Rejected by default, naming the rule
+        let err = Agent::on_configure(&agent, pushed("error"))
+            .await
+            .unwrap_err();
+        assert!(err.contains("users-v1"), "{}", err);
+        assert!(err.contains("invalid date '${SUNSET_DATE}'"), "{}", err);
+
+        // Applied without the date with ignore_and_warn, as from a file
+        Agent::on_configure(&agent, pushed("ignore_and_warn"))
+            .await
+            .unwrap();
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(decision.endpoint_id, "users-v1");
+        assert_eq!(decision.sunset_at, None);
+    }
+
+    #[test]
+    fn test_match_cache_invalidated_on_reload() {
+        let mut config = test_config();
+        config.settings.match_cache_size = 16;
+        let agent = ApiDeprecationAgent::new(config.clone());
+
+        // Populate the cache with a hit and a negative match
+        assert!(agent
+            .process_request("/api/v1/users", "GET", None)
+            .is_some());
+        assert!(agent
+            .process_request("/api/v1/items", "GET", None)
+            .is_none());
+        assert!(agent
+            .process_request("/api/v1/items", "GET", None)
+            .is_none());
+
+        // Swap to a config where the hit is gone and the miss now matches
+        config.endpoints[0].path = "/api/v1/items".into();
+        agent.update_config(config).unwrap();
+
+        assert!(agent
+            .process_request("/api/v1/users", "GET", None)
+            .is_none());
+        let decision = agent
+            .process_request("/api/v1/items", "GET", Some("page=2"))
+            .unwrap();
+        assert_eq!(decision.endpoint_id, "legacy-users");
+    }
+
+    #[test]
+    fn test_generated_documentation_url_in_headers() {
+        let mut config = test_config();
+        config.settings.documentation_base_url =
+            Some("https://docs.example.com/migration/{id}".to_string());
+        let agent = ApiDeprecationAgent::new(config);
+
+        let decision = agent
+            .process_request("/api/v1/orders", "GET", None)
+            .unwrap();
+        let link = decision.headers.get("Link").unwrap();
+        assert!(link
+            .contains("<https://docs.example.com/migration/redirect-orders>; rel=\"deprecation\""));
+        assert!(decision
+            .headers
+            .get("X-Deprecation-Notice")
+            .unwrap()
+            .contains("https://docs.example.com/migration/redirect-orders"));
+    }
+
+    #[test]
+    fn test_active_window() {
+        let yaml = r#"
+endpoints:
+  - id: tax-season
+    path: /api/v1/filings
+    active_from: "2030-01-15T00:00:00Z"
+    active_until: "2030-04-16T00:00:00Z"
+"#;
+        let from: DateTime<Utc> = "2030-01-15T00:00:00Z".parse().unwrap();
+        let clock = Arc::new(FixedClock::new(from - Duration::seconds(1)));
+        let agent = ApiDeprecationAgent::from_yaml(yaml)
+            .unwrap()
+            .with_clock(clock.clone());
+        let drive = || {
+            let decision = agent.process_request("/api/v1/filings", "GET", None);
+            if let Some(decision) = &decision {
+                agent.record_outcome(decision, "/api/v1/filings");
+            }
+            decision.is_some()
+        };
+
+        // Before the window the rule is skipped and nothing is counted
+        assert!(!drive());
+        assert!(!agent.usage_snapshot()[0].active);
+        assert_eq!(agent.usage_snapshot()[0].requests, 0);
+
+        clock.set(from);
+        assert!(drive());
+        assert!(agent.usage_snapshot()[0].active);
+        assert_eq!(agent.usage_snapshot()[0].requests, 1);
+
+        // active_until is exclusive
+        clock.set("2030-04-16T00:00:00Z".parse().unwrap());
+        assert!(!drive());
+        assert!(!agent.usage_snapshot()[0].active);
+        assert_eq!(agent.usage_snapshot()[0].requests, 1);
+    }
+
+    #[test]
+    fn test_usage_snapshot() {
+        let clock = Arc::new(FixedClock::new(
+            "2030-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        ));
+        let agent = ApiDeprecationAgent::new(test_config()).with_clock(clock.clone());
+
+        let drive = |path: &str, method: &str| {
+            if let Some(decision) = agent.process_request(path, method, None) {
+                agent.record_outcome(&decision, path);
+            }
+        };
+
+        for _ in 0..3 {
+            drive("/api/v1/users", "GET");
+        }
+        drive("/api/v1/users", "OPTIONS");
+        drive("/api/v1/posts", "GET");
+        drive("/api/v1/posts", "DELETE");
+        clock.advance(Duration::hours(1));
+        drive("/api/v1/orders", "GET");
+        drive("/api/v2/users", "GET");
+
+        let snapshot = agent.usage_snapshot();
+        assert_eq!(snapshot.len(), 3);
+
+        let users = &snapshot[0];
+        assert_eq!(users.id, "legacy-users");
+        assert_eq!(users.pattern, "/api/v1/users");
+        assert_eq!(users.status, DeprecationStatus::Deprecated);
+        assert_eq!(users.requests, 3, "preflight is not counted");
+        assert_eq!((users.redirects, users.blocked), (0, 0));
+        assert_eq!(
+            users.days_until_sunset,
+            Some((users.sunset_at.unwrap() - clock.now()).num_days())
+        );
+        assert_eq!(
+            users.last_access.unwrap().to_rfc3339(),
+            "2030-01-01T00:00:00+00:00"
+        );
+
+        let posts = &snapshot[1];
+        assert_eq!((posts.requests, posts.blocked), (2, 2));
+
+        let orders = &snapshot[2];
+        assert_eq!((orders.requests, orders.redirects), (1, 1));
+        assert_eq!(
+            orders.last_access.unwrap().to_rfc3339(),
+            "2030-01-01T01:00:00+00:00"
+        );
+        assert_eq!(orders.days_until_sunset, None);
+    }
+
+    #[test]
+    fn test_tombstone_records_callers() {
+        let yaml = r#"
+settings:
+  client_id_header: X-Client-Id
+endpoints:
+  - id: legacy-auth
+    path: /auth/legacy/*
+    status: removed
+    confirmed: true
+    tombstone:
+      sample_every: 2
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+        for (path, client) in [
+            ("/auth/legacy/login", Some("billing")),
+            ("/auth/legacy/login", Some("search")),
+            ("/auth/legacy/token", None),
+        ] {
+            let decision = agent
+                .process_client_request(path, "POST", None, client)
+                .unwrap();
+            assert!(matches!(
+                decision.action,
+                DeprecationActionResult::Block { status_code: 410 }
+            ));
+        }
+
+        let samples = agent.diagnostic_samples();
+        assert_eq!(samples.len(), 2, "one in two requests is sampled");
+        assert_eq!(samples[0].endpoint_id, "legacy-auth");
+        assert_eq!(
+            (samples[0].method.as_str(), samples[0].path.as_str()),
+            ("POST", "/auth/legacy/login")
+        );
+        assert_eq!(samples[0].client.as_deref(), Some("billing"));
+        assert_eq!(samples[1].client, None);
+
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains(
+                "tombstone_hits_total{client=\"search\",endpoint_id=\"legacy-auth\",method=\"POST\",path=\"/auth/legacy/login\"} 1"
+            ));
+            assert!(output.contains("client=\"unknown\""));
+        }
+    }
+
+    #[test]
+    fn test_panics_fail_open() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        let panicking = || -> Option<&str> { panic!("index out of bounds in template") };
+
+        for _ in 0..2 {
+            assert_eq!(agent.isolate_panics(panicking, || None), None);
+        }
+        assert_eq!(agent.internal_errors.load(Ordering::Relaxed), 2);
+        assert_eq!(agent.logged_panics.lock().unwrap().len(), 1);
+        assert!(!ISOLATING_PANICS.with(Cell::get));
+
+        // Later requests are processed as usual
+        assert_eq!(
+            agent.isolate_panics(|| Some("processed"), || None),
+            Some("processed")
+        );
+        assert!(agent
+            .process_request("/api/v1/users", "GET", None)
+            .is_some());
+    }
+
+    #[test]
+    fn test_panic_holding_a_lock_is_not_permanent() {
+        let yaml = r#"
+endpoints:
+  - id: legacy-auth
+    path: /auth/legacy/*
+    status: removed
+    tombstone:
+      sample_every: 1
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+
+        // A panic while recording a tombstone hit poisons the tracker's lock
+        agent.isolate_panics(
+            || {
+                let _tombstones = agent.tombstones.lock().unwrap();
+                panic!("failed while recording a caller");
+            },
+            || (),
+        );
+        assert!(agent.tombstones.is_poisoned());
+
+        // The next request to the endpoint is still processed and sampled
+        let decision = agent
+            .process_client_request("/auth/legacy/login", "POST", None, None)
+            .unwrap();
+        assert!(matches!(
+            decision.action,
+            DeprecationActionResult::Block { status_code: 410 }
+        ));
+        assert_eq!(agent.diagnostic_samples().len(), 1);
+        assert_eq!(agent.internal_errors.load(Ordering::Relaxed), 1);
+    }
+
+    /// Log output captured by a test subscriber.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_panic_logs_deduplicated() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .without_time()
+            .with_writer(move || writer.clone())
+            .finish();
+        let agent = ApiDeprecationAgent::new(test_config());
+
+        tracing::subscriber::with_default(subscriber, || {
+            for message in ["bad template", "bad header", "bad template", "bad template"] {
+                agent.isolate_panics(|| panic!("{}", message), || ());
+            }
+            // Past the limit, new messages are only counted
+            for i in 0..MAX_LOGGED_PANICS {
+                agent.isolate_panics(|| panic!("panic {}", i), || ());
+            }
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let logged: Vec<&str> = output
+            .lines()
+            .filter(|line| line.contains("Request processing panicked"))
+            .collect();
+        assert_eq!(logged.len(), MAX_LOGGED_PANICS, "{}", output);
+        assert_eq!(
+            logged
+                .iter()
+                .filter(|line| line.contains("panic=bad template"))
+                .count(),
+            1
+        );
+        assert_eq!(
+            agent.internal_errors.load(Ordering::Relaxed),
+            4 + MAX_LOGGED_PANICS as u64
+        );
+    }
+
+    #[test]
+    fn test_access_log_level_follows_severity() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::INFO)
+            .with_ansi(false)
+            .without_time()
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let agent = ApiDeprecationAgent::from_yaml(
+            r#"
+endpoints:
+  - id: debug-route
+    path: /api/v1/debug
+    severity: low
+  - id: users-v1
+    path: /api/v1/users
+  - id: payments-v1
+    path: /api/v1/payments
+    severity: critical
+"#,
+        )
+        .unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            for path in ["/api/v1/debug", "/api/v1/users", "/api/v1/payments"] {
+                let decision = agent.process_request(path, "GET", None).unwrap();
+                log_access(&decision, None, path, "GET");
+            }
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = |endpoint_id: &str| {
+            output
+                .lines()
+                .find(|line| line.contains(&format!("endpoint_id={}", endpoint_id)))
+                .map(str::to_string)
+        };
+        // Low severity accesses are at debug level, below the INFO filter
+        assert_eq!(line("debug-route"), None, "{}", output);
+        assert!(line("users-v1").unwrap().contains("INFO"), "{}", output);
+        let critical = line("payments-v1").unwrap();
+        assert!(critical.contains("WARN"), "{}", output);
+        assert!(critical.contains("severity=critical"), "{}", output);
+        assert!(
+            critical.contains("allow_reason=\"warn_action\""),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_upstream_header() {
+        let mut config = test_config();
+        let agent = ApiDeprecationAgent::new(config.clone());
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(upstream_header(&decision), None);
+
+        config.settings.upstream_header = Some("X-Zentinel-Deprecated".to_string());
+        config.endpoints[2].action = DeprecationAction::Warn;
+        config.endpoints.push(config.endpoints[0].clone());
+        config.endpoints[3].id = "redirect-users".to_string();
+        config.endpoints[3].methods = vec!["DELETE".to_string()];
+        config.endpoints[3].action = DeprecationAction::Redirect { status_code: 308 };
+        agent.update_config(config).unwrap();
+
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(
+            upstream_header(&decision),
+            Some((
+                "X-Zentinel-Deprecated".to_string(),
+                "legacy-users; sunset=2030-06-01T00:00:00Z".to_string()
+            ))
+        );
+        // Request side only: never among the response headers
+        assert!(!decision.headers.contains_key("X-Zentinel-Deprecated"));
+
+        let decision = agent
+            .process_request("/api/v1/orders", "GET", None)
+            .unwrap();
+        assert_eq!(
+            upstream_header(&decision).unwrap().1,
+            "redirect-orders",
+            "no sunset, no sunset parameter"
+        );
+
+        // Blocked and redirected requests never reach the upstream
+        for (path, method) in [("/api/v1/posts", "GET"), ("/api/v1/users", "DELETE")] {
+            let decision = agent.process_request(path, method, None).unwrap();
+            assert!(!matches!(decision.action, DeprecationActionResult::Warn));
+            assert_eq!(upstream_header(&decision), None);
+        }
+    }
+
+    #[test]
+    fn test_notes_stay_internal() {
+        const NOTES: &str = "Owned by identity, tracked in JIRA-123";
+        let mut config = test_config();
+        config.settings.manifest_path = Some("/.well-known/api-deprecations".to_string());
+        for endpoint in &mut config.endpoints {
+            endpoint.notes = Some(NOTES.to_string());
+        }
+        let agent = ApiDeprecationAgent::new(config);
+
+        // Reported
+        let usage = agent.usage_snapshot();
+        assert_eq!(usage[0].notes.as_deref(), Some(NOTES));
+        assert!(serde_json::to_string(&usage).unwrap().contains(NOTES));
+
+        // Never sent to clients
+        for (path, method) in [
+            ("/api/v1/users", "GET"),
+            ("/api/v1/posts", "GET"),
+            ("/api/v1/orders", "GET"),
+        ] {
+            let decision = agent.process_request(path, method, None).unwrap();
+            assert_eq!(decision.notes.as_deref(), Some(NOTES));
+            assert!(!decision.gone_body.contains("JIRA-123"));
+            assert!(decision
+                .headers
+                .values()
+                .all(|value| !value.contains("JIRA-123")));
+        }
+        let state = agent.state.load();
+        for endpoint in &state.config.endpoints {
+            for body in [
+                deprecation_response_body_with(endpoint, &state.config.settings),
+                gone_response_body_with(endpoint, &state.config.settings),
+            ] {
+                assert!(!body.contains("JIRA-123"), "{}", body);
+            }
+        }
+        let manifest = agent
+            .manifest_response("/.well-known/api-deprecations", "GET", None)
+            .unwrap();
+        assert!(!manifest.body.unwrap().contains("JIRA-123"));
+    }
+
+    #[test]
+    fn test_sunset_moves_are_tracked() {
+        let now: DateTime<Utc> = "2030-01-01T00:00:00Z".parse().unwrap();
+        let agent =
+            ApiDeprecationAgent::new(test_config()).with_clock(Arc::new(FixedClock::new(now)));
+        let reload = |sunset: Option<&str>| {
+            let mut config = test_config();
+            config.endpoints[0].sunset_at = sunset.map(|sunset| sunset.parse().unwrap());
+            agent.update_config(config).unwrap();
+        };
+
+        reload(Some("2030-06-01T00:00:00Z"));
+        assert!(agent.handle().health().sunset_changes.is_empty());
+
+        reload(Some("2030-09-01T00:00:00Z"));
+        reload(Some("2030-08-01T00:00:00Z"));
+        reload(None);
+        let health = agent.handle().health();
+        let changes = &health.sunset_changes["legacy-users"];
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].kind, SunsetMove::Extension);
+        assert_eq!(
+            changes[0].from,
+            "2030-06-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(changes[0].changed_at, now);
+        assert_eq!(changes[1].kind, SunsetMove::Acceleration);
+        assert_eq!(changes[2].kind, SunsetMove::Withdrawal);
+        assert_eq!(changes[2].to, None);
+
+        // Withdrawals count as extensions
+        #[cfg(feature = "prometheus")]
+        assert!(agent
+            .metrics()
+            .encode()
+            .contains("sunset_extensions_total{endpoint_id=\"legacy-users\"} 2"));
+    }
+
+    #[test]
+    fn test_migration_deadline() {
+        let mut config = test_config();
+        config.settings.migration_grace_days = 30;
         let agent = ApiDeprecationAgent::new(config);
 
-        let decision = agent.process_request("/api/v1/posts", "GET", None);
-        assert!(decision.is_some());
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        let sunset: DateTime<Utc> = "2030-06-01T00:00:00Z".parse().unwrap();
+        assert_eq!(
+            decision.migration_deadline,
+            Some(sunset + Duration::days(30))
+        );
 
-        let d = decision.unwrap();
-        assert_eq!(d.endpoint_id, "removed-posts");
+        // No sunset, no deadline
+        let decision = agent
+            .process_request("/api/v1/orders", "GET", None)
+            .unwrap();
+        assert_eq!(decision.migration_deadline, None);
+    }
+
+    #[test]
+    fn test_severity_is_exported() {
+        let mut config = test_config();
+        config.endpoints[0].severity = Severity::Critical;
+        let agent = ApiDeprecationAgent::new(config);
+
+        let usage = agent.usage_snapshot();
+        assert_eq!(usage[0].severity, Severity::Critical);
+        assert_eq!(usage[1].severity, Severity::Normal);
+
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains(
+                "endpoint_severity{endpoint_id=\"legacy-users\",severity=\"critical\"} 1"
+            ));
+            assert!(output.contains(
+                "endpoint_severity{endpoint_id=\"removed-posts\",severity=\"normal\"} 1"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_not_modified_strips_deprecation_headers() {
+        let mut config = test_config();
+        config.endpoints[0]
+            .headers
+            .insert("Cache-Control".to_string(), "max-age=60".to_string());
+        let agent = ApiDeprecationAgent::new(config.clone());
+
+        // Off by default
+        assert!(agent
+            .response_removals("/api/v1/users", "GET", 304)
+            .is_empty());
+
+        config.settings.strip_headers_on_not_modified = true;
+        agent.update_config(config).unwrap();
+        let removed = agent.response_removals("/api/v1/users", "GET", 304);
+        for name in ["Deprecation", "Link", "Sunset", "X-Deprecation-Notice"] {
+            assert!(
+                removed.iter().any(|removed| removed == name),
+                "{:?}",
+                removed
+            );
+        }
+        // Headers a 304 carries are kept
+        assert!(!removed.iter().any(|name| name == "Cache-Control"));
+
+        // Full responses and other paths keep everything
+        assert!(agent
+            .response_removals("/api/v1/users", "GET", 200)
+            .is_empty());
+        assert!(agent
+            .response_removals("/api/v2/users", "GET", 304)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_error_responses_skip_deprecation_headers() {
+        let mut config = test_config();
+        let agent = ApiDeprecationAgent::new(config.clone());
+
+        // 5xx responses lose every deprecation header by default
+        let removed = agent.response_removals("/api/v1/users", "GET", 503);
+        for name in ["Deprecation", "Link", "Sunset", "X-Deprecation-Notice"] {
+            assert!(
+                removed.iter().any(|removed| removed == name),
+                "{:?}",
+                removed
+            );
+        }
+        assert!(agent
+            .response_removals("/api/v1/users", "GET", 200)
+            .is_empty());
+        assert!(agent
+            .response_removals("/api/v1/users", "GET", 404)
+            .is_empty());
+
+        // The access is still recorded
+        agent.process_and_record("/api/v1/users", "GET");
+        agent.record_response("/api/v1/users", "GET", 503);
+        let usage = &agent.usage_snapshot()[0];
+        assert_eq!((usage.requests, usage.errors), (1, 1));
+
+        config.settings.skip_headers_status_classes = vec!["4xx".to_string(), "5xx".to_string()];
+        agent.update_config(config.clone()).unwrap();
+        assert!(!agent
+            .response_removals("/api/v1/users", "GET", 404)
+            .is_empty());
+        assert!(agent
+            .response_removals("/api/v1/users", "GET", 200)
+            .is_empty());
+
+        config.settings.skip_headers_on_error = false;
+        agent.update_config(config).unwrap();
+        assert!(agent
+            .response_removals("/api/v1/users", "GET", 503)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_enforcement_kill_switch() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        let action = |path: &str| agent.process_request(path, "GET", None).unwrap().action;
         assert!(matches!(
-            d.action,
-            DeprecationActionResult::Block { status_code: 410 }
+            action("/api/v1/posts"),
+            DeprecationActionResult::Block { .. }
+        ));
+
+        // Switched off, blocks and redirects only warn
+        agent.set_enforcement(false);
+        let decision = agent.process_request("/api/v1/posts", "GET", None).unwrap();
+        assert!(matches!(decision.action, DeprecationActionResult::Warn));
+        assert!(matches!(
+            decision.intended,
+            Some(DeprecationActionResult::Block { status_code: 410 })
+        ));
+        assert!(matches!(
+            action("/api/v1/orders"),
+            DeprecationActionResult::Warn
+        ));
+        assert!(!agent.handle().enforcement().enabled);
+
+        // The intended block is still counted
+        agent.process_and_record("/api/v1/posts", "GET");
+        let usage = agent.usage_snapshot();
+        let posts = usage
+            .iter()
+            .find(|usage| usage.id == "removed-posts")
+            .unwrap();
+        assert_eq!(posts.blocked, 1);
+
+        // And back on
+        agent.set_enforcement(true);
+        assert!(matches!(
+            action("/api/v1/posts"),
+            DeprecationActionResult::Block { .. }
+        ));
+        assert!(matches!(
+            action("/api/v1/orders"),
+            DeprecationActionResult::Redirect { .. }
+        ));
+
+        // The setting switches it off as well
+        let mut config = test_config();
+        config.settings.enforcement_enabled = false;
+        agent.update_config(config).unwrap();
+        assert!(matches!(
+            action("/api/v1/posts"),
+            DeprecationActionResult::Warn
         ));
+        assert_eq!(
+            agent.handle().enforcement(),
+            EnforcementStatus {
+                enabled: false,
+                switch: true,
+                setting: false,
+            }
+        );
     }
 
     #[test]
-    fn test_process_redirect_endpoint() {
-        let config = test_config();
+    fn test_warn_once_cookie() {
+        let mut config = test_config();
+        let warn_once = WarnOnce {
+            cookie_name: "users_deprecation_seen".to_string(),
+            ttl_secs: 3600,
+        };
+        config.endpoints[0].warn_once = Some(warn_once.clone());
+        config.endpoints[1].warn_once = Some(warn_once);
         let agent = ApiDeprecationAgent::new(config);
+        let now: DateTime<Utc> = "2030-01-01T00:00:00Z".parse().unwrap();
 
-        let decision = agent.process_request("/api/v1/orders", "GET", Some("page=1"));
-        assert!(decision.is_some());
+        // The first request gets the headers and the marker
+        let decision = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        let NoticeDelivery::Mark(set_cookie) = notice_delivery(&decision, None, now) else {
+            panic!("expected a marker cookie");
+        };
+        assert_eq!(
+            set_cookie,
+            "users_deprecation_seen=1893459600; Max-Age=3600; Path=/; Secure; HttpOnly; SameSite=Lax"
+        );
 
-        let d = decision.unwrap();
-        assert_eq!(d.endpoint_id, "redirect-orders");
+        // Requests sending it back are left alone until it expires
+        let cookies = format!("theme=dark; {}", set_cookie.split(';').next().unwrap());
+        let later = now + Duration::minutes(59);
+        assert_eq!(
+            notice_delivery(&decision, Some(&cookies), later),
+            NoticeDelivery::Suppressed
+        );
+        let expired = now + Duration::hours(1);
         assert!(matches!(
-            d.action,
-            DeprecationActionResult::Redirect { status_code: 308 }
+            notice_delivery(&decision, Some(&cookies), expired),
+            NoticeDelivery::Mark(_)
         ));
-        assert_eq!(d.redirect_url, Some("/api/v2/orders?page=1".to_string()));
+        assert!(matches!(
+            notice_delivery(&decision, Some("users_deprecation_seen=yes"), now),
+            NoticeDelivery::Mark(_)
+        ));
+
+        // Blocks are never suppressed
+        let decision = agent.process_request("/api/v1/posts", "GET", None).unwrap();
+        assert!(matches!(
+            decision.action,
+            DeprecationActionResult::Block { .. }
+        ));
+        assert_eq!(
+            notice_delivery(&decision, Some(&cookies), later),
+            NoticeDelivery::Always
+        );
+
+        // Without warn_once every request is told
+        let decision = agent
+            .process_request("/api/v1/orders", "GET", None)
+            .unwrap();
+        assert_eq!(
+            notice_delivery(&decision, Some(&cookies), later),
+            NoticeDelivery::Always
+        );
     }
 
     #[test]
-    fn test_non_deprecated_endpoint() {
-        let config = test_config();
+    fn test_original_path_header() {
+        let mut config = test_config();
+        config.settings.original_path_header = Some("X-Original-URI".to_string());
         let agent = ApiDeprecationAgent::new(config);
+        let settings = agent.state.load().config.settings.clone();
 
-        let decision = agent.process_request("/api/v2/users", "GET", None);
-        assert!(decision.is_none());
+        // An upstream rewrite turned /api/v1/users into /internal/users
+        let headers = HashMap::from([("X-Original-URI", "/api/v1/users?page=2")]);
+        let path = settings.match_path("/internal/users", |name| headers.get(name).copied());
+        assert_eq!(path, "/api/v1/users");
+        let decision = agent.process_request(path, "GET", None).unwrap();
+        assert_eq!(decision.endpoint_id, "legacy-users");
+
+        // Without the header (or with something that isn't a path) the
+        // request path is matched
+        let path = settings.match_path("/internal/users", |_| None);
+        assert!(agent.process_request(path, "GET", None).is_none());
+        let path = settings.match_path("/api/v1/posts", |_| Some("users"));
+        assert_eq!(
+            agent
+                .process_request(path, "GET", None)
+                .unwrap()
+                .endpoint_id,
+            "removed-posts"
+        );
+
+        // Unset, the header is ignored
+        let path = GlobalSettings::default()
+            .match_path("/internal/users", |name| headers.get(name).copied());
+        assert_eq!(path, "/internal/users");
+    }
+
+    #[tokio::test]
+    async fn test_lifetime_summary_on_shutdown() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        for (path, method) in [
+            ("/api/v1/users", "GET"),
+            ("/api/v1/users", "POST"),
+            ("/api/v1/posts", "GET"),
+            ("/api/v1/orders", "GET"),
+            ("/api/v1/orders", "GET"),
+        ] {
+            let decision = agent.process_request(path, method, None).unwrap();
+            agent.record_outcome(&decision, path);
+        }
+
+        agent.on_shutdown(ShutdownReason::Graceful, 1000).await;
+
+        let summary = agent.lifetime_summary();
+        assert_eq!(
+            (summary.requests, summary.redirects, summary.blocked),
+            (5, 2, 1)
+        );
+        assert_eq!(summary.endpoints["legacy-users"], 2);
+        assert_eq!(summary.endpoints["removed-posts"], 1);
+        assert_eq!(summary.endpoints["redirect-orders"], 2);
     }
 
     #[test]
-    fn test_method_filtering() {
-        let config = test_config();
+    fn test_usage_snapshot_survives_reload() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        agent.process_request("/api/v1/users", "GET", None).unwrap();
+
+        let mut config = test_config();
+        config.endpoints[0].id = "renamed-users".to_string();
+        config.endpoints[0].metrics_id = Some("legacy-users".to_string());
+        agent.update_config(config).unwrap();
+        agent.process_request("/api/v1/users", "GET", None).unwrap();
+
+        let snapshot = agent.usage_snapshot();
+        assert_eq!(snapshot[0].id, "renamed-users");
+        assert_eq!(snapshot[0].requests, 2);
+        assert!(snapshot[1].last_access.is_none());
+    }
+
+    #[test]
+    fn test_upstream_errors_and_ratio() {
+        let agent = ApiDeprecationAgent::new(test_config());
+
+        for status in [200, 200, 404, 500, 503] {
+            agent.record_response("/api/v1/users", "GET", status);
+        }
+        // Preflights and unmatched paths are not counted
+        agent.record_response("/api/v1/users", "OPTIONS", 500);
+        agent.record_response("/api/v2/users", "GET", 500);
+
+        let snapshot = agent.usage_snapshot();
+        assert_eq!(snapshot[0].errors, 2, "only 5xx counts as an error");
+        assert_eq!(snapshot[0].error_ratio, None, "not computed yet");
+
+        let state = agent.state.load();
+        refresh_error_ratios(&state.config, &agent.usage, agent.metrics().as_ref());
+        assert_eq!(agent.usage_snapshot()[0].error_ratio, Some(0.4));
+
+        // The ratio covers only the interval since the previous refresh
+        refresh_error_ratios(&state.config, &agent.usage, agent.metrics().as_ref());
+        assert_eq!(agent.usage_snapshot()[0].error_ratio, Some(0.0));
+        assert_eq!(agent.usage_snapshot()[0].errors, 2);
+
+        let report = agent.metrics_report().unwrap();
+        let errors = report
+            .counters
+            .iter()
+            .find(|c| c.name == "api_deprecation_errors_total")
+            .unwrap();
+        assert_eq!(errors.value, 2);
+        assert_eq!(errors.labels["endpoint_id"], "legacy-users");
+
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains("errors_total{endpoint_id=\"legacy-users\"} 2"));
+            assert!(output.contains("error_ratio{endpoint_id=\"legacy-users\"} 0"));
+        }
+    }
+
+    #[test]
+    fn test_traffic_spike_alerts_once() {
+        // The global factor is the default threshold
+        let mut config = test_config();
+        config.metrics.usage_spike_factor = Some(3.0);
         let agent = ApiDeprecationAgent::new(config);
+        let state = agent.state.load();
+        let metrics_id: Arc<str> = Arc::from("legacy-users");
 
-        // GET should match
-        let decision = agent.process_request("/api/v1/users", "GET", None);
-        assert!(decision.is_some());
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .without_time()
+            .with_writer(move || writer.clone())
+            .finish();
 
-        // DELETE should not match (only GET, POST configured)
-        let decision = agent.process_request("/api/v1/users", "DELETE", None);
-        assert!(decision.is_none());
+        tracing::subscriber::with_default(subscriber, || {
+            // Steady at 2 req/s, then 10 req/s for three intervals
+            for requests in [20, 20, 20, 20, 100, 100, 100] {
+                for _ in 0..requests {
+                    agent.usage.record_request(&metrics_id, Utc::now());
+                }
+                refresh_spikes(
+                    &state.config,
+                    &agent.usage,
+                    agent.metrics().as_ref(),
+                    10,
+                    Utc::now(),
+                );
+            }
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let spikes: Vec<_> = output
+            .lines()
+            .filter(|line| line.contains("spiked"))
+            .collect();
+        assert_eq!(spikes.len(), 1, "{}", output);
+        assert!(spikes[0].contains("endpoint_id=legacy-users"), "{}", output);
+        assert!(spikes[0].contains("rate=10.0"), "{}", output);
+
+        #[cfg(feature = "prometheus")]
+        assert!(agent
+            .metrics()
+            .encode()
+            .contains("traffic_spikes_total{endpoint_id=\"legacy-users\"} 1"));
+    }
+
+    #[test]
+    fn test_unique_consumers_per_day() {
+        let clock = Arc::new(FixedClock::new(
+            "2030-01-01T22:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        ));
+        let agent = ApiDeprecationAgent::new(test_config()).with_clock(clock.clone());
+        let request = |client: Option<&str>| {
+            agent.process_client_request("/api/v1/users", "GET", None, client);
+        };
+        let refresh = || {
+            let state = agent.state.load();
+            refresh_unique_consumers(
+                &state.config,
+                &agent.usage,
+                agent.metrics().as_ref(),
+                clock.now(),
+            );
+        };
+
+        for client in ["a", "b", "a", "c"] {
+            request(Some(client));
+        }
+        // Unidentified requests count as requests, not consumers
+        request(None);
+        refresh();
+
+        let users = &agent.usage_snapshot()[0];
+        assert_eq!(users.requests, 5);
+        assert_eq!(users.unique_consumers_today, 3);
+        assert_eq!(users.unique_consumers_yesterday, None);
+        #[cfg(feature = "prometheus")]
+        assert!(agent
+            .metrics()
+            .encode()
+            .contains("unique_consumers_today{endpoint_id=\"legacy-users\"} 3"));
+
+        // The first refresh after midnight keeps the final count as yesterday's
+        clock.advance(Duration::hours(3));
+        refresh();
+        request(Some("c"));
+
+        let users = &agent.usage_snapshot()[0];
+        assert_eq!(users.unique_consumers_today, 1);
+        assert_eq!(users.unique_consumers_yesterday, Some(3));
+        #[cfg(feature = "prometheus")]
+        {
+            let encoded = agent.metrics().encode();
+            assert!(encoded.contains("unique_consumers_today{endpoint_id=\"legacy-users\"} 0"));
+            assert!(encoded.contains("unique_consumers_yesterday{endpoint_id=\"legacy-users\"} 3"));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
+    fn test_session_window_counts_clients_once() {
+        let mut config = test_config();
+        config.metrics.session_window_secs = Some(1800);
+        let clock = Arc::new(FixedClock::new(
+            "2030-01-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        ));
+        let agent = ApiDeprecationAgent::new(config).with_clock(clock.clone());
+        let request = |client: Option<&str>| {
+            agent.process_client_request("/api/v1/users", "GET", None, client);
+        };
+        let counts = || {
+            let encoded = agent.metrics().encode();
+            let count = |metric: &str| {
+                let series = format!(
+                    "{metric}{{endpoint_id=\"legacy-users\",method=\"GET\",path=\"/api/v1/users\",status=\"deprecated\"}} "
+                );
+                encoded
+                    .lines()
+                    .find_map(|line| line.strip_prefix(series.as_str()))
+                    .map_or(0, |value| value.parse::<u64>().unwrap())
+            };
+            (
+                count("zentinel_api_deprecation_requests_total"),
+                count("zentinel_api_deprecation_requests_raw_total"),
+            )
+        };
+
+        // Repeated requests within the window count once
+        for _ in 0..3 {
+            request(Some("a"));
+            clock.advance(Duration::minutes(5));
+        }
+        assert_eq!(counts(), (1, 3));
+
+        // Other clients have their own sessions, unidentified requests none
+        request(Some("b"));
+        request(None);
+        request(None);
+        assert_eq!(counts(), (4, 6));
+
+        // A new window starts a new session
+        clock.advance(Duration::minutes(20));
+        request(Some("a"));
+        request(Some("a"));
+        assert_eq!(counts(), (5, 8));
+
+        // A reload applies new client_state limits: sessions start over, and
+        // with room for one client they evict each other
+        let mut config = test_config();
+        config.metrics.session_window_secs = Some(1800);
+        config.settings.client_state.max_entries = 1;
+        agent.update_config(config).unwrap();
+        request(Some("a"));
+        request(Some("b"));
+        request(Some("a"));
+        assert_eq!(counts(), (8, 11));
+    }
+
+    #[test]
+    fn test_usage_spike_over_weekly_average() {
+        // An endpoint's own threshold applies to the daily comparison too
+        let mut config = test_config();
+        config.endpoints[0].spike_threshold = Some(3.0);
+        config.endpoints[1].spike_threshold = Some(3.0);
+        let agent = ApiDeprecationAgent::new(config);
+        let state = agent.state.load();
+        let users: Arc<str> = Arc::from("legacy-users");
+        let posts: Arc<str> = Arc::from("removed-posts");
+        let start: DateTime<Utc> = "2030-01-01T00:00:00Z".parse().unwrap();
+        let at = |hour: i64| start + chrono::Duration::hours(hour);
+        let record = |metrics_id: &Arc<str>, hour: i64, requests: usize| {
+            for _ in 0..requests {
+                agent.usage.record_request(metrics_id, at(hour));
+            }
+        };
+
+        // Eight days at 10 requests an hour; posts sees one request a day
+        for hour in 0..8 * 24 {
+            record(&users, hour, 10);
+            if hour % 24 == 0 {
+                record(&posts, hour, 1);
+            }
+        }
+        let now = at(8 * 24 - 1) + chrono::Duration::minutes(30);
+        let trend = agent
+            .usage
+            .get("legacy-users")
+            .unwrap()
+            .usage_trend(now, None, 0.0);
+        assert_eq!(trend.last_day, 240);
+        assert_eq!(trend.daily_average, Some(240.0));
+
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .without_time()
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let refresh = || {
+                refresh_spikes(
+                    &state.config,
+                    &agent.usage,
+                    agent.metrics().as_ref(),
+                    60,
+                    now,
+                )
+            };
+            refresh();
+
+            // A new integration in the last hour: reported once, however
+            // often the trend is refreshed while it lasts
+            record(&users, 8 * 24 - 1, 1000);
+            // 50 requests against a 1/day baseline is no spike to report
+            record(&posts, 8 * 24 - 1, 50);
+            refresh();
+            refresh();
+        });
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let spikes: Vec<_> = output
+            .lines()
+            .filter(|line| line.contains("7-day average"))
+            .collect();
+        assert_eq!(spikes.len(), 1, "{}", output);
+        assert!(spikes[0].contains("endpoint_id=legacy-users"), "{}", output);
+        assert!(spikes[0].contains("last_day=1240"), "{}", output);
+
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains("usage_spike_detected_total{endpoint_id=\"legacy-users\"} 1"));
+            assert!(!output.contains("usage_spike_detected_total{endpoint_id=\"removed-posts\"}"));
+            assert!(output.contains("usage_daily_average{endpoint_id=\"legacy-users\"} 240"));
+            assert!(output.contains("usage_daily_average{endpoint_id=\"removed-posts\"} 1"));
+        }
+    }
+
+    #[cfg(feature = "redis-store")]
+    #[tokio::test]
+    async fn test_shared_store_outage_counts_locally() {
+        let mut config = test_config();
+        config.metrics.shared_store = Some(crate::config::SharedStoreConfig {
+            // Nothing listens on port 1, so every sync fails
+            url: "redis://127.0.0.1:1/".to_string(),
+            key_prefix: "test".to_string(),
+            flush_interval_secs: 1,
+        });
+        let agent = ApiDeprecationAgent::new(config);
+        let task = agent.start_shared_store().unwrap();
+
+        agent.process_request("/api/v1/users", "GET", None).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+
+        assert!(!agent.health_status().is_healthy());
+        let snapshot = agent.usage_snapshot();
+        assert_eq!(snapshot[0].requests, 1);
+        assert!(snapshot[0].cluster.is_none());
+        task.abort();
+    }
+
+    #[test]
+    fn test_manifest_follows_reload() {
+        let mut config = test_config();
+        config.settings.manifest_path = Some("/.well-known/api-deprecations".to_string());
+        let agent = ApiDeprecationAgent::new(config.clone());
+
+        let fetch = || {
+            let response = agent
+                .manifest_response("/.well-known/api-deprecations", "GET", None)
+                .unwrap();
+            let body: serde_json::Value = serde_json::from_str(&response.body.unwrap()).unwrap();
+            let etag = response
+                .headers
+                .into_iter()
+                .find(|(name, _)| *name == "ETag")
+                .unwrap()
+                .1;
+            (body, etag)
+        };
+
+        let (body, etag) = fetch();
+        assert_eq!(body["endpoints"].as_array().unwrap().len(), 3);
+
+        config.endpoints.pop();
+        agent.update_config(config).unwrap();
+
+        let (body, new_etag) = fetch();
+        assert_eq!(body["endpoints"].as_array().unwrap().len(), 2);
+        assert_ne!(etag, new_etag);
+
+        // The load time comes from the agent's clock
+        let now: DateTime<Utc> = "2030-03-01T12:00:00Z".parse().unwrap();
+        let clock = Arc::new(FixedClock::new(now));
+        let mut config = test_config();
+        config.settings.manifest_path = Some("/.well-known/api-deprecations".to_string());
+        let agent = ApiDeprecationAgent::new(config.clone()).with_clock(clock.clone());
+        let fetch = || {
+            agent
+                .manifest_response("/.well-known/api-deprecations", "GET", None)
+                .unwrap()
+        };
+        let body: serde_json::Value = serde_json::from_str(&fetch().body.unwrap()).unwrap();
+        assert_eq!(body["updated_at"], "2030-03-01T12:00:00Z");
+
+        clock.advance(Duration::days(1));
+        agent.update_config(config).unwrap();
+        let response = fetch();
+        assert!(response
+            .headers
+            .contains(&("Last-Modified", "Sat, 02 Mar 2030 12:00:00 GMT".to_string())));
+
+        // Not served when the path isn't configured
+        agent.update_config(test_config()).unwrap();
+        assert!(agent
+            .manifest_response("/.well-known/api-deprecations", "GET", None)
+            .is_none());
     }
 
     #[test]
@@ -603,6 +5144,204 @@ endpoints:
     }
 
     #[test]
+    fn test_precomputed_state_is_shared() {
+        let config = test_config();
+        let agent = ApiDeprecationAgent::new(config);
+
+        // Repeated matches hand out the same precomputed allocations
+        let first = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        let second = agent
+            .process_request("/api/v1/users", "POST", None)
+            .unwrap();
+        assert!(Arc::ptr_eq(&first.headers, &second.headers));
+        assert!(Arc::ptr_eq(&first.gone_body, &second.gone_body));
+
+        let blocked = agent.process_request("/api/v1/posts", "GET", None).unwrap();
+        assert!(blocked.gone_body.contains("endpoint_removed"));
+    }
+
+    #[test]
+    fn test_allow_reasons() {
+        let yaml = r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+  - id: posts-v1
+    path: /api/v1/posts
+    status: removed
+    confirmed: false
+  - id: comments-v1
+    path: /api/v1/comments
+    status: removed
+    confirmed: true
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+        let reason = |path: &str, method: &str| {
+            agent
+                .process_request(path, method, None)
+                .unwrap()
+                .allow_reason
+        };
+
+        assert_eq!(
+            reason("/api/v1/users", "GET"),
+            Some(AllowReason::WarnAction)
+        );
+        assert_eq!(reason("/api/v1/posts", "GET"), Some(AllowReason::Shadowed));
+        assert_eq!(reason("/api/v1/comments", "GET"), None);
+        assert_eq!(
+            reason("/api/v1/comments", "OPTIONS"),
+            Some(AllowReason::PreflightSkip)
+        );
+
+        agent.set_enforcement(false);
+        assert_eq!(
+            reason("/api/v1/comments", "GET"),
+            Some(AllowReason::EnforcementDisabled)
+        );
+
+        // Counted by reason
+        #[cfg(feature = "prometheus")]
+        {
+            agent.process_and_record("/api/v1/comments", "GET");
+            agent.process_and_record("/api/v1/posts", "GET");
+            let output = agent.metrics().encode();
+            assert!(output.contains(
+                "allowed_total{endpoint_id=\"comments-v1\",reason=\"enforcement_disabled\"} 1"
+            ));
+            assert!(
+                output.contains("allowed_total{endpoint_id=\"posts-v1\",reason=\"shadowed\"} 1")
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_config() {
+        let agent = ApiDeprecationAgent::new(test_config());
+        assert!(agent
+            .process_request("/api/v3/things", "GET", None)
+            .is_none());
+        let initial_hash = agent.handle().health().config_hash;
+        assert_eq!(initial_hash.len(), 16);
+
+        let yaml = r#"
+endpoints:
+  - id: legacy-things
+    path: /api/v3/things
+    sunset_at: "2030-01-01T00:00:00Z"
+"#;
+        agent
+            .update_config(serde_yaml::from_str(yaml).unwrap())
+            .unwrap();
+
+        let d = agent
+            .process_request("/api/v3/things", "GET", None)
+            .unwrap();
+        assert_eq!(d.endpoint_id, "legacy-things");
+        assert!(agent
+            .process_request("/api/v1/users", "GET", None)
+            .is_none());
+
+        // Sunset gauges follow the new configuration
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains("legacy-things"));
+            assert!(!output.contains("legacy-users"));
+        }
+
+        // The hash follows the swap, in health and in `config_info`
+        let hash = agent.handle().health().config_hash;
+        assert_ne!(hash, initial_hash);
+        #[cfg(feature = "prometheus")]
+        {
+            let output = agent.metrics().encode();
+            assert!(output.contains(&format!("config_info{{hash=\"{}\"", hash)));
+            assert!(!output.contains(&initial_hash));
+        }
+    }
+
+    #[test]
+    fn test_update_config_rejects_invalid() {
+        let agent = ApiDeprecationAgent::new(test_config());
+
+        let yaml = r#"
+endpoints:
+  - id: broken-redirect
+    path: /api/v1/users
+    action:
+      type: redirect
+"#;
+        assert!(agent
+            .update_config(serde_yaml::from_str(yaml).unwrap())
+            .is_err());
+
+        // The active configuration is untouched
+        let d = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(d.endpoint_id, "legacy-users");
+    }
+
+    #[test]
+    fn test_concurrent_config_swaps() {
+        fn config_for(name: &str) -> ApiDeprecationConfig {
+            let yaml = format!(
+                r#"
+endpoints:
+  - id: {name}-users
+    path: /api/v1/users
+    headers:
+      X-Config: {name}
+"#
+            );
+            serde_yaml::from_str(&yaml).unwrap()
+        }
+
+        let agent = ApiDeprecationAgent::new(config_for("a"));
+        let done = AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..500 {
+                    let name = if i % 2 == 0 { "b" } else { "a" };
+                    agent.update_config(config_for(name)).unwrap();
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let d = agent.process_request("/api/v1/users", "GET", None).unwrap();
+                        // Endpoint and headers always come from the same configuration
+                        let name = &d.headers["X-Config"];
+                        assert_eq!(d.endpoint_id, format!("{}-users", name));
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
+    fn test_metrics_id_label() {
+        let yaml = r#"
+endpoints:
+  - id: users-v1-renamed
+    path: /api/v1/users
+    metrics_id: legacy-users
+"#;
+        let agent = ApiDeprecationAgent::from_yaml(yaml).unwrap();
+
+        let d = agent.process_request("/api/v1/users", "GET", None).unwrap();
+        assert_eq!(d.endpoint_id, "users-v1-renamed");
+
+        let output = agent.metrics().encode();
+        assert!(output.contains("endpoint_id=\"legacy-users\""));
+        assert!(!output.contains("users-v1-renamed"));
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
     fn test_metrics_tracking() {
         let config = test_config();
         let agent = ApiDeprecationAgent::new(config);
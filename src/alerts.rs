@@ -0,0 +1,251 @@
+//! Prometheus alerting rules generated from the configuration.
+//!
+//! The `alerts` CLI subcommand prints a rules file with two alerts for every
+//! endpoint that has a sunset date:
+//!
+//! - `ApiDeprecationPastSunsetTraffic`: the sunset has passed and the endpoint
+//!   still receives more than `rate_threshold` requests per minute.
+//! - `ApiDeprecationSunsetTrafficNotDecreasing`: the sunset is at most
+//!   `lead_days` away and traffic is not lower than a day earlier.
+//!
+//! Both rely on the agent's own series (`requests_total`, or
+//! `requests_raw_total` with `metrics.session_window_secs`, and
+//! `days_until_sunset` under the configured metrics prefix), so the rules
+//! don't embed dates and stay valid when a sunset is moved. Each alert
+//! carries the endpoint's `labels` (e.g. `team`) and its documentation URL.
+
+use crate::config::{ApiDeprecationConfig, DeprecatedEndpoint, DocumentationLinks};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+/// Options for [`alert_rules`].
+#[derive(Debug, Clone)]
+pub struct AlertOptions {
+    /// Requests per minute above which traffic past the sunset alerts
+    pub rate_threshold: f64,
+    /// Days before the sunset from which steady traffic alerts
+    pub lead_days: i64,
+    /// How long a condition must hold before the alert fires (e.g. `15m`)
+    pub for_duration: String,
+}
+
+impl Default for AlertOptions {
+    fn default() -> Self {
+        Self {
+            rate_threshold: 1.0,
+            lead_days: 7,
+            for_duration: "15m".to_string(),
+        }
+    }
+}
+
+/// A Prometheus rules file.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleFile {
+    pub groups: Vec<RuleGroup>,
+}
+
+/// A named group of rules.
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleGroup {
+    pub name: String,
+    pub rules: Vec<AlertRule>,
+}
+
+/// A single alerting rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertRule {
+    pub alert: String,
+    pub expr: String,
+    #[serde(rename = "for")]
+    pub for_duration: String,
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl RuleFile {
+    /// Render as YAML, the format Prometheus loads rules from.
+    pub fn to_yaml(&self) -> String {
+        serde_yaml::to_string(self).expect("rule files serialize to YAML")
+    }
+}
+
+/// Build the alerting rules for a compiled configuration. Rules sharing a
+/// `metrics_id` share series, so only the first of them gets alerts.
+pub fn alert_rules(config: &ApiDeprecationConfig, options: &AlertOptions) -> RuleFile {
+    let prefix = &config.metrics.prefix;
+    let series = config.metrics.request_series();
+    let mut seen = Vec::new();
+    let mut rules = Vec::new();
+
+    for endpoint in &config.endpoints {
+        if endpoint.sunset_at.is_none() || seen.contains(&endpoint.metrics_id()) {
+            continue;
+        }
+        seen.push(endpoint.metrics_id());
+
+        let id = endpoint.metrics_id();
+        let requests = |window: &str, offset: &str| {
+            format!(r#"sum(rate({series}{{endpoint_id="{id}"}}[{window}]{offset}))"#)
+        };
+        let days = format!(r#"max({prefix}_days_until_sunset{{endpoint_id="{id}"}})"#);
+
+        rules.push(AlertRule {
+            alert: "ApiDeprecationPastSunsetTraffic".to_string(),
+            expr: format!(
+                "{} * 60 > {} and on() {days} < 0",
+                requests("5m", ""),
+                options.rate_threshold
+            ),
+            for_duration: options.for_duration.clone(),
+            labels: labels(endpoint, "critical"),
+            annotations: annotations(
+                endpoint,
+                format!("{id} is past its sunset and still receiving traffic"),
+                format!(
+                    "{} is past its sunset date and received more than {} requests per minute over the last 5 minutes.",
+                    endpoint.path.primary(),
+                    options.rate_threshold
+                ),
+            ),
+        });
+        rules.push(AlertRule {
+            alert: "ApiDeprecationSunsetTrafficNotDecreasing".to_string(),
+            expr: format!(
+                "{} >= {} and on() {days} <= {} and on() {days} >= 0",
+                requests("1h", ""),
+                requests("1h", " offset 1d"),
+                options.lead_days
+            ),
+            for_duration: options.for_duration.clone(),
+            labels: labels(endpoint, "warning"),
+            annotations: annotations(
+                endpoint,
+                format!("{id} sunsets soon and its traffic is not decreasing"),
+                format!(
+                    "{} sunsets within {} days and its request rate is not lower than a day ago.",
+                    endpoint.path.primary(),
+                    options.lead_days
+                ),
+            ),
+        });
+    }
+
+    RuleFile {
+        groups: vec![RuleGroup {
+            name: "api-deprecation".to_string(),
+            rules,
+        }],
+    }
+}
+
+fn labels(endpoint: &DeprecatedEndpoint, severity: &str) -> BTreeMap<String, String> {
+    let mut labels = endpoint.labels.clone();
+    labels.insert("endpoint_id".to_string(), endpoint.metrics_id().to_string());
+    labels.insert("severity".to_string(), severity.to_string());
+    labels
+}
+
+fn annotations(
+    endpoint: &DeprecatedEndpoint,
+    summary: String,
+    description: String,
+) -> BTreeMap<String, String> {
+    let mut annotations = BTreeMap::from([
+        ("summary".to_string(), summary),
+        ("description".to_string(), description),
+    ]);
+    if let Some(url) = endpoint
+        .documentation_url
+        .as_ref()
+        .and_then(DocumentationLinks::primary_url)
+    {
+        annotations.insert("documentation_url".to_string(), url.to_string());
+    }
+    annotations
+}
+
+/// Check a rules file with `promtool check rules`.
+pub fn promtool_check(path: &Path) -> Result<(), String> {
+    let output = Command::new("promtool")
+        .arg("check")
+        .arg("rules")
+        .arg(path)
+        .output()
+        .map_err(|e| format!("failed to run promtool: {}", e))?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!(
+            "promtool rejected the rules:\n{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+    sunset_at: 2030-01-20
+    documentation_url: https://docs.example.com/users-v2
+    labels:
+      team: identity
+  - id: users-alias
+    path: /api/v1/people
+    metrics_id: users
+    sunset_at: 2030-01-20
+  - id: orders
+    path: /api/v1/orders
+  - id: legacy-auth
+    path: /auth/legacy
+    status: removed
+    sunset_at: 2029-06-01
+metrics:
+  prefix: acme_deprecation
+"#;
+
+    #[test]
+    fn test_alert_rules_golden() {
+        let mut config = ApiDeprecationConfig::from_yaml(CONFIG).unwrap();
+        config.compile();
+        let options = AlertOptions {
+            rate_threshold: 0.5,
+            lead_days: 14,
+            for_duration: "30m".to_string(),
+        };
+
+        let rules = alert_rules(&config, &options).to_yaml();
+        assert_eq!(rules, include_str!("../tests/golden/alerts.yaml"));
+    }
+
+    #[test]
+    fn test_alert_rules_default_options() {
+        let mut config = ApiDeprecationConfig::from_yaml(CONFIG).unwrap();
+        config.compile();
+
+        let rules = alert_rules(&config, &AlertOptions::default()).to_yaml();
+        assert_eq!(rules, include_str!("../tests/golden/alerts-default.yaml"));
+    }
+
+    #[test]
+    fn test_alert_rules_with_sessions() {
+        let mut config = ApiDeprecationConfig::from_yaml(CONFIG).unwrap();
+        config.metrics.session_window_secs = Some(1800);
+        config.compile();
+
+        // requests_total counts sessions, so traffic alerts use every request
+        let rules = alert_rules(&config, &AlertOptions::default());
+        let past_sunset = &rules.groups[0].rules[0];
+        assert!(past_sunset.expr.starts_with(
+            r#"sum(rate(acme_deprecation_requests_raw_total{endpoint_id="users"}[5m]))"#
+        ));
+    }
+}
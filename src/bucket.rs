@@ -0,0 +1,66 @@
+//! Deterministic client bucketing.
+//!
+//! Percentage-based rollouts place each client in one of 100 buckets
+//! from a hash of its client id. The hash must give the same answer on every
+//! agent and across restarts, so it is a fixed algorithm rather than the
+//! randomly seeded `DefaultHasher`: 64-bit FNV-1a over the seed and the
+//! client id, followed by the SplitMix64 finalizer to spread similar ids
+//! evenly. Changing the seed (`settings.bucket_seed`) reshuffles every client.
+
+/// Number of buckets clients are spread over.
+pub const BUCKETS: u8 = 100;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Stable 64-bit hash of a client id under a seed.
+pub fn client_hash(client_id: &str, seed: u64) -> u64 {
+    let mut hash = FNV_OFFSET_BASIS;
+    for byte in seed.to_le_bytes().iter().chain(client_id.as_bytes()) {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    // SplitMix64 finalizer
+    hash ^= hash >> 30;
+    hash = hash.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash ^= hash >> 27;
+    hash = hash.wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Bucket (0-99) of a client id under a seed.
+pub fn client_bucket(client_id: &str, seed: u64) -> u8 {
+    (client_hash(client_id, seed) % u64::from(BUCKETS)) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_is_fixed() {
+        // Pinned values: changing the algorithm would move clients between buckets
+        assert_eq!(client_hash("", 0), 0x813f_0174_a236_7c13);
+        assert_eq!(client_bucket("client-42", 0), 3);
+        assert_eq!(client_bucket("client-42", 1234), 93);
+    }
+
+    #[test]
+    fn test_seed_reshuffles_buckets() {
+        let moved = (0..100)
+            .map(|i| format!("client-{i}"))
+            .filter(|id| client_bucket(id, 1) != client_bucket(id, 2))
+            .count();
+        assert!(moved > 80, "only {moved} of 100 clients changed bucket");
+    }
+
+    #[test]
+    fn test_buckets_are_spread() {
+        let mut counts = [0u32; BUCKETS as usize];
+        for i in 0..10_000 {
+            counts[client_bucket(&format!("client-{i}"), 7) as usize] += 1;
+        }
+        assert!(counts.iter().all(|&count| (50..=150).contains(&count)));
+    }
+}
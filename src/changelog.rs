@@ -0,0 +1,483 @@
+//! Deprecation changelog across configuration snapshots.
+//!
+//! The `changelog` CLI subcommand reads an ordered list of configurations
+//! (or a directory of dated snapshots, in file name order) and reports what
+//! changed in the deprecation plans from each one to the next: endpoints
+//! newly deprecated, sunsets announced or moved (extensions flagged), endpoints
+//! transitioned to removed and rules deleted. Each pair of neighbouring
+//! snapshots is compared with [`diff`](crate::diff::diff); the first snapshot
+//! is the baseline.
+//!
+//! Entries are ordered by snapshot, then kind, then rule id, so a generated
+//! changelog can be committed and diffed itself.
+
+use crate::config::{ApiDeprecationConfig, ConfigError, DeprecatedEndpoint, DeprecationStatus};
+use crate::diff::diff;
+use crate::history::SunsetMove;
+use chrono::{DateTime, SecondsFormat, Timelike, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A configuration at one point of its history.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Name of the snapshot in the changelog: the file name without its
+    /// extension for directory entries, the path otherwise
+    pub label: String,
+    pub config: ApiDeprecationConfig,
+}
+
+/// Load, validate and compile the snapshots in `paths`, in order. A single
+/// directory stands for its `.yaml` and `.yml` files, sorted by file name so
+/// dated names (`2025-01-15.yaml`) come out oldest first.
+///
+/// A snapshot that doesn't parse or validate fails the whole load with a
+/// [`ConfigError::File`] naming it.
+pub fn load_snapshots(paths: &[PathBuf]) -> Result<Vec<Snapshot>, ConfigError> {
+    let files = match paths {
+        [dir] if dir.is_dir() => snapshot_files(dir)?
+            .into_iter()
+            .map(|path| {
+                let label = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| path.display().to_string());
+                (label, path)
+            })
+            .collect(),
+        paths => paths
+            .iter()
+            .map(|path| (path.display().to_string(), path.clone()))
+            .collect::<Vec<_>>(),
+    };
+    files
+        .into_iter()
+        .map(|(label, path)| {
+            let mut config = load_snapshot(&path)?;
+            config.compile();
+            Ok(Snapshot { label, config })
+        })
+        .collect()
+}
+
+/// Read, parse and validate one snapshot, naming the file in any error
+/// other than a failed read (which names it already).
+fn load_snapshot(path: &Path) -> Result<ApiDeprecationConfig, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    ApiDeprecationConfig::from_yaml(&content)
+        .and_then(|config| {
+            config.validate()?;
+            Ok(config)
+        })
+        .map_err(|source| ConfigError::File {
+            path: path.to_path_buf(),
+            source: Box::new(source),
+        })
+}
+
+/// The YAML files of a snapshot directory, sorted by file name.
+fn snapshot_files(dir: &Path) -> Result<Vec<PathBuf>, ConfigError> {
+    let io_error = |source| ConfigError::Io {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        let yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yaml" || extension == "yml");
+        if yaml && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    Ok(files)
+}
+
+/// Kind of a changelog entry, in the order entries of a snapshot are listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    /// A rule added, or a scheduled rule now deprecated
+    Deprecated,
+    /// A sunset date set on a rule that had none
+    SunsetAnnounced,
+    /// A sunset date moved
+    SunsetMoved,
+    /// A sunset date taken back
+    SunsetWithdrawn,
+    /// A rule now (or added as) removed
+    Removed,
+    /// A rule no longer configured
+    RuleDeleted,
+}
+
+impl ChangeKind {
+    fn heading(self) -> &'static str {
+        match self {
+            Self::Deprecated => "Newly deprecated",
+            Self::SunsetAnnounced => "Sunsets announced",
+            Self::SunsetMoved => "Sunsets moved",
+            Self::SunsetWithdrawn => "Sunsets withdrawn",
+            Self::Removed => "Removed",
+            Self::RuleDeleted => "Rules deleted",
+        }
+    }
+}
+
+/// One change of a rule, as of the snapshot it first appears in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangelogEntry {
+    /// Label of the snapshot
+    pub snapshot: String,
+    pub kind: ChangeKind,
+    pub id: String,
+    pub path: String,
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset_at: Option<DateTime<Utc>>,
+    /// The sunset date before a move or withdrawal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_sunset_at: Option<DateTime<Utc>>,
+    /// Direction of a sunset move
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset_move: Option<SunsetMove>,
+}
+
+impl ChangelogEntry {
+    fn new(snapshot: &str, kind: ChangeKind, endpoint: &DeprecatedEndpoint) -> Self {
+        Self {
+            snapshot: snapshot.to_string(),
+            kind,
+            id: endpoint.id.clone(),
+            path: endpoint.path.to_string(),
+            status: endpoint.status.as_str().to_string(),
+            deprecated_at: endpoint.deprecated_at,
+            sunset_at: endpoint.sunset_at,
+            previous_sunset_at: None,
+            sunset_move: None,
+        }
+    }
+}
+
+/// Changes across a series of snapshots.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Changelog {
+    /// Labels of the snapshots compared, oldest first
+    pub snapshots: Vec<String>,
+    pub entries: Vec<ChangelogEntry>,
+}
+
+/// Changelog of `snapshots`, comparing each with the one before it.
+pub fn changelog(snapshots: &[Snapshot]) -> Changelog {
+    let mut changelog = Changelog {
+        snapshots: snapshots
+            .iter()
+            .map(|snapshot| snapshot.label.clone())
+            .collect(),
+        entries: Vec::new(),
+    };
+    for pair in snapshots.windows(2) {
+        let mut entries = changes(&pair[0].config, &pair[1]);
+        entries.sort_by(|a, b| (a.kind, &a.id).cmp(&(b.kind, &b.id)));
+        changelog.entries.extend(entries);
+    }
+    changelog
+}
+
+/// Entries for the changes from `old` to `new`, unordered.
+fn changes(old: &ApiDeprecationConfig, new: &Snapshot) -> Vec<ChangelogEntry> {
+    let (previous, current) = (by_id(old), by_id(&new.config));
+    let label = new.label.as_str();
+    let config_diff = diff(old, &new.config);
+
+    let mut entries = Vec::new();
+    for added in &config_diff.added {
+        let endpoint = current[added.id.as_str()];
+        let kind = match endpoint.status {
+            DeprecationStatus::Removed => ChangeKind::Removed,
+            DeprecationStatus::Deprecated | DeprecationStatus::Scheduled => ChangeKind::Deprecated,
+        };
+        entries.push(ChangelogEntry::new(label, kind, endpoint));
+    }
+    for modified in &config_diff.modified {
+        let (before, endpoint) = (
+            previous[modified.id.as_str()],
+            current[modified.id.as_str()],
+        );
+        for change in &modified.changes {
+            let kind = match change.field {
+                "status" => match endpoint.status {
+                    DeprecationStatus::Removed => ChangeKind::Removed,
+                    DeprecationStatus::Deprecated => ChangeKind::Deprecated,
+                    DeprecationStatus::Scheduled => continue,
+                },
+                "sunset_at" => match (before.sunset_at, endpoint.sunset_at) {
+                    (None, _) => ChangeKind::SunsetAnnounced,
+                    (Some(_), None) => ChangeKind::SunsetWithdrawn,
+                    (Some(_), Some(_)) => ChangeKind::SunsetMoved,
+                },
+                _ => continue,
+            };
+            let mut entry = ChangelogEntry::new(label, kind, endpoint);
+            if matches!(kind, ChangeKind::SunsetMoved | ChangeKind::SunsetWithdrawn) {
+                entry.previous_sunset_at = before.sunset_at;
+                entry.sunset_move = change.sunset_move;
+            }
+            entries.push(entry);
+        }
+    }
+    for removed in &config_diff.removed {
+        entries.push(ChangelogEntry::new(
+            label,
+            ChangeKind::RuleDeleted,
+            previous[removed.id.as_str()],
+        ));
+    }
+    entries
+}
+
+fn by_id(config: &ApiDeprecationConfig) -> HashMap<&str, &DeprecatedEndpoint> {
+    config
+        .endpoints
+        .iter()
+        .map(|endpoint| (endpoint.id.as_str(), endpoint))
+        .collect()
+}
+
+/// A date as `YYYY-MM-DD` at midnight UTC, RFC 3339 otherwise.
+fn date(at: DateTime<Utc>) -> String {
+    if at.num_seconds_from_midnight() == 0 {
+        at.format("%Y-%m-%d").to_string()
+    } else {
+        at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    }
+}
+
+/// The changelog as Markdown: a section per snapshot after the first, newest
+/// first, with its entries grouped by kind.
+impl fmt::Display for Changelog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Deprecation Changelog")?;
+        if let Some(baseline) = self.snapshots.first() {
+            writeln!(f)?;
+            writeln!(f, "Changes since `{}`.", baseline)?;
+        }
+        for snapshot in self.snapshots.iter().skip(1).rev() {
+            writeln!(f)?;
+            writeln!(f, "## {}", snapshot)?;
+            let entries: Vec<&ChangelogEntry> = self
+                .entries
+                .iter()
+                .filter(|entry| &entry.snapshot == snapshot)
+                .collect();
+            if entries.is_empty() {
+                writeln!(f)?;
+                writeln!(f, "No deprecation changes.")?;
+                continue;
+            }
+            let mut kind = None;
+            for entry in entries {
+                if kind != Some(entry.kind) {
+                    kind = Some(entry.kind);
+                    writeln!(f)?;
+                    writeln!(f, "### {}", entry.kind.heading())?;
+                    writeln!(f)?;
+                }
+                write!(f, "- `{}` `{}`", entry.id, entry.path)?;
+                write_dates(f, entry)?;
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_dates(f: &mut fmt::Formatter<'_>, entry: &ChangelogEntry) -> fmt::Result {
+    match entry.kind {
+        ChangeKind::SunsetMoved | ChangeKind::SunsetWithdrawn => {
+            let to = entry.sunset_at.map_or_else(|| "none".to_string(), date);
+            let from = entry.previous_sunset_at.map_or_else(String::new, date);
+            write!(f, ": sunset {} -> {}", from, to)?;
+            match entry.sunset_move {
+                Some(SunsetMove::Extension) => write!(f, " (**extended**)"),
+                Some(SunsetMove::Acceleration) => write!(f, " (brought forward)"),
+                Some(SunsetMove::Withdrawal) | None => Ok(()),
+            }
+        }
+        _ => {
+            let mut dates = Vec::new();
+            if let Some(deprecated_at) = entry.deprecated_at {
+                dates.push(format!("deprecated {}", date(deprecated_at)));
+            }
+            if let Some(sunset_at) = entry.sunset_at {
+                dates.push(format!("sunset {}", date(sunset_at)));
+            }
+            if dates.is_empty() {
+                Ok(())
+            } else {
+                write!(f, ": {}", dates.join(", "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(label: &str, yaml: &str) -> Snapshot {
+        let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        config.compile();
+        Snapshot {
+            label: label.to_string(),
+            config,
+        }
+    }
+
+    fn snapshots() -> Vec<Snapshot> {
+        vec![
+            snapshot(
+                "2030-01-01",
+                r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: "2030-06-01T00:00:00Z"
+  - id: posts-v1
+    path: /api/v1/posts
+  - id: search-v1
+    path: /api/v1/search
+    status: scheduled
+"#,
+            ),
+            snapshot(
+                "2030-04-01",
+                r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: "2030-09-01T00:00:00Z"
+  - id: posts-v1
+    path: /api/v1/posts
+    sunset_at: "2030-12-01T00:00:00Z"
+  - id: search-v1
+    path: /api/v1/search
+  - id: orders-v1
+    path: /api/v1/orders
+    deprecated_at: "2030-03-15T00:00:00Z"
+"#,
+            ),
+            snapshot(
+                "2030-07-01",
+                r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: "2030-09-01T00:00:00Z"
+    status: removed
+  - id: orders-v1
+    path: /api/v1/orders
+    deprecated_at: "2030-03-15T00:00:00Z"
+"#,
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_changelog_entries() {
+        let changelog = changelog(&snapshots());
+        assert_eq!(
+            changelog.snapshots,
+            ["2030-01-01", "2030-04-01", "2030-07-01"]
+        );
+        let entries: Vec<(&str, ChangeKind, &str)> = changelog
+            .entries
+            .iter()
+            .map(|entry| (entry.snapshot.as_str(), entry.kind, entry.id.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("2030-04-01", ChangeKind::Deprecated, "orders-v1"),
+                ("2030-04-01", ChangeKind::Deprecated, "search-v1"),
+                ("2030-04-01", ChangeKind::SunsetAnnounced, "posts-v1"),
+                ("2030-04-01", ChangeKind::SunsetMoved, "users-v1"),
+                ("2030-07-01", ChangeKind::Removed, "users-v1"),
+                ("2030-07-01", ChangeKind::RuleDeleted, "posts-v1"),
+                ("2030-07-01", ChangeKind::RuleDeleted, "search-v1"),
+            ]
+        );
+
+        let moved = &changelog.entries[3];
+        assert_eq!(moved.sunset_move, Some(SunsetMove::Extension));
+        assert_eq!(
+            moved.previous_sunset_at,
+            Some("2030-06-01T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            moved.sunset_at,
+            Some("2030-09-01T00:00:00Z".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_changelog_markdown() {
+        let markdown = changelog(&snapshots()).to_string();
+        assert!(markdown.starts_with("# Deprecation Changelog\n\nChanges since `2030-01-01`.\n"));
+        // Newest first
+        assert!(markdown.find("## 2030-07-01").unwrap() < markdown.find("## 2030-04-01").unwrap());
+        assert!(markdown.contains(
+            "- `users-v1` `/api/v1/users`: sunset 2030-06-01 -> 2030-09-01 (**extended**)\n"
+        ));
+        assert!(markdown.contains("- `orders-v1` `/api/v1/orders`: deprecated 2030-03-15\n"));
+        assert!(markdown.contains("### Rules deleted\n\n- `posts-v1`"));
+
+        // Generating it again gives the same document
+        assert_eq!(markdown, changelog(&snapshots()).to_string());
+    }
+
+    #[test]
+    fn test_load_snapshot_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, path) in [
+            ("2030-04-01.yaml", "/api/v2/users"),
+            ("2030-01-01.yaml", "/api/v1/users"),
+        ] {
+            let yaml = format!("endpoints:\n  - id: users\n    path: {path}\n");
+            std::fs::write(dir.path().join(name), yaml).unwrap();
+        }
+        std::fs::write(dir.path().join("README.md"), "not a snapshot").unwrap();
+
+        let snapshots = load_snapshots(&[dir.path().to_path_buf()]).unwrap();
+        let labels: Vec<&str> = snapshots.iter().map(|s| s.label.as_str()).collect();
+        assert_eq!(labels, ["2030-01-01", "2030-04-01"]);
+        assert_eq!(
+            snapshots[0].config.endpoints[0].path.to_string(),
+            "/api/v1/users"
+        );
+
+        // An invalid snapshot fails the load and is named
+        let invalid = dir.path().join("2030-07-01.yaml");
+        std::fs::write(
+            &invalid,
+            "endpoints:\n  - id: users\n    path: /api/v3/users\n  - id: users\n    path: /api/v4/users\n",
+        )
+        .unwrap();
+        let err = load_snapshots(&[dir.path().to_path_buf()]).unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::File { path, source }
+                if *path == invalid && matches!(**source, ConfigError::DuplicateId { .. })),
+            "{err}"
+        );
+        assert!(err.to_string().contains("2030-07-01.yaml"), "{err}");
+    }
+}
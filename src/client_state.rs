@@ -0,0 +1,299 @@
+//! Bounded per-client state.
+//!
+//! Features that remember something per client (rate limits, budgets, unique
+//! client counts) must not grow without bound when client cardinality is high.
+//! [`ClientStore`] caps the number of entries and drops entries idle for longer
+//! than a TTL: a full store evicts its least recently used entry, and an entry
+//! past its TTL is treated as absent and eventually swept.
+//!
+//! The store is split into shards, each behind its own mutex that is only held
+//! for the duration of a single operation (never across an `.await`), so it
+//! can be shared freely between request handlers and background tasks.
+
+use crate::clock::{Clock, SystemClock};
+use chrono::{DateTime, Duration, Utc};
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Stores with at least this many entries are split into [`SHARDS`] shards;
+/// smaller ones use a single shard so the capacity is exact.
+const SHARDING_THRESHOLD: usize = 1024;
+const SHARDS: usize = 16;
+
+/// Limits for per-client state (`settings.client_state`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientStateConfig {
+    /// Maximum number of clients tracked per feature (at least 1)
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+
+    /// Seconds without activity after which a client's state is dropped
+    #[serde(default = "default_idle_ttl")]
+    pub idle_ttl_secs: u64,
+}
+
+impl Default for ClientStateConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: default_max_entries(),
+            idle_ttl_secs: default_idle_ttl(),
+        }
+    }
+}
+
+fn default_max_entries() -> usize {
+    100_000
+}
+
+fn default_idle_ttl() -> u64 {
+    3600
+}
+
+struct Entry<V> {
+    value: V,
+    last_seen: DateTime<Utc>,
+}
+
+/// Size-bounded, TTL-evicting map from client key to state.
+pub struct ClientStore<V> {
+    shards: Vec<Mutex<LruCache<String, Entry<V>>>>,
+    hasher: RandomState,
+    idle_ttl: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl<V> ClientStore<V> {
+    /// Create a store using the system clock.
+    pub fn new(config: &ClientStateConfig) -> Self {
+        Self::with_clock(config, Arc::new(SystemClock))
+    }
+
+    /// Create a store reading the time from the given clock.
+    pub fn with_clock(config: &ClientStateConfig, clock: Arc<dyn Clock>) -> Self {
+        let max_entries = config.max_entries.max(1);
+        let shards = if max_entries >= SHARDING_THRESHOLD {
+            SHARDS
+        } else {
+            1
+        };
+        let capacity = NonZeroUsize::new(max_entries.div_ceil(shards)).unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            shards: (0..shards)
+                .map(|_| Mutex::new(LruCache::new(capacity)))
+                .collect(),
+            hasher: RandomState::new(),
+            idle_ttl: Duration::seconds(i64::try_from(config.idle_ttl_secs).unwrap_or(i64::MAX)),
+            clock,
+        }
+    }
+
+    fn shard(&self, key: &str) -> &Mutex<LruCache<String, Entry<V>>> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    fn expired(&self, entry: &Entry<V>, now: DateTime<Utc>) -> bool {
+        now - entry.last_seen > self.idle_ttl
+    }
+
+    /// Update a client's state, starting from `init()` when the client is
+    /// unknown or its state has expired, and mark the client as seen.
+    pub fn update<R>(&self, key: &str, init: impl FnOnce() -> V, f: impl FnOnce(&mut V) -> R) -> R {
+        let now = self.clock.now();
+        let mut shard = self
+            .shard(key)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        match shard.get_mut(key) {
+            Some(entry) if !self.expired(entry, now) => {
+                entry.last_seen = now;
+                f(&mut entry.value)
+            }
+            _ => {
+                let mut value = init();
+                let result = f(&mut value);
+                // Evicts the least recently used entry when the shard is full
+                shard.put(
+                    key.to_string(),
+                    Entry {
+                        value,
+                        last_seen: now,
+                    },
+                );
+                result
+            }
+        }
+    }
+
+    /// A client's current state, without marking it as seen.
+    pub fn get(&self, key: &str) -> Option<V>
+    where
+        V: Clone,
+    {
+        let now = self.clock.now();
+        let shard = self
+            .shard(key)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        shard
+            .peek(key)
+            .filter(|entry| !self.expired(entry, now))
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Forget a client.
+    pub fn remove(&self, key: &str) -> Option<V> {
+        self.shard(key)
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop(key)
+            .map(|entry| entry.value)
+    }
+
+    /// Drop every entry past its idle TTL and return how many were dropped.
+    ///
+    /// Expired entries are already invisible to lookups; sweeping only frees
+    /// their memory before capacity pressure would.
+    pub fn purge_expired(&self) -> usize {
+        let now = self.clock.now();
+        let mut purged = 0;
+
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+            // Entries are in recency order, so expired ones sit at the LRU end
+            while shard
+                .peek_lru()
+                .is_some_and(|(_, entry)| self.expired(entry, now))
+            {
+                shard.pop_lru();
+                purged += 1;
+            }
+        }
+
+        purged
+    }
+
+    /// Number of entries held, including expired ones not yet swept.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(PoisonError::into_inner).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn store(max_entries: usize, idle_ttl_secs: u64) -> (ClientStore<u32>, Arc<FixedClock>) {
+        let clock = Arc::new(FixedClock::new(Utc::now()));
+        let config = ClientStateConfig {
+            max_entries,
+            idle_ttl_secs,
+        };
+        (ClientStore::with_clock(&config, clock.clone()), clock)
+    }
+
+    fn hit(store: &ClientStore<u32>, key: &str) -> u32 {
+        store.update(
+            key,
+            || 0,
+            |count| {
+                *count += 1;
+                *count
+            },
+        )
+    }
+
+    #[test]
+    fn test_entries_expire_after_idle_ttl() {
+        let (store, clock) = store(10, 60);
+
+        assert_eq!(hit(&store, "alice"), 1);
+        clock.advance(Duration::seconds(60));
+        assert_eq!(
+            hit(&store, "alice"),
+            2,
+            "activity within the TTL keeps state"
+        );
+
+        clock.advance(Duration::seconds(61));
+        assert_eq!(store.get("alice"), None);
+        assert_eq!(hit(&store, "alice"), 1, "expired state starts over");
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted_when_full() {
+        let (store, _) = store(2, 3600);
+
+        hit(&store, "alice");
+        hit(&store, "bob");
+        hit(&store, "alice");
+        hit(&store, "carol");
+
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get("bob"), None);
+        assert_eq!(store.get("alice"), Some(2));
+        assert_eq!(store.get("carol"), Some(1));
+    }
+
+    #[test]
+    fn test_purge_expired() {
+        let (store, clock) = store(10, 60);
+
+        hit(&store, "alice");
+        clock.advance(Duration::seconds(30));
+        hit(&store, "bob");
+        clock.advance(Duration::seconds(31));
+
+        assert_eq!(store.purge_expired(), 1);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get("bob"), Some(1));
+    }
+
+    #[test]
+    fn test_sharded_capacity_is_bounded() {
+        let (store, _) = store(2048, 3600);
+        for i in 0..10_000 {
+            hit(&store, &format!("client-{i}"));
+        }
+        assert!(store.len() <= 2048);
+        assert_eq!(store.remove("client-9999"), Some(1));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shared_across_tasks() {
+        let (store, _) = store(100, 3600);
+        let store = Arc::new(store);
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                tokio::spawn(async move {
+                    for _ in 0..1000 {
+                        hit(&store, "shared");
+                        tokio::task::yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(store.get("shared"), Some(8000));
+    }
+}
@@ -0,0 +1,54 @@
+//! Time source for sunset and scheduling decisions.
+//!
+//! The agent reads the current time through a [`Clock`] so tests (and
+//! embedders) can control it instead of depending on the wall clock.
+
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Mutex;
+
+/// Source of the current time.
+pub trait Clock: Send + Sync {
+    /// Current time.
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock backed by the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Clock that returns a manually controlled time.
+#[derive(Debug)]
+pub struct FixedClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl FixedClock {
+    /// Create a clock frozen at the given time.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Set the current time.
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Move the current time forward (or backward, for negative durations).
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
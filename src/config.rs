@@ -2,10 +2,16 @@
 //!
 //! Defines deprecated endpoints, sunset dates, redirect rules, and tracking options.
 
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use crate::client_state::ClientStateConfig;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::collections::HashMap;
-use std::path::Path;
+use std::collections::HashSet;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Main configuration for the API Deprecation agent.
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
@@ -15,6 +21,11 @@ pub struct ApiDeprecationConfig {
     #[serde(default)]
     pub endpoints: Vec<DeprecatedEndpoint>,
 
+    /// Whole API versions deprecated in one declaration; each expands into a
+    /// prefix rule placed after `endpoints`
+    #[serde(default)]
+    pub version_deprecations: Vec<VersionDeprecation>,
+
     /// Global settings
     #[serde(default)]
     pub settings: GlobalSettings,
@@ -26,24 +37,593 @@ pub struct ApiDeprecationConfig {
 
 impl ApiDeprecationConfig {
     /// Load configuration from a YAML file.
-    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Self = serde_yaml::from_str(&content)?;
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let content = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let config = Self::from_yaml(&content)?;
         config.validate()?;
         Ok(config)
     }
 
+    /// Parse configuration from a YAML string (without validating it).
+    ///
+    /// A date that doesn't parse is an error naming its rule, unless
+    /// `settings.on_invalid_date` is `ignore_and_warn`: then the date is
+    /// dropped with a warning.
+    pub fn from_yaml(yaml: &str) -> Result<Self, ConfigError> {
+        serde_yaml::from_str(yaml).or_else(|err| {
+            // Only look for invalid dates once parsing failed, keeping the
+            // location of other errors
+            let Ok(raw) = serde_yaml::from_str::<serde_yaml::Value>(yaml) else {
+                return Err(ConfigError::from(err));
+            };
+            Self::without_invalid_dates(raw, ConfigError::from(err))
+        })
+    }
+
+    /// Parse configuration pushed as JSON (without validating it), handling
+    /// invalid dates like [`from_yaml`](Self::from_yaml).
+    pub fn from_json(config: &serde_json::Value) -> Result<Self, ConfigError> {
+        Self::deserialize(config).or_else(|err| {
+            let err = ConfigError::Parse {
+                location: None,
+                message: err.to_string(),
+            };
+            let Ok(raw) = serde_yaml::to_value(config) else {
+                return Err(err);
+            };
+            Self::without_invalid_dates(raw, err)
+        })
+    }
+
+    /// Parse a configuration that failed with `err` again, with its invalid
+    /// dates handled as `settings.on_invalid_date` says; `err` if no date is
+    /// invalid.
+    fn without_invalid_dates(
+        mut raw: serde_yaml::Value,
+        err: ConfigError,
+    ) -> Result<Self, ConfigError> {
+        let invalid = take_invalid_dates(&mut raw);
+        let Some(first) = invalid.first() else {
+            return Err(err);
+        };
+
+        let action = raw
+            .get("settings")
+            .and_then(|settings| settings.get("on_invalid_date"))
+            .and_then(|action| serde_yaml::from_value(action.clone()).ok())
+            .unwrap_or_default();
+        match action {
+            InvalidDateAction::Error => Err(match &first.endpoint_id {
+                Some(endpoint_id) => ConfigError::validation(
+                    endpoint_id,
+                    first.field,
+                    ValidationErrorKind::Invalid(invalid_date_reason(&first.value)),
+                ),
+                None => ConfigError::Parse {
+                    location: None,
+                    message: format!(
+                        "settings.{}: {}",
+                        first.field,
+                        invalid_date_reason(&first.value)
+                    ),
+                },
+            }),
+            InvalidDateAction::IgnoreAndWarn => {
+                for date in &invalid {
+                    tracing::warn!(
+                        endpoint_id = date.endpoint_id.as_deref().unwrap_or("settings"),
+                        field = date.field,
+                        value = %date.value,
+                        "Ignoring invalid date (settings.on_invalid_date: ignore_and_warn)"
+                    );
+                }
+                serde_yaml::from_value(raw).map_err(ConfigError::from)
+            }
+        }
+    }
+
     /// Validate the configuration.
-    pub fn validate(&self) -> anyhow::Result<()> {
-        for endpoint in &self.endpoints {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for version in &self.version_deprecations {
+            version.validate()?;
+        }
+        let expanded: Vec<DeprecatedEndpoint> = self
+            .version_deprecations
+            .iter()
+            .map(VersionDeprecation::to_endpoint)
+            .collect();
+
+        let mut ids = HashSet::new();
+        for endpoint in self.endpoints.iter().chain(&expanded) {
             endpoint.validate()?;
+            if !ids.insert(endpoint.id.as_str()) {
+                return Err(ConfigError::DuplicateId {
+                    endpoint_id: endpoint.id.clone(),
+                });
+            }
+        }
+
+        for endpoint in &self.endpoints {
+            for other in &endpoint.unless {
+                let reason = if *other == endpoint.id {
+                    "a rule cannot reference itself".to_string()
+                } else if !ids.contains(other.as_str()) {
+                    format!("unknown endpoint id '{}'", other)
+                } else {
+                    continue;
+                };
+                return Err(ConfigError::validation(
+                    &endpoint.id,
+                    "unless",
+                    ValidationErrorKind::Invalid(reason),
+                ));
+            }
+        }
+
+        for issue in self.link_issues() {
+            if self.settings.strict_links {
+                return Err(issue);
+            }
+            tracing::warn!(%issue, "Suspicious replacement link");
+        }
+        self.settings.error_envelope.validate()?;
+        if let Err(reason) = check_computed_headers(&self.settings.computed_headers) {
+            return Err(ConfigError::Setting {
+                field: "computed_headers",
+                kind: ValidationErrorKind::Invalid(reason),
+            });
+        }
+        if let Some(base) = &self.settings.base_path {
+            if !is_clean_base_path(base) {
+                return Err(ConfigError::Setting {
+                    field: "base_path",
+                    kind: ValidationErrorKind::Invalid(format!(
+                        "'{}' must be an absolute path like /public-api, without globs, \
+                         empty segments or a trailing slash",
+                        base
+                    )),
+                });
+            }
+        }
+        if let Some(url) = &self.settings.policy_url {
+            if url_origin(url).is_none() || url.contains(['<', '>', '"', ' ']) {
+                return Err(ConfigError::Setting {
+                    field: "policy_url",
+                    kind: ValidationErrorKind::Invalid(format!(
+                        "'{}' is not an absolute http(s) URL",
+                        url
+                    )),
+                });
+            }
+        }
+        for source in &self.settings.client_identity {
+            source.validate()?;
+        }
+        if let Some(class) = self
+            .settings
+            .skip_headers_status_classes
+            .iter()
+            .find(|class| !matches!(class.as_bytes(), [b'1'..=b'5', b'x', b'x']))
+        {
+            return Err(ConfigError::Setting {
+                field: "skip_headers_status_classes",
+                kind: ValidationErrorKind::Invalid(format!(
+                    "'{}' is not a status class like 5xx",
+                    class
+                )),
+            });
+        }
+        if crate::metrics::validate_prefix(&self.metrics.prefix).is_err() {
+            return Err(ConfigError::Setting {
+                field: "metrics.prefix",
+                kind: ValidationErrorKind::Invalid(format!(
+                    "'{}' is not a valid Prometheus metric name",
+                    self.metrics.prefix
+                )),
+            });
+        }
+        if matches!(self.metrics.usage_spike_factor, Some(factor) if !(factor > 1.0 && factor.is_finite()))
+        {
+            return Err(ConfigError::Setting {
+                field: "metrics.usage_spike_factor",
+                kind: ValidationErrorKind::Invalid("must be a multiple greater than 1".to_string()),
+            });
+        }
+        for issue in self.severity_issues() {
+            tracing::warn!(%issue, "Incomplete critical deprecation");
         }
         Ok(())
     }
 
-    /// Find a matching deprecated endpoint for a given path and method.
+    /// Absolute replacement and alternate links that downgrade the
+    /// `settings.public_origin` from https to http, and replacements pointing
+    /// to a host other than the public origin's or `settings.allowed_link_hosts`.
+    ///
+    /// Reported as warnings by [`validate`](Self::validate), or as errors with
+    /// `settings.strict_links`. Nothing is checked without a public origin.
+    pub fn link_issues(&self) -> Vec<ConfigError> {
+        let Some((origin_scheme, origin_host)) =
+            self.settings.public_origin.as_deref().and_then(url_origin)
+        else {
+            return Vec::new();
+        };
+
+        let expanded: Vec<DeprecatedEndpoint> = self
+            .version_deprecations
+            .iter()
+            .map(VersionDeprecation::to_endpoint)
+            .collect();
+
+        let mut issues = Vec::new();
+        for endpoint in self.endpoints.iter().chain(&expanded) {
+            let replacement = endpoint
+                .replacement
+                .as_ref()
+                .map(|replacement| ("replacement", replacement.path.as_str(), true));
+            let alternates = endpoint
+                .alternate_links
+                .iter()
+                .map(|link| ("alternate_links", link.href.as_str(), false));
+
+            for (field, url, check_host) in replacement.into_iter().chain(alternates) {
+                let Some((scheme, host)) = url_origin(url) else {
+                    continue;
+                };
+                let reason = if origin_scheme == "https" && scheme == "http" {
+                    format!("'{}' downgrades {} to http", url, origin_host)
+                } else if check_host
+                    && host != origin_host
+                    && !self
+                        .settings
+                        .allowed_link_hosts
+                        .iter()
+                        .any(|allowed| allowed.eq_ignore_ascii_case(&host))
+                {
+                    format!(
+                        "'{}' points to {} rather than {} (add it to settings.allowed_link_hosts)",
+                        url, host, origin_host
+                    )
+                } else {
+                    continue;
+                };
+                issues.push(ConfigError::validation(
+                    &endpoint.id,
+                    field,
+                    ValidationErrorKind::Invalid(reason),
+                ));
+            }
+        }
+        issues
+    }
+
+    /// `critical` endpoints that don't tell clients where to go: without
+    /// documentation (own or from `settings.documentation_base_url`) or
+    /// without a replacement.
+    ///
+    /// Reported as warnings by [`validate`](Self::validate) and `--validate`.
+    pub fn severity_issues(&self) -> Vec<ConfigError> {
+        let expanded: Vec<DeprecatedEndpoint> = self
+            .version_deprecations
+            .iter()
+            .map(VersionDeprecation::to_endpoint)
+            .collect();
+
+        let mut issues = Vec::new();
+        for endpoint in self.endpoints.iter().chain(&expanded) {
+            if endpoint.severity != Severity::Critical {
+                continue;
+            }
+            if endpoint.documentation_url.is_none()
+                && self.settings.documentation_base_url.is_none()
+            {
+                issues.push(ConfigError::validation(
+                    &endpoint.id,
+                    "documentation_url",
+                    ValidationErrorKind::Required(
+                        "critical endpoints should link to migration documentation".to_string(),
+                    ),
+                ));
+            }
+            if endpoint.replacement.is_none() {
+                issues.push(ConfigError::validation(
+                    &endpoint.id,
+                    "replacement",
+                    ValidationErrorKind::Required(
+                        "critical endpoints should name their replacement".to_string(),
+                    ),
+                ));
+            }
+        }
+        issues
+    }
+
+    /// Endpoints whose headers weigh more than
+    /// `settings.max_added_header_bytes`, with the headers the agent would
+    /// drop to fit.
+    ///
+    /// Reported as warnings by `--validate`. Must be called on a compiled
+    /// configuration, so default documentation links are counted. Headers
+    /// only known per request (computed headers, the countdown) are trimmed
+    /// as requests come in and not reported here.
+    pub fn header_budget_warnings(&self) -> Vec<HeaderBudgetWarning> {
+        use crate::headers::{header_bytes, DeprecationHeaders};
+
+        let limit = self.settings.max_added_header_bytes;
+        if limit == 0 {
+            return Vec::new();
+        }
+        let mut issues = Vec::new();
+        for endpoint in &self.endpoints {
+            let mut sets = vec![DeprecationHeaders::for_endpoint(endpoint, &self.settings)];
+            if matches!(endpoint.status, DeprecationStatus::Scheduled)
+                && endpoint.deprecated_at.is_some()
+            {
+                sets.push(DeprecationHeaders::for_scheduled(endpoint, &self.settings));
+            }
+            let Some(mut heaviest) = sets
+                .into_iter()
+                .max_by_key(|headers| header_bytes(headers.as_map()))
+            else {
+                continue;
+            };
+            let bytes = header_bytes(heaviest.as_map());
+            if bytes <= limit {
+                continue;
+            }
+            issues.push(HeaderBudgetWarning {
+                endpoint_id: endpoint.id.clone(),
+                bytes,
+                limit,
+                dropped: heaviest.trim(endpoint, &self.settings),
+            });
+        }
+        issues
+    }
+
+    /// Endpoint `examples` whose expectation doesn't hold at `now`, running
+    /// each request (with its query string and headers) through the agent's
+    /// own request handling.
+    ///
+    /// Examples are checked at a time the rule is active: `now`, or the
+    /// nearest edge of its `active_from`..`active_until` window. Nothing is
+    /// recorded in metrics.
+    ///
+    /// Must be called on a compiled configuration.
+    pub fn example_failures(&self, now: DateTime<Utc>) -> Vec<ExampleFailure> {
+        let mut config = self.clone();
+        config.metrics.enabled = false;
+        let clock = Arc::new(crate::clock::FixedClock::new(now));
+        let agent = crate::agent::ApiDeprecationAgent::with_metrics(
+            config,
+            Arc::new(crate::metrics::NoopMetrics),
+        )
+        .with_clock(clock.clone());
+
+        let mut failures = Vec::new();
+        for endpoint in &self.endpoints {
+            clock.set(match (endpoint.active_from, endpoint.active_until) {
+                (Some(from), _) if now < from => from,
+                (_, Some(until)) if now >= until => until - Duration::seconds(1),
+                _ => now,
+            });
+            for example in &endpoint.examples {
+                let (matched, action) = agent.example_outcome(example);
+                let matches = match example.expect {
+                    ExampleExpectation::Match => matched.as_deref() == Some(endpoint.id.as_str()),
+                    ExampleExpectation::NoMatch => matched.is_none(),
+                };
+                if !matches || example.action.is_some_and(|expected| expected != action) {
+                    failures.push(ExampleFailure {
+                        endpoint_id: endpoint.id.clone(),
+                        example: example.clone(),
+                        matched,
+                        action,
+                    });
+                }
+            }
+        }
+        failures
+    }
+
+    /// Rules added, removed and changed in `other`, matched by id.
+    ///
+    /// Both configurations should be compiled, so version deprecations are
+    /// compared as the endpoints they expand to.
+    pub fn diff(&self, other: &ApiDeprecationConfig) -> crate::diff::ConfigDiff {
+        crate::diff::diff(self, other)
+    }
+
+    /// Hash of the effective configuration, to tell what an instance enforces.
+    ///
+    /// Call it on a compiled configuration so version deprecations, defaults
+    /// and `base_path` are included. Map keys are hashed in sorted order, so
+    /// configurations differing only in the order of map entries agree.
+    pub fn hash(&self) -> String {
+        // serde_json objects keep their keys sorted
+        let canonical = serde_json::to_value(self)
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        format!("{:016x}", crate::manifest::fnv1a(canonical.as_bytes()))
+    }
+
+    /// Expand version deprecations into endpoints, resolve per-endpoint
+    /// defaults from the global settings (including `base_path`) and compile
+    /// path matchers for all endpoints.
+    pub fn compile(&mut self) {
+        let versions = std::mem::take(&mut self.version_deprecations);
+        self.endpoints
+            .extend(versions.iter().map(VersionDeprecation::to_endpoint));
+
+        for endpoint in &mut self.endpoints {
+            if let Some(base) = &self.settings.base_path {
+                endpoint.apply_base_path(base);
+            }
+            if endpoint.sunset_at.is_none() {
+                endpoint.sunset_at = self.settings.default_sunset_at;
+            }
+            if endpoint.documentation_url.is_none() {
+                if let Some(base) = &self.settings.documentation_base_url {
+                    endpoint.documentation_url = Some(DocumentationLinks::Url(
+                        base.replace("{id}", &endpoint.id)
+                            .replace("{path}", endpoint.path.primary()),
+                    ));
+                }
+            }
+            endpoint.head_as_get = self.settings.treat_head_as_get;
+            endpoint.compile();
+        }
+        for source in &mut self.settings.client_identity {
+            source.compile();
+        }
+    }
+
+    /// Rules that can block traffic but aren't `confirmed` yet.
+    pub fn unconfirmed_endpoints(&self) -> impl Iterator<Item = &DeprecatedEndpoint> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| endpoint.is_unconfirmed(&self.settings))
+    }
+
+    /// Find a matching deprecated endpoint for a given path and method,
+    /// skipping rules outside their `active_from`..`active_until` window now.
+    ///
+    /// This is a linear scan over all endpoints; the agent uses an
+    /// [`EndpointIndex`](crate::matcher::EndpointIndex) with identical results.
     pub fn find_endpoint(&self, path: &str, method: &str) -> Option<&DeprecatedEndpoint> {
-        self.endpoints.iter().find(|e| e.matches(path, method))
+        self.find_endpoint_index_at(path, method, Utc::now())
+            .map(|index| &self.endpoints[index])
+    }
+
+    /// Find the index of the first deprecated endpoint matching a path and
+    /// method among the rules active now.
+    #[deprecated(note = "use `find_endpoint_index_at` with an explicit time")]
+    pub fn find_endpoint_index(&self, path: &str, method: &str) -> Option<usize> {
+        self.find_endpoint_index_at(path, method, Utc::now())
+    }
+
+    /// Find the index of the first deprecated endpoint matching a path and
+    /// method among the rules active at `now`.
+    pub fn find_endpoint_index_at(
+        &self,
+        path: &str,
+        method: &str,
+        now: DateTime<Utc>,
+    ) -> Option<usize> {
+        let matches = |e: &DeprecatedEndpoint| e.is_active_at(now) && e.matches(path, method);
+        self.endpoints.iter().position(|e| {
+            matches(e)
+                && !e.unless.iter().any(|id| {
+                    self.endpoints
+                        .iter()
+                        .any(|other| other.id == *id && matches(other))
+                })
+        })
+    }
+}
+
+/// Error loading or validating the configuration.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    /// The configuration file could not be read
+    #[error("failed to read configuration file {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// The configuration is not valid YAML or doesn't match the schema
+    #[error("failed to parse configuration: {message}")]
+    Parse {
+        location: Option<ConfigLocation>,
+        message: String,
+    },
+
+    /// An endpoint field failed validation
+    #[error("invalid endpoint '{endpoint_id}': {field} {kind}")]
+    Validation {
+        endpoint_id: String,
+        field: &'static str,
+        kind: ValidationErrorKind,
+    },
+
+    /// Two endpoints share the same id
+    #[error("duplicate endpoint id '{endpoint_id}'")]
+    DuplicateId { endpoint_id: String },
+
+    /// A global setting failed validation
+    #[error("invalid setting '{field}': {kind}")]
+    Setting {
+        field: &'static str,
+        kind: ValidationErrorKind,
+    },
+
+    /// One of several configuration files (e.g. a changelog snapshot) failed
+    /// to parse or validate
+    #[error("invalid configuration file {}: {source}", path.display())]
+    File {
+        path: PathBuf,
+        #[source]
+        source: Box<ConfigError>,
+    },
+}
+
+impl ConfigError {
+    fn validation(endpoint_id: &str, field: &'static str, kind: ValidationErrorKind) -> Self {
+        Self::Validation {
+            endpoint_id: endpoint_id.to_string(),
+            field,
+            kind,
+        }
+    }
+}
+
+impl From<serde_yaml::Error> for ConfigError {
+    fn from(err: serde_yaml::Error) -> Self {
+        Self::Parse {
+            location: err.location().map(|l| ConfigLocation {
+                line: l.line(),
+                column: l.column(),
+            }),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Position in the configuration source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConfigLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for ConfigLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} column {}", self.line, self.column)
+    }
+}
+
+/// Why a field failed validation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    /// A required value is empty
+    Empty,
+    /// A value is present but not acceptable
+    Invalid(String),
+    /// The field is required by another setting
+    Required(String),
+}
+
+impl fmt::Display for ValidationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "cannot be empty"),
+            Self::Invalid(reason) => write!(f, "is invalid: {}", reason),
+            Self::Required(reason) => write!(f, "is required: {}", reason),
+        }
     }
 }
 
@@ -54,33 +634,90 @@ pub struct DeprecatedEndpoint {
     /// Unique identifier for this deprecation rule
     pub id: String,
 
-    /// Path pattern to match (supports glob patterns like /api/v1/*)
-    pub path: String,
+    /// Path pattern to match (supports glob patterns like /api/v1/*), or a
+    /// list of aliases matched as alternatives
+    pub path: PathPatterns,
 
-    /// HTTP methods to match (empty means all methods)
-    #[serde(default)]
+    /// HTTP methods to match (empty means all methods). Accepts a single
+    /// method or a list; names are uppercased and checked at load time.
+    #[serde(default, deserialize_with = "deserialize_methods")]
     pub methods: Vec<String>,
 
     /// Deprecation status
     #[serde(default)]
     pub status: DeprecationStatus,
 
-    /// Date when the endpoint was deprecated (RFC 3339)
-    #[serde(default)]
+    /// Date when the endpoint was deprecated (RFC 3339 or `YYYY-MM-DD`)
+    #[serde(default, deserialize_with = "deserialize_date")]
     pub deprecated_at: Option<DateTime<Utc>>,
 
-    /// Date when the endpoint will be/was removed (RFC 3339)
+    /// Date when the endpoint will be/was removed (RFC 3339 or `YYYY-MM-DD`)
     /// Used for the Sunset header (RFC 8594)
-    #[serde(default)]
+    #[serde(default, deserialize_with = "deserialize_date")]
     pub sunset_at: Option<DateTime<Utc>>,
 
+    /// Start of the window in which the rule applies at all (RFC 3339 or
+    /// `YYYY-MM-DD`). Outside `active_from`..`active_until` the rule is
+    /// skipped: no headers, metrics or logs.
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub active_from: Option<DateTime<Utc>>,
+
+    /// End of the window in which the rule applies (exclusive)
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub active_until: Option<DateTime<Utc>>,
+
     /// Replacement endpoint information
     #[serde(default)]
     pub replacement: Option<ReplacementInfo>,
 
-    /// Link to migration documentation
+    /// Link to migration documentation: a URL, or a list of typed links
+    /// (`url`, `rel`, `title`)
+    #[serde(default)]
+    pub documentation_url: Option<DocumentationLinks>,
+
+    /// Team owning the deprecation
+    #[serde(default)]
+    pub owner: Option<String>,
+
+    /// Who to ask about the deprecation: an email address or a URL
+    #[serde(default)]
+    pub contact: Option<String>,
+
+    /// How much the deprecation matters: sets the access log level and is
+    /// exported in metrics and decision metadata
+    #[serde(default)]
+    pub severity: Severity,
+
+    /// Operator notes, shown in usage reports and access logs but never sent
+    /// to clients
+    #[serde(default)]
+    pub notes: Option<String>,
+
+    /// Alert when the request rate over a metrics refresh interval exceeds
+    /// this multiple of its moving average, or the last day of requests this
+    /// multiple of the average day before it (default:
+    /// `metrics.usage_spike_factor`; unset there too: never)
+    #[serde(default)]
+    pub spike_threshold: Option<f64>,
+
+    /// Requests this rule should or should not match, checked by
+    /// `--validate` and never consulted at runtime
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub examples: Vec<MatchExample>,
+
+    /// Send the notice headers of warned requests once per browser session,
+    /// remembered with a marker cookie (unset: on every request)
     #[serde(default)]
-    pub documentation_url: Option<String>,
+    pub warn_once: Option<WarnOnce>,
+
+    /// Reporting API endpoint for browser clients, announced in
+    /// `Reporting-Endpoints` and `Report-To` headers
+    #[serde(default)]
+    pub report_to: Option<ReportTo>,
+
+    /// Alternate representations of this resource (emitted as rel="alternate" links)
+    #[serde(default)]
+    pub alternate_links: Vec<AlternateLink>,
 
     /// Custom deprecation message
     #[serde(default)]
@@ -90,31 +727,319 @@ pub struct DeprecatedEndpoint {
     #[serde(default)]
     pub action: DeprecationAction,
 
+    /// Bodies of blocked responses by status code, used instead of the
+    /// generated body when the response has that status
+    #[serde(default)]
+    pub bodies: HashMap<u16, CustomBody>,
+
     /// Additional headers to add to responses
     #[serde(default)]
     pub headers: HashMap<String, String>,
 
-    /// Whether to track usage of this endpoint
+    /// Headers rendered on every response from a template referencing
+    /// `{{status}}`, `{{sunset_iso}}` and `{{days}}` (e.g. a `Tk` or audit
+    /// header), in addition to `settings.computed_headers`
+    #[serde(default)]
+    pub computed_headers: HashMap<String, String>,
+
+    /// Whether to track usage of this endpoint (ignored when metrics are
+    /// disabled globally via `metrics.enabled`)
     #[serde(default = "default_true")]
     pub track_usage: bool,
 
-    /// Compiled path matcher (not serialized)
+    /// Identifier used as the `endpoint_id` metrics label (defaults to `id`).
+    /// Keeps metric series continuous when a rule is renamed.
+    #[serde(default)]
+    pub metrics_id: Option<String>,
+
+    /// Labels describing the rule's metrics (e.g. `team: payments`), added
+    /// to the alerting rules generated by the `alerts` subcommand
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// Ids of other rules that suppress this one: it only matches a request
+    /// none of them match. The referenced rules' own `unless` lists are not
+    /// consulted.
+    #[serde(default)]
+    pub unless: Vec<String>,
+
+    /// Path patterns this rule never matches, even when `path` does
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Query parameters deprecated on this endpoint. Requests using one get
+    /// its notice (and action) on top of the rule's own deprecation.
+    #[serde(default)]
+    pub deprecated_params: Vec<DeprecatedParam>,
+
+    /// Request headers clients should stop sending to this endpoint, in
+    /// addition to `settings.deprecated_request_headers` (entries here win
+    /// for the same name). Like `deprecated_params`, their notice and action
+    /// come on top of the rule's own deprecation.
+    #[serde(default)]
+    pub deprecated_request_headers: Vec<DeprecatedHeader>,
+
+    /// JSON pointers (e.g. `/legacy_id`) of deprecated request body fields,
+    /// shorthand for `deprecated_body_fields` entries that warn. Requests
+    /// whose JSON body contains one are told in the body phase, on top of the
+    /// rule's own deprecation.
+    #[serde(default)]
+    pub body_field_deprecated: Vec<String>,
+
+    /// Deprecated request body fields with their replacement and action
+    #[serde(default)]
+    pub deprecated_body_fields: Vec<DeprecatedBodyField>,
+
+    /// Confirms that this rule may block traffic. With `false`, or unset while
+    /// `settings.require_confirmation` is on, a rule that would block
+    /// (removed status, a block/custom/static asset action, or a blocking
+    /// past-sunset policy) only warns and tags the request `would_block`.
+    #[serde(default)]
+    pub confirmed: Option<bool>,
+
+    /// For removed endpoints: record each caller (method, path and client)
+    /// and sample rejected requests into the diagnostics buffer, to find the
+    /// last callers of an API that is already gone
+    #[serde(default)]
+    pub tombstone: Option<Tombstone>,
+
+    /// Compiled matchers, parallel to the path patterns; `None` for literal
+    /// patterns (not serialized)
+    #[serde(skip)]
+    pub path_matchers: Vec<Option<globset::GlobMatcher>>,
+
+    /// Compiled matchers, parallel to `exclude` (not serialized)
+    #[serde(skip)]
+    pub exclude_matchers: Vec<Option<globset::GlobMatcher>>,
+
+    /// Whether HEAD requests match the rule when it lists `GET`, from
+    /// `settings.treat_head_as_get` when compiled (not serialized)
     #[serde(skip)]
-    pub path_matcher: Option<globset::GlobMatcher>,
+    pub head_as_get: bool,
 }
 
 fn default_true() -> bool {
     true
 }
 
+/// Placeholder in `message` replaced with the endpoint's `contact`.
+pub const CONTACT_PLACEHOLDER: &str = "{contact}";
+
+/// Placeholder in `message` replaced with today's request count.
+pub const HITS_PLACEHOLDER: &str = "{{hits}}";
+
 impl DeprecatedEndpoint {
     /// Validate the endpoint configuration.
-    pub fn validate(&self) -> anyhow::Result<()> {
+    pub fn validate(&self) -> Result<(), ConfigError> {
         if self.id.is_empty() {
-            anyhow::bail!("Endpoint id cannot be empty");
+            return Err(ConfigError::validation(
+                &self.id,
+                "id",
+                ValidationErrorKind::Empty,
+            ));
+        }
+        if self.path.is_empty() || self.path.iter().any(str::is_empty) {
+            return Err(ConfigError::validation(
+                &self.id,
+                "path",
+                ValidationErrorKind::Empty,
+            ));
+        }
+        if let Some(docs) = &self.documentation_url {
+            docs.validate(&self.id)?;
+        }
+        if let (Some(from), Some(until)) = (self.active_from, self.active_until) {
+            if until <= from {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    "active_until",
+                    ValidationErrorKind::Invalid("must be after active_from".to_string()),
+                ));
+            }
+        }
+        if matches!(&self.owner, Some(owner) if owner.trim().is_empty()) {
+            return Err(ConfigError::validation(
+                &self.id,
+                "owner",
+                ValidationErrorKind::Empty,
+            ));
+        }
+        if let Some(contact) = &self.contact {
+            validate_contact(&self.id, contact)?;
+        }
+        if self
+            .examples
+            .iter()
+            .any(|example| !example.path.starts_with('/'))
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "examples",
+                ValidationErrorKind::Invalid("example paths must start with '/'".to_string()),
+            ));
+        }
+        if let Some(warn_once) = &self.warn_once {
+            warn_once.validate(&self.id)?;
+        }
+        if let Some(report_to) = &self.report_to {
+            report_to.validate(&self.id)?;
+        }
+        if let Err(reason) = check_computed_headers(&self.computed_headers) {
+            return Err(ConfigError::validation(
+                &self.id,
+                "computed_headers",
+                ValidationErrorKind::Invalid(reason),
+            ));
+        }
+        if let Some(status) = self
+            .bodies
+            .keys()
+            .find(|status| !(400..=599).contains(*status))
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "bodies",
+                ValidationErrorKind::Invalid(format!(
+                    "{} is not an error status code (400-599)",
+                    status
+                )),
+            ));
+        }
+        if matches!(self.spike_threshold, Some(threshold) if !(threshold > 1.0 && threshold.is_finite()))
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "spike_threshold",
+                ValidationErrorKind::Invalid("must be a multiple greater than 1".to_string()),
+            ));
+        }
+        if self
+            .alternate_links
+            .iter()
+            .any(|link| link.href.is_empty() || link.media_type.is_empty())
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "alternate_links",
+                ValidationErrorKind::Invalid("each link requires both href and type".to_string()),
+            ));
         }
-        if self.path.is_empty() {
-            anyhow::bail!("Endpoint path cannot be empty for id: {}", self.id);
+        for pattern in self.path.iter() {
+            if let Err(reason) = check_pattern(pattern) {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    "path",
+                    ValidationErrorKind::Invalid(reason),
+                ));
+            }
+        }
+        for pattern in &self.exclude {
+            if pattern.is_empty() {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    "exclude",
+                    ValidationErrorKind::Empty,
+                ));
+            }
+            if let Err(reason) = check_pattern(pattern) {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    "exclude",
+                    ValidationErrorKind::Invalid(reason),
+                ));
+            }
+        }
+        if self
+            .deprecated_params
+            .iter()
+            .any(|param| param.name.is_empty())
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "deprecated_params",
+                ValidationErrorKind::Empty,
+            ));
+        }
+        if self
+            .deprecated_request_headers
+            .iter()
+            .any(|header| header.name.is_empty())
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "deprecated_request_headers",
+                ValidationErrorKind::Empty,
+            ));
+        }
+        if let Some(tombstone) = &self.tombstone {
+            if !matches!(self.status, DeprecationStatus::Removed) {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    "tombstone",
+                    ValidationErrorKind::Invalid(
+                        "only removed endpoints can be tombstoned".to_string(),
+                    ),
+                ));
+            }
+            if tombstone.sample_every == 0 {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    "tombstone",
+                    ValidationErrorKind::Invalid("sample_every must be at least 1".to_string()),
+                ));
+            }
+        }
+        if let Some(pointer) = self
+            .body_fields()
+            .into_iter()
+            .map(|field| field.pointer)
+            .find(|pointer| !pointer.starts_with('/'))
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "deprecated_body_fields",
+                ValidationErrorKind::Invalid(format!(
+                    "'{}' is not a JSON pointer (must start with '/')",
+                    pointer
+                )),
+            ));
+        }
+        if self.inspects_body() && !matches!(self.action, DeprecationAction::Warn) {
+            return Err(ConfigError::validation(
+                &self.id,
+                "action",
+                ValidationErrorKind::Invalid(
+                    "rules on body fields only support warn (set the action per field)".to_string(),
+                ),
+            ));
+        }
+        if matches!(&self.metrics_id, Some(metrics_id) if metrics_id.is_empty()) {
+            return Err(ConfigError::validation(
+                &self.id,
+                "metrics_id",
+                ValidationErrorKind::Empty,
+            ));
+        }
+        if let Some(name) = self.labels.keys().find(|name| !is_label_name(name)) {
+            return Err(ConfigError::validation(
+                &self.id,
+                "labels",
+                ValidationErrorKind::Invalid(format!(
+                    "'{}' is not a valid Prometheus label name",
+                    name
+                )),
+            ));
+        }
+        if let Some(name) = self
+            .labels
+            .keys()
+            .find(|name| RESERVED_LABELS.contains(&name.as_str()))
+        {
+            return Err(ConfigError::validation(
+                &self.id,
+                "labels",
+                ValidationErrorKind::Invalid(format!("'{}' is set by the agent", name)),
+            ));
         }
 
         // Validate sunset date is in the future for non-removed endpoints
@@ -130,66 +1055,171 @@ impl DeprecatedEndpoint {
 
         // Validate redirect has a target
         if matches!(self.action, DeprecationAction::Redirect { .. }) && self.replacement.is_none() {
-            anyhow::bail!(
-                "Redirect action requires replacement info for endpoint: {}",
-                self.id
-            );
+            return Err(ConfigError::validation(
+                &self.id,
+                "replacement",
+                ValidationErrorKind::Required("redirect action needs a target".to_string()),
+            ));
         }
 
         Ok(())
     }
 
-    /// Check if this endpoint matches the given path and method.
-    pub fn matches(&self, path: &str, method: &str) -> bool {
-        // Check method first (quick check)
-        if !self.methods.is_empty() {
-            let method_upper = method.to_uppercase();
-            if !self
-                .methods
+    /// Compile the glob matchers for this endpoint's path patterns.
+    /// Put the rule's patterns, examples and path-relative replacement under
+    /// `settings.base_path`; paths already under it are left alone.
+    fn apply_base_path(&mut self, base: &str) {
+        self.path = PathPatterns::from(
+            self.path
                 .iter()
-                .any(|m| m.to_uppercase() == method_upper)
-            {
-                return false;
+                .map(|pattern| under_base_path(base, pattern))
+                .collect::<Vec<_>>(),
+        );
+        for pattern in &mut self.exclude {
+            *pattern = under_base_path(base, pattern);
+        }
+        for example in &mut self.examples {
+            example.path = under_base_path(base, &example.path);
+        }
+        if let Some(replacement) = &mut self.replacement {
+            // Absolute URLs point elsewhere and keep their path
+            if replacement.path.starts_with('/') {
+                replacement.path = under_base_path(base, &replacement.path);
+            }
+            if let Some(prefix) = &mut replacement.rewrite_prefix {
+                *prefix = under_base_path(base, prefix);
             }
         }
+    }
+
+    pub fn compile(&mut self) {
+        self.path_matchers = self.path.iter().map(compile_pattern).collect();
+        self.exclude_matchers = self
+            .exclude
+            .iter()
+            .map(|pattern| compile_pattern(pattern))
+            .collect();
+    }
 
-        // Check path pattern
-        self.matches_path(path)
+    /// Whether any of the path patterns is a glob rather than a literal path.
+    pub fn is_glob(&self) -> bool {
+        self.path.iter().any(is_glob_pattern)
+    }
+
+    /// Whether a compiled glob pattern failed to compile (and never matches).
+    pub fn has_invalid_pattern(&self) -> bool {
+        self.path.iter().enumerate().any(|(i, pattern)| {
+            is_glob_pattern(pattern) && self.path_matchers.get(i).is_some_and(Option::is_none)
+        })
+    }
+
+    /// Check if this endpoint matches the given path and method.
+    pub fn matches(&self, path: &str, method: &str) -> bool {
+        // Check method first (quick check), then the path pattern
+        self.matches_method(method) && self.matches_path(path) && !self.is_excluded(path)
+    }
+
+    /// Check if the method is one of the endpoint's methods (all when empty).
+    /// With `head_as_get`, HEAD also matches a listed `GET`.
+    pub fn matches_method(&self, method: &str) -> bool {
+        let head_as_get = self.head_as_get && method.eq_ignore_ascii_case("HEAD");
+        self.methods.is_empty()
+            || self.methods.iter().any(|m| {
+                m.eq_ignore_ascii_case(method) || (head_as_get && m.eq_ignore_ascii_case("GET"))
+            })
     }
 
     /// Check if the path matches the pattern.
-    fn matches_path(&self, path: &str) -> bool {
-        // Simple prefix/exact matching for common cases
-        if !self.path.contains('*') && !self.path.contains('?') {
-            // Exact match or prefix match with trailing slash
-            return path == self.path
-                || path.starts_with(&format!("{}/", self.path))
-                || (self.path.ends_with('/') && path.starts_with(&self.path));
-        }
+    ///
+    /// Invalid glob patterns are rejected by `validate()`. If one is loaded
+    /// anyway (e.g. an agent built from an unvalidated config), the rule never
+    /// matches: requests fail open and are allowed without deprecation handling.
+    pub(crate) fn matches_path(&self, path: &str) -> bool {
+        self.path
+            .iter()
+            .enumerate()
+            .any(|(i, pattern)| pattern_matches(pattern, self.path_matchers.get(i), path))
+    }
 
-        // Use glob matching for patterns
-        if let Ok(glob) = globset::Glob::new(&self.path) {
-            let matcher = glob.compile_matcher();
-            return matcher.is_match(path);
-        }
+    /// Check if the path matches one of the `exclude` patterns.
+    pub(crate) fn is_excluded(&self, path: &str) -> bool {
+        self.exclude
+            .iter()
+            .enumerate()
+            .any(|(i, pattern)| pattern_matches(pattern, self.exclude_matchers.get(i), path))
+    }
 
-        false
+    /// Identifier used to label this endpoint's metrics.
+    pub fn metrics_id(&self) -> &str {
+        self.metrics_id.as_deref().unwrap_or(&self.id)
+    }
+
+    /// Spike threshold of the endpoint: its own `spike_threshold`, or
+    /// `metrics.usage_spike_factor`.
+    pub fn spike_factor(&self, metrics: &MetricsConfig) -> Option<f64> {
+        self.spike_threshold.or(metrics.usage_spike_factor)
     }
 
     /// Check if the endpoint has passed its sunset date.
     pub fn is_past_sunset(&self) -> bool {
+        self.is_past_sunset_at(Utc::now())
+    }
+
+    /// Check if the endpoint has passed its sunset date at the given time.
+    pub fn is_past_sunset_at(&self, now: DateTime<Utc>) -> bool {
+        self.sunset_at.map(|sunset| now > sunset).unwrap_or(false)
+    }
+
+    /// Whether `now` falls in the rule's `active_from`..`active_until`
+    /// window (always, without one).
+    pub fn is_active_at(&self, now: DateTime<Utc>) -> bool {
+        self.active_from.is_none_or(|from| now >= from)
+            && self.active_until.is_none_or(|until| now < until)
+    }
+
+    /// Whole days from `now` until the sunset date (negative once past it).
+    pub fn days_until_sunset(&self, now: DateTime<Utc>) -> Option<i64> {
+        self.sunset_at.map(|sunset| (sunset - now).num_days())
+    }
+
+    /// Time clients must have migrated by: the sunset plus `grace_days`.
+    pub fn migration_deadline(&self, grace_days: u32) -> Option<DateTime<Utc>> {
         self.sunset_at
-            .map(|sunset| Utc::now() > sunset)
-            .unwrap_or(false)
+            .map(|sunset| sunset + chrono::Duration::days(grace_days.into()))
     }
 
     /// Get the deprecation warning message.
     pub fn deprecation_message(&self) -> String {
-        if let Some(msg) = &self.message {
-            return msg.clone();
+        match &self.message {
+            Some(msg) => self.fill_contact(msg),
+            None => self.default_deprecation_message(),
+        }
+    }
+
+    /// The deprecation message where no request count is available (response
+    /// bodies, reports): the generated notice instead of a configured
+    /// message using `{{hits}}`.
+    pub fn uncounted_deprecation_message(&self) -> String {
+        match &self.message {
+            Some(message) if message.contains(HITS_PLACEHOLDER) => {
+                self.default_deprecation_message()
+            }
+            _ => self.deprecation_message(),
         }
+    }
+
+    /// Fill in the `{contact}` placeholder of a configured message (empty
+    /// without a `contact`).
+    fn fill_contact(&self, message: &str) -> String {
+        message.replace(
+            CONTACT_PLACEHOLDER,
+            self.contact.as_deref().unwrap_or_default(),
+        )
+    }
 
-        let mut message = format!("This endpoint ({}) is deprecated", self.path);
+    /// Generated deprecation notice, ignoring a configured `message`.
+    pub fn default_deprecation_message(&self) -> String {
+        let mut message = format!("This endpoint ({}) is deprecated", self.path.primary());
 
         if let Some(sunset) = &self.sunset_at {
             message.push_str(&format!(
@@ -202,16 +1232,125 @@ impl DeprecatedEndpoint {
             message.push_str(&format!(". Please migrate to {}", replacement.path));
         }
 
-        if let Some(docs) = &self.documentation_url {
+        if let Some(docs) = self
+            .documentation_url
+            .as_ref()
+            .and_then(DocumentationLinks::primary_url)
+        {
             message.push_str(&format!(". See {} for migration guide", docs));
         }
 
         message.push('.');
         message
     }
-}
 
-/// Status of the deprecation.
+    /// Notice for a scheduled endpoint before its `deprecated_at` date.
+    ///
+    /// A configured `message` is used as is.
+    pub fn scheduled_message(&self) -> String {
+        match &self.message {
+            Some(msg) => self.fill_contact(msg),
+            None => self.default_scheduled_message(),
+        }
+    }
+
+    /// Generated scheduled notice, ignoring a configured `message`.
+    pub fn default_scheduled_message(&self) -> String {
+        let mut message = format!(
+            "This endpoint ({}) is scheduled for deprecation",
+            self.path.primary()
+        );
+
+        if let Some(deprecated_at) = &self.deprecated_at {
+            message.push_str(&format!(" on {}", deprecated_at.format("%Y-%m-%d")));
+        }
+
+        if let Some(replacement) = &self.replacement {
+            message.push_str(&format!(". Consider migrating to {}", replacement.path));
+        }
+
+        if let Some(docs) = self
+            .documentation_url
+            .as_ref()
+            .and_then(DocumentationLinks::primary_url)
+        {
+            message.push_str(&format!(". See {} for migration guide", docs));
+        }
+
+        message.push('.');
+        message
+    }
+
+    /// Whether this is a scheduled endpoint whose `deprecated_at` is still in
+    /// the future, so the Deprecation header is held back.
+    pub fn is_pending_deprecation(&self, now: DateTime<Utc>) -> bool {
+        matches!(self.status, DeprecationStatus::Scheduled)
+            && self.deprecated_at.is_some_and(|at| now < at)
+    }
+
+    /// Whether this rule can block traffic at some point: it is removed, its
+    /// action replaces the response, or the past-sunset policy blocks it.
+    pub fn can_block(&self, settings: &GlobalSettings) -> bool {
+        let blocks_past_sunset = self.sunset_at.is_some()
+            && match settings.past_sunset_action {
+                PastSunsetAction::Warn => false,
+                PastSunsetAction::Block => true,
+                PastSunsetAction::Redirect => self.replacement.is_none(),
+            };
+
+        matches!(self.status, DeprecationStatus::Removed)
+            || matches!(
+                self.action,
+                DeprecationAction::Block { .. } | DeprecationAction::Custom { .. }
+            )
+            || blocks_past_sunset
+            || self
+                .deprecated_params
+                .iter()
+                .any(|param| param.action == ParamAction::Block)
+            || self
+                .deprecated_request_headers
+                .iter()
+                .any(|header| header.action == ParamAction::Block)
+            || self
+                .deprecated_body_fields
+                .iter()
+                .any(|field| field.action == BodyFieldAction::Block)
+    }
+
+    /// Whether this rule is decided on the request body.
+    pub fn inspects_body(&self) -> bool {
+        !self.body_field_deprecated.is_empty() || !self.deprecated_body_fields.is_empty()
+    }
+
+    /// Deprecated body fields from `body_field_deprecated` (as warn entries)
+    /// and `deprecated_body_fields`.
+    pub fn body_fields(&self) -> Vec<DeprecatedBodyField> {
+        self.body_field_deprecated
+            .iter()
+            .map(|pointer| DeprecatedBodyField {
+                pointer: pointer.clone(),
+                replacement: None,
+                action: BodyFieldAction::Warn,
+            })
+            .chain(self.deprecated_body_fields.iter().cloned())
+            .collect()
+    }
+
+    /// Whether this rule may block traffic: its own `confirmed`, or unless
+    /// `settings.require_confirmation` is on when unset.
+    pub fn is_confirmed(&self, settings: &GlobalSettings) -> bool {
+        self.confirmed.unwrap_or(!settings.require_confirmation)
+    }
+
+    /// Whether this rule can block traffic but hasn't been `confirmed`, so it
+    /// runs in shadow mode.
+    pub fn is_unconfirmed(&self, settings: &GlobalSettings) -> bool {
+        !self.is_confirmed(settings) && self.can_block(settings)
+    }
+}
+
+/// Status of the deprecation.
 #[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum DeprecationStatus {
@@ -224,6 +1363,347 @@ pub enum DeprecationStatus {
     Scheduled,
 }
 
+impl DeprecationStatus {
+    /// Label used in metrics and exports.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Deprecated => "deprecated",
+            Self::Removed => "removed",
+            Self::Scheduled => "scheduled",
+        }
+    }
+}
+
+/// A request a rule is expected to match, or that no rule should match.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatchExample {
+    /// Request method (default: GET)
+    #[serde(default = "default_example_method")]
+    pub method: String,
+    /// Request path, with the query string deprecated parameters are
+    /// checked against
+    pub path: String,
+    /// Request headers, for deprecated headers and `original_path_header`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    pub expect: ExampleExpectation,
+    /// Action the request should get (unset: not checked)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<ExampleAction>,
+}
+
+fn default_example_method() -> String {
+    "GET".to_string()
+}
+
+/// Outcome an example expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExampleExpectation {
+    /// The rule declaring the example wins the match
+    Match,
+    /// No rule matches
+    NoMatch,
+}
+
+/// What a request is done with, as far as an example is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExampleAction {
+    /// Forwarded upstream (with or without deprecation headers)
+    Allow,
+    /// Redirected to a replacement
+    Redirect,
+    /// Answered by the agent itself (removed endpoints, block and custom
+    /// actions, deprecated parameters, headers or body fields that block)
+    Block,
+}
+
+impl ExampleAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Redirect => "redirect",
+            Self::Block => "block",
+        }
+    }
+}
+
+/// An example whose expectation doesn't hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExampleFailure {
+    /// Rule declaring the example
+    pub endpoint_id: String,
+    pub example: MatchExample,
+    /// Rule that actually matched, if any
+    pub matched: Option<String>,
+    /// Action the request actually got
+    pub action: ExampleAction,
+}
+
+impl fmt::Display for ExampleFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} {} ",
+            self.endpoint_id,
+            self.example.method.to_uppercase(),
+            self.example.path,
+        )?;
+        let matched = match self.example.expect {
+            ExampleExpectation::Match => self.matched.as_deref() == Some(&self.endpoint_id),
+            ExampleExpectation::NoMatch => self.matched.is_none(),
+        };
+        match (matched, self.example.action) {
+            (true, Some(expected)) => write!(
+                f,
+                "expected to {}, but got {}",
+                expected.as_str(),
+                self.action.as_str()
+            ),
+            _ => {
+                match self.example.expect {
+                    ExampleExpectation::Match => write!(f, "expected to match, ")?,
+                    ExampleExpectation::NoMatch => write!(f, "expected to match no rule, ")?,
+                }
+                match &self.matched {
+                    Some(id) => write!(f, "but matched '{}'", id),
+                    None => write!(f, "but matched nothing"),
+                }
+            }
+        }
+    }
+}
+
+/// An endpoint adding more header bytes than
+/// `settings.max_added_header_bytes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderBudgetWarning {
+    pub endpoint_id: String,
+    /// Bytes of the endpoint's heaviest header set
+    pub bytes: usize,
+    pub limit: usize,
+    /// Headers dropped to fit, in trimming order
+    pub dropped: Vec<String>,
+}
+
+impl fmt::Display for HeaderBudgetWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: adds {} bytes of headers, over settings.max_added_header_bytes ({}); ",
+            self.endpoint_id, self.bytes, self.limit
+        )?;
+        match self.dropped.as_slice() {
+            [] => write!(f, "dropping headers can't bring it under the limit"),
+            names => write!(f, "dropping {}", names.join(", ")),
+        }
+    }
+}
+
+/// Where browsers send deprecation reports (W3C Reporting API).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReportTo {
+    /// Collector the reports are POSTed to; browsers require https
+    pub url: String,
+
+    /// Endpoint (group) name reports are filed under (default: deprecation)
+    #[serde(default = "default_report_group")]
+    pub group: String,
+
+    /// How long browsers remember the endpoint, for `Report-To`
+    /// (default: 1 day)
+    #[serde(default = "default_report_max_age")]
+    pub max_age_secs: u64,
+}
+
+fn default_report_group() -> String {
+    "deprecation".to_string()
+}
+
+fn default_report_max_age() -> u64 {
+    86400
+}
+
+impl ReportTo {
+    /// `Reporting-Endpoints` value (Reporting API level 1).
+    pub fn reporting_endpoints(&self) -> String {
+        format!("{}=\"{}\"", self.group, self.url)
+    }
+
+    /// Legacy `Report-To` value, for browsers predating `Reporting-Endpoints`.
+    pub fn report_to(&self) -> String {
+        serde_json::json!({
+            "group": self.group,
+            "max_age": self.max_age_secs,
+            "endpoints": [{ "url": self.url }],
+        })
+        .to_string()
+    }
+
+    fn validate(&self, id: &str) -> Result<(), ConfigError> {
+        if !self.url.starts_with("https://") || self.url.contains(['"', ' ']) {
+            return Err(ConfigError::validation(
+                id,
+                "report_to.url",
+                ValidationErrorKind::Invalid("must be an https URL".to_string()),
+            ));
+        }
+        // A structured-field key in Reporting-Endpoints
+        let mut chars = self.group.chars();
+        let is_key = chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "_-".contains(c));
+        if !is_key {
+            return Err(ConfigError::validation(
+                id,
+                "report_to.group",
+                ValidationErrorKind::Invalid(
+                    "must be a lowercase name of letters, digits, '_' and '-'".to_string(),
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Body of a blocked response with a given status code. Shared with the
+/// decisions using it rather than copied per request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CustomBody {
+    /// Response body
+    pub body: Arc<str>,
+
+    /// Content-Type header
+    #[serde(default = "default_custom_body_content_type")]
+    pub content_type: Arc<str>,
+}
+
+impl CustomBody {
+    /// The body configured in `bodies` for a blocked response's status.
+    pub fn for_status(endpoint: &DeprecatedEndpoint, status_code: u16) -> Option<Self> {
+        endpoint.bodies.get(&status_code).cloned()
+    }
+}
+
+fn default_custom_body_content_type() -> Arc<str> {
+    Arc::from("application/json")
+}
+
+/// Marker cookie of `warn_once`: a request carrying it gets no notice headers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WarnOnce {
+    /// Name of the marker cookie
+    pub cookie_name: String,
+
+    /// How long the marker lasts, and so how long notices stay silent
+    /// (default: 1 day)
+    #[serde(default = "default_warn_once_ttl")]
+    pub ttl_secs: u64,
+}
+
+fn default_warn_once_ttl() -> u64 {
+    86400
+}
+
+impl WarnOnce {
+    /// Whether a `Cookie` header carries an unexpired marker. The marker's
+    /// value is its expiry as a Unix timestamp, so a marker a client keeps
+    /// past its Max-Age doesn't silence notices either.
+    pub fn is_marked(&self, cookies: Option<&str>, now: DateTime<Utc>) -> bool {
+        cookies
+            .into_iter()
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .any(|(name, value)| {
+                name == self.cookie_name
+                    && value
+                        .parse::<i64>()
+                        .is_ok_and(|expiry| expiry > now.timestamp())
+            })
+    }
+
+    /// `Set-Cookie` value for a marker set at `now`: sent over HTTPS only,
+    /// hidden from scripts and left out of cross-site requests.
+    pub fn set_cookie(&self, now: DateTime<Utc>) -> String {
+        let expiry = now.timestamp().saturating_add_unsigned(self.ttl_secs);
+        format!(
+            "{}={}; Max-Age={}; Path=/; Secure; HttpOnly; SameSite=Lax",
+            self.cookie_name, expiry, self.ttl_secs
+        )
+    }
+
+    fn validate(&self, id: &str) -> Result<(), ConfigError> {
+        let is_token = |name: &str| {
+            !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+        };
+        if !is_token(&self.cookie_name) {
+            return Err(ConfigError::validation(
+                id,
+                "warn_once.cookie_name",
+                ValidationErrorKind::Invalid("must be a valid cookie name".to_string()),
+            ));
+        }
+        if self.ttl_secs == 0 {
+            return Err(ConfigError::validation(
+                id,
+                "warn_once.ttl_secs",
+                ValidationErrorKind::Invalid("must be greater than 0".to_string()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// How much a deprecation matters to its consumers.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Internal or debug routes; accesses are logged at debug level
+    Low,
+    /// Accesses are logged at info level
+    #[default]
+    Normal,
+    /// Accesses are logged at warn level
+    High,
+    /// Partner-facing APIs; accesses are logged at warn level, and missing
+    /// documentation or replacement is warned about
+    Critical,
+}
+
+impl Severity {
+    /// Label used in metrics and decision metadata.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::High => "high",
+            Self::Critical => "critical",
+        }
+    }
+
+    /// Level at which accesses to the endpoint are logged.
+    pub fn access_log_level(self) -> tracing::Level {
+        match self {
+            Self::Low => tracing::Level::DEBUG,
+            Self::Normal => tracing::Level::INFO,
+            Self::High | Self::Critical => tracing::Level::WARN,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// Information about the replacement endpoint.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -235,6 +1715,12 @@ pub struct ReplacementInfo {
     #[serde(default = "default_true")]
     pub preserve_query: bool,
 
+    /// Prefix of the request path replaced by `path` when redirecting, keeping
+    /// the rest of the request path (e.g. `/api/v1` with path `/api/v2`
+    /// redirects `/api/v1/users/5` to `/api/v2/users/5`)
+    #[serde(default)]
+    pub rewrite_prefix: Option<String>,
+
     /// Path parameter mappings (old param name -> new param name)
     #[serde(default)]
     pub param_mappings: HashMap<String, String>,
@@ -244,234 +1730,2767 @@ pub struct ReplacementInfo {
     pub method: Option<String>,
 }
 
-/// Action to take when a deprecated endpoint is accessed.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-pub enum DeprecationAction {
-    /// Allow the request but add deprecation headers
-    #[default]
-    Warn,
+impl ReplacementInfo {
+    /// Redirect target for a request path, before the query string is added.
+    pub fn target_for(&self, request_path: &str) -> String {
+        match self
+            .rewrite_prefix
+            .as_deref()
+            .and_then(|prefix| request_path.strip_prefix(prefix))
+        {
+            Some(rest) => format!("{}{}", self.path, rest),
+            None => self.path.clone(),
+        }
+    }
+}
 
-    /// Redirect to the replacement endpoint
-    Redirect {
-        /// HTTP status code for redirect (default: 308 Permanent Redirect)
-        #[serde(default = "default_redirect_code")]
-        status_code: u16,
-    },
+/// Deprecation of an entire API version.
+///
+/// Expands into a rule matching every path under `prefix`. When `successor`
+/// is set, the rule's replacement swaps the prefix for the successor and keeps
+/// the rest of the path, so a redirect action sends `/api/v1/users/5` to
+/// `/api/v2/users/5`. Explicit `endpoints` take precedence over the expanded
+/// rule, since it is placed after them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VersionDeprecation {
+    /// Unique identifier for the expanded rule
+    pub id: String,
 
-    /// Block the request with an error response
-    Block {
-        /// HTTP status code (default: 410 Gone)
-        #[serde(default = "default_gone_code")]
-        status_code: u16,
-    },
+    /// Path prefix of the deprecated version (e.g. `/api/v1`)
+    pub prefix: String,
 
-    /// Custom response
-    Custom {
-        /// HTTP status code
-        status_code: u16,
-        /// Response body
-        body: String,
-        /// Content-Type header
-        #[serde(default = "default_content_type")]
-        content_type: String,
-    },
-}
+    /// Path prefix of the successor version (e.g. `/api/v2`)
+    #[serde(default)]
+    pub successor: Option<String>,
 
-fn default_redirect_code() -> u16 {
-    308
-}
+    /// Paths under the prefix that are not deprecated (globs allowed)
+    #[serde(default)]
+    pub exclude: Vec<String>,
 
-fn default_gone_code() -> u16 {
-    410
-}
+    /// Deprecation status
+    #[serde(default)]
+    pub status: DeprecationStatus,
 
-fn default_content_type() -> String {
-    "application/json".to_string()
-}
+    /// Date when the version was deprecated (RFC 3339 or `YYYY-MM-DD`)
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub deprecated_at: Option<DateTime<Utc>>,
 
-/// Global settings for the agent.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct GlobalSettings {
-    /// Header name for deprecation warnings (default: Deprecation)
-    #[serde(default = "default_deprecation_header")]
-    pub deprecation_header: String,
+    /// Date when the version will be/was removed (RFC 3339 or `YYYY-MM-DD`)
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub sunset_at: Option<DateTime<Utc>>,
 
-    /// Header name for sunset date (default: Sunset)
-    #[serde(default = "default_sunset_header")]
-    pub sunset_header: String,
+    /// Link to migration documentation: a URL, or a list of typed links
+    #[serde(default)]
+    pub documentation_url: Option<DocumentationLinks>,
 
-    /// Header name for link to documentation (default: Link)
-    #[serde(default = "default_link_header")]
-    pub link_header: String,
+    /// Team owning the deprecation
+    #[serde(default)]
+    pub owner: Option<String>,
 
-    /// Header name for deprecation message (default: X-Deprecation-Notice)
-    #[serde(default = "default_notice_header")]
-    pub notice_header: String,
+    /// Who to ask about the deprecation: an email address or a URL
+    #[serde(default)]
+    pub contact: Option<String>,
 
-    /// Whether to include deprecation headers on all matching requests
-    #[serde(default = "default_true")]
-    pub include_headers: bool,
+    /// How much the deprecation matters: sets the access log level and is
+    /// exported in metrics and decision metadata
+    #[serde(default)]
+    pub severity: Severity,
 
-    /// Default action for endpoints past their sunset date
+    /// Operator notes, shown in usage reports and access logs but never sent
+    /// to clients
     #[serde(default)]
-    pub past_sunset_action: PastSunsetAction,
+    pub notes: Option<String>,
 
-    /// Whether to log all deprecated endpoint access
+    /// Alert when the request rate over a metrics refresh interval exceeds
+    /// this multiple of its moving average, or the last day of requests this
+    /// multiple of the average day before it (default:
+    /// `metrics.usage_spike_factor`; unset there too: never)
+    #[serde(default)]
+    pub spike_threshold: Option<f64>,
+
+    /// Custom deprecation message
+    #[serde(default)]
+    pub message: Option<String>,
+
+    /// Action to take when the version is accessed
+    #[serde(default)]
+    pub action: DeprecationAction,
+
+    /// Additional headers to add to responses
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+
+    /// Whether to track usage of the version
     #[serde(default = "default_true")]
-    pub log_access: bool,
+    pub track_usage: bool,
+
+    /// Identifier used as the `endpoint_id` metrics label (defaults to `id`)
+    #[serde(default)]
+    pub metrics_id: Option<String>,
+
+    /// Labels describing the version's metrics, as for endpoints
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// Confirms that the version's rule may block traffic (see
+    /// `DeprecatedEndpoint::confirmed`)
+    #[serde(default)]
+    pub confirmed: Option<bool>,
 }
 
-impl Default for GlobalSettings {
-    fn default() -> Self {
-        Self {
-            deprecation_header: default_deprecation_header(),
-            sunset_header: default_sunset_header(),
-            link_header: default_link_header(),
-            notice_header: default_notice_header(),
-            include_headers: true,
-            past_sunset_action: PastSunsetAction::default(),
-            log_access: true,
+impl VersionDeprecation {
+    /// Validate the parts that don't carry over to the expanded rule.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (field, prefix) in [
+            ("prefix", Some(&self.prefix)),
+            ("successor", self.successor.as_ref()),
+        ] {
+            let Some(prefix) = prefix else {
+                continue;
+            };
+            if prefix.is_empty() {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    field,
+                    ValidationErrorKind::Empty,
+                ));
+            }
+            if !prefix.starts_with('/') || is_glob_pattern(prefix) {
+                return Err(ConfigError::validation(
+                    &self.id,
+                    field,
+                    ValidationErrorKind::Invalid(
+                        "must be a literal path starting with '/'".to_string(),
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The rule this version deprecation expands into.
+    pub fn to_endpoint(&self) -> DeprecatedEndpoint {
+        let prefix = self.prefix.trim_end_matches('/');
+
+        DeprecatedEndpoint {
+            id: self.id.clone(),
+            path: prefix.into(),
+            methods: vec![],
+            status: self.status.clone(),
+            deprecated_at: self.deprecated_at,
+            sunset_at: self.sunset_at,
+            active_from: None,
+            active_until: None,
+            replacement: self.successor.as_ref().map(|successor| ReplacementInfo {
+                path: successor.trim_end_matches('/').to_string(),
+                preserve_query: true,
+                rewrite_prefix: Some(prefix.to_string()),
+                param_mappings: HashMap::new(),
+                method: None,
+            }),
+            documentation_url: self.documentation_url.clone(),
+            alternate_links: vec![],
+            message: self.message.clone(),
+            action: self.action.clone(),
+            headers: self.headers.clone(),
+            computed_headers: HashMap::new(),
+            track_usage: self.track_usage,
+            metrics_id: self.metrics_id.clone(),
+            labels: self.labels.clone(),
+            unless: vec![],
+            exclude: self.exclude.clone(),
+            deprecated_params: vec![],
+            deprecated_request_headers: vec![],
+            tombstone: None,
+            owner: self.owner.clone(),
+            contact: self.contact.clone(),
+            severity: self.severity,
+            notes: self.notes.clone(),
+            spike_threshold: self.spike_threshold,
+            examples: vec![],
+            warn_once: None,
+            report_to: None,
+            bodies: HashMap::new(),
+            body_field_deprecated: vec![],
+            deprecated_body_fields: vec![],
+            confirmed: self.confirmed,
+            path_matchers: vec![],
+            exclude_matchers: vec![],
+            head_as_get: false,
         }
     }
 }
 
-fn default_deprecation_header() -> String {
-    "Deprecation".to_string()
+/// Scheme and lowercased host (without userinfo or port) of an absolute
+/// http(s) URL.
+fn url_origin(url: &str) -> Option<(&'static str, String)> {
+    let url = url::Url::parse(url).ok()?;
+    let scheme = match url.scheme() {
+        "https" => "https",
+        "http" => "http",
+        _ => return None,
+    };
+    Some((scheme, url.host_str()?.to_ascii_lowercase()))
 }
 
-fn default_sunset_header() -> String {
-    "Sunset".to_string()
+/// `path` prefixed with `base`, unless it already starts with it.
+fn under_base_path(base: &str, path: &str) -> String {
+    match path.strip_prefix(base) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => path.to_string(),
+        _ => format!("{}{}", base, path),
+    }
 }
 
-fn default_link_header() -> String {
-    "Link".to_string()
+/// Whether a base path is `/` followed by non-empty, literal segments.
+fn is_clean_base_path(base: &str) -> bool {
+    base.strip_prefix('/').is_some_and(|segments| {
+        segments.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+        })
+    })
 }
 
-fn default_notice_header() -> String {
-    "X-Deprecation-Notice".to_string()
+/// Compile a glob pattern; `None` for literal or invalid patterns.
+fn compile_pattern(pattern: &str) -> Option<globset::GlobMatcher> {
+    is_glob_pattern(pattern)
+        .then(|| globset::Glob::new(pattern).ok())
+        .flatten()
+        .map(|glob| glob.compile_matcher())
 }
 
-/// Action to take when an endpoint is accessed past its sunset date.
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Check if a path matches a pattern, given its compiled matcher (`None` when
+/// the rule hasn't been compiled).
+fn pattern_matches(
+    pattern: &str,
+    matcher: Option<&Option<globset::GlobMatcher>>,
+    path: &str,
+) -> bool {
+    // Simple prefix/exact matching for common cases
+    if !is_glob_pattern(pattern) {
+        // Exact match or prefix match with trailing slash
+        return path == pattern
+            || path.starts_with(&format!("{}/", pattern))
+            || (pattern.ends_with('/') && path.starts_with(pattern));
+    }
+
+    match matcher {
+        // Use the precompiled matcher when available
+        Some(Some(matcher)) => matcher.is_match(path),
+        // The pattern failed to compile
+        Some(None) => false,
+        // Use glob matching for patterns that haven't been compiled
+        None => globset::Glob::new(pattern)
+            .map(|glob| glob.compile_matcher().is_match(path))
+            .unwrap_or(false),
+    }
+}
+
+/// Whether a path pattern is a glob rather than a literal path.
+///
+/// Only `*` and `?` make a pattern a glob, so literal paths documenting
+/// parameters like `/users/{id}` keep matching as written. Within a glob,
+/// `[...]` classes and `{a,b}` alternatives work as usual.
+pub fn is_glob_pattern(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// Check that a path pattern can match what it looks like it should: a glob
+/// must compile, and a literal path must not have an unbalanced `[` or `{`.
+/// Such a path is almost always a glob typo (`/api/v1/[unclosed`), and as a
+/// literal it would only match itself, leaving the rule silently dead.
+fn check_pattern(pattern: &str) -> Result<(), String> {
+    if is_glob_pattern(pattern) {
+        return globset::Glob::new(pattern)
+            .map(drop)
+            .map_err(|err| err.to_string());
+    }
+
+    let (mut brackets, mut braces) = (0i32, 0i32);
+    for c in pattern.chars() {
+        match c {
+            '[' => brackets += 1,
+            ']' => brackets -= 1,
+            '{' => braces += 1,
+            '}' => braces -= 1,
+            _ => {}
+        }
+        if brackets < 0 || braces < 0 {
+            break;
+        }
+    }
+    if brackets != 0 || braces != 0 {
+        return Err(format!(
+            "unbalanced '[' or '{{' in literal path '{}'",
+            pattern
+        ));
+    }
+    Ok(())
+}
+
+/// Labels the generated alerting rules set themselves, which a rule's
+/// `labels` can't override.
+const RESERVED_LABELS: &[&str] = &["alertname", "endpoint_id", "severity"];
+
+/// Whether `name` is a valid Prometheus label name (`[a-zA-Z_][a-zA-Z0-9_]*`,
+/// not starting with the reserved `__`).
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
+
+/// One or more path patterns for a rule, matched as alternatives.
+///
+/// Deserializes from a single pattern or a list of aliases, and serializes
+/// back the same way. The first pattern is the primary one, used wherever a
+/// single path is shown (messages, metric labels).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathPatterns(Vec<String>);
+
+impl PathPatterns {
+    /// The first pattern.
+    pub fn primary(&self) -> &str {
+        self.0.first().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Patterns after the primary one.
+    pub fn aliases(&self) -> &[String] {
+        self.0.get(1..).unwrap_or_default()
+    }
+
+    /// All patterns, primary first.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Display for PathPatterns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0.join(", "))
+    }
+}
+
+impl From<&str> for PathPatterns {
+    fn from(pattern: &str) -> Self {
+        Self(vec![pattern.to_string()])
+    }
+}
+
+impl From<String> for PathPatterns {
+    fn from(pattern: String) -> Self {
+        Self(vec![pattern])
+    }
+}
+
+impl From<Vec<String>> for PathPatterns {
+    fn from(patterns: Vec<String>) -> Self {
+        Self(patterns)
+    }
+}
+
+impl PartialEq<&str> for PathPatterns {
+    fn eq(&self, other: &&str) -> bool {
+        self.0.len() == 1 && self.0[0] == *other
+    }
+}
+
+impl Serialize for PathPatterns {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0.as_slice() {
+            [single] => serializer.serialize_str(single),
+            patterns => patterns.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for PathPatterns {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct PatternsVisitor;
+
+        impl<'de> de::Visitor<'de> for PatternsVisitor {
+            type Value = PathPatterns;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a path pattern or a list of path patterns")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                Ok(PathPatterns::from(value))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut patterns = Vec::new();
+                while let Some(pattern) = seq.next_element::<String>()? {
+                    patterns.push(pattern);
+                }
+                Ok(PathPatterns(patterns))
+            }
+        }
+
+        deserializer.deserialize_any(PatternsVisitor)
+    }
+}
+
+/// Alternate representation of a deprecated resource.
+///
+/// Used for representation-level deprecations, e.g. an XML format being
+/// retired in favor of JSON at the same path.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlternateLink {
+    /// Link target
+    pub href: String,
+
+    /// Media type of the alternate representation
+    #[serde(rename = "type")]
+    pub media_type: String,
+}
+
+/// Documentation links of a rule: a single URL (`rel="deprecation"`) or a
+/// list of typed links, each emitted as its own Link header value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DocumentationLinks {
+    Url(String),
+    Links(Vec<DocumentationLink>),
+}
+
+/// A typed documentation link (e.g. a migration guide, changelog or support
+/// contact).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DocumentationLink {
+    /// Link target
+    pub url: String,
+
+    /// Link relation (e.g. `deprecation`, `describedby`, `help`)
+    #[serde(default = "default_documentation_rel")]
+    pub rel: String,
+
+    /// Human-readable title, sent as the `title` parameter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+fn default_documentation_rel() -> String {
+    "deprecation".to_string()
+}
+
+impl DocumentationLinks {
+    /// All links, with a plain URL as a `deprecation` link.
+    pub fn links(&self) -> Vec<DocumentationLink> {
+        match self {
+            Self::Url(url) => vec![DocumentationLink {
+                url: url.clone(),
+                rel: default_documentation_rel(),
+                title: None,
+            }],
+            Self::Links(links) => links.clone(),
+        }
+    }
+
+    /// URL quoted in notices: the first `deprecation` link, or the first link.
+    pub fn primary_url(&self) -> Option<&str> {
+        match self {
+            Self::Url(url) => Some(url),
+            Self::Links(links) => links
+                .iter()
+                .find(|link| link.rel == "deprecation")
+                .or_else(|| links.first())
+                .map(|link| link.url.as_str()),
+        }
+    }
+
+    /// Link header values (RFC 8288), one per link.
+    pub fn link_values(&self) -> Vec<String> {
+        self.links()
+            .iter()
+            .map(DocumentationLink::link_value)
+            .collect()
+    }
+
+    /// Links for JSON bodies: `[{url, rel, title}]`.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self.links()).unwrap_or_default()
+    }
+
+    fn validate(&self, endpoint_id: &str) -> Result<(), ConfigError> {
+        let invalid = |reason: String| {
+            ConfigError::validation(
+                endpoint_id,
+                "documentation_url",
+                ValidationErrorKind::Invalid(reason),
+            )
+        };
+        let links = self.links();
+        if links.is_empty() {
+            return Err(ConfigError::validation(
+                endpoint_id,
+                "documentation_url",
+                ValidationErrorKind::Empty,
+            ));
+        }
+        for link in &links {
+            if !is_link_target(&link.url) {
+                return Err(invalid(format!("'{}' is not a valid URL", link.url)));
+            }
+            if link.rel.is_empty() || !link.rel.bytes().all(is_token_byte) {
+                return Err(invalid(format!(
+                    "rel '{}' must be a non-empty token",
+                    link.rel
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&str> for DocumentationLinks {
+    fn from(url: &str) -> Self {
+        Self::Url(url.to_string())
+    }
+}
+
+impl DocumentationLink {
+    /// `<url>; rel="..."; title="..."`
+    pub fn link_value(&self) -> String {
+        let mut value = format!("<{}>; rel=\"{}\"", self.url, self.rel);
+        if let Some(title) = &self.title {
+            value.push_str(&format!(
+                "; title=\"{}\"",
+                title.replace('\\', "\\\\").replace('"', "\\\"")
+            ));
+        }
+        value
+    }
+}
+
+/// Whether a link target is an absolute URL or an absolute path, and safe to
+/// put between `<` and `>` in a Link header.
+fn is_link_target(target: &str) -> bool {
+    let parses = url::Url::parse(target).is_ok()
+        || (target.starts_with('/')
+            && url::Url::parse("http://localhost")
+                .and_then(|base| base.join(target))
+                .is_ok());
+    parses
+        && !target
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '<' || c == '>')
+}
+
+/// Check that a contact is an email address or an http(s)/mailto URL.
+/// Check `computed_headers`: header names can't be empty and templates may
+/// only use the known placeholders.
+fn check_computed_headers(headers: &HashMap<String, String>) -> Result<(), String> {
+    if headers.keys().any(|name| name.trim().is_empty()) {
+        return Err("header names cannot be empty".to_string());
+    }
+    let mut names: Vec<_> = headers.keys().collect();
+    names.sort();
+    for name in names {
+        if let Some(placeholder) = crate::headers::unknown_placeholder(&headers[name]) {
+            return Err(format!(
+                "'{}' uses unknown placeholder '{}' (expected {})",
+                name,
+                placeholder,
+                crate::headers::COMPUTED_HEADER_PLACEHOLDERS.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn validate_contact(endpoint_id: &str, contact: &str) -> Result<(), ConfigError> {
+    if contact.is_empty() {
+        return Err(ConfigError::validation(
+            endpoint_id,
+            "contact",
+            ValidationErrorKind::Empty,
+        ));
+    }
+    let is_email = contact.split_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty()
+            && !domain.contains('@')
+            && domain.contains('.')
+            && !domain.starts_with('.')
+            && !domain.ends_with('.')
+    }) && !contact.contains(char::is_whitespace)
+        && !contact.contains(':');
+    let is_url = url::Url::parse(contact)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https" | "mailto"))
+        && !contact.contains(char::is_whitespace);
+    if is_email || is_url {
+        Ok(())
+    } else {
+        Err(ConfigError::validation(
+            endpoint_id,
+            "contact",
+            ValidationErrorKind::Invalid(format!(
+                "'{}' must be an email address or an http(s) URL",
+                contact
+            )),
+        ))
+    }
+}
+
+/// Whether a byte may appear in an HTTP token (RFC 9110 `tchar`).
+fn is_token_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+/// A deprecated query parameter on an otherwise supported endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeprecatedParam {
+    /// Parameter name
+    pub name: String,
+
+    /// When the parameter will stop being accepted
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub sunset_at: Option<DateTime<Utc>>,
+
+    /// Parameter to use instead
+    #[serde(default)]
+    pub replacement: Option<String>,
+
+    /// What to do with requests using the parameter
+    #[serde(default)]
+    pub action: ParamAction,
+}
+
+impl DeprecatedParam {
+    /// Notice naming the parameter, its sunset date and replacement.
+    pub fn message(&self) -> String {
+        let mut message = format!("The query parameter '{}' is deprecated", self.name);
+
+        if let Some(sunset) = &self.sunset_at {
+            message.push_str(&format!(
+                " and will be removed on {}",
+                sunset.format("%Y-%m-%d")
+            ));
+        }
+
+        if let Some(replacement) = &self.replacement {
+            message.push_str(&format!(". Please use '{}' instead", replacement));
+        }
+
+        message.push('.');
+        message
+    }
+}
+
+/// A request header clients should stop sending.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeprecatedHeader {
+    /// Header name (matched case-insensitively)
+    pub name: String,
+
+    /// When the header will stop being accepted
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub sunset_at: Option<DateTime<Utc>>,
+
+    /// Header to send instead
+    #[serde(default)]
+    pub replacement: Option<String>,
+
+    /// What to do with requests sending the header: `warn`, `strip` (remove
+    /// it before forwarding) or `block`
+    #[serde(default)]
+    pub action: ParamAction,
+
+    /// Confirms that a `block` may reject requests. Unset, an endpoint's
+    /// entries follow the rule's `confirmed` and global entries
+    /// `settings.require_confirmation`
+    #[serde(default)]
+    pub confirmed: Option<bool>,
+}
+
+impl DeprecatedHeader {
+    /// Notice naming the header, its sunset date and replacement.
+    pub fn message(&self) -> String {
+        let mut message = format!("The request header '{}' is deprecated", self.name);
+
+        if let Some(sunset) = &self.sunset_at {
+            message.push_str(&format!(
+                " and will be removed on {}",
+                sunset.format("%Y-%m-%d")
+            ));
+        }
+
+        if let Some(replacement) = &self.replacement {
+            message.push_str(&format!(". Please use '{}' instead", replacement));
+        }
+
+        message.push('.');
+        message
+    }
+}
+
+/// Caller tracking for a removed endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tombstone {
+    /// Sample one in this many rejected requests into the diagnostics buffer
+    #[serde(default = "default_sample_every")]
+    pub sample_every: u32,
+}
+
+fn default_sample_every() -> u32 {
+    1
+}
+
+/// A deprecated field of a JSON request body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeprecatedBodyField {
+    /// JSON pointer to the field (e.g. `/user_name`)
+    pub pointer: String,
+
+    /// Pointer to the field to use instead
+    #[serde(default)]
+    pub replacement: Option<String>,
+
+    /// What to do with requests whose body contains the field
+    #[serde(default)]
+    pub action: BodyFieldAction,
+}
+
+impl DeprecatedBodyField {
+    /// Notice naming the field and its replacement.
+    pub fn message(&self, sunset_at: Option<DateTime<Utc>>) -> String {
+        let mut message = format!("The request body field '{}' is deprecated", self.pointer);
+
+        if let Some(sunset) = &sunset_at {
+            message.push_str(&format!(
+                " and will be removed on {}",
+                sunset.format("%Y-%m-%d")
+            ));
+        }
+
+        if let Some(replacement) = &self.replacement {
+            message.push_str(&format!(". Please use '{}' instead", replacement));
+        }
+
+        message.push('.');
+        message
+    }
+}
+
+/// Action for requests whose body contains a deprecated field.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
-pub enum PastSunsetAction {
-    /// Continue to allow with headers (default)
+pub enum BodyFieldAction {
+    /// Allow the request, listing the fields in a response header
     #[default]
     Warn,
-    /// Block with 410 Gone
+    /// Reject the request with 400 and a problem body naming the fields
     Block,
-    /// Redirect to replacement (if available)
-    Redirect,
 }
 
-/// Metrics configuration.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(deny_unknown_fields)]
-pub struct MetricsConfig {
-    /// Whether to expose Prometheus metrics
-    #[serde(default = "default_true")]
-    pub enabled: bool,
+impl BodyFieldAction {
+    /// Name of the action, as used in metric labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Warn => "warn",
+            Self::Block => "block",
+        }
+    }
+}
+
+/// Action for requests using a deprecated query parameter or request header.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamAction {
+    /// Allow the request with a notice header
+    #[default]
+    Warn,
+    /// Redirect (307) to the same URL without the parameter; remove a header
+    /// before forwarding
+    Strip,
+    /// Reject the request with 400 and a body naming the parameter or header
+    Block,
+}
+
+impl ParamAction {
+    /// Name of the action, as used in metric labels.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Warn => "warn",
+            Self::Strip => "strip",
+            Self::Block => "block",
+        }
+    }
+}
+
+/// Action to take when a deprecated endpoint is accessed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeprecationAction {
+    /// Allow the request but add deprecation headers
+    #[default]
+    Warn,
+
+    /// Redirect to the replacement endpoint
+    Redirect {
+        /// HTTP status code for redirect (default: 308 Permanent Redirect)
+        #[serde(default = "default_redirect_code")]
+        status_code: u16,
+    },
+
+    /// Block the request with an error response
+    Block {
+        /// HTTP status code (default: 410 Gone)
+        #[serde(default = "default_gone_code")]
+        status_code: u16,
+    },
+
+    /// Custom response
+    Custom {
+        /// HTTP status code
+        status_code: u16,
+        /// Response body (default: the endpoint's gone body, in the
+        /// configured `settings.error_envelope`)
+        #[serde(default)]
+        body: Option<String>,
+        /// Content-Type header
+        #[serde(default = "default_content_type")]
+        content_type: String,
+    },
+}
+
+fn default_redirect_code() -> u16 {
+    308
+}
+
+fn default_gone_code() -> u16 {
+    410
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+/// Shape of the JSON error bodies of deprecated and removed endpoints, and
+/// of the 400s rejecting deprecated parameters, headers and body fields, for
+/// APIs with their own error contract.
+///
+/// The bodies are built with `error` (a code such as `endpoint_removed`) and
+/// `message` fields plus details (`endpoint`, `sunset`, `replacement`,
+/// `documentation`, ...). The envelope renames the first two, can nest the
+/// details and the whole body under keys of their own, and adds static fields.
+/// The default keeps the flat `{"error": ..., "message": ...}` shape.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorEnvelope {
+    /// Key of the error code (default: error)
+    #[serde(default = "default_error_key")]
+    pub error_key: String,
+
+    /// Key of the human-readable message (default: message)
+    #[serde(default = "default_message_key")]
+    pub message_key: String,
+
+    /// Key the details are nested under (unset: alongside the code)
+    #[serde(default)]
+    pub details_key: Option<String>,
+
+    /// Key the whole body is nested under (unset: not wrapped)
+    #[serde(default)]
+    pub wrapper_key: Option<String>,
+
+    /// Static fields added next to the code, unless a field of the body
+    /// already uses the key
+    #[serde(default)]
+    pub extra_fields: BTreeMap<String, serde_json::Value>,
+}
+
+impl Default for ErrorEnvelope {
+    fn default() -> Self {
+        Self {
+            error_key: default_error_key(),
+            message_key: default_message_key(),
+            details_key: None,
+            wrapper_key: None,
+            extra_fields: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_error_key() -> String {
+    "error".to_string()
+}
+
+fn default_message_key() -> String {
+    "message".to_string()
+}
+
+impl ErrorEnvelope {
+    /// Whether this is the default, flat shape.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Content type of the deprecated body field rejections: RFC 9457
+    /// problem details in the default shape, plain JSON in any other.
+    pub fn body_fields_content_type(&self) -> &'static str {
+        if self.is_default() {
+            "application/problem+json"
+        } else {
+            "application/json"
+        }
+    }
+
+    /// Reshape a body built with `error` and `message` fields.
+    pub fn apply(&self, body: serde_json::Value) -> serde_json::Value {
+        let serde_json::Value::Object(mut fields) = body else {
+            return body;
+        };
+        let error = fields.remove("error");
+        let message = fields.remove("message");
+
+        let mut shaped = serde_json::Map::new();
+        if let Some(error) = error {
+            shaped.insert(self.error_key.clone(), error);
+        }
+        if let Some(message) = message {
+            shaped.insert(self.message_key.clone(), message);
+        }
+        match &self.details_key {
+            Some(key) if !fields.is_empty() => {
+                shaped.insert(key.clone(), serde_json::Value::Object(fields));
+            }
+            Some(_) => {}
+            None => shaped.extend(fields),
+        }
+        for (key, value) in &self.extra_fields {
+            shaped.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+
+        match &self.wrapper_key {
+            Some(key) => serde_json::json!({ key.clone(): shaped }),
+            None => serde_json::Value::Object(shaped),
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let keys = [
+            Some(&self.error_key),
+            Some(&self.message_key),
+            self.details_key.as_ref(),
+            self.wrapper_key.as_ref(),
+        ];
+        if keys.iter().flatten().any(|key| key.is_empty()) {
+            return Err(ConfigError::Setting {
+                field: "error_envelope",
+                kind: ValidationErrorKind::Invalid("keys cannot be empty".to_string()),
+            });
+        }
+        if self.error_key == self.message_key
+            || self
+                .details_key
+                .as_ref()
+                .is_some_and(|key| *key == self.error_key || *key == self.message_key)
+        {
+            return Err(ConfigError::Setting {
+                field: "error_envelope",
+                kind: ValidationErrorKind::Invalid(
+                    "error_key, message_key and details_key must differ".to_string(),
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Where to read the identity of the client sending a request from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClientIdentitySource {
+    /// A request header carrying the client id as is
+    Header { name: String },
+    /// A header in which the proxy terminating mTLS forwards the client
+    /// certificate's subject (a CN, or a whole DN)
+    ClientCert {
+        #[serde(default = "default_client_cert_header")]
+        header: String,
+        /// Regex pulling the identity out of the header value (e.g. the
+        /// organization of a DN): its first capture group, or the whole
+        /// match, with DN escapes (`\,`, `\2C`) decoded. Without one the
+        /// value is used as is.
+        #[serde(default)]
+        pattern: Option<String>,
+        /// Compiled `pattern` (not serialized)
+        #[serde(skip)]
+        matcher: Option<regex::Regex>,
+    },
+}
+
+fn default_client_cert_header() -> String {
+    "X-Client-Cert-CN".to_string()
+}
+
+impl ClientIdentitySource {
+    /// Request header the source reads.
+    pub fn header(&self) -> &str {
+        match self {
+            Self::Header { name } => name,
+            Self::ClientCert { header, .. } => header,
+        }
+    }
+
+    /// Identity in a value of the source's header, if there is one.
+    ///
+    /// Requires [`compile`](Self::compile) for patterns to apply.
+    pub fn identity(&self, value: &str) -> Option<String> {
+        let value = value.trim();
+        let identity = match self {
+            Self::ClientCert {
+                matcher: Some(matcher),
+                ..
+            } => {
+                let captures = matcher.captures(value)?;
+                let matched = captures.get(1).or_else(|| captures.get(0))?;
+                unescape_dn_value(matched.as_str().trim())
+            }
+            _ => value.to_string(),
+        };
+        (!identity.is_empty()).then_some(identity)
+    }
+
+    fn compile(&mut self) {
+        if let Self::ClientCert {
+            pattern, matcher, ..
+        } = self
+        {
+            *matcher = pattern
+                .as_deref()
+                .and_then(|pattern| regex::Regex::new(pattern).ok());
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self.header().is_empty() {
+            return Err(ConfigError::Setting {
+                field: "client_identity",
+                kind: ValidationErrorKind::Invalid("header names cannot be empty".to_string()),
+            });
+        }
+        if let Self::ClientCert {
+            pattern: Some(pattern),
+            ..
+        } = self
+        {
+            if let Err(err) = regex::Regex::new(pattern) {
+                return Err(ConfigError::Setting {
+                    field: "client_identity",
+                    kind: ValidationErrorKind::Invalid(err.to_string()),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decode the escapes of an attribute value in a DN string (RFC 4514): a
+/// backslash followed by a special character, or by two hex digits of a
+/// UTF-8 byte.
+fn unescape_dn_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 1 < bytes.len() {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .and_then(|pair| std::str::from_utf8(pair).ok())
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok());
+            match hex {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                None => {
+                    decoded.push(bytes[i + 1]);
+                    i += 2;
+                }
+            }
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Global settings for the agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalSettings {
+    /// Header name for deprecation warnings (default: Deprecation)
+    #[serde(default = "default_deprecation_header")]
+    pub deprecation_header: String,
+
+    /// Transitional mode sending `Deprecation: true` for legacy clients and
+    /// the `@<timestamp>` form in `deprecation_timestamp_header`
+    #[serde(default)]
+    pub dual_deprecation_headers: bool,
+
+    /// Header carrying the deprecation timestamp with
+    /// `dual_deprecation_headers` (default: X-Deprecation-Timestamp)
+    #[serde(default = "default_deprecation_timestamp_header")]
+    pub deprecation_timestamp_header: String,
+
+    /// Header name for sunset date (default: Sunset)
+    #[serde(default = "default_sunset_header")]
+    pub sunset_header: String,
+
+    /// Also send the sunset as an RFC 3339 timestamp in `iso_sunset_header`,
+    /// next to the HTTP-date in `sunset_header`
+    #[serde(default)]
+    pub emit_iso_sunset: bool,
+
+    /// Header carrying the RFC 3339 sunset with `emit_iso_sunset`
+    /// (default: X-Sunset-ISO8601)
+    #[serde(default = "default_iso_sunset_header")]
+    pub iso_sunset_header: String,
+
+    /// Header name for link to documentation (default: Link)
+    #[serde(default = "default_link_header")]
+    pub link_header: String,
+
+    /// Header name for deprecation message (default: X-Deprecation-Notice)
+    #[serde(default = "default_notice_header")]
+    pub notice_header: String,
+
+    /// Header carrying an endpoint's `contact`, when set (default:
+    /// X-Deprecation-Contact)
+    #[serde(default = "default_contact_header")]
+    pub contact_header: String,
+
+    /// Budget for the bytes of headers an endpoint adds to a response (names,
+    /// values and separators); headers over it are dropped by priority, see
+    /// `DeprecationHeaders::trim`. 0 disables the limit (default: 4096)
+    #[serde(default = "default_max_added_header_bytes")]
+    pub max_added_header_bytes: usize,
+
+    /// Whether to include deprecation headers on all matching requests
+    #[serde(default = "default_true")]
+    pub include_headers: bool,
+
+    /// Default action for endpoints past their sunset date
+    #[serde(default)]
+    pub past_sunset_action: PastSunsetAction,
+
+    /// Whether to log all deprecated endpoint access
+    #[serde(default = "default_true")]
+    pub log_access: bool,
+
+    /// Headers added to every deprecated response (endpoint headers win on conflict)
+    #[serde(default)]
+    pub global_headers: HashMap<String, String>,
+
+    /// Templated headers added to every deprecated response, rendered per
+    /// request (endpoint `computed_headers` win on conflict)
+    #[serde(default)]
+    pub computed_headers: HashMap<String, String>,
+
+    /// Apply endpoint actions to `OPTIONS` requests. By default CORS preflight
+    /// requests always get a warn (headers only) so browsers aren't broken.
+    #[serde(default)]
+    pub block_options: bool,
+
+    /// Add a countdown header with the days left until sunset to warn responses
+    #[serde(default)]
+    pub sunset_reminder: bool,
+
+    /// Header name for the sunset countdown (default: X-Days-Until-Sunset)
+    #[serde(default = "default_sunset_reminder_header")]
+    pub sunset_reminder_header: String,
+
+    /// Add an escalating warning level (0-3) to warn responses, for clients
+    /// that only branch on a number
+    #[serde(default)]
+    pub warning_level: bool,
+
+    /// Header name for the warning level (default: X-Deprecation-Warning-Level)
+    #[serde(default = "default_warning_level_header")]
+    pub warning_level_header: String,
+
+    /// Days before sunset at which the warning level rises to 1, 2 and 3
+    /// (default: 90, 30, 7)
+    #[serde(default = "default_warning_level_thresholds")]
+    pub warning_level_thresholds: [i64; 3],
+
+    /// What a failed configuration reload does to the agent's health
+    #[serde(default)]
+    pub reload_failure_mode: ReloadFailureMode,
+
+    /// Kill-switch for blocks and redirects: while false every action only
+    /// warns, and metrics still count the intended one
+    #[serde(default = "default_true")]
+    pub enforcement_enabled: bool,
+
+    /// Hold rules that can block in shadow mode until they set
+    /// `confirmed: true`. Off by default, so existing blocks keep blocking;
+    /// a rule with `confirmed: false` is held back either way
+    #[serde(default)]
+    pub require_confirmation: bool,
+
+    /// Number of (method, path) lookup results to cache (0 disables the cache)
+    #[serde(default)]
+    pub match_cache_size: usize,
+
+    /// Documentation URL template for endpoints without `documentation_url`,
+    /// with `{id}` and `{path}` placeholders
+    #[serde(default)]
+    pub documentation_base_url: Option<String>,
+
+    /// The API program's deprecation policy, linked from every deprecated
+    /// response as rel="deprecation-policy" and included in bodies and the
+    /// manifest
+    #[serde(default)]
+    pub policy_url: Option<String>,
+
+    /// Periodically send a HEAD request to each absolute replacement URL and
+    /// report the result (requires the `replacement-check` feature)
+    #[serde(default)]
+    pub check_replacements: bool,
+
+    /// Seconds between replacement checks (0 checks once at startup)
+    #[serde(default = "default_replacement_check_interval")]
+    pub replacement_check_interval_secs: u64,
+
+    /// Add debugging headers (e.g. X-Deprecation-Matched-Priority) to responses
+    #[serde(default)]
+    pub debug_headers: bool,
+
+    /// Path at which the agent serves a JSON catalog of all deprecations
+    /// (e.g. /.well-known/api-deprecations)
+    #[serde(default)]
+    pub manifest_path: Option<String>,
+
+    /// Log a per-rule match trace at debug level for requests carrying the
+    /// `X-Deprecation-Trace` header
+    #[serde(default)]
+    pub trace_match: bool,
+
+    /// Limits for state kept per client
+    #[serde(default)]
+    pub client_state: ClientStateConfig,
+
+    /// Only add the Sunset, countdown and warning level headers within this
+    /// many days of `sunset_at` (unset: always)
+    #[serde(default)]
+    pub sunset_header_window_days: Option<u32>,
+
+    /// Largest request body inspected for deprecated body fields
+    #[serde(default = "default_max_body_inspection_bytes")]
+    pub max_body_inspection_bytes: usize,
+
+    /// Pass bodies that can't be inspected (too large or not JSON) through
+    /// unchecked (default); otherwise reject them with 400 on confirmed rules
+    #[serde(default = "default_true")]
+    pub body_inspection_fail_open: bool,
+
+    /// Response header listing the deprecated body fields a request sent
+    #[serde(default = "default_deprecated_body_fields_header")]
+    pub deprecated_body_fields_header: String,
+
+    /// Seed for bucketing clients in gradual rollouts (default: 0). Agents
+    /// sharing a seed put each client in the same bucket, across restarts too.
+    #[serde(default)]
+    pub bucket_seed: Option<u64>,
+
+    /// Request header identifying the client (e.g. X-Client-Id), used for
+    /// per-client counts such as `{{hits}}`; tried after `client_identity`
+    #[serde(default)]
+    pub client_id_header: Option<String>,
+
+    /// Sources of client identity in priority order, the first one present
+    /// in a request winning; `client_id_header` is tried last
+    #[serde(default)]
+    pub client_identity: Vec<ClientIdentitySource>,
+
+    /// Prefix of every rule's path patterns and path-relative replacements,
+    /// for agents only seeing traffic under it (e.g. `/public-api`)
+    #[serde(default)]
+    pub base_path: Option<String>,
+
+    /// Request header carrying the path before an upstream rewrite (e.g.
+    /// X-Original-URI from nginx); rules match it instead of the request
+    /// path when present and the request path matches no rule. The proxy
+    /// must overwrite any client-sent value
+    #[serde(default)]
+    pub original_path_header: Option<String>,
+
+    /// Origin the deprecated API is served from (e.g. `https://api.example.com`).
+    /// Absolute replacement and alternate links are checked against it.
+    #[serde(default)]
+    pub public_origin: Option<String>,
+
+    /// Hosts other than the public origin's that absolute replacements may
+    /// point to
+    #[serde(default)]
+    pub allowed_link_hosts: Vec<String>,
+
+    /// Reject configurations with link issues instead of warning about them
+    #[serde(default)]
+    pub strict_links: bool,
+
+    /// Most recent requests kept in the diagnostics buffer (read at startup)
+    #[serde(default = "default_diagnostics_buffer_size")]
+    pub diagnostics_buffer_size: usize,
+
+    /// Shape of the JSON bodies of blocked requests (default: flat
+    /// `{"error": ..., "message": ...}`)
+    #[serde(default)]
+    pub error_envelope: ErrorEnvelope,
+
+    /// Request header added toward the upstream on allowed requests, naming
+    /// the matched rule and its sunset (unset: not added). A value sent by
+    /// the client is always removed, whether or not a rule matches
+    #[serde(default)]
+    pub upstream_header: Option<String>,
+
+    /// Days after an endpoint's sunset that clients still have to migrate,
+    /// reported in the `migration_deadline` decision metadata
+    #[serde(default)]
+    pub migration_grace_days: u32,
+
+    /// Take the deprecation headers the agent added back off upstream 304
+    /// (Not Modified) responses, which should only carry the headers RFC
+    /// 7232 allows
+    #[serde(default)]
+    pub strip_headers_on_not_modified: bool,
+
+    /// Ask the proxy to close the client connection after blocked responses
+    /// (the `close_connection` decision metadata), so clients retrying a
+    /// removed endpoint in a loop have to reconnect before trying again.
+    /// Warned requests keep their connection.
+    #[serde(default)]
+    pub close_connection_on_block: bool,
+
+    /// Match HEAD requests against rules listing `GET`, so monitoring probes
+    /// are counted and treated like the GET requests they stand in for.
+    /// HEAD blocks keep the status and headers but drop the body.
+    #[serde(default = "default_true")]
+    pub treat_head_as_get: bool,
+
+    /// Take the deprecation headers the agent added (and any `warn_once`
+    /// marker cookie) back off upstream responses whose status is in
+    /// `skip_headers_status_classes` (default: true), so error responses
+    /// aren't decorated with migration notices. Headers the upstream set
+    /// itself are kept, and accesses are recorded either way.
+    #[serde(default = "default_true")]
+    pub skip_headers_on_error: bool,
+
+    /// Status classes (`4xx`, `5xx`, ...) `skip_headers_on_error` applies to
+    /// (default: `[5xx]`)
+    #[serde(default = "default_skip_headers_status_classes")]
+    pub skip_headers_status_classes: Vec<String>,
+
+    /// Concurrent requests the agent advertises to the proxy (default: 100)
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: u32,
+
+    /// Request headers whose values are replaced with `***` wherever request
+    /// headers are recorded (case-insensitive)
+    #[serde(default = "default_redact_headers")]
+    pub redact_headers: Vec<String>,
+
+    /// Sunset date for endpoints that don't set their own `sunset_at`, for
+    /// deprecation campaigns with one deadline
+    #[serde(default, deserialize_with = "deserialize_date")]
+    pub default_sunset_at: Option<DateTime<Utc>>,
+
+    /// What to do with a date (`sunset_at`, `deprecated_at`) that doesn't
+    /// parse: reject the configuration, or drop the date with a warning
+    #[serde(default)]
+    pub on_invalid_date: InvalidDateAction,
+
+    /// Request headers clients should stop sending, on any path
+    #[serde(default)]
+    pub deprecated_request_headers: Vec<DeprecatedHeader>,
+
+    /// Response header naming the deprecated request headers a request sent
+    #[serde(default = "default_deprecated_header_used_header")]
+    pub deprecated_header_used_header: String,
+}
+
+impl GlobalSettings {
+    /// Rollout bucket (0-99) of a client under `bucket_seed`.
+    /// Identity of the client sending a request, given its header lookup:
+    /// from the first `client_identity` source that yields one, then
+    /// `client_id_header`.
+    pub fn client_id<'a>(&self, header: impl Fn(&str) -> Option<&'a str>) -> Option<String> {
+        self.client_identity
+            .iter()
+            .find_map(|source| source.identity(header(source.header())?))
+            .or_else(|| {
+                let value = header(self.client_id_header.as_deref()?)?;
+                Some(value.to_string())
+            })
+    }
+
+    /// Path rules are matched against, given the request path and header
+    /// lookup: the `original_path_header` value without its query string,
+    /// when sent and starting with `/`, or the request path.
+    pub fn match_path<'a>(
+        &self,
+        path: &'a str,
+        header: impl Fn(&str) -> Option<&'a str>,
+    ) -> &'a str {
+        self.original_path_header
+            .as_deref()
+            .and_then(header)
+            .map(|original| original.split_once('?').map_or(original, |(path, _)| path))
+            .filter(|original| original.starts_with('/'))
+            .unwrap_or(path)
+    }
+
+    /// Whether upstream responses with this status lose their deprecation
+    /// headers (`skip_headers_on_error`).
+    pub fn skips_headers_on_status(&self, status: u16) -> bool {
+        self.skip_headers_on_error
+            && self
+                .skip_headers_status_classes
+                .iter()
+                .filter_map(|class| class.strip_suffix("xx")?.parse::<u16>().ok())
+                .any(|class| status / 100 == class)
+    }
+
+    pub fn client_bucket(&self, client_id: &str) -> u8 {
+        crate::bucket::client_bucket(client_id, self.bucket_seed.unwrap_or_default())
+    }
+
+    /// Whether sunset-related headers should be sent at `now` for an endpoint
+    /// with the given sunset date: always without a window or a sunset date,
+    /// otherwise only within `sunset_header_window_days` of the sunset.
+    pub fn within_sunset_header_window(
+        &self,
+        sunset_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        match (self.sunset_header_window_days, sunset_at) {
+            (Some(days), Some(sunset_at)) => now >= sunset_at - Duration::days(days.into()),
+            _ => true,
+        }
+    }
+
+    /// Warning level for an endpoint: the number of thresholds reached, or 3
+    /// once past sunset. Endpoints without a sunset date stay at 0.
+    pub fn warning_level(&self, days_until_sunset: Option<i64>) -> u8 {
+        match days_until_sunset {
+            None => 0,
+            Some(days) if days < 0 => 3,
+            Some(days) => self
+                .warning_level_thresholds
+                .iter()
+                .filter(|&&threshold| days <= threshold)
+                .count() as u8,
+        }
+    }
+}
+
+impl Default for GlobalSettings {
+    fn default() -> Self {
+        Self {
+            deprecation_header: default_deprecation_header(),
+            dual_deprecation_headers: false,
+            deprecation_timestamp_header: default_deprecation_timestamp_header(),
+            sunset_header: default_sunset_header(),
+            emit_iso_sunset: false,
+            iso_sunset_header: default_iso_sunset_header(),
+            link_header: default_link_header(),
+            notice_header: default_notice_header(),
+            contact_header: default_contact_header(),
+            max_added_header_bytes: default_max_added_header_bytes(),
+            include_headers: true,
+            past_sunset_action: PastSunsetAction::default(),
+            log_access: true,
+            global_headers: HashMap::new(),
+            computed_headers: HashMap::new(),
+            block_options: false,
+            sunset_reminder: false,
+            sunset_reminder_header: default_sunset_reminder_header(),
+            warning_level: false,
+            warning_level_header: default_warning_level_header(),
+            warning_level_thresholds: default_warning_level_thresholds(),
+            reload_failure_mode: ReloadFailureMode::default(),
+            enforcement_enabled: true,
+            require_confirmation: false,
+            match_cache_size: 0,
+            documentation_base_url: None,
+            policy_url: None,
+            check_replacements: false,
+            replacement_check_interval_secs: default_replacement_check_interval(),
+            debug_headers: false,
+            manifest_path: None,
+            trace_match: false,
+            client_state: ClientStateConfig::default(),
+            sunset_header_window_days: None,
+            max_body_inspection_bytes: default_max_body_inspection_bytes(),
+            body_inspection_fail_open: true,
+            deprecated_body_fields_header: default_deprecated_body_fields_header(),
+            bucket_seed: None,
+            client_id_header: None,
+            client_identity: vec![],
+            base_path: None,
+            original_path_header: None,
+            public_origin: None,
+            allowed_link_hosts: Vec::new(),
+            strict_links: false,
+            diagnostics_buffer_size: default_diagnostics_buffer_size(),
+            error_envelope: ErrorEnvelope::default(),
+            upstream_header: None,
+            migration_grace_days: 0,
+            strip_headers_on_not_modified: false,
+            close_connection_on_block: false,
+            treat_head_as_get: true,
+            skip_headers_on_error: true,
+            skip_headers_status_classes: default_skip_headers_status_classes(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            redact_headers: default_redact_headers(),
+            default_sunset_at: None,
+            on_invalid_date: InvalidDateAction::default(),
+            deprecated_request_headers: Vec::new(),
+            deprecated_header_used_header: default_deprecated_header_used_header(),
+        }
+    }
+}
+
+fn default_deprecation_header() -> String {
+    "Deprecation".to_string()
+}
+
+fn default_deprecation_timestamp_header() -> String {
+    "X-Deprecation-Timestamp".to_string()
+}
+
+fn default_sunset_header() -> String {
+    "Sunset".to_string()
+}
+
+fn default_skip_headers_status_classes() -> Vec<String> {
+    vec!["5xx".to_string()]
+}
+
+fn default_iso_sunset_header() -> String {
+    "X-Sunset-ISO8601".to_string()
+}
+
+fn default_link_header() -> String {
+    "Link".to_string()
+}
+
+fn default_notice_header() -> String {
+    "X-Deprecation-Notice".to_string()
+}
+
+fn default_contact_header() -> String {
+    "X-Deprecation-Contact".to_string()
+}
+
+fn default_max_added_header_bytes() -> usize {
+    4096
+}
+
+fn default_sunset_reminder_header() -> String {
+    "X-Days-Until-Sunset".to_string()
+}
+
+fn default_warning_level_header() -> String {
+    "X-Deprecation-Warning-Level".to_string()
+}
+
+fn default_warning_level_thresholds() -> [i64; 3] {
+    [90, 30, 7]
+}
+
+fn default_replacement_check_interval() -> u64 {
+    300
+}
+
+/// Action to take when an endpoint is accessed past its sunset date.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PastSunsetAction {
+    /// Continue to allow with headers (default)
+    #[default]
+    Warn,
+    /// Block with 410 Gone
+    Block,
+    /// Redirect to replacement (if available)
+    Redirect,
+}
+
+/// Handling of configured dates that fail to parse.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidDateAction {
+    /// Reject the configuration, naming the rule and field (default)
+    #[default]
+    Error,
+    /// Load the rule without the date and log a warning. The rule then has
+    /// no sunset, so it never blocks past one.
+    IgnoreAndWarn,
+}
+
+/// Behavior when a configuration reload is rejected.
+///
+/// In both modes the last-good configuration stays active.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadFailureMode {
+    /// Log the error and keep serving (default)
+    #[default]
+    FailOpen,
+    /// Also report degraded health until a reload succeeds
+    Degrade,
+}
+
+/// Metrics configuration.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Whether to collect and expose metrics at all
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Prefix for metric names
+    #[serde(default = "default_metrics_prefix")]
+    pub prefix: String,
+
+    /// Labels to include in metrics
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+
+    /// Port for metrics endpoint (0 = disabled)
+    #[serde(default)]
+    pub port: u16,
+
+    /// Address the metrics endpoint binds to
+    #[serde(default = "default_metrics_bind_address")]
+    pub bind_address: IpAddr,
+
+    /// Redis store for summing usage across replicas (requires the
+    /// `redis-store` feature)
+    #[serde(default)]
+    pub shared_store: Option<SharedStoreConfig>,
+
+    /// Seconds between refreshes of the computed gauges (days until sunset,
+    /// upstream error ratio)
+    #[serde(default = "default_metrics_refresh_interval")]
+    pub refresh_interval_secs: u64,
+
+    /// Log a warning when the upstream error ratio of an endpoint over one
+    /// refresh interval exceeds this value (0.0-1.0)
+    #[serde(default)]
+    pub error_ratio_warn_threshold: Option<f64>,
+
+    /// Spike threshold of endpoints without their own `spike_threshold`:
+    /// log a warning when an endpoint's request rate exceeds this multiple
+    /// of its moving average, or its requests over the last 24 hours this
+    /// multiple of its average day over the week before
+    #[serde(default)]
+    pub usage_spike_factor: Option<f64>,
+
+    /// Average requests per day below which usage spikes are not reported,
+    /// so barely used endpoints don't alarm on a handful of requests
+    #[serde(default = "default_usage_spike_min_daily_average")]
+    pub usage_spike_min_daily_average: f64,
+
+    /// Bearer token required by `POST /reload`, `POST /admin/enforcement`
+    /// and `GET /export.csv` on the metrics listener; those routes are
+    /// disabled while unset
+    #[serde(default)]
+    pub admin_token: Option<String>,
+
+    /// Count an identified client's requests to an endpoint in
+    /// `requests_total` once per this many seconds, for a sessions rather
+    /// than hits signal; `requests_raw_total` then counts every request.
+    /// Unset counts every request in `requests_total`
+    #[serde(default)]
+    pub session_window_secs: Option<u64>,
+}
+
+/// Redis store shared by all replicas of the agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SharedStoreConfig {
+    /// Redis connection URL (e.g. redis://redis:6379/0)
+    pub url: String,
+
+    /// Prefix for the Redis keys holding the counters
+    #[serde(default = "default_shared_store_prefix")]
+    pub key_prefix: String,
+
+    /// Seconds between pushing local counts and reading cluster totals
+    #[serde(default = "default_shared_store_flush_interval")]
+    pub flush_interval_secs: u64,
+}
+
+impl MetricsConfig {
+    /// Name of the series counting every request, under the prefix:
+    /// `requests_raw_total` when `session_window_secs` makes
+    /// `requests_total` count sessions instead.
+    pub fn request_series(&self) -> String {
+        let name = if self.session_window_secs.is_some() {
+            "requests_raw_total"
+        } else {
+            "requests_total"
+        };
+        format!("{}_{name}", self.prefix)
+    }
+
+    /// Resolve where the metrics server should listen, if anywhere.
+    ///
+    /// Precedence, highest first:
+    /// 1. `enabled: false` in the config disables the server entirely.
+    /// 2. CLI flags (`--metrics` / `--metrics-port`) override the config port;
+    ///    `--metrics` alone uses the default port 9090.
+    /// 3. A config `port` greater than zero starts the server on that port.
+    ///
+    /// The server always binds to `bind_address`.
+    pub fn resolve_server(
+        &self,
+        cli_enabled: bool,
+        cli_port: Option<u16>,
+    ) -> Option<(SocketAddr, MetricsServerSource)> {
+        if !self.enabled {
+            return None;
+        }
+
+        let (port, source) = if cli_enabled || cli_port.is_some() {
+            (
+                cli_port.unwrap_or(DEFAULT_METRICS_SERVER_PORT),
+                MetricsServerSource::Cli,
+            )
+        } else if self.port > 0 {
+            (self.port, MetricsServerSource::Config)
+        } else {
+            return None;
+        };
+
+        Some((SocketAddr::new(self.bind_address, port), source))
+    }
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            prefix: default_metrics_prefix(),
+            labels: HashMap::new(),
+            port: 0,
+            bind_address: default_metrics_bind_address(),
+            shared_store: None,
+            refresh_interval_secs: default_metrics_refresh_interval(),
+            error_ratio_warn_threshold: None,
+            usage_spike_factor: None,
+            usage_spike_min_daily_average: default_usage_spike_min_daily_average(),
+            admin_token: None,
+            session_window_secs: None,
+        }
+    }
+}
+
+/// Where the metrics server settings came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsServerSource {
+    /// `--metrics` / `--metrics-port` command line flags
+    Cli,
+    /// `metrics.port` in the configuration file
+    Config,
+}
+
+/// Metrics server port used when enabled from the CLI without a port.
+pub const DEFAULT_METRICS_SERVER_PORT: u16 = 9090;
+
+fn default_max_body_inspection_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_diagnostics_buffer_size() -> usize {
+    100
+}
+
+fn default_max_concurrent_requests() -> u32 {
+    100
+}
+
+fn default_redact_headers() -> Vec<String> {
+    crate::redact::DEFAULT_REDACT_HEADERS
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn default_deprecated_body_fields_header() -> String {
+    "X-Deprecated-Body-Fields".to_string()
+}
+
+fn default_deprecated_header_used_header() -> String {
+    "X-Deprecated-Header-Used".to_string()
+}
+
+fn default_metrics_prefix() -> String {
+    "zentinel_api_deprecation".to_string()
+}
+
+fn default_metrics_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+fn default_metrics_refresh_interval() -> u64 {
+    60
+}
+
+fn default_usage_spike_min_daily_average() -> f64 {
+    100.0
+}
+
+fn default_shared_store_prefix() -> String {
+    "zentinel:api-deprecation".to_string()
+}
+
+fn default_shared_store_flush_interval() -> u64 {
+    10
+}
+
+/// HTTP methods accepted in `methods`.
+const KNOWN_METHODS: &[&str] = &[
+    "GET", "HEAD", "POST", "PUT", "DELETE", "CONNECT", "OPTIONS", "TRACE", "PATCH",
+];
+
+/// Deserialize `methods` from a single string or a list, uppercasing and
+/// rejecting unknown verbs.
+fn deserialize_methods<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct MethodsVisitor;
+
+    impl<'de> de::Visitor<'de> for MethodsVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("an HTTP method or a list of HTTP methods")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(vec![normalize_method(value)?])
+        }
+
+        fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut methods = Vec::new();
+            while let Some(value) = seq.next_element::<String>()? {
+                methods.push(normalize_method(&value)?);
+            }
+            Ok(methods)
+        }
+    }
+
+    deserializer.deserialize_any(MethodsVisitor)
+}
+
+pub(crate) fn normalize_method<E: de::Error>(value: &str) -> Result<String, E> {
+    let method = value.trim().to_ascii_uppercase();
+    if KNOWN_METHODS.contains(&method.as_str()) {
+        Ok(method)
+    } else {
+        Err(E::custom(format!(
+            "unknown HTTP method '{}', expected one of {}",
+            value,
+            KNOWN_METHODS.join(", ")
+        )))
+    }
+}
+
+/// Deserialize an optional timestamp from RFC 3339 or a bare `YYYY-MM-DD`
+/// date, which is taken as midnight UTC.
+pub(crate) fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(value) = Option::<String>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+
+    parse_date(&value)
+        .map(Some)
+        .ok_or_else(|| de::Error::custom(invalid_date_reason(&value)))
+}
+
+/// Parse an RFC 3339 timestamp or a bare `YYYY-MM-DD` date (midnight UTC).
+fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .map(|date| date.and_time(chrono::NaiveTime::MIN).and_utc())
+}
+
+fn invalid_date_reason(value: &str) -> String {
+    format!(
+        "invalid date '{}', expected RFC 3339 (2025-06-01T00:00:00Z) or YYYY-MM-DD",
+        value
+    )
+}
+
+/// A date in the raw configuration that doesn't parse.
+struct InvalidDate {
+    /// Rule the date belongs to (`None` for `settings`)
+    endpoint_id: Option<String>,
+    field: &'static str,
+    value: String,
+}
+
+/// Find the dates in a raw configuration that don't parse, replacing them
+/// with null.
+fn take_invalid_dates(config: &mut serde_yaml::Value) -> Vec<InvalidDate> {
+    use serde_yaml::Value;
+
+    fn take(
+        map: &mut Value,
+        key: &str,
+        endpoint_id: Option<&str>,
+        field: &'static str,
+        invalid: &mut Vec<InvalidDate>,
+    ) {
+        let Some(value) = map.get_mut(key) else {
+            return;
+        };
+        let valid = match &*value {
+            Value::Null => true,
+            Value::String(date) => parse_date(date).is_some(),
+            _ => false,
+        };
+        if !valid {
+            let raw = match &*value {
+                Value::String(date) => date.clone(),
+                other => serde_yaml::to_string(other)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            };
+            invalid.push(InvalidDate {
+                endpoint_id: endpoint_id.map(str::to_string),
+                field,
+                value: raw,
+            });
+            *value = Value::Null;
+        }
+    }
+
+    // Per-entry sunset dates of deprecated parameters and headers
+    fn take_nested(
+        map: &mut Value,
+        key: &'static str,
+        endpoint_id: Option<&str>,
+        invalid: &mut Vec<InvalidDate>,
+    ) {
+        if let Some(Value::Sequence(entries)) = map.get_mut(key) {
+            for entry in entries {
+                take(entry, "sunset_at", endpoint_id, key, invalid);
+            }
+        }
+    }
+
+    let mut invalid = Vec::new();
+    for rules in ["endpoints", "version_deprecations"] {
+        let Some(Value::Sequence(rules)) = config.get_mut(rules) else {
+            continue;
+        };
+        for rule in rules {
+            let id = rule
+                .get("id")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            take(
+                rule,
+                "deprecated_at",
+                Some(&id),
+                "deprecated_at",
+                &mut invalid,
+            );
+            take(rule, "sunset_at", Some(&id), "sunset_at", &mut invalid);
+            take_nested(rule, "deprecated_params", Some(&id), &mut invalid);
+            take_nested(rule, "deprecated_request_headers", Some(&id), &mut invalid);
+        }
+    }
+    if let Some(settings) = config.get_mut("settings") {
+        take(
+            settings,
+            "default_sunset_at",
+            None,
+            "default_sunset_at",
+            &mut invalid,
+        );
+        take_nested(settings, "deprecated_request_headers", None, &mut invalid);
+    }
+    invalid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_basic_config() {
+        let yaml = r#"
+endpoints:
+  - id: legacy-users-api
+    path: /api/v1/users
+    methods: [GET, POST]
+    status: deprecated
+    sunset_at: "2025-06-01T00:00:00Z"
+    replacement:
+      path: /api/v2/users
+    documentation_url: https://docs.example.com/migration/users
+    message: "Please migrate to the v2 API"
+"#;
+        let config: ApiDeprecationConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.endpoints.len(), 1);
+        assert_eq!(config.endpoints[0].id, "legacy-users-api");
+        assert_eq!(config.endpoints[0].path, "/api/v1/users");
+        assert_eq!(config.endpoints[0].methods, vec!["GET", "POST"]);
+    }
+
+    #[test]
+    fn test_endpoint_matching() {
+        let endpoint = DeprecatedEndpoint {
+            id: "test".to_string(),
+            path: "/api/v1/users".into(),
+            methods: vec!["GET".to_string()],
+            status: DeprecationStatus::Deprecated,
+            deprecated_at: None,
+            sunset_at: None,
+            active_from: None,
+            active_until: None,
+            replacement: None,
+            documentation_url: None,
+            alternate_links: vec![],
+            message: None,
+            action: DeprecationAction::Warn,
+            headers: HashMap::new(),
+            computed_headers: HashMap::new(),
+            track_usage: true,
+            metrics_id: None,
+            labels: BTreeMap::new(),
+            unless: vec![],
+            exclude: vec![],
+            deprecated_params: vec![],
+            deprecated_request_headers: vec![],
+            tombstone: None,
+            owner: None,
+            contact: None,
+            severity: Severity::Normal,
+            notes: None,
+            spike_threshold: None,
+            examples: vec![],
+            warn_once: None,
+            report_to: None,
+            bodies: HashMap::new(),
+            body_field_deprecated: vec![],
+            deprecated_body_fields: vec![],
+            confirmed: None,
+            path_matchers: vec![],
+            exclude_matchers: vec![],
+            head_as_get: false,
+        };
+
+        assert!(endpoint.matches("/api/v1/users", "GET"));
+        assert!(endpoint.matches("/api/v1/users", "get"));
+        assert!(!endpoint.matches("/api/v1/users", "POST"));
+        assert!(!endpoint.matches("/api/v2/users", "GET"));
+    }
+
+    #[test]
+    fn test_glob_pattern_matching() {
+        let endpoint = DeprecatedEndpoint {
+            id: "test".to_string(),
+            path: "/api/v1/*".into(),
+            methods: vec![],
+            status: DeprecationStatus::Deprecated,
+            deprecated_at: None,
+            sunset_at: None,
+            active_from: None,
+            active_until: None,
+            replacement: None,
+            documentation_url: None,
+            alternate_links: vec![],
+            message: None,
+            action: DeprecationAction::Warn,
+            headers: HashMap::new(),
+            computed_headers: HashMap::new(),
+            track_usage: true,
+            metrics_id: None,
+            labels: BTreeMap::new(),
+            unless: vec![],
+            exclude: vec![],
+            deprecated_params: vec![],
+            deprecated_request_headers: vec![],
+            tombstone: None,
+            owner: None,
+            contact: None,
+            severity: Severity::Normal,
+            notes: None,
+            spike_threshold: None,
+            examples: vec![],
+            warn_once: None,
+            report_to: None,
+            bodies: HashMap::new(),
+            body_field_deprecated: vec![],
+            deprecated_body_fields: vec![],
+            confirmed: None,
+            path_matchers: vec![],
+            exclude_matchers: vec![],
+            head_as_get: false,
+        };
+
+        assert!(endpoint.matches("/api/v1/users", "GET"));
+        assert!(endpoint.matches("/api/v1/posts", "POST"));
+        assert!(!endpoint.matches("/api/v2/users", "GET"));
+    }
+
+    #[test]
+    fn test_invalid_glob_patterns_fail_validation() {
+        for pattern in [
+            "/api/v1/[unclosed",
+            "/api/v1/{id",
+            "/api/v1/*/[unclosed",
+            "/api/{v1,v2/*",
+            "/api/[z-a]/*",
+            "/api/v1/users*[",
+        ] {
+            let yaml = format!("endpoints:\n  - id: broken\n    path: \"{}\"\n", pattern);
+            let config = ApiDeprecationConfig::from_yaml(&yaml).unwrap();
+            match config.validate() {
+                Err(ConfigError::Validation {
+                    endpoint_id,
+                    field: "path",
+                    kind: ValidationErrorKind::Invalid(reason),
+                }) => {
+                    assert_eq!(endpoint_id, "broken");
+                    assert!(!reason.is_empty());
+                }
+                other => panic!("Expected invalid glob for {}, got {:?}", pattern, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_valid_glob_patterns_pass_validation() {
+        let yaml = r#"
+endpoints:
+  - id: star
+    path: /api/v1/*
+  - id: class
+    path: /api/v[12]/users/*
+  - id: alternates
+    path: /api/{v1,v2}/posts/*
+  - id: parameter
+    path: /users/{id}
+"#;
+        let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        config.validate().unwrap();
+        config.compile();
+
+        assert_eq!(
+            config.find_endpoint("/api/v2/users/1", "GET").unwrap().id,
+            "class"
+        );
+        assert_eq!(
+            config.find_endpoint("/api/v2/posts/1", "GET").unwrap().id,
+            "alternates"
+        );
+
+        // Without `*` or `?`, braces and brackets are literal
+        assert!(!config.endpoints[3].is_glob());
+        assert_eq!(
+            config.find_endpoint("/users/{id}", "GET").unwrap().id,
+            "parameter"
+        );
+        assert!(config.find_endpoint("/users/id", "GET").is_none());
+    }
+
+    #[test]
+    fn test_path_aliases() {
+        let yaml = r#"
+endpoints:
+  - id: users
+    path:
+      - /api/v1/users
+      - /v1/users
+      - /legacy/*/users
+"#;
+        let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        config.validate().unwrap();
+        config.compile();
+
+        let endpoint = &config.endpoints[0];
+        assert_eq!(endpoint.path.len(), 3);
+        assert_eq!(endpoint.path.primary(), "/api/v1/users");
+        assert!(endpoint.is_glob());
+        for path in ["/api/v1/users", "/v1/users/42", "/legacy/mobile/users"] {
+            assert_eq!(config.find_endpoint(path, "GET").unwrap().id, "users");
+        }
+        assert!(config.find_endpoint("/v2/users", "GET").is_none());
+    }
+
+    #[test]
+    fn test_path_aliases_round_trip() {
+        let yaml = "endpoints:\n  - id: users\n    path: [/api/v1/users, /v1/users]\n  - id: posts\n    path: /api/v1/posts\n";
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+
+        let value = serde_json::to_value(&config.endpoints).unwrap();
+        assert_eq!(
+            value[0]["path"],
+            serde_json::json!(["/api/v1/users", "/v1/users"])
+        );
+        assert_eq!(value[1]["path"], "/api/v1/posts");
+    }
+
+    #[test]
+    fn test_empty_path_aliases_rejected() {
+        for path in ["[]", "[/api/v1/users, \"\"]"] {
+            let yaml = format!("endpoints:\n  - id: users\n    path: {}\n", path);
+            let config = ApiDeprecationConfig::from_yaml(&yaml).unwrap();
+            assert!(matches!(
+                config.validate(),
+                Err(ConfigError::Validation {
+                    field: "path",
+                    kind: ValidationErrorKind::Empty,
+                    ..
+                })
+            ));
+        }
+
+        // An alias with an unclosed bracket is a literal path that would never match
+        let yaml = "endpoints:\n  - id: users\n    path: [/api/v1/users, \"/v1/[users\"]\n";
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Validation {
+                field: "path",
+                kind: ValidationErrorKind::Invalid(reason),
+                ..
+            }) if reason.contains("unbalanced")
+        ));
+    }
+
+    #[test]
+    fn test_version_deprecation_expands_into_prefix_rule() {
+        let yaml = r#"
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+version_deprecations:
+  - id: api-v1
+    prefix: /api/v1/
+    successor: /api/v2
+    sunset_at: 2030-01-01
+    exclude: [/api/v1/health, /api/v1/internal/*]
+"#;
+        let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        config.validate().unwrap();
+        config.compile();
+
+        assert!(config.version_deprecations.is_empty());
+        let version = &config.endpoints[1];
+        assert_eq!(version.id, "api-v1");
+        assert_eq!(version.path, "/api/v1");
+        assert_eq!(
+            version
+                .replacement
+                .as_ref()
+                .unwrap()
+                .target_for("/api/v1/posts/7"),
+            "/api/v2/posts/7"
+        );
+
+        let id = |path| config.find_endpoint(path, "GET").map(|e| e.id.as_str());
+        assert_eq!(id("/api/v1/users"), Some("legacy-users"));
+        assert_eq!(id("/api/v1/posts/7"), Some("api-v1"));
+        assert_eq!(id("/api/v1"), Some("api-v1"));
+        assert_eq!(id("/api/v1/health"), None);
+        assert_eq!(id("/api/v1/internal/jobs"), None);
+        assert_eq!(id("/api/v10/posts"), None);
+
+        // Compiling again doesn't expand the block twice
+        config.compile();
+        assert_eq!(config.endpoints.len(), 2);
+    }
+
+    #[test]
+    fn test_header_budget_warnings() {
+        let mut config = ApiDeprecationConfig::from_yaml(&format!(
+            r#"
+settings:
+  max_added_header_bytes: 512
+endpoints:
+  - id: chatty
+    path: /api/v1/users
+    message: "{}"
+  - id: quiet
+    path: /api/v1/posts
+"#,
+            "x".repeat(600)
+        ))
+        .unwrap();
+        config.compile();
+
+        let warnings = config.header_budget_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].endpoint_id, "chatty");
+        assert_eq!(warnings[0].dropped, ["X-Deprecation-Notice"]);
+        let warning = warnings[0].to_string();
+        assert!(warning.starts_with("chatty: adds "), "{}", warning);
+        assert!(
+            warning.ends_with(
+                "over settings.max_added_header_bytes (512); dropping X-Deprecation-Notice"
+            ),
+            "{}",
+            warning
+        );
+
+        config.settings.max_added_header_bytes = 0;
+        assert!(config.header_budget_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_config_hash() {
+        let compiled = |yaml: &str| {
+            let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+            config.compile();
+            config
+        };
+        let config = compiled(
+            r#"
+settings:
+  global_headers: {X-Team: api, X-Owner: platform}
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    headers: {X-One: "1", X-Two: "2", X-Three: "3"}
+"#,
+        );
+        let reordered = compiled(
+            r#"
+settings:
+  global_headers: {X-Owner: platform, X-Team: api}
+endpoints:
+  - path: /api/v1/users
+    headers: {X-Three: "3", X-Two: "2", X-One: "1"}
+    id: users-v1
+"#,
+        );
+        let hash = config.hash();
+        assert_eq!(hash.len(), 16);
+        assert_eq!(hash, reordered.hash());
+        assert_eq!(hash, config.clone().hash());
+
+        let mut changed = config.clone();
+        changed.endpoints[0]
+            .headers
+            .insert("X-Two".to_string(), "two".to_string());
+        assert_ne!(hash, changed.hash());
+    }
+
+    #[test]
+    fn test_base_path() {
+        let mut config = ApiDeprecationConfig::from_yaml(
+            r#"
+settings:
+  base_path: /public-api
+endpoints:
+  - id: users-v1
+    path: [/api/v1/users, /v1/users]
+    exclude: [/api/v1/users/me]
+    replacement:
+      path: /api/v2/users
+    examples:
+      - path: /api/v1/users
+        expect: match
+  - id: orders-v1
+    path: /public-api/api/v1/orders
+    replacement:
+      path: https://orders.example.com/v2/orders
+version_deprecations:
+  - id: api-v1
+    prefix: /api/v1
+    successor: /api/v2
+"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        config.compile();
+
+        let users = &config.endpoints[0];
+        assert_eq!(users.path.primary(), "/public-api/api/v1/users");
+        assert_eq!(users.path.aliases(), ["/public-api/v1/users"]);
+        assert_eq!(users.exclude, ["/public-api/api/v1/users/me"]);
+        assert_eq!(
+            users.replacement.as_ref().unwrap().path,
+            "/public-api/api/v2/users"
+        );
+        assert_eq!(users.examples[0].path, "/public-api/api/v1/users");
+        assert!(config.example_failures(Utc::now()).is_empty());
+
+        // Neither prefixed twice nor moved off another host
+        let orders = &config.endpoints[1];
+        assert_eq!(orders.path.primary(), "/public-api/api/v1/orders");
+        assert_eq!(
+            orders.replacement.as_ref().unwrap().path,
+            "https://orders.example.com/v2/orders"
+        );
+
+        // Version rules redirect within the base path
+        let version = &config.endpoints[2];
+        let replacement = version.replacement.as_ref().unwrap();
+        assert_eq!(
+            replacement.target_for("/public-api/api/v1/posts/5"),
+            "/public-api/api/v2/posts/5"
+        );
+
+        assert_eq!(
+            config
+                .find_endpoint("/public-api/v1/users", "GET")
+                .unwrap()
+                .id,
+            "users-v1"
+        );
+        assert!(config.find_endpoint("/api/v1/users", "GET").is_none());
+        assert_eq!(
+            config
+                .find_endpoint("/public-api/api/v1/users/me", "GET")
+                .unwrap()
+                .id,
+            "api-v1"
+        );
+
+        for base in [
+            "public-api",
+            "/public-api/",
+            "/",
+            "/a//b",
+            "/api/*",
+            "/a/../b",
+        ] {
+            let mut config = ApiDeprecationConfig::default();
+            config.settings.base_path = Some(base.to_string());
+            let err = config.validate().unwrap_err().to_string();
+            assert!(
+                err.contains("invalid setting 'base_path'"),
+                "{}: {}",
+                base,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_version_deprecation_validation() {
+        let invalid = [
+            ("prefix: \"\"", "prefix"),
+            ("prefix: /api/v1/*", "prefix"),
+            ("prefix: /api/v1\n    successor: api/v2", "successor"),
+            // A literal exclude with an unclosed bracket
+            ("prefix: /api/v1\n    exclude: [\"/api/v1/[x\"]", "exclude"),
+        ];
+        for (block, expected) in invalid {
+            let yaml = format!("version_deprecations:\n  - id: v1\n    {}\n", block);
+            let config = ApiDeprecationConfig::from_yaml(&yaml).unwrap();
+            match config.validate() {
+                Err(ConfigError::Validation { field, .. }) => assert_eq!(field, expected),
+                other => panic!("Expected invalid {}, got {:?}", expected, other),
+            }
+        }
 
-    /// Prefix for metric names
-    #[serde(default = "default_metrics_prefix")]
-    pub prefix: String,
+        let yaml = "endpoints:\n  - id: v1\n    path: /v1/users\nversion_deprecations:\n  - id: v1\n    prefix: /v1\n";
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::DuplicateId { .. })
+        ));
+    }
 
-    /// Labels to include in metrics
-    #[serde(default)]
-    pub labels: HashMap<String, String>,
+    #[test]
+    fn test_methods_accepts_single_string() {
+        let config = ApiDeprecationConfig::from_yaml(
+            "endpoints:\n  - id: users\n    path: /api/v1/users\n    methods: GET\n",
+        )
+        .unwrap();
+        assert_eq!(config.endpoints[0].methods, vec!["GET"]);
+    }
 
-    /// Port for metrics endpoint (0 = disabled)
-    #[serde(default)]
-    pub port: u16,
-}
+    #[test]
+    fn test_methods_are_uppercased() {
+        let config = ApiDeprecationConfig::from_yaml(
+            "endpoints:\n  - id: users\n    path: /api/v1/users\n    methods: [get, Post]\n",
+        )
+        .unwrap();
+        assert_eq!(config.endpoints[0].methods, vec!["GET", "POST"]);
 
-impl Default for MetricsConfig {
-    fn default() -> Self {
-        Self {
-            enabled: true,
-            prefix: default_metrics_prefix(),
-            labels: HashMap::new(),
-            port: 0,
+        let config = ApiDeprecationConfig::from_yaml(
+            "endpoints:\n  - id: users\n    path: /api/v1/users\n    methods: delete\n",
+        )
+        .unwrap();
+        assert_eq!(config.endpoints[0].methods, vec!["DELETE"]);
+    }
+
+    #[test]
+    fn test_unknown_method_rejected() {
+        for methods in ["FETCH", "[GET, GRAB]", "42", "{ verb: GET }"] {
+            let yaml = format!(
+                "endpoints:\n  - id: users\n    path: /api/v1/users\n    methods: {}\n",
+                methods
+            );
+            let err = ApiDeprecationConfig::from_yaml(&yaml).unwrap_err();
+            assert!(matches!(err, ConfigError::Parse { .. }), "{}", methods);
         }
+
+        let err = ApiDeprecationConfig::from_yaml(
+            "endpoints:\n  - id: users\n    path: /api/v1/users\n    methods: FETCH\n",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown HTTP method 'FETCH'"));
     }
-}
 
-fn default_metrics_prefix() -> String {
-    "zentinel_api_deprecation".to_string()
-}
+    #[test]
+    fn test_date_only_values() {
+        let config = ApiDeprecationConfig::from_yaml(
+            r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+    deprecated_at: 2025-01-15
+    sunset_at: "2025-06-01"
+"#,
+        )
+        .unwrap();
+        let endpoint = &config.endpoints[0];
+        assert_eq!(
+            endpoint.deprecated_at.unwrap().to_rfc3339(),
+            "2025-01-15T00:00:00+00:00"
+        );
+        assert_eq!(
+            endpoint.sunset_at.unwrap().to_rfc3339(),
+            "2025-06-01T00:00:00+00:00"
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_rfc3339_dates_still_accepted() {
+        let config = ApiDeprecationConfig::from_yaml(
+            r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+    sunset_at: "2025-06-01T12:30:00+02:00"
+    deprecated_at: ~
+"#,
+        )
+        .unwrap();
+        let endpoint = &config.endpoints[0];
+        assert_eq!(
+            endpoint.sunset_at.unwrap().to_rfc3339(),
+            "2025-06-01T10:30:00+00:00"
+        );
+        assert!(endpoint.deprecated_at.is_none());
+    }
 
     #[test]
-    fn test_parse_basic_config() {
-        let yaml = r#"
+    fn test_invalid_dates_rejected() {
+        for date in ["2025-13-01", "06/01/2025", "soon", "2025-06-01T25:00:00Z"] {
+            let yaml = format!(
+                "endpoints:\n  - id: users\n    path: /api/v1/users\n    sunset_at: \"{}\"\n",
+                date
+            );
+            let err = ApiDeprecationConfig::from_yaml(&yaml).unwrap_err();
+            assert!(
+                err.to_string()
+                    .contains(&format!("invalid date '{}'", date)),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_link_downgrades_and_foreign_hosts() {
+        let yaml = |strict: bool| {
+            format!(
+                r#"
+settings:
+  public_origin: https://api.example.com
+  allowed_link_hosts: [api-v2.example.com]
+  strict_links: {strict}
 endpoints:
-  - id: legacy-users-api
+  - id: users
     path: /api/v1/users
-    methods: [GET, POST]
-    status: deprecated
-    sunset_at: "2025-06-01T00:00:00Z"
     replacement:
-      path: /api/v2/users
-    documentation_url: https://docs.example.com/migration/users
-    message: "Please migrate to the v2 API"
-"#;
-        let config: ApiDeprecationConfig = serde_yaml::from_str(yaml).unwrap();
-        assert_eq!(config.endpoints.len(), 1);
-        assert_eq!(config.endpoints[0].id, "legacy-users-api");
-        assert_eq!(config.endpoints[0].path, "/api/v1/users");
-        assert_eq!(config.endpoints[0].methods, vec!["GET", "POST"]);
+      path: http://api.example.com/api/v2/users
+  - id: orders
+    path: /api/v1/orders
+    replacement:
+      path: https://API-V2.example.com:8443/orders
+    alternate_links:
+      - href: http://api.example.com/api/v1/orders.csv
+        type: text/csv
+  - id: posts
+    path: /api/v1/posts
+    replacement:
+      path: https://elsewhere.example.net/posts
+"#
+            )
+        };
+
+        let config = ApiDeprecationConfig::from_yaml(&yaml(false)).unwrap();
+        let issues: Vec<_> = config
+            .link_issues()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(issues.len(), 3, "{issues:?}");
+        assert!(issues[0].contains("users") && issues[0].contains("downgrades"));
+        assert!(issues[1].contains("alternate_links") && issues[1].contains("downgrades"));
+        assert!(issues[2].contains("points to elsewhere.example.net"));
+        // Warnings only
+        assert!(config.validate().is_ok());
+
+        let config = ApiDeprecationConfig::from_yaml(&yaml(true)).unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Validation { ref endpoint_id, field: "replacement", .. })
+                if endpoint_id == "users"
+        ));
     }
 
     #[test]
-    fn test_endpoint_matching() {
-        let endpoint = DeprecatedEndpoint {
-            id: "test".to_string(),
-            path: "/api/v1/users".to_string(),
-            methods: vec!["GET".to_string()],
-            status: DeprecationStatus::Deprecated,
-            deprecated_at: None,
-            sunset_at: None,
-            replacement: None,
-            documentation_url: None,
-            message: None,
-            action: DeprecationAction::Warn,
-            headers: HashMap::new(),
-            track_usage: true,
-            path_matcher: None,
-        };
+    fn test_documentation_base_url() {
+        let mut config = ApiDeprecationConfig::from_yaml(
+            r#"
+settings:
+  documentation_base_url: "https://docs.example.com/migration/{id}?from={path}"
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+  - id: legacy-posts
+    path: /api/v1/posts
+    documentation_url: https://docs.example.com/posts
+"#,
+        )
+        .unwrap();
+        config.compile();
 
-        assert!(endpoint.matches("/api/v1/users", "GET"));
-        assert!(endpoint.matches("/api/v1/users", "get"));
-        assert!(!endpoint.matches("/api/v1/users", "POST"));
-        assert!(!endpoint.matches("/api/v2/users", "GET"));
+        assert_eq!(
+            config.endpoints[0]
+                .documentation_url
+                .as_ref()
+                .and_then(DocumentationLinks::primary_url),
+            Some("https://docs.example.com/migration/legacy-users?from=/api/v1/users")
+        );
+        // Explicit URLs win
+        assert_eq!(
+            config.endpoints[1]
+                .documentation_url
+                .as_ref()
+                .and_then(DocumentationLinks::primary_url),
+            Some("https://docs.example.com/posts")
+        );
     }
 
     #[test]
-    fn test_glob_pattern_matching() {
-        let endpoint = DeprecatedEndpoint {
-            id: "test".to_string(),
-            path: "/api/v1/*".to_string(),
-            methods: vec![],
-            status: DeprecationStatus::Deprecated,
-            deprecated_at: None,
-            sunset_at: None,
-            replacement: None,
-            documentation_url: None,
-            message: None,
-            action: DeprecationAction::Warn,
-            headers: HashMap::new(),
-            track_usage: true,
-            path_matcher: None,
-        };
+    fn test_unless_suppresses_broad_rule() {
+        let mut config = ApiDeprecationConfig::from_yaml(
+            r#"
+endpoints:
+  - id: all-v1
+    path: /api/v1/*
+    unless: [admin-v1]
+  - id: admin-v1
+    path: /api/v1/admin/*
+    methods: [POST]
+"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        config.compile();
 
-        assert!(endpoint.matches("/api/v1/users", "GET"));
-        assert!(endpoint.matches("/api/v1/posts", "POST"));
-        assert!(!endpoint.matches("/api/v2/users", "GET"));
+        assert_eq!(
+            config.find_endpoint("/api/v1/users", "GET").unwrap().id,
+            "all-v1"
+        );
+        // The admin subset is handled by the specific rule
+        assert_eq!(
+            config
+                .find_endpoint("/api/v1/admin/keys", "POST")
+                .unwrap()
+                .id,
+            "admin-v1"
+        );
+        // The specific rule doesn't match GET, so the broad rule applies again
+        assert_eq!(
+            config
+                .find_endpoint("/api/v1/admin/keys", "GET")
+                .unwrap()
+                .id,
+            "all-v1"
+        );
+    }
+
+    #[test]
+    fn test_unless_references_are_validated() {
+        let config = ApiDeprecationConfig::from_yaml(
+            "endpoints:\n  - id: all-v1\n    path: /api/v1/*\n    unless: [missing]\n",
+        )
+        .unwrap();
+        match config.validate() {
+            Err(ConfigError::Validation {
+                endpoint_id,
+                field: "unless",
+                kind: ValidationErrorKind::Invalid(reason),
+            }) => {
+                assert_eq!(endpoint_id, "all-v1");
+                assert!(reason.contains("'missing'"));
+            }
+            other => panic!("Expected unless validation error, got {:?}", other),
+        }
+
+        let config = ApiDeprecationConfig::from_yaml(
+            "endpoints:\n  - id: all-v1\n    path: /api/v1/*\n    unless: [all-v1]\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Validation {
+                field: "unless",
+                ..
+            })
+        ));
     }
 
     #[test]
@@ -493,23 +4512,49 @@ status_code: 301
     fn test_deprecation_message() {
         let endpoint = DeprecatedEndpoint {
             id: "test".to_string(),
-            path: "/api/v1/users".to_string(),
+            path: "/api/v1/users".into(),
             methods: vec![],
             status: DeprecationStatus::Deprecated,
             deprecated_at: None,
             sunset_at: Some("2025-06-01T00:00:00Z".parse().unwrap()),
+            active_from: None,
+            active_until: None,
             replacement: Some(ReplacementInfo {
                 path: "/api/v2/users".to_string(),
                 preserve_query: true,
+                rewrite_prefix: None,
                 param_mappings: HashMap::new(),
                 method: None,
             }),
-            documentation_url: Some("https://docs.example.com".to_string()),
+            documentation_url: Some("https://docs.example.com".into()),
+            alternate_links: vec![],
             message: None,
             action: DeprecationAction::Warn,
             headers: HashMap::new(),
+            computed_headers: HashMap::new(),
             track_usage: true,
-            path_matcher: None,
+            metrics_id: None,
+            labels: BTreeMap::new(),
+            unless: vec![],
+            exclude: vec![],
+            deprecated_params: vec![],
+            deprecated_request_headers: vec![],
+            tombstone: None,
+            owner: None,
+            contact: None,
+            severity: Severity::Normal,
+            notes: None,
+            spike_threshold: None,
+            examples: vec![],
+            warn_once: None,
+            report_to: None,
+            bodies: HashMap::new(),
+            body_field_deprecated: vec![],
+            deprecated_body_fields: vec![],
+            confirmed: None,
+            path_matchers: vec![],
+            exclude_matchers: vec![],
+            head_as_get: false,
         };
 
         let msg = endpoint.deprecation_message();
@@ -523,20 +4568,643 @@ status_code: 301
     fn test_custom_message() {
         let endpoint = DeprecatedEndpoint {
             id: "test".to_string(),
-            path: "/api/v1/users".to_string(),
+            path: "/api/v1/users".into(),
             methods: vec![],
             status: DeprecationStatus::Deprecated,
             deprecated_at: None,
             sunset_at: None,
+            active_from: None,
+            active_until: None,
             replacement: None,
             documentation_url: None,
+            alternate_links: vec![],
             message: Some("Custom deprecation message".to_string()),
             action: DeprecationAction::Warn,
             headers: HashMap::new(),
+            computed_headers: HashMap::new(),
             track_usage: true,
-            path_matcher: None,
+            metrics_id: None,
+            labels: BTreeMap::new(),
+            unless: vec![],
+            exclude: vec![],
+            deprecated_params: vec![],
+            deprecated_request_headers: vec![],
+            tombstone: None,
+            owner: None,
+            contact: None,
+            severity: Severity::Normal,
+            notes: None,
+            spike_threshold: None,
+            examples: vec![],
+            warn_once: None,
+            report_to: None,
+            bodies: HashMap::new(),
+            body_field_deprecated: vec![],
+            deprecated_body_fields: vec![],
+            confirmed: None,
+            path_matchers: vec![],
+            exclude_matchers: vec![],
+            head_as_get: false,
         };
 
         assert_eq!(endpoint.deprecation_message(), "Custom deprecation message");
     }
+
+    #[test]
+    fn test_metrics_server_precedence() {
+        let config_port = MetricsConfig {
+            port: 9100,
+            ..MetricsConfig::default()
+        };
+        let no_port = MetricsConfig::default();
+        let disabled = MetricsConfig {
+            enabled: false,
+            port: 9100,
+            ..MetricsConfig::default()
+        };
+
+        // Nothing configured anywhere
+        assert_eq!(no_port.resolve_server(false, None), None);
+
+        // Config port only
+        let (addr, source) = config_port.resolve_server(false, None).unwrap();
+        assert_eq!(addr.port(), 9100);
+        assert_eq!(source, MetricsServerSource::Config);
+
+        // CLI flag only uses the default port
+        let (addr, source) = no_port.resolve_server(true, None).unwrap();
+        assert_eq!(addr.port(), DEFAULT_METRICS_SERVER_PORT);
+        assert_eq!(source, MetricsServerSource::Cli);
+
+        // CLI port overrides the config port
+        let (addr, source) = config_port.resolve_server(true, Some(9200)).unwrap();
+        assert_eq!(addr.port(), 9200);
+        assert_eq!(source, MetricsServerSource::Cli);
+
+        // CLI port alone enables the server
+        let (addr, _) = no_port.resolve_server(false, Some(9200)).unwrap();
+        assert_eq!(addr.port(), 9200);
+
+        // Disabled metrics win over everything
+        assert_eq!(disabled.resolve_server(false, None), None);
+        assert_eq!(disabled.resolve_server(true, Some(9200)), None);
+    }
+
+    #[test]
+    fn test_metrics_bind_address() {
+        let yaml = r#"
+metrics:
+  port: 9100
+  bind_address: 127.0.0.1
+"#;
+        let config: ApiDeprecationConfig = serde_yaml::from_str(yaml).unwrap();
+        let (addr, _) = config.metrics.resolve_server(false, None).unwrap();
+        assert_eq!(addr, "127.0.0.1:9100".parse().unwrap());
+    }
+
+    #[test]
+    fn test_config_error_validation() {
+        let yaml = r#"
+endpoints:
+  - id: broken-redirect
+    path: /api/v1/users
+    action:
+      type: redirect
+"#;
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        match config.validate() {
+            Err(ConfigError::Validation {
+                endpoint_id,
+                field,
+                kind: ValidationErrorKind::Required(_),
+            }) => {
+                assert_eq!(endpoint_id, "broken-redirect");
+                assert_eq!(field, "replacement");
+            }
+            other => panic!("Expected validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_config_error_duplicate_id() {
+        let yaml = r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+  - id: users
+    path: /api/v1/accounts
+"#;
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::DuplicateId { endpoint_id }) if endpoint_id == "users"
+        ));
+    }
+
+    #[test]
+    fn test_config_error_parse_location() {
+        let yaml = "endpoints:\n  - id: users\n    path: /api/v1/users\n    unknown_field: 1\n";
+        let err = ApiDeprecationConfig::from_yaml(yaml).unwrap_err();
+        assert!(err.to_string().contains("unknown_field"));
+        match err {
+            ConfigError::Parse { location, .. } => assert!(location.is_some()),
+            other => panic!("Expected parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_config_error_io() {
+        let err =
+            ApiDeprecationConfig::from_file(Path::new("/nonexistent/config.yaml")).unwrap_err();
+        assert!(matches!(err, ConfigError::Io { .. }));
+        assert!(err.to_string().contains("/nonexistent/config.yaml"));
+    }
+
+    #[test]
+    fn test_metrics_id_defaults_to_id() {
+        let yaml = r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+  - id: users-v1-renamed
+    path: /api/v1/accounts
+    metrics_id: accounts-v1
+"#;
+        let config: ApiDeprecationConfig = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.endpoints[0].metrics_id(), "users-v1");
+        assert_eq!(config.endpoints[1].metrics_id(), "accounts-v1");
+    }
+
+    #[test]
+    fn test_label_names_are_validated() {
+        let config = |labels: &str| {
+            ApiDeprecationConfig::from_yaml(&format!(
+                "endpoints:\n  - id: users-v1\n    path: /api/v1/users\n    labels: {}\n",
+                labels
+            ))
+            .unwrap()
+        };
+
+        assert!(config("{team: identity, cost_center: c42}")
+            .validate()
+            .is_ok());
+        for labels in [
+            "{team-name: identity}",
+            "{1team: identity}",
+            "{__team: identity}",
+        ] {
+            let err = config(labels).validate().unwrap_err().to_string();
+            assert!(err.contains("not a valid Prometheus label name"), "{}", err);
+        }
+        let err = config("{severity: page}")
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("'severity' is set by the agent"), "{}", err);
+    }
+
+    #[test]
+    fn test_documentation_links_are_validated() {
+        let config = |docs: &str| {
+            ApiDeprecationConfig::from_yaml(&format!(
+                "endpoints:\n  - id: users-v1\n    path: /api/v1/users\n    documentation_url: {}\n",
+                docs
+            ))
+            .unwrap()
+        };
+
+        for docs in [
+            "https://docs.example.com/migration",
+            "/docs/migration",
+            "[{url: https://docs.example.com/migration, title: Migration guide}, {url: https://docs.example.com/changelog, rel: describedby}]",
+        ] {
+            assert!(config(docs).validate().is_ok(), "{}", docs);
+        }
+        let links = config(
+            "[{url: /docs/changelog, rel: describedby}, {url: mailto:api@example.com, rel: help}]",
+        )
+        .endpoints
+        .remove(0)
+        .documentation_url
+        .unwrap();
+        assert_eq!(links.primary_url(), Some("/docs/changelog"));
+
+        for (docs, expected) in [
+            ("docs/migration", "'docs/migration' is not a valid URL"),
+            ("\"https://docs.example.com/a b\"", "is not a valid URL"),
+            (
+                "[{url: /docs, rel: \"\"}]",
+                "rel '' must be a non-empty token",
+            ),
+            (
+                "[{url: /docs, rel: \"see also\"}]",
+                "rel 'see also' must be a non-empty token",
+            ),
+            ("[]", "documentation_url cannot be empty"),
+        ] {
+            let err = config(docs).validate().unwrap_err().to_string();
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_active_window_is_validated() {
+        let config = |window: &str| {
+            ApiDeprecationConfig::from_yaml(&format!(
+                "endpoints:\n  - id: filings\n    path: /api/v1/filings\n{}",
+                window
+            ))
+            .unwrap()
+        };
+
+        assert!(
+            config("    active_from: 2025-01-15\n    active_until: 2025-04-16\n")
+                .validate()
+                .is_ok()
+        );
+        assert!(config("    active_until: 2025-04-16\n").validate().is_ok());
+        for window in [
+            "    active_from: 2025-04-16\n    active_until: 2025-01-15\n",
+            "    active_from: 2025-01-15\n    active_until: 2025-01-15\n",
+        ] {
+            assert_eq!(
+                config(window).validate().unwrap_err().to_string(),
+                "invalid endpoint 'filings': active_until is invalid: must be after active_from"
+            );
+        }
+    }
+
+    #[test]
+    fn test_owner_and_contact_are_validated() {
+        let config = |extra: &str| {
+            ApiDeprecationConfig::from_yaml(&format!(
+                "endpoints:\n  - id: users-v1\n    path: /api/v1/users\n{}",
+                extra
+            ))
+            .unwrap()
+        };
+
+        for contact in [
+            "identity@example.com",
+            "https://chat.example.com/identity",
+            "mailto:identity@example.com",
+        ] {
+            let extra = format!("    owner: identity\n    contact: \"{}\"\n", contact);
+            assert!(config(&extra).validate().is_ok(), "{}", contact);
+        }
+        for (extra, expected) in [
+            ("    owner: \"\"\n", "owner cannot be empty"),
+            ("    contact: \"\"\n", "contact cannot be empty"),
+            (
+                "    contact: identity\n",
+                "'identity' must be an email address",
+            ),
+            (
+                "    contact: identity@localhost\n",
+                "must be an email address",
+            ),
+            (
+                "    contact: ftp://files.example.com\n",
+                "must be an email address",
+            ),
+        ] {
+            let err = config(extra).validate().unwrap_err().to_string();
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_error_envelope_is_validated() {
+        let config = |envelope: &str| {
+            ApiDeprecationConfig::from_yaml(&format!(
+                "endpoints:\n  - id: users-v1\n    path: /api/v1/users\n    action: {{type: custom, status_code: 410}}\nsettings:\n  error_envelope: {}\n",
+                envelope
+            ))
+            .unwrap()
+        };
+
+        assert!(
+            config("{error_key: code, message_key: detail, details_key: meta}")
+                .validate()
+                .is_ok()
+        );
+        for (envelope, expected) in [
+            ("{error_key: \"\"}", "keys cannot be empty"),
+            ("{message_key: error}", "must differ"),
+            ("{details_key: message}", "must differ"),
+        ] {
+            let err = config(envelope).validate().unwrap_err().to_string();
+            assert!(
+                err.starts_with("invalid setting 'error_envelope'") && err.contains(expected),
+                "{}",
+                err
+            );
+        }
+
+        // Custom actions without a body answer with the gone body
+        let mut config = config("{error_key: code}");
+        config.compile();
+        let processed = crate::processed::ProcessedConfig::new(config, Utc::now());
+        let (_, endpoint) = processed.find("/api/v1/users", "GET", Utc::now()).unwrap();
+        let crate::processed::DeprecationActionResult::Custom { body, .. } = &endpoint.action
+        else {
+            panic!("expected a custom action");
+        };
+        assert!(body.contains("\"code\": \"endpoint_removed\""), "{}", body);
+    }
+
+    #[test]
+    fn test_report_to_is_validated() {
+        let config = |report_to: &str| {
+            ApiDeprecationConfig::from_yaml(&format!(
+                "endpoints:\n  - id: users-v1\n    path: /api/v1/users\n    report_to: {}\n",
+                report_to
+            ))
+            .unwrap()
+        };
+
+        let valid = config("{url: https://reports.example.com/deprecations}");
+        assert!(valid.validate().is_ok());
+        let report_to = valid.endpoints[0].report_to.as_ref().unwrap();
+        assert_eq!(report_to.group, "deprecation");
+        assert_eq!(report_to.max_age_secs, 86400);
+
+        for (report_to, expected) in [
+            (
+                "{url: http://reports.example.com}",
+                "report_to.url is invalid: must be an https URL",
+            ),
+            (
+                "{url: https://reports.example.com, group: \"API reports\"}",
+                "report_to.group is invalid: must be a lowercase name",
+            ),
+        ] {
+            let err = config(report_to).validate().unwrap_err().to_string();
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_example_failures() {
+        let mut config = ApiDeprecationConfig::from_yaml(
+            r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    deprecated_params:
+      - name: legacy_auth
+        action: block
+    examples:
+      - path: /api/v1/users/42
+        expect: match
+        action: allow
+      - method: delete
+        path: /api/v1/users-export
+        expect: no_match
+      - path: /api/v1/users?page=2
+        expect: no_match
+      - path: /api/v1/users?legacy_auth=1
+        expect: match
+        action: block
+      - path: /api/v1/users?page=2
+        expect: match
+        action: block
+  - id: orders-v1
+    path: /api/v1/orders
+    methods: [POST]
+    deprecated_request_headers:
+      - name: X-Api-Version
+        action: block
+    examples:
+      - method: POST
+        path: /api/v1/orders
+        expect: match
+      - path: /api/v1/orders
+        expect: match
+      - path: /api/v1/users/7/orders
+        expect: match
+      - method: POST
+        path: /api/v1/orders
+        headers:
+          X-Api-Version: "1"
+        expect: match
+        action: block
+  - id: posts-v1
+    path: /api/v1/posts
+    status: removed
+    active_until: 2030-01-01
+    examples:
+      - path: /api/v1/posts
+        expect: match
+        action: block
+"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        config.compile();
+
+        // The rule window ended before now: examples run just before its end
+        let now = "2031-01-01T00:00:00Z".parse().unwrap();
+        let failures: Vec<String> = config
+            .example_failures(now)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            failures,
+            [
+                "users-v1: GET /api/v1/users?page=2 expected to match no rule, but matched 'users-v1'",
+                "users-v1: GET /api/v1/users?page=2 expected to block, but got allow",
+                "orders-v1: GET /api/v1/orders expected to match, but matched nothing",
+                "orders-v1: GET /api/v1/users/7/orders expected to match, but matched 'users-v1'",
+            ]
+        );
+
+        config.endpoints[0].examples[0].path = "api/v1/users".to_string();
+        let err = config.validate().unwrap_err().to_string();
+        assert!(err.contains("example paths must start with '/'"), "{}", err);
+    }
+
+    #[test]
+    fn test_client_identity_sources() {
+        let mut config = ApiDeprecationConfig::from_yaml(
+            r#"
+settings:
+  client_id_header: X-Client-Id
+  client_identity:
+    - type: client_cert
+      pattern: '(?:^|,)\s*O=((?:[^,\\]|\\.)+)'
+    - type: header
+      name: X-Partner
+"#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+        config.compile();
+
+        let client_id = |headers: &[(&str, &str)]| {
+            let headers: HashMap<&str, &str> = headers.iter().copied().collect();
+            config.settings.client_id(|name| headers.get(name).copied())
+        };
+        let cert = |dn| client_id(&[("X-Client-Cert-CN", dn), ("X-Client-Id", "key-1")]);
+
+        assert_eq!(
+            cert(r"CN=billing-svc,O=Acme\, Inc.,C=US").as_deref(),
+            Some("Acme, Inc.")
+        );
+        assert_eq!(
+            cert(r#"CN=Caf\C3\A9\, Ltd,O=Caf\C3\A9 \"Bleu\" \+ Co,C=FR"#).as_deref(),
+            Some("Café \"Bleu\" + Co")
+        );
+        assert_eq!(
+            cert(r"O=Back\\slash\,Comma").as_deref(),
+            Some(r"Back\slash,Comma")
+        );
+
+        // Sources without an identity fall through, in order
+        assert_eq!(
+            client_id(&[
+                ("X-Client-Cert-CN", "CN=no-org"),
+                ("X-Partner", "partner-7"),
+                ("X-Client-Id", "key-1")
+            ])
+            .as_deref(),
+            Some("partner-7")
+        );
+        assert_eq!(
+            client_id(&[("X-Client-Id", "key-1")]).as_deref(),
+            Some("key-1")
+        );
+        assert_eq!(client_id(&[]), None);
+
+        // Without a pattern the header value is the identity, unchanged
+        let source = ClientIdentitySource::ClientCert {
+            header: "X-Client-Cert-CN".to_string(),
+            pattern: None,
+            matcher: None,
+        };
+        assert_eq!(
+            source.identity(r" CN=svc\, inc ").as_deref(),
+            Some(r"CN=svc\, inc")
+        );
+
+        config.settings.client_identity[0] = ClientIdentitySource::ClientCert {
+            header: "X-Client-Cert-CN".to_string(),
+            pattern: Some("O=(".to_string()),
+            matcher: None,
+        };
+        let err = config.validate().unwrap_err().to_string();
+        assert!(
+            err.starts_with("invalid setting 'client_identity'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_invalid_metrics_prefix() {
+        let mut config = ApiDeprecationConfig::default();
+        config.metrics.prefix = "api-deprecation".to_string();
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "invalid setting 'metrics.prefix': is invalid: 'api-deprecation' is not a valid \
+             Prometheus metric name"
+        );
+    }
+
+    #[test]
+    fn test_severity_issues() {
+        let config = ApiDeprecationConfig::from_yaml(
+            r#"
+endpoints:
+  - id: payments-v1
+    path: /api/v1/payments
+    severity: critical
+  - id: debug-v1
+    path: /debug/v1
+    severity: low
+version_deprecations:
+  - id: api-v1
+    prefix: /api/v1
+    successor: /api/v2
+    severity: critical
+    documentation_url: https://docs.example.com/v2
+"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.endpoints[1].severity, Severity::Low);
+
+        let issues: Vec<String> = config
+            .severity_issues()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert!(issues[0].contains("'payments-v1'") && issues[0].contains("documentation_url"));
+        assert!(issues[1].contains("'payments-v1'") && issues[1].contains("replacement"));
+
+        // A documentation_base_url covers every endpoint
+        let mut config = config;
+        config.settings.documentation_base_url = Some("https://docs.example.com/{id}".to_string());
+        assert_eq!(config.severity_issues().len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_dates() {
+        let yaml = |on_invalid_date: &str| {
+            format!(
+                r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    deprecated_at: 2024-01-01
+    sunset_at: "${{SUNSET_DATE}}"
+  - id: orders-v1
+    path: /api/v1/orders
+    sunset_at: 2030-06-01
+    deprecated_params:
+      - name: legacy
+        sunset_at: soon
+settings:
+  on_invalid_date: {}
+"#,
+                on_invalid_date
+            )
+        };
+
+        let err = ApiDeprecationConfig::from_yaml(&yaml("error")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid endpoint 'users-v1': sunset_at is invalid: invalid date '${SUNSET_DATE}', expected RFC 3339 (2025-06-01T00:00:00Z) or YYYY-MM-DD"
+        );
+
+        let config = ApiDeprecationConfig::from_yaml(&yaml("ignore_and_warn")).unwrap();
+        config.validate().unwrap();
+        assert!(config.endpoints[0].sunset_at.is_none());
+        assert!(config.endpoints[0].deprecated_at.is_some());
+        assert!(config.endpoints[1].sunset_at.is_some());
+        assert!(config.endpoints[1].deprecated_params[0].sunset_at.is_none());
+    }
+
+    #[test]
+    fn test_invalid_dates_default_to_error() {
+        let err = ApiDeprecationConfig::from_yaml("settings:\n  default_sunset_at: 2030-13-01\n")
+            .unwrap_err();
+        assert!(matches!(err, ConfigError::Parse { .. }));
+        assert!(err
+            .to_string()
+            .contains("settings.default_sunset_at: invalid date '2030-13-01'"));
+
+        // Other parse errors keep their location
+        let err = ApiDeprecationConfig::from_yaml("endpoints:\n  - id: users\n    pth: /users\n")
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            ConfigError::Parse {
+                location: Some(_),
+                ..
+            }
+        ));
+    }
 }
@@ -0,0 +1,311 @@
+//! Grafana dashboard generated from the configuration.
+//!
+//! The `dashboard` CLI subcommand prints a dashboard with the panels every
+//! team ends up building by hand: requests per endpoint, redirects and blocks
+//! over time, a table of days until sunset and a stat panel per endpoint whose
+//! sunset is near. Queries use the configured metrics prefix, and an
+//! `endpoint` variable lists the configured `endpoint_id` label values.
+
+use crate::config::ApiDeprecationConfig;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+
+/// Options for [`dashboard`].
+#[derive(Debug, Clone)]
+pub struct DashboardOptions {
+    /// Dashboard title
+    pub title: String,
+    /// Name of the Prometheus datasource, shown in the datasource variable
+    pub datasource_name: String,
+    /// UID of the Prometheus datasource
+    pub datasource_uid: String,
+    /// Endpoints whose sunset is at most this many days away get a stat panel
+    pub lead_days: i64,
+}
+
+impl Default for DashboardOptions {
+    fn default() -> Self {
+        Self {
+            title: "API Deprecations".to_string(),
+            datasource_name: "Prometheus".to_string(),
+            datasource_uid: "prometheus".to_string(),
+            lead_days: 90,
+        }
+    }
+}
+
+/// Panels are laid out on Grafana's 24-column grid.
+const GRID_WIDTH: u64 = 24;
+const STAT_WIDTH: u64 = 6;
+
+/// Build the dashboard for a compiled configuration as of `now`.
+pub fn dashboard(
+    config: &ApiDeprecationConfig,
+    options: &DashboardOptions,
+    now: DateTime<Utc>,
+) -> Value {
+    let prefix = &config.metrics.prefix;
+    let series = config.metrics.request_series();
+    let datasource = json!({ "type": "prometheus", "uid": "${datasource}" });
+    let selector = r#"endpoint_id=~"$endpoint""#;
+
+    let mut endpoint_ids: Vec<&str> = Vec::new();
+    for endpoint in &config.endpoints {
+        if !endpoint_ids.contains(&endpoint.metrics_id()) {
+            endpoint_ids.push(endpoint.metrics_id());
+        }
+    }
+
+    let mut panels = vec![
+        json!({
+            "id": 1,
+            "type": "timeseries",
+            "title": "Requests by endpoint",
+            "datasource": datasource,
+            "gridPos": { "x": 0, "y": 0, "w": GRID_WIDTH / 2, "h": 8 },
+            "fieldConfig": { "defaults": { "unit": "reqps" }, "overrides": [] },
+            "targets": [{
+                "refId": "A",
+                "datasource": datasource,
+                "expr": format!("sum by (endpoint_id) (rate({series}{{{selector}}}[5m]))"),
+                "legendFormat": "{{endpoint_id}}",
+            }],
+        }),
+        json!({
+            "id": 2,
+            "type": "timeseries",
+            "title": "Redirects and blocks",
+            "datasource": datasource,
+            "gridPos": { "x": GRID_WIDTH / 2, "y": 0, "w": GRID_WIDTH / 2, "h": 8 },
+            "fieldConfig": { "defaults": { "unit": "reqps" }, "overrides": [] },
+            "targets": [
+                {
+                    "refId": "A",
+                    "datasource": datasource,
+                    "expr": format!("sum by (endpoint_id) (rate({prefix}_redirects_total{{{selector}}}[5m]))"),
+                    "legendFormat": "{{endpoint_id}} redirected",
+                },
+                {
+                    "refId": "B",
+                    "datasource": datasource,
+                    "expr": format!("sum by (endpoint_id) (rate({prefix}_blocked_total{{{selector}}}[5m]))"),
+                    "legendFormat": "{{endpoint_id}} blocked",
+                },
+            ],
+        }),
+        json!({
+            "id": 3,
+            "type": "table",
+            "title": "Days until sunset",
+            "datasource": datasource,
+            "gridPos": { "x": 0, "y": 8, "w": GRID_WIDTH, "h": 8 },
+            "targets": [{
+                "refId": "A",
+                "datasource": datasource,
+                "expr": format!("min by (endpoint_id, path) ({prefix}_days_until_sunset{{{selector}}})"),
+                "format": "table",
+                "instant": true,
+            }],
+            "transformations": [{
+                "id": "organize",
+                "options": {
+                    "excludeByName": { "Time": true },
+                    "renameByName": { "Value": "days until sunset" },
+                },
+            }],
+        }),
+    ];
+
+    // One stat panel per endpoint nearing its sunset, soonest first
+    let mut nearing: Vec<(&str, i64)> = config
+        .endpoints
+        .iter()
+        .filter_map(|endpoint| {
+            let days = endpoint.days_until_sunset(now)?;
+            (0..=options.lead_days)
+                .contains(&days)
+                .then_some((endpoint.metrics_id(), days))
+        })
+        .collect();
+    nearing.sort_by_key(|&(id, days)| (days, id));
+    nearing.dedup_by_key(|(id, _)| *id);
+
+    let per_row = GRID_WIDTH / STAT_WIDTH;
+    for (index, (endpoint_id, _)) in nearing.iter().enumerate() {
+        let index = index as u64;
+        panels.push(json!({
+            "id": 4 + index,
+            "type": "stat",
+            "title": format!("{endpoint_id}: days until sunset"),
+            "datasource": datasource,
+            "gridPos": {
+                "x": (index % per_row) * STAT_WIDTH,
+                "y": 16 + (index / per_row) * 4,
+                "w": STAT_WIDTH,
+                "h": 4,
+            },
+            "fieldConfig": {
+                "defaults": {
+                    "unit": "d",
+                    "thresholds": {
+                        "mode": "absolute",
+                        "steps": [
+                            { "color": "red", "value": null },
+                            { "color": "orange", "value": 7 },
+                            { "color": "green", "value": 30 },
+                        ],
+                    },
+                },
+                "overrides": [],
+            },
+            "targets": [{
+                "refId": "A",
+                "datasource": datasource,
+                "expr": format!(r#"min({prefix}_days_until_sunset{{endpoint_id="{endpoint_id}"}})"#),
+                "instant": true,
+            }],
+        }));
+    }
+
+    json!({
+        "title": options.title,
+        "uid": null,
+        "tags": ["api-deprecation"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "time": { "from": "now-7d", "to": "now" },
+        "templating": {
+            "list": [
+                {
+                    "name": "datasource",
+                    "label": "Datasource",
+                    "type": "datasource",
+                    "query": "prometheus",
+                    "current": {
+                        "text": options.datasource_name,
+                        "value": options.datasource_uid,
+                    },
+                },
+                {
+                    "name": "endpoint",
+                    "label": "Endpoint",
+                    "type": "custom",
+                    "query": endpoint_ids.join(","),
+                    "multi": true,
+                    "includeAll": true,
+                    "allValue": ".*",
+                    "current": { "text": "All", "value": "$__all" },
+                },
+            ],
+        },
+        "panels": panels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dashboard_snapshot() {
+        let mut config = ApiDeprecationConfig::from_yaml(
+            r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+    sunset_at: 2030-01-20
+  - id: orders
+    path: /api/v1/orders
+    metrics_id: orders-v1
+    sunset_at: 2030-01-05
+  - id: posts
+    path: /api/v1/posts
+    sunset_at: 2031-01-01
+metrics:
+  prefix: acme_deprecation
+"#,
+        )
+        .unwrap();
+        config.compile();
+        let options = DashboardOptions {
+            datasource_name: "Mimir".to_string(),
+            datasource_uid: "mimir-prod".to_string(),
+            ..DashboardOptions::default()
+        };
+        let dashboard = dashboard(&config, &options, "2030-01-01T00:00:00Z".parse().unwrap());
+
+        let variables = &dashboard["templating"]["list"];
+        assert_eq!(variables[0]["current"]["value"], "mimir-prod");
+        assert_eq!(variables[0]["current"]["text"], "Mimir");
+        assert_eq!(variables[1]["query"], "users,orders-v1,posts");
+
+        let panels = dashboard["panels"].as_array().unwrap();
+        let summary: Vec<_> = panels
+            .iter()
+            .map(|panel| {
+                (
+                    panel["type"].as_str().unwrap(),
+                    panel["title"].as_str().unwrap(),
+                    panel["targets"][0]["expr"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "timeseries",
+                    "Requests by endpoint",
+                    r#"sum by (endpoint_id) (rate(acme_deprecation_requests_total{endpoint_id=~"$endpoint"}[5m]))"#
+                ),
+                (
+                    "timeseries",
+                    "Redirects and blocks",
+                    r#"sum by (endpoint_id) (rate(acme_deprecation_redirects_total{endpoint_id=~"$endpoint"}[5m]))"#
+                ),
+                (
+                    "table",
+                    "Days until sunset",
+                    r#"min by (endpoint_id, path) (acme_deprecation_days_until_sunset{endpoint_id=~"$endpoint"})"#
+                ),
+                (
+                    "stat",
+                    "orders-v1: days until sunset",
+                    r#"min(acme_deprecation_days_until_sunset{endpoint_id="orders-v1"})"#
+                ),
+                (
+                    "stat",
+                    "users: days until sunset",
+                    r#"min(acme_deprecation_days_until_sunset{endpoint_id="users"})"#
+                ),
+            ]
+        );
+        assert_eq!(panels[4]["gridPos"]["x"], 6);
+        assert_eq!(panels[4]["datasource"]["uid"], "${datasource}");
+    }
+
+    #[test]
+    fn test_dashboard_with_sessions() {
+        let mut config = ApiDeprecationConfig::from_yaml(
+            r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+metrics:
+  session_window_secs: 1800
+"#,
+        )
+        .unwrap();
+        config.compile();
+        let dashboard = dashboard(
+            &config,
+            &DashboardOptions::default(),
+            "2030-01-01T00:00:00Z".parse().unwrap(),
+        );
+
+        assert_eq!(
+            dashboard["panels"][0]["targets"][0]["expr"],
+            r#"sum by (endpoint_id) (rate(zentinel_api_deprecation_requests_raw_total{endpoint_id=~"$endpoint"}[5m]))"#
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Bounded buffer of sampled requests for diagnostics.
+//!
+//! Tombstoned endpoints (removed endpoints with `tombstone` set) sample the
+//! requests they reject into a [`DiagnosticsBuffer`], so operators can find
+//! the last callers of an API that is already gone. The buffer keeps the most
+//! recent `settings.diagnostics_buffer_size` samples and is read through
+//! [`ApiDeprecationAgent::diagnostic_samples`](crate::ApiDeprecationAgent::diagnostic_samples).
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Mutex, PoisonError};
+
+/// Distinct `(endpoint, method, path, client)` metric series kept for
+/// tombstoned endpoints; further callers are reported as `other`.
+pub const MAX_TOMBSTONE_SERIES: usize = 1000;
+
+/// A request sampled for diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiagnosticSample {
+    /// When the request was seen
+    pub at: DateTime<Utc>,
+    /// Rule that handled the request
+    pub endpoint_id: String,
+    pub method: String,
+    pub path: String,
+    /// Client identity (from `settings.client_identity` or
+    /// `settings.client_id_header`), if configured and sent
+    pub client: Option<String>,
+}
+
+/// Ring buffer of the most recent samples.
+#[derive(Debug)]
+pub struct DiagnosticsBuffer {
+    samples: Mutex<VecDeque<DiagnosticSample>>,
+    capacity: usize,
+}
+
+impl DiagnosticsBuffer {
+    /// Create a buffer keeping at most `capacity` samples (0 keeps none).
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            samples: Mutex::new(VecDeque::with_capacity(capacity.min(1024))),
+            capacity,
+        }
+    }
+
+    /// Add a sample, dropping the oldest one when full.
+    pub fn push(&self, sample: DiagnosticSample) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut samples = self.samples.lock().unwrap_or_else(PoisonError::into_inner);
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// Current samples, oldest first.
+    pub fn snapshot(&self) -> Vec<DiagnosticSample> {
+        self.samples
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Per-caller bookkeeping for tombstoned endpoints.
+#[derive(Debug, Default)]
+pub struct TombstoneTracker {
+    series: HashSet<(String, String, String, String)>,
+    hits: HashMap<String, u64>,
+}
+
+impl TombstoneTracker {
+    /// Labels for a hit: the caller's path and client while fewer than
+    /// [`MAX_TOMBSTONE_SERIES`] series exist (or the series is known),
+    /// `other` for both afterwards.
+    pub fn labels(
+        &mut self,
+        endpoint_id: &str,
+        method: &str,
+        path: &str,
+        client: Option<&str>,
+    ) -> (String, String) {
+        let key = (
+            endpoint_id.to_string(),
+            method.to_string(),
+            path.to_string(),
+            client.unwrap_or("unknown").to_string(),
+        );
+        if self.series.contains(&key) || self.series.len() < MAX_TOMBSTONE_SERIES {
+            let labels = (key.2.clone(), key.3.clone());
+            self.series.insert(key);
+            labels
+        } else {
+            ("other".to_string(), "other".to_string())
+        }
+    }
+
+    /// Count a hit on an endpoint and tell whether to sample it: the first
+    /// of every `sample_every` hits is.
+    pub fn should_sample(&mut self, endpoint_id: &str, sample_every: u32) -> bool {
+        let hits = self.hits.entry(endpoint_id.to_string()).or_default();
+        *hits += 1;
+        (*hits - 1).is_multiple_of(u64::from(sample_every.max(1)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(path: &str) -> DiagnosticSample {
+        DiagnosticSample {
+            at: "2030-01-01T00:00:00Z".parse().unwrap(),
+            endpoint_id: "legacy-auth".to_string(),
+            method: "GET".to_string(),
+            path: path.to_string(),
+            client: None,
+        }
+    }
+
+    #[test]
+    fn test_keeps_most_recent_samples() {
+        let buffer = DiagnosticsBuffer::new(2);
+        for path in ["/a", "/b", "/c"] {
+            buffer.push(sample(path));
+        }
+
+        let paths: Vec<_> = buffer.snapshot().into_iter().map(|s| s.path).collect();
+        assert_eq!(paths, ["/b", "/c"]);
+
+        let disabled = DiagnosticsBuffer::new(0);
+        disabled.push(sample("/a"));
+        assert!(disabled.snapshot().is_empty());
+    }
+
+    #[test]
+    fn test_tombstone_series_are_bounded() {
+        let mut tracker = TombstoneTracker::default();
+        for i in 0..MAX_TOMBSTONE_SERIES {
+            tracker.labels("legacy-auth", "GET", "/auth/legacy", Some(&format!("c{i}")));
+        }
+
+        assert_eq!(
+            tracker.labels("legacy-auth", "GET", "/auth/legacy", Some("c0")),
+            ("/auth/legacy".to_string(), "c0".to_string())
+        );
+        assert_eq!(
+            tracker.labels("legacy-auth", "GET", "/auth/legacy", Some("new")),
+            ("other".to_string(), "other".to_string())
+        );
+
+        let sampled: Vec<_> = (0..5)
+            .map(|_| tracker.should_sample("legacy-auth", 2))
+            .collect();
+        assert_eq!(sampled, [true, false, true, false, true]);
+    }
+}
@@ -0,0 +1,352 @@
+//! Enforcement differences between two configurations.
+//!
+//! The `diff` CLI subcommand compares the configuration in `--config` with a
+//! proposed one, so a reviewer sees what a change does before it ships: rules
+//! added and removed (with the paths they cover) and, for rules kept under the
+//! same id, the fields that decide how requests are treated. Actions are
+//! reported as enforced: a blocking rule that isn't `confirmed` only warns.
+
+use crate::config::{ApiDeprecationConfig, DeprecatedEndpoint, GlobalSettings};
+use crate::history::SunsetMove;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Differences between two configurations, by rule id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConfigDiff {
+    /// [`ApiDeprecationConfig::hash`] of the old configuration
+    pub old_hash: String,
+    /// [`ApiDeprecationConfig::hash`] of the new configuration
+    pub new_hash: String,
+    /// Rules only in the new configuration
+    pub added: Vec<EndpointSummary>,
+    /// Rules only in the old configuration
+    pub removed: Vec<EndpointSummary>,
+    /// Rules in both whose enforcement changed
+    pub modified: Vec<EndpointChanges>,
+}
+
+impl ConfigDiff {
+    /// Whether the configurations enforce the same way (their hashes may
+    /// still differ in settings outside the rules).
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+/// A rule added or removed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EndpointSummary {
+    pub id: String,
+    pub path: String,
+    pub status: String,
+    pub action: String,
+}
+
+impl EndpointSummary {
+    fn of(endpoint: &DeprecatedEndpoint, settings: &GlobalSettings) -> Self {
+        Self {
+            id: endpoint.id.clone(),
+            path: endpoint.path.to_string(),
+            status: label(&endpoint.status),
+            action: effective_action(endpoint, settings),
+        }
+    }
+}
+
+/// The changed fields of a rule kept under the same id.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EndpointChanges {
+    pub id: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// One field of a rule, before and after.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub from: String,
+    pub to: String,
+    /// Direction of a sunset move
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sunset_move: Option<SunsetMove>,
+}
+
+impl FieldChange {
+    fn new(field: &'static str, from: String, to: String) -> Option<Self> {
+        (from != to).then_some(Self {
+            field,
+            from,
+            to,
+            sunset_move: None,
+        })
+    }
+}
+
+/// Compare the rules of `old` and `new`; rules keep their order within each
+/// list (`removed` in `old`'s order, the others in `new`'s).
+pub fn diff(old: &ApiDeprecationConfig, new: &ApiDeprecationConfig) -> ConfigDiff {
+    let previous: HashMap<&str, &DeprecatedEndpoint> = old
+        .endpoints
+        .iter()
+        .map(|endpoint| (endpoint.id.as_str(), endpoint))
+        .collect();
+    let current: HashMap<&str, &DeprecatedEndpoint> = new
+        .endpoints
+        .iter()
+        .map(|endpoint| (endpoint.id.as_str(), endpoint))
+        .collect();
+
+    let mut diff = ConfigDiff {
+        old_hash: old.hash(),
+        new_hash: new.hash(),
+        ..ConfigDiff::default()
+    };
+    for endpoint in &new.endpoints {
+        match previous.get(endpoint.id.as_str()) {
+            None => diff
+                .added
+                .push(EndpointSummary::of(endpoint, &new.settings)),
+            Some(before) => {
+                let changes = field_changes((before, &old.settings), (endpoint, &new.settings));
+                if !changes.is_empty() {
+                    diff.modified.push(EndpointChanges {
+                        id: endpoint.id.clone(),
+                        changes,
+                    });
+                }
+            }
+        }
+    }
+    diff.removed = old
+        .endpoints
+        .iter()
+        .filter(|endpoint| !current.contains_key(endpoint.id.as_str()))
+        .map(|endpoint| EndpointSummary::of(endpoint, &old.settings))
+        .collect();
+    diff
+}
+
+fn field_changes(
+    (old, old_settings): (&DeprecatedEndpoint, &GlobalSettings),
+    (new, new_settings): (&DeprecatedEndpoint, &GlobalSettings),
+) -> Vec<FieldChange> {
+    let date = |at: Option<chrono::DateTime<chrono::Utc>>| {
+        at.map_or_else(|| "none".to_string(), |at| at.to_rfc3339())
+    };
+    let methods = |endpoint: &DeprecatedEndpoint| match endpoint.methods.as_slice() {
+        [] => "any".to_string(),
+        methods => methods.join(", "),
+    };
+
+    let mut changes: Vec<FieldChange> = [
+        FieldChange::new("path", old.path.to_string(), new.path.to_string()),
+        FieldChange::new("methods", methods(old), methods(new)),
+        FieldChange::new("status", label(&old.status), label(&new.status)),
+        FieldChange::new(
+            "action",
+            effective_action(old, old_settings),
+            effective_action(new, new_settings),
+        ),
+        FieldChange::new("active_from", date(old.active_from), date(new.active_from)),
+        FieldChange::new(
+            "active_until",
+            date(old.active_until),
+            date(new.active_until),
+        ),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if let Some(mut change) =
+        FieldChange::new("sunset_at", date(old.sunset_at), date(new.sunset_at))
+    {
+        change.sunset_move = match (old.sunset_at, new.sunset_at) {
+            (Some(from), Some(to)) => SunsetMove::classify(from, to),
+            (Some(_), None) => Some(SunsetMove::Withdrawal),
+            (None, _) => None,
+        };
+        changes.push(change);
+    }
+    changes
+}
+
+/// The rule's action as enforced: an unconfirmed rule that could block is
+/// shown as warning, with the action it holds back.
+fn effective_action(endpoint: &DeprecatedEndpoint, settings: &GlobalSettings) -> String {
+    let action = label(&endpoint.action);
+    if endpoint.is_unconfirmed(settings) {
+        format!("warn (unconfirmed {action})")
+    } else {
+        action
+    }
+}
+
+/// Short form of a status or action: its name, followed by any settings.
+fn label(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(name)) => name,
+        Ok(Value::Object(fields)) => {
+            let mut label = fields
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or("custom")
+                .to_string();
+            for (key, value) in fields.iter().filter(|(key, _)| *key != "type") {
+                match value {
+                    Value::Null => continue,
+                    Value::String(value) => label.push_str(&format!(" {key}={value}")),
+                    value => label.push_str(&format!(" {key}={value}")),
+                }
+            }
+            label
+        }
+        Ok(other) => other.to_string(),
+        Err(_) => "?".to_string(),
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No endpoint changes");
+        }
+        for endpoint in &self.added {
+            writeln!(
+                f,
+                "+ {} {} (status: {}, action: {})",
+                endpoint.id, endpoint.path, endpoint.status, endpoint.action
+            )?;
+        }
+        for endpoint in &self.removed {
+            writeln!(
+                f,
+                "- {} {} (status: {}, action: {})",
+                endpoint.id, endpoint.path, endpoint.status, endpoint.action
+            )?;
+        }
+        for endpoint in &self.modified {
+            writeln!(f, "~ {}", endpoint.id)?;
+            for change in &endpoint.changes {
+                write!(f, "    {}: {} -> {}", change.field, change.from, change.to)?;
+                match change.sunset_move {
+                    Some(SunsetMove::Extension) => write!(f, " (extended)")?,
+                    Some(SunsetMove::Acceleration) => write!(f, " (brought forward)")?,
+                    Some(SunsetMove::Withdrawal) => write!(f, " (withdrawn)")?,
+                    None => {}
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> ApiDeprecationConfig {
+        let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        config.compile();
+        config
+    }
+
+    const OLD: &str = r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: "2030-06-01T00:00:00Z"
+  - id: posts-v1
+    path: /api/v1/posts
+    status: removed
+    action:
+      type: block
+"#;
+
+    #[test]
+    fn test_diff_detects_changes() {
+        let old = config(OLD);
+        let new = config(
+            r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: "2030-09-01T00:00:00Z"
+    action:
+      type: block
+      status_code: 410
+  - id: orders-v1
+    path: /api/v1/orders/*
+"#,
+        );
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.old_hash, old.hash());
+        assert_ne!(diff.old_hash, diff.new_hash);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].id, "orders-v1");
+        assert_eq!(diff.added[0].path, "/api/v1/orders/*");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].id, "posts-v1");
+
+        assert_eq!(diff.modified.len(), 1);
+        let users = &diff.modified[0];
+        assert_eq!(users.id, "users-v1");
+        let action = users.changes.iter().find(|c| c.field == "action").unwrap();
+        assert_eq!(action.from, "warn");
+        assert_eq!(action.to, "block status_code=410");
+        let sunset = users
+            .changes
+            .iter()
+            .find(|c| c.field == "sunset_at")
+            .unwrap();
+        assert_eq!(sunset.sunset_move, Some(SunsetMove::Extension));
+        assert!(!users.changes.iter().any(|c| c.field == "path"));
+
+        let report = diff.to_string();
+        assert!(report.contains("+ orders-v1 /api/v1/orders/*"));
+        assert!(report.contains("- posts-v1 /api/v1/posts"));
+        assert!(report.contains("    action: warn -> block status_code=410"));
+        assert!(report.contains("(extended)"));
+    }
+
+    #[test]
+    fn test_diff_reports_effective_action() {
+        let yaml = r#"
+settings:
+  require_confirmation: true
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    action:
+      type: block
+"#;
+        let old = config(yaml);
+        let new = config(&yaml.replace("type: block", "type: block\n    confirmed: true"));
+
+        // Confirming the rule is what makes it block
+        let diff = old.diff(&new);
+        assert_eq!(diff.modified.len(), 1);
+        let action = &diff.modified[0].changes[0];
+        assert_eq!(action.field, "action");
+        assert_eq!(action.from, "warn (unconfirmed block status_code=410)");
+        assert_eq!(action.to, "block status_code=410");
+
+        let added = ApiDeprecationConfig::default().diff(&old);
+        assert_eq!(
+            added.added[0].action,
+            "warn (unconfirmed block status_code=410)"
+        );
+    }
+
+    #[test]
+    fn test_diff_of_identical_configs_is_empty() {
+        let diff = config(OLD).diff(&config(OLD));
+        assert!(diff.is_empty());
+        assert_eq!(diff.old_hash, diff.new_hash);
+        assert_eq!(diff.to_string(), "No endpoint changes\n");
+    }
+}
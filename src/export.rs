@@ -0,0 +1,242 @@
+//! CSV export of the usage snapshot and the endpoint catalog.
+//!
+//! `GET /export.csv` on the admin listener answers with one row per
+//! configured endpoint: the same data as
+//! [`usage_snapshot`](crate::ApiDeprecationAgent::usage_snapshot), in RFC 4180
+//! CSV (header row, CRLF line endings, fields with commas, quotes or line
+//! breaks quoted, formula-like text prefixed with `'`) for spreadsheets. It
+//! names owners and carries operator notes, so it requires the admin token.
+//!
+//! `?window=30d` (or `12h`) swaps the lifetime request count for the requests
+//! of that trailing window: by the hour up to eight days
+//! ([`TREND_HOURS`](crate::usage::TREND_HOURS)), in whole UTC days beyond
+//! that, up to [`HISTORY_DAYS`]. Redirects and blocks aren't kept over time,
+//! so those columns are left empty in windowed exports.
+//!
+//! The `export` CLI subcommand fetches the export from a running agent, and
+//! falls back to [`catalog_csv`] (the configuration-only columns) when no
+//! agent answers.
+
+use crate::config::ApiDeprecationConfig;
+use crate::usage::{EndpointUsage, HISTORY_DAYS};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Columns of the export, in order.
+pub const COLUMNS: [&str; 14] = [
+    "id",
+    "path",
+    "status",
+    "sunset_at",
+    "days_until_sunset",
+    "requests",
+    "redirects",
+    "blocked",
+    "unique_consumers_today",
+    "unique_consumers_yesterday",
+    "last_access",
+    "owner",
+    "severity",
+    "notes",
+];
+
+/// Parse a `window` parameter (`<N>h` or `<N>d`) into hours, refusing
+/// windows longer than the usage history ([`HISTORY_DAYS`]).
+pub fn parse_window(window: &str) -> Result<usize, String> {
+    let (count, hours_per_unit) = match window.strip_suffix('d') {
+        Some(days) => (days, 24),
+        None => match window.strip_suffix('h') {
+            Some(hours) => (hours, 1),
+            None => return Err(format!("'{}' is not a window like 30d or 12h", window)),
+        },
+    };
+    let hours = count
+        .parse::<usize>()
+        .ok()
+        .filter(|count| *count > 0)
+        .ok_or_else(|| format!("'{}' is not a window like 30d or 12h", window))?
+        .saturating_mul(hours_per_unit);
+    if hours > HISTORY_DAYS * 24 {
+        return Err(format!(
+            "window '{}' is longer than the {} days of usage history",
+            window, HISTORY_DAYS
+        ));
+    }
+    Ok(hours)
+}
+
+/// The usage snapshot as CSV. With `recent` (requests per `metrics_id` over
+/// a window), the requests column holds those and redirects and blocks are
+/// left empty.
+pub fn usage_csv(usage: &[EndpointUsage], recent: Option<&HashMap<String, u64>>) -> String {
+    let mut csv = csv_line(COLUMNS.iter().map(|column| Cow::Borrowed(*column)));
+    for endpoint in usage {
+        let (requests, redirects, blocked) = match recent {
+            Some(recent) => (
+                recent
+                    .get(&endpoint.metrics_id)
+                    .copied()
+                    .unwrap_or(0)
+                    .to_string(),
+                String::new(),
+                String::new(),
+            ),
+            None => (
+                endpoint.requests.to_string(),
+                endpoint.redirects.to_string(),
+                endpoint.blocked.to_string(),
+            ),
+        };
+        csv.push_str(&csv_line([
+            Cow::Borrowed(endpoint.id.as_str()),
+            Cow::Borrowed(endpoint.pattern.as_str()),
+            Cow::Borrowed(endpoint.status.as_str()),
+            timestamp(endpoint.sunset_at).into(),
+            optional(endpoint.days_until_sunset).into(),
+            requests.into(),
+            redirects.into(),
+            blocked.into(),
+            endpoint.unique_consumers_today.to_string().into(),
+            optional(endpoint.unique_consumers_yesterday).into(),
+            timestamp(endpoint.last_access).into(),
+            Cow::Borrowed(endpoint.owner.as_deref().unwrap_or_default()),
+            Cow::Borrowed(endpoint.severity.as_str()),
+            Cow::Borrowed(endpoint.notes.as_deref().unwrap_or_default()),
+        ]));
+    }
+    csv
+}
+
+/// The configured endpoints as CSV with only the columns the configuration
+/// knows; usage columns are left empty.
+pub fn catalog_csv(config: &ApiDeprecationConfig, now: DateTime<Utc>) -> String {
+    let mut csv = csv_line(COLUMNS.iter().map(|column| Cow::Borrowed(*column)));
+    for endpoint in &config.endpoints {
+        csv.push_str(&csv_line([
+            Cow::Borrowed(endpoint.id.as_str()),
+            Cow::Borrowed(endpoint.path.primary()),
+            Cow::Borrowed(endpoint.status.as_str()),
+            timestamp(endpoint.sunset_at).into(),
+            optional(endpoint.days_until_sunset(now)).into(),
+            Cow::Borrowed(""),
+            Cow::Borrowed(""),
+            Cow::Borrowed(""),
+            Cow::Borrowed(""),
+            Cow::Borrowed(""),
+            Cow::Borrowed(""),
+            Cow::Borrowed(endpoint.owner.as_deref().unwrap_or_default()),
+            Cow::Borrowed(endpoint.severity.as_str()),
+            Cow::Borrowed(endpoint.notes.as_deref().unwrap_or_default()),
+        ]));
+    }
+    csv
+}
+
+fn timestamp(time: Option<DateTime<Utc>>) -> String {
+    time.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+        .unwrap_or_default()
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// One CSV record, terminated by CRLF.
+fn csv_line<'a>(fields: impl IntoIterator<Item = Cow<'a, str>>) -> String {
+    let mut line = fields
+        .into_iter()
+        .map(|field| quote(&neutralize(field)).into_owned())
+        .collect::<Vec<_>>()
+        .join(",");
+    line.push_str("\r\n");
+    line
+}
+
+/// Prefix a field a spreadsheet would read as a formula (starting with `=`,
+/// `+`, `-`, `@`, a tab or a carriage return) with `'`, so operator-written
+/// text like `notes` can't run in whoever opens the export. Numbers, such as
+/// negative days until sunset, are left alone.
+fn neutralize(field: Cow<'_, str>) -> Cow<'_, str> {
+    if field.starts_with(['=', '+', '-', '@', '\t', '\r']) && field.parse::<f64>().is_err() {
+        Cow::Owned(format!("'{}", field))
+    } else {
+        field
+    }
+}
+
+/// Quote a field containing a comma, quote or line break, doubling quotes.
+fn quote(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\r', '\n']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote("a, b"), "\"a, b\"");
+        assert_eq!(quote("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(quote("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn test_neutralize() {
+        for (field, expected) in [
+            ("=HYPERLINK(\"http://x\")", "'=HYPERLINK(\"http://x\")"),
+            ("+1 555 0100", "'+1 555 0100"),
+            ("-see ticket", "'-see ticket"),
+            ("@owner", "'@owner"),
+            ("-42", "-42"),
+            ("plain", "plain"),
+        ] {
+            assert_eq!(neutralize(Cow::Borrowed(field)), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_window() {
+        assert_eq!(parse_window("12h"), Ok(12));
+        assert_eq!(parse_window("7d"), Ok(168));
+        assert_eq!(parse_window("30d"), Ok(720));
+        assert_eq!(parse_window("31d"), Ok(HISTORY_DAYS * 24));
+        assert!(parse_window("32d").unwrap_err().contains("31 days"));
+        for invalid in ["", "d", "0d", "-1d", "7w", "1.5d"] {
+            assert!(parse_window(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_catalog_csv() {
+        let yaml = r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: "2030-06-01T00:00:00Z"
+    owner: identity-team
+    notes: "Mobile clients, v3 and older"
+    severity: high
+  - id: posts-v1
+    path: /api/v1/posts
+    status: removed
+"#;
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        let now = "2030-05-01T00:00:00Z".parse().unwrap();
+
+        let csv = catalog_csv(&config, now);
+        let lines: Vec<&str> = csv.split_terminator("\r\n").collect();
+        assert_eq!(lines[0], COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            "users-v1,/api/v1/users,deprecated,2030-06-01T00:00:00Z,31,,,,,,,identity-team,high,\"Mobile clients, v3 and older\""
+        );
+        assert_eq!(lines[2], "posts-v1,/api/v1/posts,removed,,,,,,,,,,normal,");
+        assert_eq!(lines.len(), 3);
+    }
+}
@@ -0,0 +1,183 @@
+//! Seeded configuration generator for load tests, benchmarks and fuzzing.
+//!
+//! [`generate_config`] builds a large, valid configuration from a seed: the
+//! same seed and size always give the same rules, so a benchmark run or a
+//! failing fuzz case can be reproduced exactly. Rules mix literal and glob
+//! paths, method lists, statuses and actions, and all dates are fixed
+//! relative to [`BASE_DATE`] rather than the current time.
+//!
+//! Available in tests and with the `fixtures` feature.
+
+use crate::config::ApiDeprecationConfig;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+
+/// Date the generated `deprecated_at` and `sunset_at` dates are spread around.
+pub const BASE_DATE: &str = "2030-01-01T00:00:00Z";
+
+const RESOURCES: &[&str] = &[
+    "users", "orders", "posts", "invoices", "products", "carts", "reports", "tokens",
+];
+const METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE"];
+
+/// Generate a valid configuration of `n_endpoints` rules from `seed`.
+///
+/// The configuration is not compiled.
+pub fn generate_config(seed: u64, n_endpoints: usize) -> ApiDeprecationConfig {
+    let mut rng = SplitMix64(seed);
+    let endpoints: Vec<Value> = (0..n_endpoints)
+        .map(|i| generate_endpoint(&mut rng, i))
+        .collect();
+    serde_json::from_value(json!({ "endpoints": endpoints }))
+        .expect("generated endpoints deserialize")
+}
+
+fn generate_endpoint(rng: &mut SplitMix64, i: usize) -> Value {
+    let resource = rng.pick(RESOURCES);
+    let version = 1 + rng.below(4);
+    let path = match rng.below(4) {
+        0 => format!("/service-{}/v{version}/*/{resource}", rng.below(8)),
+        1 => format!("/api/v{version}/{resource}-{i}/*"),
+        _ => format!("/api/v{version}/{resource}-{i}"),
+    };
+
+    let mut endpoint = json!({
+        "id": format!("rule-{i}"),
+        "path": path,
+        "confirmed": rng.below(2) == 0,
+    });
+
+    let methods: Vec<&str> = METHODS
+        .iter()
+        .copied()
+        .filter(|_| rng.below(3) == 0)
+        .collect();
+    if !methods.is_empty() {
+        endpoint["methods"] = json!(methods);
+    }
+
+    let base = DateTime::parse_from_rfc3339(BASE_DATE)
+        .expect("BASE_DATE is RFC 3339")
+        .with_timezone(&Utc);
+    let offset = |days: u64| base + Duration::days(days as i64);
+    let date = |at: DateTime<Utc>| Value::String(at.to_rfc3339());
+    match rng.below(5) {
+        0 => {
+            endpoint["status"] = json!("scheduled");
+            endpoint["deprecated_at"] = date(offset(rng.below(180)));
+            endpoint["sunset_at"] = date(offset(180 + rng.below(365)));
+        }
+        1 => {
+            endpoint["status"] = json!("removed");
+            endpoint["sunset_at"] = date(base - Duration::days(1 + rng.below(365) as i64));
+        }
+        _ => {
+            endpoint["status"] = json!("deprecated");
+            endpoint["deprecated_at"] = date(base - Duration::days(rng.below(365) as i64));
+            if rng.below(4) != 0 {
+                endpoint["sunset_at"] = date(offset(rng.below(730)));
+            }
+        }
+    }
+
+    let replacement = format!("/api/v{}/{resource}", version + 1);
+    match rng.below(6) {
+        0 => {
+            endpoint["replacement"] = json!({ "path": replacement });
+            endpoint["action"] = json!({
+                "type": "redirect",
+                "status_code": rng.pick(&[301u16, 302, 307, 308]),
+            });
+        }
+        1 => {
+            endpoint["action"] = json!({
+                "type": "block",
+                "status_code": rng.pick(&[403u16, 404, 410]),
+            });
+        }
+        2 => {
+            endpoint["action"] = json!({
+                "type": "custom",
+                "status_code": 403,
+                "body": format!("{{\"error\": \"{resource} v{version} is retired\"}}"),
+            });
+        }
+        _ => {
+            if rng.below(2) == 0 {
+                endpoint["replacement"] = json!({ "path": replacement });
+            }
+        }
+    }
+    endpoint
+}
+
+/// SplitMix64: small, fast and identical on every platform.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A value in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len() as u64) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_config() {
+        let first = generate_config(7, 200);
+        let second = generate_config(7, 200);
+        assert_eq!(first.endpoints.len(), 200);
+        assert_eq!(
+            serde_json::to_value(&first).unwrap(),
+            serde_json::to_value(&second).unwrap()
+        );
+        assert_eq!(first.hash(), second.hash());
+        assert_ne!(first.hash(), generate_config(8, 200).hash());
+    }
+
+    #[test]
+    fn test_generated_configs_are_valid() {
+        for seed in 0..64 {
+            let config = generate_config(seed, 50);
+            if let Err(err) = config.validate() {
+                panic!("seed {}: {}", seed, err);
+            }
+
+            let mut compiled = config;
+            compiled.compile();
+            assert!(compiled.endpoints.iter().all(|e| !e.has_invalid_pattern()));
+        }
+    }
+
+    #[test]
+    fn test_generated_configs_vary() {
+        let config = generate_config(1, 500);
+        let json = serde_json::to_string(&config).unwrap();
+        for needle in [
+            "\"scheduled\"",
+            "\"removed\"",
+            "\"redirect\"",
+            "\"block\"",
+            "\"custom\"",
+            "/*",
+            "\"DELETE\"",
+        ] {
+            assert!(json.contains(needle), "no {} in generated rules", needle);
+        }
+    }
+}
@@ -5,8 +5,11 @@
 //! - Sunset header (RFC 8594)
 //! - Link header with documentation
 
-use crate::config::{DeprecatedEndpoint, GlobalSettings};
-use chrono::{DateTime, Utc};
+use crate::config::{
+    DeprecatedBodyField, DeprecatedEndpoint, DeprecatedHeader, DeprecatedParam, ErrorEnvelope,
+    GlobalSettings,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
 use std::collections::HashMap;
 
 /// Builder for deprecation-related HTTP headers.
@@ -24,11 +27,40 @@ impl DeprecationHeaders {
 
     /// Build headers for a deprecated endpoint.
     pub fn for_endpoint(endpoint: &DeprecatedEndpoint, settings: &GlobalSettings) -> Self {
+        Self::build_for(endpoint, settings, false)
+    }
+
+    /// Build headers for a scheduled endpoint whose `deprecated_at` hasn't
+    /// arrived yet: no Deprecation header, and the scheduled notice instead
+    /// of the deprecation notice.
+    pub fn for_scheduled(endpoint: &DeprecatedEndpoint, settings: &GlobalSettings) -> Self {
+        Self::build_for(endpoint, settings, true)
+    }
+
+    fn build_for(
+        endpoint: &DeprecatedEndpoint,
+        settings: &GlobalSettings,
+        scheduled: bool,
+    ) -> Self {
         let mut builder = Self::new();
 
         // Add Deprecation header (draft-ietf-httpapi-deprecation-header)
         // Format: Deprecation: true or Deprecation: @timestamp
-        if let Some(deprecated_at) = &endpoint.deprecated_at {
+        if scheduled {
+            // Not deprecated yet
+        } else if settings.dual_deprecation_headers {
+            // Transitional: the boolean form for legacy clients, and the
+            // timestamp in a header of its own
+            builder
+                .headers
+                .insert(settings.deprecation_header.clone(), "true".to_string());
+            if let Some(deprecated_at) = &endpoint.deprecated_at {
+                builder.headers.insert(
+                    settings.deprecation_timestamp_header.clone(),
+                    format!("@{}", deprecated_at.timestamp()),
+                );
+            }
+        } else if let Some(deprecated_at) = &endpoint.deprecated_at {
             builder.headers.insert(
                 settings.deprecation_header.clone(),
                 format!("@{}", deprecated_at.timestamp()),
@@ -45,50 +77,124 @@ impl DeprecationHeaders {
             builder
                 .headers
                 .insert(settings.sunset_header.clone(), format_http_date(sunset_at));
+            // The same instant in RFC 3339, for clients that can't parse HTTP-dates
+            if settings.emit_iso_sunset {
+                builder.headers.insert(
+                    settings.iso_sunset_header.clone(),
+                    sunset_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                );
+            }
         }
 
-        // Add Link header for documentation
-        if let Some(docs_url) = &endpoint.documentation_url {
-            let link_value = format!("<{}>; rel=\"deprecation\"", docs_url);
-            builder
-                .headers
-                .insert(settings.link_header.clone(), link_value);
+        // Collect Link header values (documentation, replacement, alternates,
+        // policy)
+        let mut links = Vec::new();
+
+        if let Some(docs) = &endpoint.documentation_url {
+            links.extend(docs.link_values());
         }
 
-        // Add replacement link if available
         if let Some(replacement) = &endpoint.replacement {
-            let existing_link = builder.headers.get(&settings.link_header).cloned();
-            let successor_link = format!("<{}>; rel=\"successor-version\"", replacement.path);
+            links.push(format!("<{}>; rel=\"successor-version\"", replacement.path));
+        }
+
+        // Alternate representations (e.g. JSON replacing a deprecated XML format)
+        for alternate in &endpoint.alternate_links {
+            links.push(format!(
+                "<{}>; rel=\"alternate\"; type=\"{}\"",
+                alternate.href, alternate.media_type
+            ));
+        }
 
-            let link_value = match existing_link {
-                Some(existing) => format!("{}, {}", existing, successor_link),
-                None => successor_link,
-            };
+        if let Some(policy) = &settings.policy_url {
+            links.push(format!("<{}>; rel=\"deprecation-policy\"", policy));
+        }
+
+        if !links.is_empty() {
             builder
                 .headers
-                .insert(settings.link_header.clone(), link_value);
+                .insert(settings.link_header.clone(), links.join(", "));
         }
 
         // Add deprecation notice message
-        let message = endpoint.deprecation_message();
+        let message = if scheduled {
+            endpoint.scheduled_message()
+        } else {
+            endpoint.deprecation_message()
+        };
         builder
             .headers
             .insert(settings.notice_header.clone(), message);
 
-        // Add any custom headers from the endpoint config
+        if let Some(contact) = &endpoint.contact {
+            builder
+                .headers
+                .insert(settings.contact_header.clone(), contact.clone());
+        }
+
+        // Reporting API endpoint, in the current and the legacy header
+        if let Some(report_to) = &endpoint.report_to {
+            builder.headers.insert(
+                "Reporting-Endpoints".to_string(),
+                report_to.reporting_endpoints(),
+            );
+            builder
+                .headers
+                .insert("Report-To".to_string(), report_to.report_to());
+        }
+
+        // Add shared headers from the global settings
+        for (key, value) in &settings.global_headers {
+            builder.insert_custom(settings, key, value);
+        }
+
+        // Add any custom headers from the endpoint config (overriding global ones)
         for (key, value) in &endpoint.headers {
-            builder.headers.insert(key.clone(), value.clone());
+            builder.insert_custom(settings, key, value);
         }
 
         builder
     }
 
+    /// Add a configured header. Link values are appended to the generated
+    /// Link header, so responses keep a single one.
+    fn insert_custom(&mut self, settings: &GlobalSettings, name: &str, value: &str) {
+        if !name.eq_ignore_ascii_case(&settings.link_header) {
+            self.headers.insert(name.to_string(), value.to_string());
+            return;
+        }
+        self.headers
+            .entry(settings.link_header.clone())
+            .and_modify(|links| {
+                links.push_str(", ");
+                links.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+
+    /// Drop headers until the set fits in `settings.max_added_header_bytes`;
+    /// see [`trim_headers`].
+    ///
+    /// Returns the names of the dropped headers.
+    pub fn trim(
+        &mut self,
+        endpoint: &DeprecatedEndpoint,
+        settings: &GlobalSettings,
+    ) -> Vec<String> {
+        trim_headers(&mut self.headers, endpoint, settings, 0)
+    }
+
     /// Add a custom header.
     pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
         self.headers.insert(name.into(), value.into());
         self
     }
 
+    /// The headers built so far.
+    pub fn as_map(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
     /// Get all headers.
     pub fn build(self) -> HashMap<String, String> {
         self.headers
@@ -106,9 +212,136 @@ impl Default for DeprecationHeaders {
     }
 }
 
+/// Drop headers until they fit in `settings.max_added_header_bytes` less
+/// `reserved` bytes for headers added alongside them (see [`header_bytes`]),
+/// lowest priority first: the notice, the computed headers, the custom
+/// headers (`global_headers`, then the endpoint's `headers`, by name),
+/// `Report-To`, `Reporting-Endpoints`, the contact, the RFC 3339 sunset and
+/// deprecation timestamp copies, and finally the Link header. The
+/// Deprecation and Sunset headers are never dropped.
+///
+/// Returns the names of the dropped headers.
+pub fn trim_headers(
+    headers: &mut HashMap<String, String>,
+    endpoint: &DeprecatedEndpoint,
+    settings: &GlobalSettings,
+    reserved: usize,
+) -> Vec<String> {
+    let limit = settings.max_added_header_bytes;
+    let mut dropped = Vec::new();
+    if limit == 0 || header_bytes(headers) + reserved <= limit {
+        return dropped;
+    }
+
+    let mut computed: Vec<&str> = settings
+        .computed_headers
+        .keys()
+        .chain(endpoint.computed_headers.keys())
+        .map(String::as_str)
+        .collect();
+    computed.sort_unstable();
+    computed.dedup();
+
+    let order = std::iter::once(settings.notice_header.as_str())
+        .chain(computed)
+        .chain(custom_header_names(&settings.global_headers, settings))
+        .chain(custom_header_names(&endpoint.headers, settings))
+        .chain([
+            "Report-To",
+            "Reporting-Endpoints",
+            settings.contact_header.as_str(),
+            settings.iso_sunset_header.as_str(),
+            settings.deprecation_timestamp_header.as_str(),
+            settings.link_header.as_str(),
+        ]);
+    for name in order {
+        if header_bytes(headers) + reserved <= limit {
+            break;
+        }
+        let protected = [&settings.deprecation_header, &settings.sunset_header]
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name));
+        if !protected && headers.remove(name).is_some() {
+            dropped.push(name.to_string());
+        }
+    }
+    dropped
+}
+
+/// Names of configured headers in trimming order. Custom Link values are
+/// part of the Link header, which is dropped last.
+fn custom_header_names<'a>(
+    headers: &'a HashMap<String, String>,
+    settings: &GlobalSettings,
+) -> Vec<&'a str> {
+    let mut names: Vec<&str> = headers
+        .keys()
+        .map(String::as_str)
+        .filter(|name| !name.eq_ignore_ascii_case(&settings.link_header))
+        .collect();
+    names.sort_unstable();
+    names
+}
+
+/// Bytes a header set adds to a response: `name: value` plus CRLF for each.
+pub fn header_bytes(headers: &HashMap<String, String>) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.len() + value.len() + 4)
+        .sum()
+}
+
+/// Placeholders of `computed_headers` templates.
+pub const COMPUTED_HEADER_PLACEHOLDERS: &[&str] = &["{{status}}", "{{sunset_iso}}", "{{days}}"];
+
+/// Render a `computed_headers` template for a request at `now`: `{{status}}`
+/// is the endpoint's status (`deprecated`, `removed` or `scheduled`),
+/// `{{sunset_iso}}` its sunset in RFC 3339 and `{{days}}` the days left until
+/// it (negative once past). Without a sunset the last two are empty.
+pub fn render_computed_header(
+    template: &str,
+    endpoint: &DeprecatedEndpoint,
+    status: &str,
+    now: DateTime<Utc>,
+) -> String {
+    let mut value = template.replace("{{status}}", status);
+    if value.contains("{{sunset_iso}}") {
+        let sunset = endpoint
+            .sunset_at
+            .map(|sunset| sunset.to_rfc3339_opts(SecondsFormat::Secs, true))
+            .unwrap_or_default();
+        value = value.replace("{{sunset_iso}}", &sunset);
+    }
+    if value.contains("{{days}}") {
+        let days = endpoint
+            .days_until_sunset(now)
+            .map(|days| days.to_string())
+            .unwrap_or_default();
+        value = value.replace("{{days}}", &days);
+    }
+    value
+}
+
+/// The first `{{...}}` placeholder of a template that isn't one of
+/// [`COMPUTED_HEADER_PLACEHOLDERS`].
+pub(crate) fn unknown_placeholder(template: &str) -> Option<&str> {
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .map_or(rest.len(), |end| start + end + 2);
+        let placeholder = &rest[start..end];
+        if !COMPUTED_HEADER_PLACEHOLDERS.contains(&placeholder) {
+            return Some(placeholder);
+        }
+        rest = &rest[end..];
+    }
+    None
+}
+
 /// Format a datetime as an HTTP date (RFC 7231).
 /// Example: Sun, 06 Nov 1994 08:49:37 GMT
-fn format_http_date(dt: &DateTime<Utc>) -> String {
+pub(crate) fn format_http_date(dt: &DateTime<Utc>) -> String {
     dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
 }
 
@@ -131,12 +364,39 @@ pub fn parse_http_date(s: &str) -> Option<DateTime<Utc>> {
     None
 }
 
-/// Generate a standard deprecation response body.
+/// Headers a 304 (Not Modified) response keeps (RFC 7232, section 4.1); it
+/// shouldn't carry other metadata about the representation.
+pub(crate) const NOT_MODIFIED_HEADERS: &[&str] = &[
+    "Cache-Control",
+    "Content-Location",
+    "Date",
+    "ETag",
+    "Expires",
+    "Vary",
+];
+
+/// Whether a 304 (Not Modified) response may carry the header.
+pub(crate) fn allowed_on_not_modified(name: &str) -> bool {
+    NOT_MODIFIED_HEADERS
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(name))
+}
+
+/// Generate a standard deprecation response body, with default settings.
 pub fn deprecation_response_body(endpoint: &DeprecatedEndpoint) -> String {
+    deprecation_response_body_with(endpoint, &GlobalSettings::default())
+}
+
+/// Generate a standard deprecation response body, in the configured
+/// `settings.error_envelope`.
+pub fn deprecation_response_body_with(
+    endpoint: &DeprecatedEndpoint,
+    settings: &GlobalSettings,
+) -> String {
     let mut response = serde_json::json!({
         "error": "deprecated_endpoint",
-        "message": endpoint.deprecation_message(),
-        "endpoint": endpoint.path,
+        "message": endpoint.uncounted_deprecation_message(),
+        "endpoint": endpoint.path.primary(),
     });
 
     if let Some(sunset) = &endpoint.sunset_at {
@@ -147,60 +407,218 @@ pub fn deprecation_response_body(endpoint: &DeprecatedEndpoint) -> String {
         response["replacement"] = serde_json::Value::String(replacement.path.clone());
     }
 
-    if let Some(docs) = &endpoint.documentation_url {
-        response["documentation"] = serde_json::Value::String(docs.clone());
-    }
+    add_documentation(&mut response, endpoint, settings);
 
-    serde_json::to_string_pretty(&response).unwrap_or_default()
+    serde_json::to_string_pretty(&settings.error_envelope.apply(response)).unwrap_or_default()
 }
 
-/// Generate a "410 Gone" response body.
+/// Generate a "410 Gone" response body, with default settings.
 pub fn gone_response_body(endpoint: &DeprecatedEndpoint) -> String {
+    gone_response_body_with(endpoint, &GlobalSettings::default())
+}
+
+/// Generate a "410 Gone" response body, in the configured
+/// `settings.error_envelope`.
+pub fn gone_response_body_with(endpoint: &DeprecatedEndpoint, settings: &GlobalSettings) -> String {
     let mut response = serde_json::json!({
         "error": "endpoint_removed",
-        "message": format!("The endpoint {} has been removed", endpoint.path),
+        "message": format!("The endpoint {} has been removed", endpoint.path.primary()),
     });
 
     if let Some(replacement) = &endpoint.replacement {
         response["replacement"] = serde_json::Value::String(replacement.path.clone());
         response["message"] = serde_json::Value::String(format!(
             "The endpoint {} has been removed. Please use {} instead",
-            endpoint.path, replacement.path
+            endpoint.path.primary(),
+            replacement.path
         ));
     }
 
+    add_documentation(&mut response, endpoint, settings);
+
+    serde_json::to_string_pretty(&settings.error_envelope.apply(response)).unwrap_or_default()
+}
+
+/// Add an endpoint's documentation to a JSON body: the primary URL as
+/// `documentation`, every link as `links`, its `owner` and `contact`, and
+/// the deprecation `policy`.
+fn add_documentation(
+    response: &mut serde_json::Value,
+    endpoint: &DeprecatedEndpoint,
+    settings: &GlobalSettings,
+) {
     if let Some(docs) = &endpoint.documentation_url {
-        response["documentation"] = serde_json::Value::String(docs.clone());
+        if let Some(url) = docs.primary_url() {
+            response["documentation"] = serde_json::Value::String(url.to_string());
+        }
+        response["links"] = docs.to_json();
+    }
+    if let Some(owner) = &endpoint.owner {
+        response["owner"] = serde_json::Value::String(owner.clone());
+    }
+    if let Some(contact) = &endpoint.contact {
+        response["contact"] = serde_json::Value::String(contact.clone());
+    }
+    if let Some(policy) = &settings.policy_url {
+        response["policy"] = serde_json::Value::String(policy.clone());
+    }
+}
+
+/// Body of a Reporting API deprecation report about an endpoint, in the
+/// shape browsers use for deprecated web platform features (`id`,
+/// `anticipatedRemoval`, `message`), so the `report_to` collector can file
+/// reports queued by client code or backends alongside browser ones.
+pub fn deprecation_report_body(endpoint: &DeprecatedEndpoint) -> serde_json::Value {
+    serde_json::json!({
+        "id": endpoint.id,
+        "anticipatedRemoval": endpoint
+            .sunset_at
+            .map(|sunset| sunset.to_rfc3339_opts(SecondsFormat::Secs, true)),
+        "message": endpoint.uncounted_deprecation_message(),
+    })
+}
+
+/// Generate the body rejecting a request that uses a deprecated query parameter.
+pub fn deprecated_param_body(param: &DeprecatedParam) -> String {
+    deprecated_param_body_with(param, &ErrorEnvelope::default())
+}
+
+/// Generate the body rejecting a request that uses a deprecated query
+/// parameter, in `envelope`.
+pub fn deprecated_param_body_with(param: &DeprecatedParam, envelope: &ErrorEnvelope) -> String {
+    let mut response = serde_json::json!({
+        "error": "deprecated_parameter",
+        "parameter": param.name,
+        "message": param.message(),
+    });
+
+    if let Some(replacement) = &param.replacement {
+        response["replacement"] = serde_json::Value::String(replacement.clone());
+    }
+
+    serde_json::to_string_pretty(&envelope.apply(response)).unwrap_or_default()
+}
+
+/// Generate the body rejecting a request that sends a deprecated header.
+pub fn deprecated_header_body(header: &DeprecatedHeader) -> String {
+    deprecated_header_body_with(header, &ErrorEnvelope::default())
+}
+
+/// Generate the body rejecting a request that sends a deprecated header, in
+/// `envelope`.
+pub fn deprecated_header_body_with(header: &DeprecatedHeader, envelope: &ErrorEnvelope) -> String {
+    let mut response = serde_json::json!({
+        "error": "deprecated_header",
+        "header": header.name,
+        "message": header.message(),
+    });
+
+    if let Some(replacement) = &header.replacement {
+        response["replacement"] = serde_json::Value::String(replacement.clone());
+    }
+
+    serde_json::to_string_pretty(&envelope.apply(response)).unwrap_or_default()
+}
+
+/// Generate the problem body (RFC 9457) rejecting a request whose body
+/// contains deprecated fields, or couldn't be inspected when `fields` is empty.
+pub fn deprecated_body_fields_body(fields: &[&DeprecatedBodyField], detail: &str) -> String {
+    deprecated_body_fields_body_with(fields, detail, &ErrorEnvelope::default())
+}
+
+/// Generate the body rejecting a request whose body contains deprecated
+/// fields: the RFC 9457 problem body with the default envelope, and an
+/// `error` and `message` body in any other `envelope` (see
+/// [`ErrorEnvelope::body_fields_content_type`]).
+pub fn deprecated_body_fields_body_with(
+    fields: &[&DeprecatedBodyField],
+    detail: &str,
+    envelope: &ErrorEnvelope,
+) -> String {
+    let mut response = if envelope.is_default() {
+        serde_json::json!({
+            "type": "about:blank",
+            "title": "Deprecated request body fields",
+            "status": 400,
+            "detail": detail,
+        })
+    } else {
+        serde_json::json!({
+            "error": "deprecated_body_fields",
+            "message": detail,
+        })
+    };
+
+    if !fields.is_empty() {
+        response["fields"] = fields
+            .iter()
+            .map(|field| {
+                serde_json::json!({
+                    "pointer": field.pointer,
+                    "replacement": field.replacement,
+                })
+            })
+            .collect();
     }
 
-    serde_json::to_string_pretty(&response).unwrap_or_default()
+    serde_json::to_string_pretty(&envelope.apply(response)).unwrap_or_default()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{DeprecationAction, DeprecationStatus, ReplacementInfo};
+    use crate::config::{
+        AlternateLink, DeprecationAction, DeprecationStatus, DocumentationLink, DocumentationLinks,
+        ReplacementInfo, ReportTo, Severity,
+    };
+    use std::collections::BTreeMap;
 
     fn test_endpoint() -> DeprecatedEndpoint {
         DeprecatedEndpoint {
             id: "test".to_string(),
-            path: "/api/v1/users".to_string(),
+            path: "/api/v1/users".into(),
             methods: vec![],
             status: DeprecationStatus::Deprecated,
             deprecated_at: Some("2024-01-01T00:00:00Z".parse().unwrap()),
             sunset_at: Some("2025-06-01T00:00:00Z".parse().unwrap()),
+            active_from: None,
+            active_until: None,
             replacement: Some(ReplacementInfo {
                 path: "/api/v2/users".to_string(),
                 preserve_query: true,
+                rewrite_prefix: None,
                 param_mappings: HashMap::new(),
                 method: None,
             }),
-            documentation_url: Some("https://docs.example.com/migration".to_string()),
+            documentation_url: Some("https://docs.example.com/migration".into()),
+            alternate_links: vec![],
             message: None,
             action: DeprecationAction::Warn,
             headers: HashMap::new(),
+            computed_headers: HashMap::new(),
             track_usage: true,
-            path_matcher: None,
+            metrics_id: None,
+            labels: BTreeMap::new(),
+            unless: vec![],
+            exclude: vec![],
+            deprecated_params: vec![],
+            deprecated_request_headers: vec![],
+            tombstone: None,
+            owner: None,
+            contact: None,
+            severity: Severity::Normal,
+            notes: None,
+            spike_threshold: None,
+            examples: vec![],
+            warn_once: None,
+            report_to: None,
+            bodies: HashMap::new(),
+            body_field_deprecated: vec![],
+            deprecated_body_fields: vec![],
+            confirmed: None,
+            path_matchers: vec![],
+            exclude_matchers: vec![],
+            head_as_get: false,
         }
     }
 
@@ -208,6 +626,13 @@ mod tests {
         GlobalSettings::default()
     }
 
+    fn enveloped(error_envelope: ErrorEnvelope) -> GlobalSettings {
+        GlobalSettings {
+            error_envelope,
+            ..test_settings()
+        }
+    }
+
     #[test]
     fn test_deprecation_header() {
         let endpoint = test_endpoint();
@@ -219,6 +644,30 @@ mod tests {
         assert!(headers["Deprecation"].starts_with('@'));
     }
 
+    #[test]
+    fn test_dual_deprecation_headers() {
+        let endpoint = test_endpoint();
+        let mut settings = test_settings();
+        settings.dual_deprecation_headers = true;
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &settings).build();
+
+        assert_eq!(headers["Deprecation"], "true");
+        let single = DeprecationHeaders::for_endpoint(&endpoint, &test_settings()).build();
+        assert_eq!(headers["X-Deprecation-Timestamp"], single["Deprecation"]);
+        assert_eq!(
+            headers["X-Deprecation-Timestamp"],
+            format!("@{}", endpoint.deprecated_at.unwrap().timestamp())
+        );
+        assert!(!single.contains_key("X-Deprecation-Timestamp"));
+
+        // Nothing to timestamp without deprecated_at
+        let mut undated = test_endpoint();
+        undated.deprecated_at = None;
+        let headers = DeprecationHeaders::for_endpoint(&undated, &settings).build();
+        assert_eq!(headers["Deprecation"], "true");
+        assert!(!headers.contains_key("X-Deprecation-Timestamp"));
+    }
+
     #[test]
     fn test_sunset_header() {
         let endpoint = test_endpoint();
@@ -231,6 +680,31 @@ mod tests {
         assert!(headers["Sunset"].ends_with("GMT"));
     }
 
+    #[test]
+    fn test_iso_sunset_header() {
+        let endpoint = test_endpoint();
+        let mut settings = test_settings();
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &settings).build();
+        assert!(!headers.contains_key("X-Sunset-ISO8601"));
+
+        settings.emit_iso_sunset = true;
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &settings).build();
+        let iso: DateTime<Utc> = headers["X-Sunset-ISO8601"].parse().unwrap();
+        assert_eq!(parse_http_date(&headers["Sunset"]), Some(iso));
+        assert_eq!(Some(iso), endpoint.sunset_at);
+        assert!(headers["X-Sunset-ISO8601"].ends_with('Z'));
+        // The standard header is unchanged
+        assert_eq!(
+            headers["Sunset"],
+            format_http_date(&endpoint.sunset_at.unwrap())
+        );
+
+        let mut no_sunset = test_endpoint();
+        no_sunset.sunset_at = None;
+        let headers = DeprecationHeaders::for_endpoint(&no_sunset, &settings).build();
+        assert!(!headers.contains_key("X-Sunset-ISO8601"));
+    }
+
     #[test]
     fn test_link_header() {
         let endpoint = test_endpoint();
@@ -245,6 +719,351 @@ mod tests {
         assert!(link.contains("/api/v2/users"));
     }
 
+    #[test]
+    fn test_alternate_link_header() {
+        let mut endpoint = test_endpoint();
+        endpoint.alternate_links = vec![AlternateLink {
+            href: "/api/v1/users.json".to_string(),
+            media_type: "application/json".to_string(),
+        }];
+        let settings = test_settings();
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &settings).build();
+
+        let link = &headers["Link"];
+        assert!(link.contains("</api/v1/users.json>; rel=\"alternate\"; type=\"application/json\""));
+        // Existing relations are kept alongside the alternate
+        assert!(link.contains("rel=\"deprecation\""));
+        assert!(link.contains("rel=\"successor-version\""));
+    }
+
+    #[test]
+    fn test_alternate_link_without_other_links() {
+        let mut endpoint = test_endpoint();
+        endpoint.documentation_url = None;
+        endpoint.replacement = None;
+        endpoint.alternate_links = vec![AlternateLink {
+            href: "/api/v1/users".to_string(),
+            media_type: "application/json".to_string(),
+        }];
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &test_settings()).build();
+
+        assert_eq!(
+            headers["Link"],
+            "</api/v1/users>; rel=\"alternate\"; type=\"application/json\""
+        );
+    }
+
+    #[test]
+    fn test_typed_documentation_links() {
+        let mut endpoint = test_endpoint();
+        endpoint.replacement = None;
+        endpoint.documentation_url = Some(DocumentationLinks::Links(vec![
+            DocumentationLink {
+                url: "https://docs.example.com/migration".to_string(),
+                rel: "deprecation".to_string(),
+                title: Some("Migrating to \"v2\"".to_string()),
+            },
+            DocumentationLink {
+                url: "https://docs.example.com/changelog".to_string(),
+                rel: "describedby".to_string(),
+                title: None,
+            },
+        ]));
+        let mut settings = test_settings();
+        settings.global_headers.insert(
+            "link".to_string(),
+            "<mailto:api@example.com>; rel=\"help\"".to_string(),
+        );
+
+        // One Link header, with the configured value appended
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &settings).build();
+        assert!(!headers.contains_key("link"));
+        assert_eq!(
+            headers["Link"],
+            "<https://docs.example.com/migration>; rel=\"deprecation\"; title=\"Migrating to \\\"v2\\\"\", \
+             <https://docs.example.com/changelog>; rel=\"describedby\", \
+             <mailto:api@example.com>; rel=\"help\""
+        );
+
+        let body: serde_json::Value =
+            serde_json::from_str(&gone_response_body_with(&endpoint, &test_settings())).unwrap();
+        assert_eq!(body["documentation"], "https://docs.example.com/migration");
+        assert_eq!(
+            body["links"],
+            serde_json::json!([
+                {
+                    "url": "https://docs.example.com/migration",
+                    "rel": "deprecation",
+                    "title": "Migrating to \"v2\"",
+                },
+                {
+                    "url": "https://docs.example.com/changelog",
+                    "rel": "describedby",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_policy_url() {
+        let mut settings = test_settings();
+        settings.policy_url = Some("https://example.com/api/deprecation-policy".to_string());
+        settings.global_headers.insert(
+            "Link".to_string(),
+            "<https://status.example.com>; rel=\"status\"".to_string(),
+        );
+
+        // One Link header with every relation intact
+        let endpoint = test_endpoint();
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &settings).build();
+        let links: Vec<&str> = headers["Link"].split(", ").collect();
+        assert_eq!(
+            links,
+            [
+                "<https://docs.example.com/migration>; rel=\"deprecation\"",
+                "</api/v2/users>; rel=\"successor-version\"",
+                "<https://example.com/api/deprecation-policy>; rel=\"deprecation-policy\"",
+                "<https://status.example.com>; rel=\"status\"",
+            ]
+        );
+
+        // Also without documentation of its own
+        let mut plain = test_endpoint();
+        plain.documentation_url = None;
+        plain.replacement = None;
+        let headers = DeprecationHeaders::for_scheduled(&plain, &settings).build();
+        assert!(headers["Link"].starts_with(
+            "<https://example.com/api/deprecation-policy>; rel=\"deprecation-policy\""
+        ));
+
+        for body in [
+            deprecation_response_body_with(&endpoint, &settings),
+            gone_response_body_with(&endpoint, &settings),
+            gone_response_body_with(&plain, &settings),
+        ] {
+            let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(body["policy"], "https://example.com/api/deprecation-policy");
+        }
+        let body: serde_json::Value =
+            serde_json::from_str(&gone_response_body_with(&endpoint, &test_settings())).unwrap();
+        assert!(body.get("policy").is_none());
+    }
+
+    #[test]
+    fn test_reporting_headers() {
+        let plain = test_endpoint();
+        let headers = DeprecationHeaders::for_endpoint(&plain, &test_settings()).build();
+        assert!(!headers.contains_key("Reporting-Endpoints"));
+        assert!(!headers.contains_key("Report-To"));
+
+        let mut endpoint = test_endpoint();
+        endpoint.report_to = Some(ReportTo {
+            url: "https://reports.example.com/deprecations".to_string(),
+            group: "deprecation".to_string(),
+            max_age_secs: 3600,
+        });
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &test_settings()).build();
+        assert_eq!(
+            headers["Reporting-Endpoints"],
+            "deprecation=\"https://reports.example.com/deprecations\""
+        );
+        let report_to: serde_json::Value = serde_json::from_str(&headers["Report-To"]).unwrap();
+        assert_eq!(
+            report_to,
+            serde_json::json!({
+                "group": "deprecation",
+                "max_age": 3600,
+                "endpoints": [{ "url": "https://reports.example.com/deprecations" }],
+            })
+        );
+
+        let body = deprecation_report_body(&endpoint);
+        assert_eq!(body["id"], "test");
+        assert_eq!(body["anticipatedRemoval"], "2025-06-01T00:00:00Z");
+        assert_eq!(body["message"], endpoint.deprecation_message());
+    }
+
+    #[test]
+    fn test_error_envelope() {
+        let endpoint = test_endpoint();
+
+        let legacy: serde_json::Value =
+            serde_json::from_str(&gone_response_body_with(&endpoint, &test_settings())).unwrap();
+        assert_eq!(legacy["error"], "endpoint_removed");
+        assert!(legacy["message"]
+            .as_str()
+            .unwrap()
+            .contains("Please use /api/v2/users instead"));
+        assert_eq!(legacy["replacement"], "/api/v2/users");
+
+        // {"code", "title", "detail", "meta": {...}}
+        let envelope = ErrorEnvelope {
+            error_key: "code".to_string(),
+            message_key: "detail".to_string(),
+            details_key: Some("meta".to_string()),
+            wrapper_key: None,
+            extra_fields: BTreeMap::from([(
+                "title".to_string(),
+                serde_json::json!("Endpoint removed"),
+            )]),
+        };
+        let shaped: serde_json::Value =
+            serde_json::from_str(&gone_response_body_with(&endpoint, &enveloped(envelope)))
+                .unwrap();
+        assert_eq!(
+            shaped,
+            serde_json::json!({
+                "code": "endpoint_removed",
+                "title": "Endpoint removed",
+                "detail": legacy["message"],
+                "meta": {
+                    "replacement": "/api/v2/users",
+                    "documentation": legacy["documentation"],
+                    "links": legacy["links"],
+                },
+            })
+        );
+
+        // Wrapped, with the details kept alongside the code
+        let envelope = ErrorEnvelope {
+            wrapper_key: Some("errors".to_string()),
+            ..ErrorEnvelope::default()
+        };
+        let wrapped: serde_json::Value = serde_json::from_str(&deprecation_response_body_with(
+            &endpoint,
+            &enveloped(envelope),
+        ))
+        .unwrap();
+        assert_eq!(wrapped["errors"]["error"], "deprecated_endpoint");
+        assert_eq!(wrapped["errors"]["endpoint"], "/api/v1/users");
+        assert_eq!(wrapped.as_object().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_hits_placeholder_in_bodies() {
+        let mut endpoint = test_endpoint();
+        endpoint.message = Some("You have called this endpoint {{hits}} times today.".to_string());
+
+        // Bodies have no count to fill in, so they carry the generated notice
+        let body: serde_json::Value =
+            serde_json::from_str(&deprecation_response_body_with(&endpoint, &test_settings()))
+                .unwrap();
+        assert_eq!(body["message"], endpoint.default_deprecation_message());
+        assert_eq!(
+            deprecation_report_body(&endpoint)["message"],
+            endpoint.default_deprecation_message()
+        );
+
+        // Headers keep the placeholder for the agent to fill in per request
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &test_settings()).build();
+        assert!(headers["X-Deprecation-Notice"].contains("{{hits}}"));
+    }
+
+    #[test]
+    fn test_error_envelope_for_rejections() {
+        let param: DeprecatedParam =
+            serde_yaml::from_str("name: legacy\nreplacement: modern").unwrap();
+        let header: DeprecatedHeader =
+            serde_yaml::from_str("name: X-Auth-Token\nreplacement: Authorization").unwrap();
+        let field: DeprecatedBodyField = serde_yaml::from_str("pointer: /user_name").unwrap();
+        let envelope = ErrorEnvelope {
+            error_key: "code".to_string(),
+            message_key: "detail".to_string(),
+            details_key: Some("meta".to_string()),
+            wrapper_key: None,
+            extra_fields: BTreeMap::new(),
+        };
+        let parse = |body: String| serde_json::from_str::<serde_json::Value>(&body).unwrap();
+
+        let shaped = parse(deprecated_param_body_with(&param, &envelope));
+        assert_eq!(shaped["code"], "deprecated_parameter");
+        assert_eq!(shaped["meta"]["parameter"], "legacy");
+        assert_eq!(shaped["meta"]["replacement"], "modern");
+
+        let shaped = parse(deprecated_header_body_with(&header, &envelope));
+        assert_eq!(shaped["code"], "deprecated_header");
+        assert_eq!(shaped["meta"]["header"], "X-Auth-Token");
+
+        let shaped = parse(deprecated_body_fields_body_with(
+            &[&field],
+            "Rename it",
+            &envelope,
+        ));
+        assert_eq!(shaped["code"], "deprecated_body_fields");
+        assert_eq!(shaped["detail"], "Rename it");
+        assert_eq!(shaped["meta"]["fields"][0]["pointer"], "/user_name");
+        assert_eq!(envelope.body_fields_content_type(), "application/json");
+
+        // The default envelope keeps the existing bodies
+        assert_eq!(
+            deprecated_param_body_with(&param, &ErrorEnvelope::default()),
+            deprecated_param_body(&param)
+        );
+        let problem = parse(deprecated_body_fields_body(&[&field], "Rename it"));
+        assert_eq!(problem["status"], 400);
+        assert_eq!(problem["detail"], "Rename it");
+        assert_eq!(
+            ErrorEnvelope::default().body_fields_content_type(),
+            "application/problem+json"
+        );
+    }
+
+    #[test]
+    fn test_owner_and_contact() {
+        let mut endpoint = test_endpoint();
+        endpoint.owner = Some("identity".to_string());
+        endpoint.contact = Some("identity@example.com".to_string());
+        endpoint.message = Some("Questions? Ask {contact}.".to_string());
+
+        let headers = DeprecationHeaders::for_endpoint(&endpoint, &test_settings()).build();
+        assert_eq!(headers["X-Deprecation-Contact"], "identity@example.com");
+        assert_eq!(
+            headers["X-Deprecation-Notice"],
+            "Questions? Ask identity@example.com."
+        );
+
+        let body: serde_json::Value =
+            serde_json::from_str(&deprecation_response_body_with(&endpoint, &test_settings()))
+                .unwrap();
+        assert_eq!(body["owner"], "identity");
+        assert_eq!(body["contact"], "identity@example.com");
+
+        // Absent fields are left out
+        let plain = test_endpoint();
+        let headers = DeprecationHeaders::for_endpoint(&plain, &test_settings()).build();
+        assert!(!headers.contains_key("X-Deprecation-Contact"));
+        let body: serde_json::Value =
+            serde_json::from_str(&gone_response_body_with(&plain, &test_settings())).unwrap();
+        assert!(body.get("owner").is_none());
+        assert!(body.get("contact").is_none());
+    }
+
+    #[test]
+    fn test_global_headers() {
+        let mut settings = test_settings();
+        settings.global_headers.insert(
+            "X-Api-Policy".to_string(),
+            "https://example.com/api-policy".to_string(),
+        );
+        settings
+            .global_headers
+            .insert("X-Api-Owner".to_string(), "platform".to_string());
+
+        let plain = test_endpoint();
+        let headers = DeprecationHeaders::for_endpoint(&plain, &settings).build();
+        assert_eq!(headers["X-Api-Policy"], "https://example.com/api-policy");
+        assert_eq!(headers["X-Api-Owner"], "platform");
+
+        // Endpoint-level headers win over global ones
+        let mut overriding = test_endpoint();
+        overriding
+            .headers
+            .insert("X-Api-Owner".to_string(), "payments".to_string());
+        let headers = DeprecationHeaders::for_endpoint(&overriding, &settings).build();
+        assert_eq!(headers["X-Api-Policy"], "https://example.com/api-policy");
+        assert_eq!(headers["X-Api-Owner"], "payments");
+    }
+
     #[test]
     fn test_notice_header() {
         let endpoint = test_endpoint();
@@ -256,6 +1075,74 @@ mod tests {
         assert!(notice.contains("deprecated"));
     }
 
+    #[test]
+    fn test_scheduled_headers() {
+        let mut endpoint = test_endpoint();
+        endpoint.status = DeprecationStatus::Scheduled;
+        let headers = DeprecationHeaders::for_scheduled(&endpoint, &test_settings()).build();
+
+        assert!(!headers.contains_key("Deprecation"));
+        assert!(headers.contains_key("Sunset"));
+        assert!(headers["X-Deprecation-Notice"].starts_with(
+            "This endpoint (/api/v1/users) is scheduled for deprecation on 2024-01-01"
+        ));
+    }
+
+    #[test]
+    fn test_trim_to_header_budget() {
+        let mut endpoint = test_endpoint();
+        endpoint.message = Some("x".repeat(300));
+        endpoint.contact = Some("api-team@example.com".to_string());
+        endpoint.headers = HashMap::from([
+            ("X-Team".to_string(), "y".repeat(200)),
+            ("X-Runbook".to_string(), "z".repeat(200)),
+        ]);
+        let mut settings = test_settings();
+        settings.emit_iso_sunset = true;
+
+        let untrimmed = DeprecationHeaders::for_endpoint(&endpoint, &settings);
+        let full = header_bytes(untrimmed.as_map());
+        let notice_bytes = "X-Deprecation-Notice".len() + 300 + 4;
+
+        // The notice goes first
+        settings.max_added_header_bytes = full - 1;
+        let mut headers = DeprecationHeaders::for_endpoint(&endpoint, &settings);
+        assert_eq!(headers.trim(&endpoint, &settings), ["X-Deprecation-Notice"]);
+        assert_eq!(header_bytes(headers.as_map()), full - notice_bytes);
+
+        // Then custom headers by name, then the others
+        settings.max_added_header_bytes = full - notice_bytes - 1;
+        let mut headers = DeprecationHeaders::for_endpoint(&endpoint, &settings);
+        assert_eq!(
+            headers.trim(&endpoint, &settings),
+            ["X-Deprecation-Notice", "X-Runbook"]
+        );
+
+        // Deprecation and Sunset are never dropped
+        settings.max_added_header_bytes = 1;
+        let mut headers = DeprecationHeaders::for_endpoint(&endpoint, &settings);
+        assert_eq!(
+            headers.trim(&endpoint, &settings),
+            [
+                "X-Deprecation-Notice",
+                "X-Runbook",
+                "X-Team",
+                "X-Deprecation-Contact",
+                "X-Sunset-ISO8601",
+                "Link",
+            ]
+        );
+        let mut names: Vec<_> = headers.build().into_keys().collect();
+        names.sort();
+        assert_eq!(names, ["Deprecation", "Sunset"]);
+
+        // 0 disables the budget
+        settings.max_added_header_bytes = 0;
+        let mut headers = DeprecationHeaders::for_endpoint(&endpoint, &settings);
+        assert!(headers.trim(&endpoint, &settings).is_empty());
+        assert_eq!(header_bytes(headers.as_map()), full);
+    }
+
     #[test]
     fn test_format_http_date() {
         let dt: DateTime<Utc> = "2025-06-01T12:00:00Z".parse().unwrap();
@@ -280,7 +1167,7 @@ mod tests {
     #[test]
     fn test_deprecation_response_body() {
         let endpoint = test_endpoint();
-        let body = deprecation_response_body(&endpoint);
+        let body = deprecation_response_body_with(&endpoint, &test_settings());
 
         assert!(body.contains("deprecated_endpoint"));
         assert!(body.contains("/api/v1/users"));
@@ -290,7 +1177,7 @@ mod tests {
     #[test]
     fn test_gone_response_body() {
         let endpoint = test_endpoint();
-        let body = gone_response_body(&endpoint);
+        let body = gone_response_body_with(&endpoint, &test_settings());
 
         assert!(body.contains("endpoint_removed"));
         assert!(body.contains("has been removed"));
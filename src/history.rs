@@ -0,0 +1,187 @@
+//! Sunset date changes across configuration reloads.
+//!
+//! Sunset dates that quietly slip forward are how deprecations stall. On each
+//! reload the agent compares every rule's `sunset_at` with the one it
+//! replaces: extensions and withdrawn sunsets are warned about and counted,
+//! accelerations logged at info level, and the last [`CHANGES_PER_RULE`] changes of each rule are
+//! reported at `GET /health`.
+
+use crate::config::{ApiDeprecationConfig, DeprecatedEndpoint};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+
+/// Changes kept per rule, oldest dropped first.
+pub const CHANGES_PER_RULE: usize = 10;
+
+/// Direction a sunset date moved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SunsetMove {
+    /// Moved later: clients get more time
+    Extension,
+    /// Moved earlier: clients get less time
+    Acceleration,
+    /// Taken back: the rule no longer has a sunset
+    Withdrawal,
+}
+
+impl SunsetMove {
+    /// Classify a move from `from` to `to` (`None` when unchanged).
+    pub fn classify(from: DateTime<Utc>, to: DateTime<Utc>) -> Option<Self> {
+        match to.cmp(&from) {
+            std::cmp::Ordering::Greater => Some(Self::Extension),
+            std::cmp::Ordering::Less => Some(Self::Acceleration),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+}
+
+/// A rule's sunset date moving between two configurations.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SunsetChange {
+    pub kind: SunsetMove,
+    pub from: DateTime<Utc>,
+    /// `None` when the sunset was withdrawn
+    pub to: Option<DateTime<Utc>>,
+    /// When the configuration with the new date was applied
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Rules present in both configurations whose sunset date moved or was
+/// withdrawn, with the rule from `new` and the change as of `now`. Rules
+/// without a sunset in `old` are left out.
+pub fn sunset_moves<'a>(
+    old: &ApiDeprecationConfig,
+    new: &'a ApiDeprecationConfig,
+    now: DateTime<Utc>,
+) -> Vec<(&'a DeprecatedEndpoint, SunsetChange)> {
+    let previous: HashMap<&str, DateTime<Utc>> = old
+        .endpoints
+        .iter()
+        .filter_map(|endpoint| Some((endpoint.id.as_str(), endpoint.sunset_at?)))
+        .collect();
+
+    new.endpoints
+        .iter()
+        .filter_map(|endpoint| {
+            let from = *previous.get(endpoint.id.as_str())?;
+            let to = endpoint.sunset_at;
+            let kind = match to {
+                Some(to) => SunsetMove::classify(from, to)?,
+                None => SunsetMove::Withdrawal,
+            };
+            let change = SunsetChange {
+                kind,
+                from,
+                to,
+                changed_at: now,
+            };
+            Some((endpoint, change))
+        })
+        .collect()
+}
+
+/// The last [`CHANGES_PER_RULE`] sunset changes of each rule, by rule id.
+#[derive(Debug, Default)]
+pub struct SunsetHistory {
+    changes: BTreeMap<String, VecDeque<SunsetChange>>,
+}
+
+impl SunsetHistory {
+    pub fn record(&mut self, endpoint_id: &str, change: SunsetChange) {
+        let changes = self.changes.entry(endpoint_id.to_string()).or_default();
+        if changes.len() == CHANGES_PER_RULE {
+            changes.pop_front();
+        }
+        changes.push_back(change);
+    }
+
+    /// Recorded changes by rule id, oldest first.
+    pub fn snapshot(&self) -> BTreeMap<String, Vec<SunsetChange>> {
+        self.changes
+            .iter()
+            .map(|(id, changes)| (id.clone(), changes.iter().cloned().collect()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(users_sunset: &str) -> ApiDeprecationConfig {
+        let mut config = ApiDeprecationConfig::from_yaml(&format!(
+            r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: "{users_sunset}"
+  - id: orders-v1
+    path: /api/v1/orders
+    sunset_at: "2030-01-01T00:00:00Z"
+  - id: posts-v1
+    path: /api/v1/posts
+"#
+        ))
+        .unwrap();
+        config.compile();
+        config
+    }
+
+    #[test]
+    fn test_sunset_moves() {
+        let now: DateTime<Utc> = "2030-01-01T00:00:00Z".parse().unwrap();
+        let old = config("2030-06-01T00:00:00Z");
+
+        let later = config("2030-09-01T00:00:00Z");
+        let moves = sunset_moves(&old, &later, now);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].0.id, "users-v1");
+        assert_eq!(
+            moves[0].1,
+            SunsetChange {
+                kind: SunsetMove::Extension,
+                from: "2030-06-01T00:00:00Z".parse().unwrap(),
+                to: Some("2030-09-01T00:00:00Z".parse().unwrap()),
+                changed_at: now,
+            }
+        );
+
+        let earlier = config("2030-03-01T00:00:00Z");
+        let moves = sunset_moves(&old, &earlier, now);
+        assert_eq!(moves[0].1.kind, SunsetMove::Acceleration);
+
+        assert!(sunset_moves(&old, &old, now).is_empty());
+
+        // Removed sunsets are withdrawals; added ones aren't moves
+        let mut without = config("2030-06-01T00:00:00Z");
+        without.endpoints[0].sunset_at = None;
+        let moves = sunset_moves(&old, &without, now);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].1.kind, SunsetMove::Withdrawal);
+        assert_eq!(moves[0].1.to, None);
+        assert!(sunset_moves(&without, &old, now).is_empty());
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let start: DateTime<Utc> = "2030-01-01T00:00:00Z".parse().unwrap();
+        let mut history = SunsetHistory::default();
+        for day in 0..(CHANGES_PER_RULE as i64 + 5) {
+            history.record(
+                "users-v1",
+                SunsetChange {
+                    kind: SunsetMove::Extension,
+                    from: start + chrono::Duration::days(day),
+                    to: Some(start + chrono::Duration::days(day + 1)),
+                    changed_at: start,
+                },
+            );
+        }
+
+        let changes = &history.snapshot()["users-v1"];
+        assert_eq!(changes.len(), CHANGES_PER_RULE);
+        assert_eq!(changes[0].from, start + chrono::Duration::days(5));
+    }
+}
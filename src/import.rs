@@ -0,0 +1,477 @@
+//! Import of deprecation annotations from Envoy-style route configurations.
+//!
+//! Teams moving from another gateway often keep deprecation data next to
+//! their Envoy routes. [`ApiDeprecationConfig::from_envoy_style`] translates
+//! the following subset into endpoints; everything else is ignored:
+//!
+//! - Routes come from a top-level `routes` list or `virtual_hosts[].routes`.
+//!   Only routes with a `metadata.filter_metadata.deprecation` block are
+//!   imported.
+//! - `name` becomes the endpoint id (`route-<n>` when missing).
+//! - `match.path` becomes the path with its sub-paths excluded, as Envoy
+//!   matches it exactly; `match.prefix` becomes the glob `<prefix>*`, keeping
+//!   Envoy's plain string-prefix semantics. Glob metacharacters in either are
+//!   escaped, since Envoy takes them literally.
+//! - `match.headers` entries named `:method` with `exact_match` (or
+//!   `string_match.exact`) become `methods`.
+//! - The deprecation block's `sunset`, `deprecated_at`, `documentation_url`
+//!   and `message` map to the fields of the same meaning.
+//! - `redirect.path_redirect` or `redirect.prefix_rewrite` becomes the
+//!   replacement and a redirect action with the status from `response_code`
+//!   (default `MOVED_PERMANENTLY`, as in Envoy); `strip_query` disables query
+//!   preservation. A prefix rewrite keeps the rest of the request path.
+
+use crate::config::{
+    deserialize_date, is_glob_pattern, normalize_method, ApiDeprecationConfig, ConfigError,
+    DeprecatedEndpoint, DeprecationAction, DocumentationLinks, ReplacementInfo, Severity,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Deserialize)]
+struct RouteConfig {
+    #[serde(default)]
+    virtual_hosts: Vec<VirtualHost>,
+    #[serde(default)]
+    routes: Vec<Route>,
+}
+
+#[derive(Debug, Deserialize)]
+struct VirtualHost {
+    #[serde(default)]
+    routes: Vec<Route>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Route {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(rename = "match")]
+    route_match: RouteMatch,
+    #[serde(default)]
+    redirect: Option<Redirect>,
+    #[serde(default)]
+    metadata: Option<Metadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RouteMatch {
+    #[serde(default)]
+    prefix: Option<String>,
+    #[serde(default)]
+    path: Option<String>,
+    #[serde(default)]
+    headers: Vec<HeaderMatcher>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeaderMatcher {
+    name: String,
+    #[serde(default)]
+    exact_match: Option<String>,
+    #[serde(default)]
+    string_match: Option<StringMatch>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StringMatch {
+    #[serde(default)]
+    exact: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Redirect {
+    #[serde(default)]
+    path_redirect: Option<String>,
+    #[serde(default)]
+    prefix_rewrite: Option<String>,
+    #[serde(default)]
+    response_code: Option<String>,
+    #[serde(default)]
+    strip_query: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    #[serde(default)]
+    filter_metadata: HashMap<String, serde_yaml::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Deprecation {
+    #[serde(default, deserialize_with = "deserialize_date")]
+    sunset: Option<DateTime<Utc>>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    deprecated_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    documentation_url: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+}
+
+impl ApiDeprecationConfig {
+    /// Translate an Envoy-style route configuration into endpoints (without
+    /// validating them). See the [module docs](crate::import) for the
+    /// supported subset.
+    pub fn from_envoy_style(yaml: &str) -> Result<Self, ConfigError> {
+        let routes: RouteConfig = serde_yaml::from_str(yaml)?;
+
+        let endpoints = routes
+            .routes
+            .into_iter()
+            .chain(
+                routes
+                    .virtual_hosts
+                    .into_iter()
+                    .flat_map(|host| host.routes),
+            )
+            .enumerate()
+            .filter_map(|(i, route)| route_endpoint(i, route).transpose())
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            endpoints,
+            ..Self::default()
+        })
+    }
+}
+
+/// Endpoint for a route, if it carries a deprecation annotation.
+fn route_endpoint(i: usize, route: Route) -> Result<Option<DeprecatedEndpoint>, ConfigError> {
+    let Some(deprecation) = route
+        .metadata
+        .and_then(|metadata| metadata.filter_metadata.get("deprecation").cloned())
+    else {
+        return Ok(None);
+    };
+    let deprecation: Deprecation = serde_yaml::from_value(deprecation)?;
+    let id = route.name.unwrap_or_else(|| format!("route-{}", i));
+
+    let RouteMatch {
+        prefix,
+        path,
+        headers,
+    } = route.route_match;
+    let (pattern, exclude) = match (path, &prefix) {
+        (Some(path), _) => {
+            let exact = if is_glob_pattern(&path) {
+                escape_glob(&path)
+            } else {
+                path.clone()
+            };
+            // Anything longer than the path itself: its sub-paths
+            let longer = format!("{}?*", escape_glob(&path));
+            (exact, vec![longer])
+        }
+        (None, Some(prefix)) => (format!("{}*", escape_glob(prefix)), vec![]),
+        (None, None) => return Err(import_error(&id, "match needs a path or a prefix")),
+    };
+
+    let methods = headers
+        .iter()
+        .filter(|header| header.name == ":method")
+        .filter_map(|header| {
+            header.exact_match.as_deref().or_else(|| {
+                header
+                    .string_match
+                    .as_ref()
+                    .and_then(|string_match| string_match.exact.as_deref())
+            })
+        })
+        .map(normalize_method::<serde_yaml::Error>)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (replacement, action) = match route.redirect {
+        Some(redirect) => {
+            let (target, rewrite_prefix) = match (redirect.path_redirect, redirect.prefix_rewrite) {
+                (Some(path), _) => (path, None),
+                (None, Some(rewrite)) => (rewrite, prefix.clone()),
+                (None, None) => {
+                    return Err(import_error(
+                        &id,
+                        "redirect needs path_redirect or prefix_rewrite",
+                    ))
+                }
+            };
+            let status_code = match redirect.response_code.as_deref() {
+                None | Some("MOVED_PERMANENTLY") => 301,
+                Some("FOUND") => 302,
+                Some("SEE_OTHER") => 303,
+                Some("TEMPORARY_REDIRECT") => 307,
+                Some("PERMANENT_REDIRECT") => 308,
+                Some(other) => {
+                    return Err(import_error(
+                        &id,
+                        &format!("unknown redirect response_code '{}'", other),
+                    ))
+                }
+            };
+            (
+                Some(ReplacementInfo {
+                    path: target,
+                    preserve_query: !redirect.strip_query,
+                    rewrite_prefix,
+                    param_mappings: HashMap::new(),
+                    method: None,
+                }),
+                DeprecationAction::Redirect { status_code },
+            )
+        }
+        None => (None, DeprecationAction::Warn),
+    };
+
+    Ok(Some(DeprecatedEndpoint {
+        id,
+        path: pattern.into(),
+        methods,
+        status: Default::default(),
+        deprecated_at: deprecation.deprecated_at,
+        sunset_at: deprecation.sunset,
+        active_from: None,
+        active_until: None,
+        replacement,
+        documentation_url: deprecation.documentation_url.map(DocumentationLinks::Url),
+        alternate_links: vec![],
+        message: deprecation.message,
+        action,
+        headers: HashMap::new(),
+        computed_headers: HashMap::new(),
+        track_usage: true,
+        metrics_id: None,
+        labels: BTreeMap::new(),
+        unless: vec![],
+        exclude,
+        deprecated_params: vec![],
+        deprecated_request_headers: vec![],
+        tombstone: None,
+        owner: None,
+        contact: None,
+        severity: Severity::Normal,
+        notes: None,
+        spike_threshold: None,
+        examples: vec![],
+        warn_once: None,
+        report_to: None,
+        bodies: HashMap::new(),
+        body_field_deprecated: vec![],
+        deprecated_body_fields: vec![],
+        confirmed: None,
+        path_matchers: vec![],
+        exclude_matchers: vec![],
+        head_as_get: false,
+    }))
+}
+
+/// Escape glob metacharacters so a literal Envoy path matches only itself.
+fn escape_glob(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        match c {
+            '*' | '?' | '[' | ']' | '{' | '}' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn import_error(route: &str, reason: &str) -> ConfigError {
+    ConfigError::Parse {
+        location: None,
+        message: format!("route '{}': {}", route, reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeprecationStatus;
+
+    const SAMPLE: &str = r#"
+virtual_hosts:
+  - name: api
+    domains: ["*"]
+    routes:
+      - name: legacy-users
+        match:
+          prefix: /api/v1/users
+          headers:
+            - name: ":method"
+              exact_match: get
+            - name: ":method"
+              string_match:
+                exact: POST
+        redirect:
+          prefix_rewrite: /api/v2/users
+          response_code: PERMANENT_REDIRECT
+        metadata:
+          filter_metadata:
+            deprecation:
+              sunset: 2030-06-01
+              documentation_url: https://docs.example.com/users
+      - match:
+          path: /api/v1/reports
+        route:
+          cluster: reports
+        metadata:
+          filter_metadata:
+            deprecation:
+              sunset: "2030-01-01T12:00:00Z"
+              message: Reports move to the analytics API
+      - name: current-users
+        match:
+          prefix: /api/v2/users
+        route:
+          cluster: users
+"#;
+
+    #[test]
+    fn test_import_envoy_routes() {
+        let mut config = ApiDeprecationConfig::from_envoy_style(SAMPLE).unwrap();
+        config.validate().unwrap();
+        assert_eq!(
+            config.endpoints.len(),
+            2,
+            "routes without annotations are skipped"
+        );
+
+        let users = &config.endpoints[0];
+        assert_eq!(users.id, "legacy-users");
+        assert_eq!(users.path, "/api/v1/users*");
+        assert_eq!(users.methods, vec!["GET", "POST"]);
+        assert_eq!(users.status, DeprecationStatus::Deprecated);
+        assert_eq!(
+            users.sunset_at,
+            Some("2030-06-01T00:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            users
+                .documentation_url
+                .as_ref()
+                .and_then(DocumentationLinks::primary_url),
+            Some("https://docs.example.com/users")
+        );
+        assert!(matches!(
+            users.action,
+            DeprecationAction::Redirect { status_code: 308 }
+        ));
+        let replacement = users.replacement.as_ref().unwrap();
+        assert!(replacement.preserve_query);
+        assert_eq!(replacement.target_for("/api/v1/users/5"), "/api/v2/users/5");
+
+        let reports = &config.endpoints[1];
+        assert_eq!(reports.id, "route-1");
+        assert_eq!(reports.path, "/api/v1/reports");
+        assert_eq!(reports.exclude, vec!["/api/v1/reports?*"]);
+        assert!(reports.methods.is_empty());
+        assert!(matches!(reports.action, DeprecationAction::Warn));
+        assert!(reports.replacement.is_none());
+        assert_eq!(
+            reports.message.as_deref(),
+            Some("Reports move to the analytics API")
+        );
+
+        config.compile();
+        assert_eq!(
+            config.find_endpoint("/api/v1/users/5", "GET").unwrap().id,
+            "legacy-users"
+        );
+        assert!(config.find_endpoint("/api/v1/users/5", "DELETE").is_none());
+        assert_eq!(
+            config.find_endpoint("/api/v1/reports", "GET").unwrap().id,
+            "route-1"
+        );
+        assert!(
+            config.find_endpoint("/api/v1/reports/7", "GET").is_none(),
+            "an exact Envoy path doesn't match its sub-paths"
+        );
+    }
+
+    #[test]
+    fn test_import_literal_metacharacters() {
+        let yaml = r#"
+routes:
+  - name: search
+    match:
+      path: /api/v1/search*
+    metadata:
+      filter_metadata:
+        deprecation: {}
+  - name: files
+    match:
+      prefix: /files/{id}?
+    metadata:
+      filter_metadata:
+        deprecation: {}
+"#;
+        let mut config = ApiDeprecationConfig::from_envoy_style(yaml).unwrap();
+        config.validate().unwrap();
+        config.compile();
+
+        let find = |path: &str| config.find_endpoint(path, "GET").map(|e| e.id.as_str());
+        assert_eq!(find("/api/v1/search*"), Some("search"));
+        assert_eq!(find("/api/v1/search/all"), None);
+        assert_eq!(find("/api/v1/searches"), None);
+        assert_eq!(find("/files/{id}?/a.txt"), Some("files"));
+        assert_eq!(find("/files/7x/a.txt"), None);
+    }
+
+    #[test]
+    fn test_import_path_redirect() {
+        let yaml = r#"
+routes:
+  - name: old-login
+    match:
+      path: /login
+    redirect:
+      path_redirect: /auth/login
+      strip_query: true
+    metadata:
+      filter_metadata:
+        deprecation: {}
+"#;
+        let config = ApiDeprecationConfig::from_envoy_style(yaml).unwrap();
+        let login = &config.endpoints[0];
+        assert!(matches!(
+            login.action,
+            DeprecationAction::Redirect { status_code: 301 }
+        ));
+        let replacement = login.replacement.as_ref().unwrap();
+        assert_eq!(replacement.target_for("/login"), "/auth/login");
+        assert!(!replacement.preserve_query);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let route = |body: &str| {
+            format!(
+                "routes:\n  - name: r\n{}\n    metadata:\n      filter_metadata:\n        deprecation: {{}}\n",
+                body
+            )
+        };
+
+        for (body, expected) in [
+            ("    match: {}", "needs a path or a prefix"),
+            (
+                "    match: {path: /a}\n    redirect: {path_redirect: /b, response_code: MOVED}",
+                "unknown redirect response_code",
+            ),
+            (
+                "    match: {path: /a}\n    redirect: {}",
+                "needs path_redirect",
+            ),
+        ] {
+            let err = ApiDeprecationConfig::from_envoy_style(&route(body)).unwrap_err();
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+
+        let unknown_method = route(
+            "    match:\n      path: /a\n      headers: [{name: \":method\", exact_match: FETCH}]",
+        );
+        assert!(ApiDeprecationConfig::from_envoy_style(&unknown_method).is_err());
+    }
+}
@@ -27,10 +27,35 @@
 //!       type: warn
 //! ```
 
+pub mod admin;
 pub mod agent;
+pub mod alerts;
+pub mod bucket;
+pub mod changelog;
+pub mod client_state;
+pub mod clock;
 pub mod config;
+pub mod dashboard;
+pub mod diagnostics;
+pub mod diff;
+pub mod export;
+#[cfg(any(test, feature = "fixtures"))]
+pub mod fixtures;
 pub mod headers;
+pub mod history;
+pub mod import;
+mod manifest;
+pub mod matcher;
 pub mod metrics;
+pub mod notify;
+mod processed;
+#[cfg(feature = "replacement-check")]
+pub mod reachability;
+pub mod redact;
+#[cfg(feature = "redis-store")]
+mod shared_store;
+pub mod trace;
+pub mod usage;
 
 pub use agent::ApiDeprecationAgent;
 pub use config::ApiDeprecationConfig;
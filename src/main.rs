@@ -1,19 +1,44 @@
 //! Zentinel API Deprecation Agent - CLI Entry Point
 
-use anyhow::Result;
-use clap::Parser;
-use std::net::SocketAddr;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use tracing::{info, Level};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::FmtSubscriber;
+use zentinel_agent_api_deprecation::admin::{self, AdminServer};
+use zentinel_agent_api_deprecation::alerts::{self, AlertOptions};
+use zentinel_agent_api_deprecation::changelog;
+use zentinel_agent_api_deprecation::config::{MetricsServerSource, DEFAULT_METRICS_SERVER_PORT};
+use zentinel_agent_api_deprecation::dashboard::{self, DashboardOptions};
+use zentinel_agent_api_deprecation::export;
+use zentinel_agent_api_deprecation::metrics::MetricsSink;
+use zentinel_agent_api_deprecation::notify;
+use zentinel_agent_api_deprecation::trace::MatchTrace;
 use zentinel_agent_api_deprecation::{ApiDeprecationAgent, ApiDeprecationConfig};
 use zentinel_agent_sdk::v2::{AgentRunnerV2, TransportConfig};
 
+const SYSTEMD_HELP: &str = "\
+Running under systemd (agent built with the `systemd` feature):
+  [Service]
+  Type=notify            READY=1 is sent once --socket accepts connections
+  NotifyAccess=main
+  WatchdogSec=30s        optional; WATCHDOG=1 is sent every WatchdogSec/2
+  ExecStart=/usr/local/bin/zentinel-api-deprecation-agent -c /etc/zentinel/api-deprecation.yaml
+
+STATUS= reports configuration reloads, draining and degraded health, and
+STOPPING=1 is sent on shutdown. Without NOTIFY_SOCKET nothing is sent, and
+notification failures never stop the agent.";
+
 #[derive(Parser, Debug)]
 #[command(
     name = "zentinel-agent-api-deprecation",
     about = "API deprecation management agent for Zentinel proxy",
-    version
+    version,
+    after_long_help = SYSTEMD_HELP
 )]
 struct Args {
     /// Path to configuration file
@@ -40,22 +65,154 @@ struct Args {
     #[arg(long)]
     validate: bool,
 
-    /// Enable metrics server
+    /// Print a JSON trace of how each rule matches a request and exit
+    /// (e.g. --trace-match "GET /api/v1/users")
+    #[arg(long, value_name = "METHOD PATH")]
+    trace_match: Option<String>,
+
+    /// Enable metrics server (overrides metrics.port from the config)
     #[arg(long)]
     metrics: bool,
 
-    /// Metrics server port
-    #[arg(long, default_value = "9090")]
-    metrics_port: u16,
+    /// Metrics server port [default: 9090] (overrides metrics.port from the config)
+    #[arg(long)]
+    metrics_port: Option<u16>,
+
+    /// Serve metrics on this Unix domain socket instead of a TCP port
+    #[arg(long, value_name = "PATH")]
+    metrics_socket: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print a Grafana dashboard (JSON) for the configured endpoints and exit
+    Dashboard {
+        /// Name of the Prometheus datasource
+        #[arg(long, default_value = "Prometheus")]
+        datasource: String,
+
+        /// UID of the Prometheus datasource
+        #[arg(long, default_value = "prometheus")]
+        datasource_uid: String,
+
+        /// Dashboard title
+        #[arg(long, default_value = "API Deprecations")]
+        title: String,
+
+        /// Add a stat panel for endpoints sunsetting within this many days
+        #[arg(long, default_value_t = 90)]
+        lead_days: i64,
+    },
+
+    /// Print Prometheus alerting rules (YAML) for the configured sunsets and exit
+    Alerts {
+        /// Requests per minute above which traffic past the sunset alerts
+        #[arg(long, default_value_t = 1.0)]
+        rate_threshold: f64,
+
+        /// Days before the sunset from which traffic that isn't decreasing alerts
+        #[arg(long, default_value_t = 7)]
+        lead_days: i64,
+
+        /// How long a condition must hold before an alert fires
+        #[arg(long = "for", default_value = "15m", value_name = "DURATION")]
+        for_duration: String,
+
+        /// Write the rules to this file instead of stdout
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        /// Check the generated rules with `promtool check rules` (requires promtool on PATH)
+        #[arg(long)]
+        promtool_check: bool,
+    },
+
+    /// Compare the configuration with a proposed one and print the endpoints
+    /// added, removed and changed
+    Diff {
+        /// The proposed configuration
+        #[arg(value_name = "PATH")]
+        proposed: PathBuf,
+
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print a changelog of the deprecation plans across configuration
+    /// snapshots: endpoints deprecated, sunsets announced or moved, endpoints
+    /// removed and rules deleted (Markdown, newest first)
+    Changelog {
+        /// Configuration snapshots, oldest first, or a directory of snapshots
+        /// named so they sort oldest first (e.g. 2025-01-15.yaml)
+        #[arg(value_name = "PATH", required = true)]
+        snapshots: Vec<PathBuf>,
+
+        /// Print the changelog as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the headers a warned request to an endpoint would get now and exit
+    Preview {
+        /// Rule id of the endpoint
+        #[arg(long)]
+        id: String,
+    },
+
+    /// Print usage and catalog data of every endpoint as CSV from a running
+    /// agent (GET /export.csv), or only the configured columns if none answers
+    Export {
+        /// Metrics listener of the agent [default: from --metrics-port or
+        /// metrics.port, on localhost]
+        #[arg(long, value_name = "ADDR")]
+        address: Option<SocketAddr>,
+
+        /// Count requests over this trailing window instead of the agent's
+        /// lifetime (e.g. 7d or 12h)
+        #[arg(long)]
+        window: Option<String>,
+
+        /// Give up on the agent after this many milliseconds
+        #[arg(long, default_value_t = 2000)]
+        timeout_ms: u64,
+    },
+
+    /// Check a running agent through GET /health on its metrics listener;
+    /// exits 1 if it is unreachable or has no endpoints configured
+    Healthcheck {
+        /// Metrics listener of the agent [default: from --metrics-port or
+        /// metrics.port, on localhost]
+        #[arg(long, value_name = "ADDR")]
+        address: Option<SocketAddr>,
+
+        /// Give up after this many milliseconds
+        #[arg(long, default_value_t = 2000)]
+        timeout_ms: u64,
+
+        /// Also fail while the agent reports degraded health
+        #[arg(long)]
+        strict: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
-    // Initialize logging
+    // Initialize logging; subcommands print their output on stdout, so
+    // their logs go to stderr
+    let writer = if args.command.is_some() {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
     let subscriber = FmtSubscriber::builder()
         .with_max_level(args.log_level)
+        .with_writer(writer)
         .with_target(false)
         .finish();
     tracing::subscriber::set_global_default(subscriber)?;
@@ -71,32 +228,340 @@ async fn main() -> Result<()> {
     let config = if args.config.exists() {
         info!(path = ?args.config, "Loading configuration");
         ApiDeprecationConfig::from_file(&args.config)?
-    } else if args.validate {
+    } else if args.validate
+        || args.trace_match.is_some()
+        || matches!(
+            args.command,
+            Some(
+                Command::Dashboard { .. }
+                    | Command::Alerts { .. }
+                    | Command::Diff { .. }
+                    | Command::Preview { .. }
+            )
+        )
+    {
         anyhow::bail!("Configuration file not found: {:?}", args.config);
     } else {
         info!("Using default configuration");
         ApiDeprecationConfig::default()
     };
 
+    if let Some(command) = args.command {
+        match command {
+            Command::Dashboard {
+                datasource,
+                datasource_uid,
+                title,
+                lead_days,
+            } => {
+                let mut config = config;
+                config.compile();
+                let options = DashboardOptions {
+                    title,
+                    datasource_name: datasource,
+                    datasource_uid,
+                    lead_days,
+                };
+                let dashboard = dashboard::dashboard(&config, &options, chrono::Utc::now());
+                println!("{}", serde_json::to_string_pretty(&dashboard)?);
+            }
+            Command::Alerts {
+                rate_threshold,
+                lead_days,
+                for_duration,
+                output,
+                promtool_check,
+            } => {
+                let mut config = config;
+                config.compile();
+                let options = AlertOptions {
+                    rate_threshold,
+                    lead_days,
+                    for_duration,
+                };
+                let rules = alerts::alert_rules(&config, &options).to_yaml();
+
+                // promtool needs a file; check a temporary one when printing
+                let rules_path = match &output {
+                    Some(path) => {
+                        std::fs::write(path, &rules)?;
+                        Some(path.clone())
+                    }
+                    None if promtool_check => {
+                        let path = std::env::temp_dir().join(format!(
+                            "api-deprecation-alerts-{}.yaml",
+                            std::process::id()
+                        ));
+                        std::fs::write(&path, &rules)?;
+                        Some(path)
+                    }
+                    None => None,
+                };
+                if promtool_check {
+                    let path = rules_path.as_deref().expect("rules were written to a file");
+                    let checked = alerts::promtool_check(path);
+                    if output.is_none() {
+                        let _ = std::fs::remove_file(path);
+                    }
+                    checked.map_err(anyhow::Error::msg)?;
+                }
+                if output.is_none() {
+                    print!("{}", rules);
+                }
+            }
+            Command::Diff { proposed, json } => {
+                config
+                    .validate()
+                    .context("The current configuration is invalid")?;
+                let mut current = config;
+                current.compile();
+                let mut proposed = ApiDeprecationConfig::from_file(&proposed)?;
+                proposed
+                    .validate()
+                    .context("The proposed configuration is invalid")?;
+                proposed.compile();
+                let diff = current.diff(&proposed);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&diff)?);
+                } else {
+                    println!("Current configuration hash:  {}", diff.old_hash);
+                    println!("Proposed configuration hash: {}", diff.new_hash);
+                    println!();
+                    print!("{}", diff);
+                }
+            }
+            Command::Changelog { snapshots, json } => {
+                let snapshots = changelog::load_snapshots(&snapshots)?;
+                if snapshots.len() < 2 {
+                    anyhow::bail!(
+                        "A changelog needs at least two configuration snapshots, found {}",
+                        snapshots.len()
+                    );
+                }
+                let changelog = changelog::changelog(&snapshots);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&changelog)?);
+                } else {
+                    print!("{}", changelog);
+                }
+            }
+            Command::Preview { id } => {
+                config.validate()?;
+                let agent = ApiDeprecationAgent::new(config);
+                let Some(headers) = agent.preview_headers(&id) else {
+                    anyhow::bail!("No endpoint with id '{}'", id);
+                };
+                for (name, value) in headers {
+                    println!("{}: {}", name, value);
+                }
+            }
+            Command::Export {
+                address,
+                window,
+                timeout_ms,
+            } => {
+                if let Some(window) = &window {
+                    export::parse_window(window).map_err(anyhow::Error::msg)?;
+                }
+                let address = address.unwrap_or_else(|| {
+                    local_metrics_address(&config, args.metrics, args.metrics_port)
+                });
+                let timeout = Duration::from_millis(timeout_ms);
+                let token = config.metrics.admin_token.as_deref();
+                match admin::fetch_export(address, window.as_deref(), token, timeout).await {
+                    Ok(csv) => print!("{}", csv),
+                    Err(err) => {
+                        tracing::warn!(
+                            error = %err,
+                            "Could not export from the agent; exporting configured columns only"
+                        );
+                        let mut config = config;
+                        config.compile();
+                        print!("{}", export::catalog_csv(&config, chrono::Utc::now()));
+                    }
+                }
+            }
+            Command::Healthcheck {
+                address,
+                timeout_ms,
+                strict,
+            } => {
+                let address = address.unwrap_or_else(|| {
+                    local_metrics_address(&config, args.metrics, args.metrics_port)
+                });
+                let checked = admin::fetch_health(address, Duration::from_millis(timeout_ms))
+                    .await
+                    .and_then(|health| {
+                        admin::check_health(&health, strict, chrono::Utc::now())?;
+                        Ok(health)
+                    });
+                match checked {
+                    Ok(health) => println!(
+                        "{}: {} endpoint(s), configuration {} loaded at {}",
+                        health.status,
+                        health.endpoints,
+                        health.config_hash,
+                        health.config_loaded_at
+                    ),
+                    Err(err) => {
+                        eprintln!("unhealthy: {}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+
     // Validate and exit if requested
     if args.validate {
         config.validate()?;
+
+        let mut compiled = config.clone();
+        compiled.compile();
+        let failures = compiled.example_failures(chrono::Utc::now());
+        if !failures.is_empty() {
+            println!("{} example(s) don't hold:", failures.len());
+            for failure in &failures {
+                println!("  - {}", failure);
+            }
+            anyhow::bail!("Endpoint examples failed");
+        }
         println!("Configuration is valid");
+        println!("Configuration hash: {}", compiled.hash());
+
+        let severity_issues = config.severity_issues();
+        if !severity_issues.is_empty() {
+            println!();
+            println!(
+                "WARNING: {} issue(s) with critical deprecations:",
+                severity_issues.len()
+            );
+            for issue in severity_issues {
+                println!("  - {}", issue);
+            }
+        }
+
+        let header_warnings = compiled.header_budget_warnings();
+        if !header_warnings.is_empty() {
+            println!();
+            println!(
+                "WARNING: {} endpoint(s) add more header bytes than settings.max_added_header_bytes:",
+                header_warnings.len()
+            );
+            for warning in header_warnings {
+                println!("  - {}", warning);
+            }
+        }
+
+        let unconfirmed: Vec<_> = compiled.unconfirmed_endpoints().collect();
+        if !unconfirmed.is_empty() {
+            println!();
+            println!(
+                "WARNING: {} rule(s) can block traffic but are not confirmed; they only warn until `confirmed: true` is set:",
+                unconfirmed.len()
+            );
+            for endpoint in unconfirmed {
+                println!("  - {} ({})", endpoint.id, endpoint.path);
+            }
+        }
         return Ok(());
     }
 
+    // Trace a request against the configuration and exit if requested
+    if let Some(request) = &args.trace_match {
+        let Some((method, path)) = request.trim().split_once(char::is_whitespace) else {
+            anyhow::bail!("--trace-match expects a method and a path, e.g. \"GET /api/v1/users\"");
+        };
+        let path = path.trim();
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+        let mut config = config;
+        config.compile();
+        let trace = MatchTrace::at(&config, path, &method.to_uppercase(), chrono::Utc::now());
+        println!("{}", serde_json::to_string_pretty(&trace)?);
+        return Ok(());
+    }
+
+    // Resolve the metrics server: CLI flags override metrics.port, a
+    // --metrics-socket replaces the TCP listener, and metrics.enabled: false
+    // disables it regardless of any of them
+    let cli_metrics = args.metrics || args.metrics_port.is_some() || args.metrics_socket.is_some();
+    let metrics_socket = args
+        .metrics_socket
+        .clone()
+        .filter(|_| config.metrics.enabled);
+    let metrics_server = config
+        .metrics
+        .resolve_server(args.metrics, args.metrics_port)
+        .filter(|_| metrics_socket.is_none());
+    if cli_metrics && !config.metrics.enabled {
+        tracing::warn!("Metrics are disabled in the configuration (metrics.enabled: false); not starting metrics server");
+    }
+
+    #[cfg(not(feature = "redis-store"))]
+    let shared_store = config.metrics.shared_store.is_some();
+
+    // Replacement checks run against the startup configuration
+    let replacement_checks = config.settings.check_replacements.then(|| config.clone());
+
+    let admin_token = config.metrics.admin_token.clone();
+
     // Create agent
-    let agent = ApiDeprecationAgent::new(config);
+    let agent = ApiDeprecationAgent::try_new(config)?;
+
+    if let Some(config) = replacement_checks {
+        start_replacement_checks(config, agent.metrics().clone())?;
+    }
+
+    // Started even with metrics disabled, as a reload may enable them
+    agent.start_gauge_refresh();
+
+    agent.start_watchdog();
+
+    #[cfg(feature = "redis-store")]
+    agent.start_shared_store();
+    #[cfg(not(feature = "redis-store"))]
+    if shared_store {
+        tracing::warn!(
+            "metrics.shared_store is set but the agent was built without the redis-store feature"
+        );
+    }
 
     // Start metrics server if enabled
-    if args.metrics {
-        let metrics = agent.metrics().clone();
-        let port = args.metrics_port;
+    let config_path = args.config.exists().then(|| args.config.clone());
+    let admin_server = Arc::new(AdminServer::new(
+        agent.handle(),
+        agent.metrics().clone(),
+        config_path,
+        admin_token,
+    ));
+    if let Some(path) = metrics_socket {
+        info!(socket = ?path, "Starting metrics server on a Unix socket");
+        tokio::spawn(start_metrics_socket(admin_server, path));
+    } else if let Some((address, source)) = metrics_server {
+        let source = match source {
+            MetricsServerSource::Cli => "cli",
+            MetricsServerSource::Config => "config",
+        };
+        info!(address = %address, source, "Starting metrics server (CLI flags take precedence over metrics.port)");
+
         tokio::spawn(async move {
-            start_metrics_server(metrics, port).await;
+            start_metrics_server(admin_server, address).await;
         });
     }
 
+    // Reload the configuration file on SIGHUP
+    #[cfg(unix)]
+    if args.config.exists() {
+        tokio::spawn(reload_on_sighup(agent.handle(), args.config.clone()));
+    }
+
+    // Report readiness to systemd once the UDS listener accepts connections
+    if notify::enabled() {
+        tokio::spawn(notify_ready(args.socket.clone()));
+    }
+
     // Determine transport configuration
     let transport = match args.grpc_address {
         Some(grpc_addr) => {
@@ -137,14 +602,85 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn start_metrics_server(
-    metrics: zentinel_agent_api_deprecation::metrics::DeprecationMetrics,
-    port: u16,
+#[cfg(feature = "replacement-check")]
+fn start_replacement_checks(
+    config: ApiDeprecationConfig,
+    metrics: std::sync::Arc<dyn MetricsSink>,
+) -> Result<()> {
+    use zentinel_agent_api_deprecation::reachability;
+
+    let client =
+        reachability::try_client().context("Failed to build the replacement check client")?;
+
+    let interval = config.settings.replacement_check_interval_secs;
+    info!(
+        interval_secs = interval,
+        "Starting replacement reachability checks"
+    );
+
+    tokio::spawn(async move {
+        loop {
+            reachability::record_replacement_checks(&config, &client, metrics.as_ref()).await;
+            if interval == 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "replacement-check"))]
+fn start_replacement_checks(
+    _config: ApiDeprecationConfig,
+    _metrics: std::sync::Arc<dyn MetricsSink>,
+) -> Result<()> {
+    tracing::warn!(
+        "settings.check_replacements is set but the agent was built without the replacement-check feature"
+    );
+    Ok(())
+}
+
+/// Send READY=1 once the agent's socket accepts connections. The transport is
+/// bound inside the runner, so readiness is probed by connecting.
+async fn notify_ready(socket: PathBuf) {
+    loop {
+        if tokio::net::UnixStream::connect(&socket).await.is_ok() {
+            notify::notify(&format!("READY=1\nSTATUS=Serving on {}", socket.display()));
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+}
+
+/// Re-read the configuration file on every SIGHUP; an invalid file is
+/// reported and the last-good configuration stays active.
+#[cfg(unix)]
+async fn reload_on_sighup(
+    handle: zentinel_agent_api_deprecation::agent::AgentHandle,
+    path: PathBuf,
 ) {
-    use tokio::io::AsyncWriteExt;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangups = match signal(SignalKind::hangup()) {
+        Ok(hangups) => hangups,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to install SIGHUP handler");
+            return;
+        }
+    };
+    while hangups.recv().await.is_some() {
+        info!(path = ?path, "SIGHUP received, reloading configuration");
+        if let Err(e) = handle.reload_from_file(&path) {
+            tracing::error!(error = %e, "Configuration reload failed");
+        }
+    }
+}
+
+async fn start_metrics_server(server: Arc<AdminServer>, address: SocketAddr) {
     use tokio::net::TcpListener;
 
-    let listener = match TcpListener::bind(format!("0.0.0.0:{}", port)).await {
+    let listener = match TcpListener::bind(address).await {
         Ok(l) => l,
         Err(e) => {
             tracing::error!(error = %e, "Failed to start metrics server");
@@ -152,18 +688,13 @@ async fn start_metrics_server(
         }
     };
 
-    info!(port = port, "Metrics server started");
+    info!(address = %address, "Metrics server started");
 
+    // One task per connection, so a slow client can't hold up the others
     loop {
         match listener.accept().await {
-            Ok((mut socket, _)) => {
-                let output = metrics.encode();
-                let response = format!(
-                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
-                    output.len(),
-                    output
-                );
-                let _ = socket.write_all(response.as_bytes()).await;
+            Ok((socket, _)) => {
+                tokio::spawn(Arc::clone(&server).serve_connection(socket));
             }
             Err(e) => {
                 tracing::warn!(error = %e, "Failed to accept metrics connection");
@@ -171,3 +702,44 @@ async fn start_metrics_server(
         }
     }
 }
+
+/// Serve the metrics listener on a Unix domain socket, replacing a stale
+/// socket left at `path` by a previous run.
+#[cfg(unix)]
+async fn start_metrics_socket(server: Arc<AdminServer>, path: PathBuf) {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::UnixListener;
+
+    if std::fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        let _ = std::fs::remove_file(&path);
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(l) => l,
+        Err(e) => {
+            tracing::error!(error = %e, socket = ?path, "Failed to start metrics server");
+            return;
+        }
+    };
+
+    info!(socket = ?path, "Metrics server started");
+    admin::serve_unix(server, listener).await;
+}
+
+#[cfg(not(unix))]
+async fn start_metrics_socket(_server: Arc<AdminServer>, path: PathBuf) {
+    tracing::error!(socket = ?path, "Unix domain sockets are not supported on this platform");
+}
+
+/// Metrics listener of a local agent: the port from `--metrics-port` or
+/// `metrics.port`, on localhost.
+fn local_metrics_address(
+    config: &ApiDeprecationConfig,
+    metrics: bool,
+    metrics_port: Option<u16>,
+) -> SocketAddr {
+    let port = config
+        .metrics
+        .resolve_server(metrics, metrics_port)
+        .map_or(DEFAULT_METRICS_SERVER_PORT, |(address, _)| address.port());
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+}
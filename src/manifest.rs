@@ -0,0 +1,319 @@
+//! Machine-readable catalog of the configured deprecations.
+//!
+//! When `settings.manifest_path` is set, the agent answers that path (e.g.
+//! `/.well-known/api-deprecations`) itself with a JSON document listing every
+//! deprecated endpoint. The document is built once per configuration, so it
+//! follows reloads, and carries an ETag derived from its content so clients
+//! can revalidate cheaply.
+
+use crate::config::{ApiDeprecationConfig, DocumentationLinks};
+use crate::headers::{deprecation_report_body, format_http_date};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+/// How long clients may cache the manifest before revalidating.
+const MAX_AGE_SECS: u32 = 300;
+
+/// Prebuilt manifest response.
+#[derive(Debug)]
+pub(crate) struct Manifest {
+    /// Path the manifest is served at
+    pub path: String,
+    /// JSON document
+    pub body: Arc<str>,
+    /// Strong ETag over the endpoint catalog
+    pub etag: Arc<str>,
+    /// When this configuration was loaded
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Response to a manifest request.
+#[derive(Debug)]
+pub(crate) struct ManifestResponse {
+    pub status: u16,
+    /// Body, absent for `304 Not Modified`
+    pub body: Option<Arc<str>>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+impl Manifest {
+    /// Build the manifest for a configuration, if one is configured.
+    pub fn new(config: &ApiDeprecationConfig, updated_at: DateTime<Utc>) -> Option<Self> {
+        let path = config.settings.manifest_path.clone()?;
+
+        let endpoints: Vec<serde_json::Value> = config
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let mut entry = serde_json::json!({
+                    "id": endpoint.id,
+                    "path": endpoint.path.primary(),
+                    "aliases": endpoint.path.aliases(),
+                    "methods": endpoint.methods,
+                    "status": endpoint.status,
+                    "deprecated_at": endpoint.deprecated_at,
+                    "sunset_at": endpoint.sunset_at,
+                    "replacement": endpoint.replacement.as_ref().map(|replacement| {
+                        serde_json::json!({
+                            "path": replacement.path,
+                            "method": replacement.method,
+                        })
+                    }),
+                    "documentation_url": endpoint
+                        .documentation_url
+                        .as_ref()
+                        .and_then(DocumentationLinks::primary_url),
+                    "links": endpoint.documentation_url.as_ref().map(DocumentationLinks::to_json),
+                    "deprecated_params": endpoint.deprecated_params,
+                    "deprecated_request_headers": endpoint.deprecated_request_headers,
+                    "deprecated_body_fields": endpoint.body_fields(),
+                });
+                if let Some(owner) = &endpoint.owner {
+                    entry["owner"] = serde_json::Value::String(owner.clone());
+                }
+                // Seasonal rules: the window in which they apply at all
+                if let Some(from) = endpoint.active_from {
+                    entry["active_from"] = serde_json::json!(from);
+                }
+                if let Some(until) = endpoint.active_until {
+                    entry["active_until"] = serde_json::json!(until);
+                }
+                if let Some(contact) = &endpoint.contact {
+                    entry["contact"] = serde_json::Value::String(contact.clone());
+                }
+                // Where and in what shape client code can file its own
+                // deprecation reports
+                if let Some(report_to) = &endpoint.report_to {
+                    entry["report_to"] = serde_json::json!({
+                        "url": report_to.url,
+                        "group": report_to.group,
+                        "body": deprecation_report_body(endpoint),
+                    });
+                }
+                entry
+            })
+            .collect();
+
+        // The ETag only covers the catalog (and policy), so reloading an
+        // unchanged configuration doesn't invalidate client caches
+        let mut catalog = serde_json::to_string(&endpoints).unwrap_or_default();
+        if let Some(policy) = &config.settings.policy_url {
+            catalog.push_str(policy);
+        }
+        let etag = format!("\"{:016x}\"", fnv1a(catalog.as_bytes()));
+
+        let mut body = serde_json::json!({
+            "updated_at": updated_at,
+            "endpoints": endpoints,
+        });
+        if let Some(policy) = &config.settings.policy_url {
+            body["policy"] = serde_json::Value::String(policy.clone());
+        }
+
+        Some(Self {
+            path,
+            body: Arc::from(serde_json::to_string_pretty(&body).unwrap_or_default()),
+            etag: Arc::from(etag),
+            updated_at,
+        })
+    }
+
+    /// Answer a request for the manifest, if it is one.
+    pub fn respond(
+        &self,
+        path: &str,
+        method: &str,
+        if_none_match: Option<&str>,
+    ) -> Option<ManifestResponse> {
+        if path != self.path
+            || !(method.eq_ignore_ascii_case("GET") || method.eq_ignore_ascii_case("HEAD"))
+        {
+            return None;
+        }
+
+        let headers = vec![
+            ("ETag", self.etag.to_string()),
+            ("Cache-Control", format!("public, max-age={}", MAX_AGE_SECS)),
+            ("Last-Modified", format_http_date(&self.updated_at)),
+        ];
+
+        let not_modified = if_none_match.is_some_and(|value| {
+            value
+                .split(',')
+                .any(|tag| tag.trim() == "*" || tag.trim() == self.etag.as_ref())
+        });
+        if not_modified {
+            return Some(ManifestResponse {
+                status: 304,
+                body: None,
+                headers,
+            });
+        }
+
+        let mut headers = headers;
+        headers.push(("Content-Type", "application/json".to_string()));
+        Some(ManifestResponse {
+            status: 200,
+            body: Some(Arc::clone(&self.body)),
+            headers,
+        })
+    }
+}
+
+/// 64-bit FNV-1a, stable across builds and platforms.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const YAML: &str = r#"
+settings:
+  manifest_path: /.well-known/api-deprecations
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    methods: [GET]
+    sunset_at: 2030-06-01
+    replacement:
+      path: /api/v2/users
+    documentation_url: https://docs.example.com/users
+  - id: removed-posts
+    path: /api/v1/posts
+    status: removed
+"#;
+
+    const UPDATED_AT: &str = "2030-01-15T08:30:00Z";
+
+    fn manifest(yaml: &str) -> Manifest {
+        let config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        Manifest::new(&config, UPDATED_AT.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_manifest_document() {
+        let manifest = manifest(YAML);
+        let response = manifest
+            .respond("/.well-known/api-deprecations", "GET", None)
+            .unwrap();
+        assert_eq!(response.status, 200);
+
+        let body: serde_json::Value = serde_json::from_str(&response.body.unwrap()).unwrap();
+        assert_eq!(body["updated_at"], UPDATED_AT);
+        let users = &body["endpoints"][0];
+        assert_eq!(users["path"], "/api/v1/users");
+        assert_eq!(users["aliases"], serde_json::json!([]));
+        assert_eq!(users["methods"], serde_json::json!(["GET"]));
+        assert_eq!(users["status"], "deprecated");
+        assert_eq!(users["sunset_at"], "2030-06-01T00:00:00Z");
+        assert_eq!(users["replacement"]["path"], "/api/v2/users");
+        assert_eq!(users["documentation_url"], "https://docs.example.com/users");
+        assert!(users.get("report_to").is_none());
+        assert_eq!(body["endpoints"][1]["status"], "removed");
+
+        let header = |name: &str| {
+            response
+                .headers
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.clone())
+        };
+        assert_eq!(header("ETag").unwrap(), manifest.etag.as_ref());
+        assert!(header("Cache-Control").unwrap().starts_with("public"));
+        assert_eq!(header("Content-Type").unwrap(), "application/json");
+    }
+
+    #[test]
+    fn test_manifest_lists_path_aliases() {
+        let manifest = manifest(&YAML.replace(
+            "path: /api/v1/users\n",
+            "path: [/api/v1/users, /v1/users]\n",
+        ));
+        let body: serde_json::Value = serde_json::from_str(&manifest.body).unwrap();
+        assert_eq!(body["endpoints"][0]["path"], "/api/v1/users");
+        assert_eq!(
+            body["endpoints"][0]["aliases"],
+            serde_json::json!(["/v1/users"])
+        );
+    }
+
+    #[test]
+    fn test_manifest_report_to() {
+        let manifest = manifest(&YAML.replace(
+            "path: /api/v1/users\n",
+            "path: /api/v1/users\n    report_to:\n      url: https://reports.example.com/deprecations\n",
+        ));
+        let body: serde_json::Value = serde_json::from_str(&manifest.body).unwrap();
+        let report_to = &body["endpoints"][0]["report_to"];
+        assert_eq!(report_to["url"], "https://reports.example.com/deprecations");
+        assert_eq!(report_to["group"], "deprecation");
+        assert_eq!(report_to["body"]["id"], body["endpoints"][0]["id"]);
+        assert_eq!(
+            report_to["body"]["anticipatedRemoval"],
+            "2030-06-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_manifest_policy() {
+        let without = manifest(YAML);
+        let body: serde_json::Value = serde_json::from_str(&without.body).unwrap();
+        assert!(body.get("policy").is_none());
+
+        let with = manifest(&YAML.replace(
+            "  manifest_path:",
+            "  policy_url: https://example.com/api/deprecation-policy\n  manifest_path:",
+        ));
+        let body: serde_json::Value = serde_json::from_str(&with.body).unwrap();
+        assert_eq!(body["policy"], "https://example.com/api/deprecation-policy");
+        assert_ne!(with.etag, without.etag);
+    }
+
+    #[test]
+    fn test_manifest_not_modified() {
+        let manifest = manifest(YAML);
+        let etag = manifest.etag.to_string();
+
+        let response = manifest
+            .respond("/.well-known/api-deprecations", "GET", Some(&etag))
+            .unwrap();
+        assert_eq!(response.status, 304);
+        assert!(response.body.is_none());
+
+        let response = manifest
+            .respond("/.well-known/api-deprecations", "GET", Some("\"stale\""))
+            .unwrap();
+        assert_eq!(response.status, 200);
+    }
+
+    #[test]
+    fn test_manifest_only_answers_its_path() {
+        let manifest = manifest(YAML);
+        assert!(manifest.respond("/api/v1/users", "GET", None).is_none());
+        assert!(manifest
+            .respond("/.well-known/api-deprecations", "POST", None)
+            .is_none());
+        assert!(manifest
+            .respond("/.well-known/api-deprecations", "HEAD", None)
+            .is_some());
+    }
+
+    #[test]
+    fn test_etag_tracks_catalog_content() {
+        let first = manifest(YAML);
+        assert_eq!(first.etag, manifest(YAML).etag);
+
+        let changed = manifest(&YAML.replace("2030-06-01", "2031-01-01"));
+        assert_ne!(first.etag, changed.etag);
+    }
+
+    #[test]
+    fn test_manifest_disabled_by_default() {
+        let config = ApiDeprecationConfig::default();
+        assert!(Manifest::new(&config, Utc::now()).is_none());
+    }
+}
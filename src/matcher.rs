@@ -0,0 +1,483 @@
+//! Indexed endpoint lookup for large rule sets.
+//!
+//! [`ApiDeprecationConfig::find_endpoint`](crate::config::ApiDeprecationConfig::find_endpoint)
+//! scans every rule in order. The index built here gives the same answer
+//! (the first matching rule in configuration order) without the full scan:
+//!
+//! - Literal rules (no glob metacharacters) are keyed by their path. A request path can
+//!   only match a literal rule whose path is the request path itself or one of
+//!   its `/`-delimited prefixes, so lookups probe those keys directly.
+//! - Glob rules are grouped by the literal first segment of their patterns
+//!   (`/api/v1/*` under `api`) and only checked up to the best literal match
+//!   found so far. Globs whose first segment isn't literal (`/*/users`) are
+//!   checked for every request.
+//! - The first segments of all patterns form a set, so a request whose first
+//!   segment isn't in it is rejected with a single hash lookup, without
+//!   touching any rule (unless some pattern can match any first segment).
+//!
+//! For `n` rules, a lookup costs `O(d)` hash probes for a path of depth `d`
+//! plus a scan of the glob rules sharing its first segment, instead of `O(n)`
+//! pattern checks. Misses on an unknown first segment, the common case for
+//! unrelated traffic, cost `O(1)`.
+//!
+//! `unless` references are resolved to indices when the index is built.
+//! Rules outside their `active_from`..`active_until` window are skipped at
+//! lookup time, so the same index serves the configuration all year.
+
+use crate::config::{is_glob_pattern, DeprecatedEndpoint};
+use chrono::{DateTime, Utc};
+use std::collections::{HashMap, HashSet};
+
+/// Index over a list of endpoints for fast first-match lookup.
+#[derive(Debug, Clone, Default)]
+pub struct EndpointIndex {
+    /// Literal rules keyed by path, each list in configuration order
+    literal: HashMap<String, Vec<usize>>,
+    /// Glob rules keyed by the literal first segment of their patterns, each
+    /// list in configuration order
+    globs: HashMap<String, Vec<usize>>,
+    /// Glob rules with a pattern that can match any first segment, in
+    /// configuration order
+    unanchored: Vec<usize>,
+    /// First segments of all anchored patterns
+    segments: HashSet<String>,
+    /// Whether some pattern can match any first segment, disabling the
+    /// first-segment rejection
+    catch_all: bool,
+    /// Rules suppressing each rule that has an `unless` list
+    unless: HashMap<usize, Vec<usize>>,
+}
+
+impl EndpointIndex {
+    /// Build an index over the given endpoints.
+    pub fn new(endpoints: &[DeprecatedEndpoint]) -> Self {
+        let mut index = Self::default();
+        let positions: HashMap<&str, usize> = endpoints
+            .iter()
+            .enumerate()
+            .map(|(i, endpoint)| (endpoint.id.as_str(), i))
+            .collect();
+
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            if !endpoint.unless.is_empty() {
+                let suppressors = endpoint
+                    .unless
+                    .iter()
+                    .filter_map(|id| positions.get(id.as_str()).copied())
+                    .collect();
+                index.unless.insert(i, suppressors);
+            }
+
+            let segments: Option<Vec<&str>> = endpoint.path.iter().map(pattern_segment).collect();
+            match &segments {
+                Some(segments) => index
+                    .segments
+                    .extend(segments.iter().map(|s| s.to_string())),
+                None => index.catch_all = true,
+            }
+
+            // A rule with any glob alias is checked as a whole with the globs;
+            // otherwise it is reachable through each of its literal aliases
+            if endpoint.is_glob() {
+                match segments {
+                    Some(segments) => {
+                        for segment in segments {
+                            push_once(index.globs.entry(segment.to_string()).or_default(), i);
+                        }
+                    }
+                    None => index.unanchored.push(i),
+                }
+            } else {
+                for pattern in endpoint.path.iter() {
+                    push_once(index.literal.entry(pattern.to_string()).or_default(), i);
+                }
+            }
+        }
+
+        index
+    }
+
+    /// Find the index of the first endpoint active now matching a path and
+    /// method.
+    ///
+    /// `endpoints` must be the slice the index was built from.
+    #[deprecated(note = "use `find_at` with an explicit time")]
+    pub fn find(
+        &self,
+        endpoints: &[DeprecatedEndpoint],
+        path: &str,
+        method: &str,
+    ) -> Option<usize> {
+        self.find_at(endpoints, path, method, Utc::now())
+    }
+
+    /// Find the index of the first endpoint active at `now` matching a path
+    /// and method.
+    ///
+    /// `endpoints` must be the slice the index was built from.
+    pub fn find_at(
+        &self,
+        endpoints: &[DeprecatedEndpoint],
+        path: &str,
+        method: &str,
+        now: DateTime<Utc>,
+    ) -> Option<usize> {
+        let segment = request_segment(path);
+        if !self.catch_all && !segment.is_some_and(|s| self.segments.contains(s)) {
+            return None;
+        }
+
+        let mut best: Option<usize> = None;
+
+        for key in candidate_keys(path) {
+            let Some(indices) = self.literal.get(key) else {
+                continue;
+            };
+            for &i in indices {
+                if best.is_some_and(|b| i >= b) {
+                    break;
+                }
+                if self.rule_matches(endpoints, i, path, method, now) {
+                    best = Some(i);
+                    break;
+                }
+            }
+        }
+
+        // Glob rules only matter if they come before the best literal match
+        let anchored = segment
+            .and_then(|s| self.globs.get(s))
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for i in merge_ordered(anchored, &self.unanchored) {
+            if best.is_some_and(|b| i > b) {
+                break;
+            }
+            if self.rule_matches(endpoints, i, path, method, now) {
+                return Some(i);
+            }
+        }
+
+        best
+    }
+
+    /// Whether rule `i` is active and matches, and none of its active
+    /// `unless` rules do.
+    fn rule_matches(
+        &self,
+        endpoints: &[DeprecatedEndpoint],
+        i: usize,
+        path: &str,
+        method: &str,
+        now: DateTime<Utc>,
+    ) -> bool {
+        endpoints[i].is_active_at(now)
+            && endpoints[i].matches(path, method)
+            && self.suppressor(endpoints, i, path, method, now).is_none()
+    }
+
+    /// The first of rule `i`'s `unless` rules that is active and matches,
+    /// if any.
+    pub fn suppressor(
+        &self,
+        endpoints: &[DeprecatedEndpoint],
+        i: usize,
+        path: &str,
+        method: &str,
+        now: DateTime<Utc>,
+    ) -> Option<usize> {
+        self.unless
+            .get(&i)?
+            .iter()
+            .copied()
+            .find(|&j| endpoints[j].is_active_at(now) && endpoints[j].matches(path, method))
+    }
+}
+
+/// First segment of a request path (`api` for `/api/v1/users`), if it starts
+/// with `/`.
+fn request_segment(path: &str) -> Option<&str> {
+    let rest = path.strip_prefix('/')?;
+    Some(rest.split('/').next().unwrap_or_default())
+}
+
+/// The first segment every path matched by a pattern must have, or `None` if
+/// the pattern can match paths with any first segment (`/*/users`, `/`).
+fn pattern_segment(pattern: &str) -> Option<&str> {
+    let rest = pattern.strip_prefix('/')?;
+    let (segment, terminated) = match rest.split_once('/') {
+        Some((segment, _)) => (segment, true),
+        None => (rest, false),
+    };
+    // Inside a glob, classes and alternatives make a segment variable too
+    let variable = is_glob_pattern(pattern) && segment.contains(['[', '{']);
+    if segment.is_empty() || is_glob_pattern(segment) || variable {
+        return None;
+    }
+    // A literal rule matches its path and everything below it, but a glob
+    // like `/api*` could continue the segment
+    if is_glob_pattern(pattern) && !terminated {
+        return None;
+    }
+    Some(segment)
+}
+
+/// Append a rule index unless it was just added.
+fn push_once(indices: &mut Vec<usize>, i: usize) {
+    if indices.last() != Some(&i) {
+        indices.push(i);
+    }
+}
+
+/// Merge two ascending index lists into one ascending sequence.
+fn merge_ordered<'a>(a: &'a [usize], b: &'a [usize]) -> impl Iterator<Item = usize> + 'a {
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    std::iter::from_fn(move || match (a.peek(), b.peek()) {
+        (Some(&&x), Some(&&y)) if x <= y => a.next().copied(),
+        (Some(_), None) => a.next().copied(),
+        _ => b.next().copied(),
+    })
+}
+
+/// Literal rule paths that could match a request path: the path itself and
+/// every prefix ending just before or at a `/`.
+fn candidate_keys(path: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(path).chain(
+        path.match_indices('/')
+            .flat_map(move |(i, _)| [&path[..i], &path[..=i]]),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiDeprecationConfig;
+
+    /// Small deterministic generator so the comparison is reproducible.
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn next(&mut self, bound: usize) -> usize {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((self.0 >> 33) as usize) % bound
+        }
+    }
+
+    const SEGMENTS: &[&str] = &[
+        "api", "v1", "v2", "users", "posts", "42", "legacy", "", "apix", "other",
+    ];
+    const METHODS: &[&str] = &["GET", "POST", "DELETE"];
+
+    fn now() -> DateTime<Utc> {
+        "2025-06-01T00:00:00Z".parse().unwrap()
+    }
+
+    fn random_path(rng: &mut Lcg) -> String {
+        let depth = 1 + rng.next(4);
+        let mut path = String::new();
+        for _ in 0..depth {
+            path.push('/');
+            path.push_str(SEGMENTS[rng.next(SEGMENTS.len())]);
+        }
+        path
+    }
+
+    /// A pattern: mostly literal and anchored globs, with the occasional
+    /// pattern that can match any first segment unless `anchored`.
+    fn random_pattern(rng: &mut Lcg, anchored: bool) -> String {
+        let mut path = random_path(rng);
+        if anchored && path.starts_with("//") {
+            path.insert_str(0, "/legacy");
+        }
+        match rng.next(if anchored { 5 } else { 12 }) {
+            0..=2 => path.push_str("/*"),
+            3 | 4 => path.push('/'),
+            5 => path.insert_str(0, "/*"),
+            6 => path.push('*'),
+            7 => path = format!("/{{api,legacy}}{}", path),
+            8 if rng.next(4) == 0 => path = "/".to_string(),
+            _ => {}
+        }
+        path
+    }
+
+    fn random_config(rng: &mut Lcg, n: usize) -> ApiDeprecationConfig {
+        // Half the configs exercise the first-segment rejection
+        let anchored = rng.next(2) == 0;
+        let mut yaml = String::from("endpoints:\n");
+        for i in 0..n {
+            let path = match rng.next(5) {
+                0 => format!(
+                    "[\"{}\", \"{}\"]",
+                    random_pattern(rng, anchored),
+                    random_pattern(rng, anchored)
+                ),
+                _ => format!("\"{}\"", random_pattern(rng, anchored)),
+            };
+            let methods = match rng.next(3) {
+                0 => "[]".to_string(),
+                _ => format!("[{}]", METHODS[rng.next(METHODS.len())]),
+            };
+            let unless = match rng.next(5) {
+                0 if n > 1 => format!("[rule-{}]", (i + 1 + rng.next(n - 1)) % n),
+                _ => "[]".to_string(),
+            };
+            // Some rules are outside their window at `now()`
+            let window = match rng.next(6) {
+                0 => "\n    active_from: 2026-01-01",
+                1 => "\n    active_until: 2025-01-01",
+                2 => "\n    active_from: 2025-01-01\n    active_until: 2026-01-01",
+                _ => "",
+            };
+            yaml.push_str(&format!(
+                "  - id: rule-{i}\n    path: {path}\n    methods: {methods}\n    unless: {unless}{window}\n"
+            ));
+        }
+        let mut config: ApiDeprecationConfig = serde_yaml::from_str(&yaml).unwrap();
+        config.compile();
+        config
+    }
+
+    #[test]
+    fn test_index_matches_linear_scan() {
+        let mut rng = Lcg(0x5eed);
+        let mut anchored_configs = 0;
+
+        for _ in 0..40 {
+            let config = random_config(&mut rng, 50);
+            let index = EndpointIndex::new(&config.endpoints);
+            anchored_configs += usize::from(!index.catch_all);
+
+            for _ in 0..500 {
+                let path = random_path(&mut rng);
+                let method = METHODS[rng.next(METHODS.len())];
+                assert_eq!(
+                    index.find_at(&config.endpoints, &path, method, now()),
+                    config.find_endpoint_index_at(&path, method, now()),
+                    "mismatch for {method} {path}"
+                );
+            }
+        }
+
+        assert!(
+            anchored_configs > 0,
+            "no config exercised segment rejection"
+        );
+    }
+
+    #[test]
+    fn test_index_prefers_earlier_glob() {
+        let yaml = r#"
+endpoints:
+  - id: all-v1
+    path: /api/v1/*
+  - id: users
+    path: /api/v1/users
+"#;
+        let mut config: ApiDeprecationConfig = serde_yaml::from_str(yaml).unwrap();
+        config.compile();
+        let index = EndpointIndex::new(&config.endpoints);
+
+        assert_eq!(
+            index.find_at(&config.endpoints, "/api/v1/users", "GET", now()),
+            Some(0)
+        );
+        assert_eq!(
+            index.find_at(&config.endpoints, "/api/v2/users", "GET", now()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_inactive_rules_skipped() {
+        let yaml = r#"
+endpoints:
+  - id: tax-season
+    path: /api/v1/filings
+    active_from: 2025-01-15
+    active_until: 2025-04-16
+  - id: all-v1
+    path: /api/v1/*
+    unless: [tax-season]
+"#;
+        let mut config: ApiDeprecationConfig = serde_yaml::from_str(yaml).unwrap();
+        config.compile();
+        let index = EndpointIndex::new(&config.endpoints);
+        let find = |at: &str| {
+            index.find_at(
+                &config.endpoints,
+                "/api/v1/filings",
+                "GET",
+                at.parse().unwrap(),
+            )
+        };
+
+        // In the window the seasonal rule wins and suppresses the catch-all
+        assert_eq!(find("2025-01-15T00:00:00Z"), Some(0));
+        assert_eq!(find("2025-04-15T23:59:59Z"), Some(0));
+        // Outside it, it neither matches nor suppresses
+        assert_eq!(find("2025-01-14T23:59:59Z"), Some(1));
+        assert_eq!(find("2025-04-16T00:00:00Z"), Some(1));
+    }
+
+    #[test]
+    fn test_unknown_first_segment_rejected() {
+        let yaml = r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+  - id: legacy
+    path: /legacy/*/items
+"#;
+        let mut config: ApiDeprecationConfig = serde_yaml::from_str(yaml).unwrap();
+        config.compile();
+        let index = EndpointIndex::new(&config.endpoints);
+
+        assert!(!index.catch_all);
+        assert_eq!(
+            index.segments,
+            HashSet::from(["api".to_string(), "legacy".to_string()])
+        );
+        assert_eq!(
+            index.find_at(&config.endpoints, "/static/app.js", "GET", now()),
+            None
+        );
+        assert_eq!(
+            index.find_at(&config.endpoints, "/legacy/7/items", "GET", now()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_pattern_segment() {
+        assert_eq!(pattern_segment("/api/v1/users"), Some("api"));
+        assert_eq!(pattern_segment("/api"), Some("api"));
+        assert_eq!(pattern_segment("/api/"), Some("api"));
+        assert_eq!(pattern_segment("/api/*"), Some("api"));
+        assert_eq!(pattern_segment("/api*"), None);
+        assert_eq!(pattern_segment("/*/users"), None);
+        assert_eq!(pattern_segment("/{api,legacy}/v1/*"), None);
+        assert_eq!(pattern_segment("/[al]pi/v1/*"), None);
+        // Without `*` or `?`, braces are part of a literal path
+        assert_eq!(pattern_segment("/{api,legacy}/v1"), Some("{api,legacy}"));
+        assert_eq!(pattern_segment("/"), None);
+    }
+
+    #[test]
+    fn test_merge_ordered() {
+        let merged: Vec<usize> = merge_ordered(&[1, 4, 9], &[0, 5, 6, 10]).collect();
+        assert_eq!(merged, vec![0, 1, 4, 5, 6, 9, 10]);
+    }
+
+    #[test]
+    fn test_candidate_keys() {
+        let keys: Vec<&str> = candidate_keys("/api/v1/").collect();
+        assert_eq!(
+            keys,
+            vec!["/api/v1/", "", "/", "/api", "/api/", "/api/v1", "/api/v1/"]
+        );
+    }
+}
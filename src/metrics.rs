@@ -1,10 +1,316 @@
 //! Metrics for tracking deprecated API usage.
 //!
-//! Provides Prometheus metrics for monitoring deprecated endpoint access.
+//! The agent records usage through the [`MetricsSink`] trait. The
+//! Prometheus-backed [`DeprecationMetrics`] is available with the default
+//! `prometheus` feature; without it, [`NoopMetrics`] is used and embedders can
+//! plug in their own sink.
 
-use prometheus::{HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry};
+use crate::config::Severity;
+use crate::usage::{ClusterTotals, UniqueConsumers};
+use chrono::{DateTime, Utc};
+#[cfg(feature = "prometheus")]
+use prometheus::{
+    GaugeVec, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+};
 
-/// Metrics collector for deprecated API usage.
+/// Destination for deprecated endpoint usage metrics.
+pub trait MetricsSink: Send + Sync {
+    /// Record a request to a deprecated endpoint.
+    fn record_request(&self, endpoint_id: &str, path: &str, method: &str, status: &str);
+
+    /// Record every request to a deprecated endpoint, also those not counted
+    /// by `record_request` within a client's session.
+    fn record_raw_request(&self, endpoint_id: &str, path: &str, method: &str, status: &str);
+
+    /// Record a redirect from a deprecated endpoint.
+    fn record_redirect(&self, endpoint_id: &str, from_path: &str, to_path: &str);
+
+    /// Record a blocked request.
+    fn record_blocked(&self, endpoint_id: &str, path: &str, reason: &str);
+
+    /// Update the days until sunset gauge.
+    fn set_days_until_sunset(&self, endpoint_id: &str, path: &str, days: i64);
+
+    /// Clear the days until sunset gauge for all endpoints.
+    fn clear_days_until_sunset(&self);
+
+    /// Record a reload moving an endpoint's sunset date later or withdrawing it.
+    fn record_sunset_extension(&self, endpoint_id: &str);
+
+    /// Record an endpoint's request rate spiking above its moving average.
+    fn record_traffic_spike(&self, endpoint_id: &str);
+
+    /// Set the severity series of an endpoint.
+    fn set_endpoint_severity(&self, endpoint_id: &str, severity: Severity);
+
+    /// Clear the severity series of all endpoints.
+    fn clear_endpoint_severity(&self);
+
+    /// Record an endpoint whose path pattern could not be compiled.
+    fn record_matcher_error(&self, endpoint_id: &str);
+
+    /// Record whether an endpoint's absolute replacement URL responded.
+    fn set_replacement_reachable(&self, endpoint_id: &str, reachable: bool);
+
+    /// Update the usage totals summed across all replicas.
+    fn set_cluster_totals(&self, endpoint_id: &str, totals: &ClusterTotals);
+
+    /// Record an upstream error (5xx) response from a deprecated endpoint.
+    fn record_error(&self, endpoint_id: &str);
+
+    /// Update the upstream error ratio over the last refresh interval.
+    fn set_error_ratio(&self, endpoint_id: &str, ratio: f64);
+
+    /// Update the 7-day moving average of an endpoint's daily requests.
+    fn set_usage_daily_average(&self, endpoint_id: &str, average: f64);
+
+    /// Record an endpoint's last day of requests spiking above its average.
+    fn record_usage_spike(&self, endpoint_id: &str);
+
+    /// Update the distinct consumers of an endpoint today and yesterday.
+    fn set_unique_consumers(&self, endpoint_id: &str, consumers: &UniqueConsumers);
+
+    /// Update the number of rules that could block traffic but aren't confirmed.
+    fn set_unconfirmed_rules(&self, count: usize);
+
+    /// Record a request to a deprecated endpoint allowed through, by reason
+    /// (see `AllowReason`).
+    fn record_allowed(&self, endpoint_id: &str, reason: &str);
+
+    /// Record an endpoint whose headers were trimmed to
+    /// `settings.max_added_header_bytes` when its configuration was loaded.
+    fn record_header_truncation(&self, endpoint_id: &str);
+
+    /// Record the hash and load time of the active configuration.
+    fn set_config_info(&self, hash: &str, loaded_at: DateTime<Utc>);
+
+    /// Record a request using a deprecated query parameter.
+    fn record_deprecated_param(&self, endpoint_id: &str, param: &str, action: &str);
+
+    /// Record a request sending a deprecated request header.
+    fn record_deprecated_header(&self, header: &str, action: &str);
+
+    /// Record a request whose body contains a deprecated field.
+    fn record_deprecated_body_field(&self, endpoint_id: &str, pointer: &str, action: &str);
+
+    /// Record a request to a tombstoned endpoint by caller.
+    fn record_tombstone_hit(&self, endpoint_id: &str, method: &str, path: &str, client: &str);
+
+    /// Record a request allowed unprocessed because processing panicked.
+    fn record_internal_error(&self);
+
+    /// Record request duration.
+    fn observe_duration(&self, endpoint_id: &str, duration_secs: f64);
+
+    /// Encode metrics in Prometheus text format (empty if not supported).
+    fn encode(&self) -> String {
+        String::new()
+    }
+
+    /// Encode only the series selected by `filter` (everything, for sinks
+    /// that can't filter).
+    fn encode_filtered(&self, filter: &MetricsFilter) -> String {
+        let _ = filter;
+        self.encode()
+    }
+}
+
+/// Series selected for a partial scrape, parsed from the metrics server's
+/// query string (`/metrics?endpoint_id=users-v1&prefix=/api/v1`).
+///
+/// Once any filter is set, series without the filtered label (such as the
+/// agent-wide `internal_errors_total`) are left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsFilter {
+    /// Keep series whose `endpoint_id` label is one of these (any when empty);
+    /// `endpoint_id` may be repeated
+    pub endpoint_ids: Vec<String>,
+    /// Keep series whose `path` or `from_path` label starts with this
+    pub path_prefix: Option<String>,
+}
+
+impl MetricsFilter {
+    /// Parse the `endpoint_id` and `prefix` parameters of a query string;
+    /// other parameters are ignored.
+    pub fn from_query(query: &str) -> Self {
+        let mut filter = Self::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode_query_value(value);
+            match name {
+                "endpoint_id" if !value.is_empty() => filter.endpoint_ids.push(value),
+                "prefix" if !value.is_empty() => filter.path_prefix = Some(value),
+                _ => {}
+            }
+        }
+        filter
+    }
+
+    /// Whether the filter selects every series.
+    pub fn is_empty(&self) -> bool {
+        self.endpoint_ids.is_empty() && self.path_prefix.is_none()
+    }
+
+    /// Whether a series with these labels is selected.
+    pub fn matches<'a>(&self, labels: impl IntoIterator<Item = (&'a str, &'a str)>) -> bool {
+        let mut endpoint_matches = self.endpoint_ids.is_empty();
+        let mut path_matches = self.path_prefix.is_none();
+        for (name, value) in labels {
+            match name {
+                "endpoint_id" => endpoint_matches |= self.endpoint_ids.iter().any(|id| id == value),
+                "path" | "from_path" => {
+                    path_matches |= self
+                        .path_prefix
+                        .as_deref()
+                        .is_some_and(|prefix| value.starts_with(prefix))
+                }
+                _ => {}
+            }
+        }
+        endpoint_matches && path_matches
+    }
+}
+
+/// Decode a percent-encoded query value (`+` is a space); malformed escapes
+/// are kept as they are.
+fn decode_query_value(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match hex {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Error creating the Prometheus metrics collector.
+#[derive(Debug, thiserror::Error)]
+pub enum MetricsError {
+    /// `metrics.prefix` can't start a Prometheus metric name
+    #[error(
+        "invalid metrics prefix '{prefix}': must start with a letter, '_' or ':' \
+         followed by letters, digits, '_' or ':'"
+    )]
+    InvalidPrefix { prefix: String },
+
+    /// A metric couldn't be created or registered
+    #[cfg(feature = "prometheus")]
+    #[error("failed to register metric '{metric}': {source}")]
+    Registration {
+        metric: String,
+        #[source]
+        source: prometheus::Error,
+    },
+}
+
+impl MetricsError {
+    #[cfg(feature = "prometheus")]
+    fn registration(metric: impl Into<String>, source: prometheus::Error) -> Self {
+        Self::Registration {
+            metric: metric.into(),
+            source,
+        }
+    }
+}
+
+/// Check that `prefix` is a valid Prometheus metric name
+/// (`[a-zA-Z_:][a-zA-Z0-9_:]*`), so names built from it are too.
+pub fn validate_prefix(prefix: &str) -> Result<(), MetricsError> {
+    let mut chars = prefix.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+    if valid {
+        Ok(())
+    } else {
+        Err(MetricsError::InvalidPrefix {
+            prefix: prefix.to_string(),
+        })
+    }
+}
+
+/// Metrics sink that discards everything.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopMetrics;
+
+impl MetricsSink for NoopMetrics {
+    fn record_request(&self, _endpoint_id: &str, _path: &str, _method: &str, _status: &str) {}
+
+    fn record_raw_request(&self, _endpoint_id: &str, _path: &str, _method: &str, _status: &str) {}
+
+    fn record_redirect(&self, _endpoint_id: &str, _from_path: &str, _to_path: &str) {}
+
+    fn record_blocked(&self, _endpoint_id: &str, _path: &str, _reason: &str) {}
+
+    fn set_days_until_sunset(&self, _endpoint_id: &str, _path: &str, _days: i64) {}
+
+    fn clear_days_until_sunset(&self) {}
+
+    fn record_sunset_extension(&self, _endpoint_id: &str) {}
+
+    fn record_traffic_spike(&self, _endpoint_id: &str) {}
+
+    fn set_endpoint_severity(&self, _endpoint_id: &str, _severity: Severity) {}
+
+    fn clear_endpoint_severity(&self) {}
+
+    fn record_matcher_error(&self, _endpoint_id: &str) {}
+
+    fn set_replacement_reachable(&self, _endpoint_id: &str, _reachable: bool) {}
+
+    fn set_cluster_totals(&self, _endpoint_id: &str, _totals: &ClusterTotals) {}
+
+    fn record_error(&self, _endpoint_id: &str) {}
+
+    fn set_error_ratio(&self, _endpoint_id: &str, _ratio: f64) {}
+
+    fn set_usage_daily_average(&self, _endpoint_id: &str, _average: f64) {}
+
+    fn record_usage_spike(&self, _endpoint_id: &str) {}
+
+    fn set_unique_consumers(&self, _endpoint_id: &str, _consumers: &UniqueConsumers) {}
+
+    fn set_unconfirmed_rules(&self, _count: usize) {}
+
+    fn record_allowed(&self, _endpoint_id: &str, _reason: &str) {}
+
+    fn record_header_truncation(&self, _endpoint_id: &str) {}
+
+    fn set_config_info(&self, _hash: &str, _loaded_at: DateTime<Utc>) {}
+
+    fn record_deprecated_param(&self, _endpoint_id: &str, _param: &str, _action: &str) {}
+
+    fn record_deprecated_header(&self, _header: &str, _action: &str) {}
+
+    fn record_deprecated_body_field(&self, _endpoint_id: &str, _pointer: &str, _action: &str) {}
+
+    fn record_tombstone_hit(&self, _endpoint_id: &str, _method: &str, _path: &str, _client: &str) {}
+
+    fn record_internal_error(&self) {}
+
+    fn observe_duration(&self, _endpoint_id: &str, _duration_secs: f64) {}
+}
+
+/// Prometheus metrics collector for deprecated API usage.
+#[cfg(feature = "prometheus")]
 #[derive(Clone)]
 pub struct DeprecationMetrics {
     /// Registry for all metrics
@@ -13,6 +319,10 @@ pub struct DeprecationMetrics {
     /// Counter for deprecated endpoint requests
     pub requests_total: IntCounterVec,
 
+    /// Counter for every deprecated endpoint request, with
+    /// `metrics.session_window_secs` set
+    pub requests_raw_total: IntCounterVec,
+
     /// Counter for redirects performed
     pub redirects_total: IntCounterVec,
 
@@ -22,13 +332,88 @@ pub struct DeprecationMetrics {
     /// Gauge for days until sunset for each endpoint
     pub days_until_sunset: IntGaugeVec,
 
+    /// Always 1, labelled with each endpoint's severity (join on `endpoint_id`)
+    pub endpoint_severity: IntGaugeVec,
+
+    /// Counter for reloads moving an endpoint's sunset date later
+    pub sunset_extensions_total: IntCounterVec,
+
+    /// Counter for request rate spikes on deprecated endpoints
+    pub traffic_spikes_total: IntCounterVec,
+
     /// Histogram for request latency by deprecated endpoint
     pub request_duration_seconds: HistogramVec,
+
+    /// Counter for rules loaded with a path pattern that failed to compile
+    pub matcher_errors_total: IntCounterVec,
+
+    /// Gauge for whether each absolute replacement URL is reachable (1/0)
+    pub replacement_reachable: IntGaugeVec,
+
+    /// Gauge for requests summed across all replicas (shared store)
+    pub requests_cluster_total: IntGaugeVec,
+
+    /// Gauge for redirects summed across all replicas (shared store)
+    pub redirects_cluster_total: IntGaugeVec,
+
+    /// Gauge for blocked requests summed across all replicas (shared store)
+    pub blocked_cluster_total: IntGaugeVec,
+
+    /// Counter for upstream 5xx responses from deprecated endpoints
+    pub errors_total: IntCounterVec,
+
+    /// Gauge for the upstream error ratio over the last refresh interval
+    pub error_ratio: GaugeVec,
+
+    /// Gauge for the 7-day moving average of daily requests
+    pub usage_daily_average: GaugeVec,
+
+    /// Counter for days of requests spiking above the moving average
+    pub usage_spike_detected_total: IntCounterVec,
+
+    /// Gauge for the distinct identified clients since midnight UTC
+    pub unique_consumers_today: IntGaugeVec,
+
+    /// Gauge for the distinct identified clients over the previous UTC day
+    pub unique_consumers_yesterday: IntGaugeVec,
+
+    /// Gauge for rules that could block traffic but run in shadow mode
+    /// until confirmed
+    pub unconfirmed_rules: IntGauge,
+
+    /// Always 1, labelled with the active configuration's hash and load time
+    pub config_info: IntGaugeVec,
+
+    /// Counter for requests to deprecated endpoints allowed through, by reason
+    pub allowed_total: IntCounterVec,
+
+    /// Counter for loads trimming an endpoint's headers to the byte budget
+    pub header_truncations_total: IntCounterVec,
+
+    /// Counter for requests using deprecated query parameters
+    pub deprecated_params_total: IntCounterVec,
+
+    /// Counter for requests sending deprecated request headers
+    pub deprecated_headers_total: IntCounterVec,
+
+    /// Counter for requests whose body contains deprecated fields
+    pub deprecated_body_fields_total: IntCounterVec,
+
+    /// Counter for requests to tombstoned endpoints, by caller
+    pub tombstone_hits_total: IntCounterVec,
+
+    /// Counter for requests allowed unprocessed because processing panicked
+    pub internal_errors_total: IntCounter,
 }
 
+#[cfg(feature = "prometheus")]
 impl DeprecationMetrics {
     /// Create a new metrics collector with the given prefix.
-    pub fn new(prefix: &str) -> Self {
+    ///
+    /// Fails on a prefix that isn't a valid Prometheus metric name (see
+    /// [`validate_prefix`]) or if a metric can't be registered.
+    pub fn new(prefix: &str) -> Result<Self, MetricsError> {
+        validate_prefix(prefix)?;
         let registry = Registry::new();
 
         let requests_total = IntCounterVec::new(
@@ -38,7 +423,16 @@ impl DeprecationMetrics {
             ),
             &["endpoint_id", "path", "method", "status"],
         )
-        .expect("Failed to create requests_total metric");
+        .map_err(|source| MetricsError::registration("requests_total", source))?;
+
+        let requests_raw_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_requests_raw_total", prefix),
+                "Total number of requests to deprecated endpoints, including repeats within a client session",
+            ),
+            &["endpoint_id", "path", "method", "status"],
+        )
+        .map_err(|source| MetricsError::registration("requests_raw_total", source))?;
 
         let redirects_total = IntCounterVec::new(
             Opts::new(
@@ -47,7 +441,7 @@ impl DeprecationMetrics {
             ),
             &["endpoint_id", "from_path", "to_path"],
         )
-        .expect("Failed to create redirects_total metric");
+        .map_err(|source| MetricsError::registration("redirects_total", source))?;
 
         let blocked_total = IntCounterVec::new(
             Opts::new(
@@ -56,7 +450,7 @@ impl DeprecationMetrics {
             ),
             &["endpoint_id", "path", "reason"],
         )
-        .expect("Failed to create blocked_total metric");
+        .map_err(|source| MetricsError::registration("blocked_total", source))?;
 
         let days_until_sunset = IntGaugeVec::new(
             Opts::new(
@@ -65,7 +459,34 @@ impl DeprecationMetrics {
             ),
             &["endpoint_id", "path"],
         )
-        .expect("Failed to create days_until_sunset metric");
+        .map_err(|source| MetricsError::registration("days_until_sunset", source))?;
+
+        let endpoint_severity = IntGaugeVec::new(
+            Opts::new(
+                format!("{}_endpoint_severity", prefix),
+                "Severity of each deprecated endpoint (always 1)",
+            ),
+            &["endpoint_id", "severity"],
+        )
+        .map_err(|source| MetricsError::registration("endpoint_severity", source))?;
+
+        let sunset_extensions_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_sunset_extensions_total", prefix),
+                "Total number of reloads moving an endpoint's sunset date later or withdrawing it",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("sunset_extensions_total", source))?;
+
+        let traffic_spikes_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_traffic_spikes_total", prefix),
+                "Total number of request rate spikes above an endpoint's spike threshold",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("traffic_spikes_total", source))?;
 
         let request_duration_seconds = HistogramVec::new(
             prometheus::HistogramOpts::new(
@@ -75,89 +496,527 @@ impl DeprecationMetrics {
             .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
             &["endpoint_id"],
         )
-        .expect("Failed to create request_duration_seconds metric");
+        .map_err(|source| MetricsError::registration("request_duration_seconds", source))?;
+
+        let matcher_errors_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_matcher_errors_total", prefix),
+                "Rules loaded with a path pattern that failed to compile (never match)",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("matcher_errors_total", source))?;
+
+        let replacement_reachable = IntGaugeVec::new(
+            Opts::new(
+                format!("{}_replacement_reachable", prefix),
+                "Whether the endpoint's absolute replacement URL responded (1) or not (0)",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("replacement_reachable", source))?;
+
+        let cluster_gauge = |name: &str, help: &str| {
+            IntGaugeVec::new(
+                Opts::new(format!("{}_{}_cluster_total", prefix, name), help),
+                &["endpoint_id"],
+            )
+            .map_err(|source| MetricsError::registration(format!("{}_cluster_total", name), source))
+        };
+        let requests_cluster_total = cluster_gauge(
+            "requests",
+            "Requests to deprecated endpoints summed across all replicas",
+        )?;
+        let redirects_cluster_total =
+            cluster_gauge("redirects", "Redirects summed across all replicas")?;
+        let blocked_cluster_total =
+            cluster_gauge("blocked", "Blocked requests summed across all replicas")?;
+
+        let errors_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_errors_total", prefix),
+                "Upstream 5xx responses from deprecated endpoints",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("errors_total", source))?;
+
+        let error_ratio = GaugeVec::new(
+            Opts::new(
+                format!("{}_error_ratio", prefix),
+                "Share of upstream 5xx responses over the last refresh interval",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("error_ratio", source))?;
+
+        let usage_daily_average = GaugeVec::new(
+            Opts::new(
+                format!("{}_usage_daily_average", prefix),
+                "Average requests per day over the 7 days before the last 24 hours",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("usage_daily_average", source))?;
+
+        let usage_spike_detected_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_usage_spike_detected_total", prefix),
+                "Total number of times the last 24 hours of requests exceeded an endpoint's spike threshold times the daily average",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("usage_spike_detected_total", source))?;
+
+        let unique_consumers_today = IntGaugeVec::new(
+            Opts::new(
+                format!("{}_unique_consumers_today", prefix),
+                "Estimated distinct identified clients since midnight UTC",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("unique_consumers_today", source))?;
+
+        let unique_consumers_yesterday = IntGaugeVec::new(
+            Opts::new(
+                format!("{}_unique_consumers_yesterday", prefix),
+                "Estimated distinct identified clients over the previous UTC day",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("unique_consumers_yesterday", source))?;
+
+        let unconfirmed_rules = IntGauge::new(
+            format!("{}_unconfirmed_rules", prefix),
+            "Rules that could block traffic but only warn until confirmed",
+        )
+        .map_err(|source| MetricsError::registration("unconfirmed_rules", source))?;
+
+        let config_info = IntGaugeVec::new(
+            Opts::new(
+                format!("{}_config_info", prefix),
+                "Active configuration (always 1), by hash and load time",
+            ),
+            &["hash", "loaded_at"],
+        )
+        .map_err(|source| MetricsError::registration("config_info", source))?;
+
+        let allowed_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_allowed_total", prefix),
+                "Total number of requests to deprecated endpoints allowed through, by reason",
+            ),
+            &["endpoint_id", "reason"],
+        )
+        .map_err(|source| MetricsError::registration("allowed_total", source))?;
+
+        let header_truncations_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_header_truncations_total", prefix),
+                "Total number of loads dropping headers over max_added_header_bytes",
+            ),
+            &["endpoint_id"],
+        )
+        .map_err(|source| MetricsError::registration("header_truncations_total", source))?;
+
+        let deprecated_params_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_deprecated_params_total", prefix),
+                "Total number of requests using deprecated query parameters",
+            ),
+            &["endpoint_id", "param", "action"],
+        )
+        .map_err(|source| MetricsError::registration("deprecated_params_total", source))?;
+
+        let deprecated_headers_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_deprecated_headers_total", prefix),
+                "Total number of requests sending deprecated request headers",
+            ),
+            &["header", "action"],
+        )
+        .map_err(|source| MetricsError::registration("deprecated_headers_total", source))?;
+
+        let deprecated_body_fields_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_deprecated_body_fields_total", prefix),
+                "Total number of requests whose body contains deprecated fields",
+            ),
+            &["endpoint_id", "pointer", "action"],
+        )
+        .map_err(|source| MetricsError::registration("deprecated_body_fields_total", source))?;
+
+        let tombstone_hits_total = IntCounterVec::new(
+            Opts::new(
+                format!("{}_tombstone_hits_total", prefix),
+                "Total number of requests to tombstoned endpoints, by caller",
+            ),
+            &["endpoint_id", "method", "path", "client"],
+        )
+        .map_err(|source| MetricsError::registration("tombstone_hits_total", source))?;
+
+        let internal_errors_total = IntCounter::new(
+            format!("{}_internal_errors_total", prefix),
+            "Total number of requests allowed unprocessed because processing panicked",
+        )
+        .map_err(|source| MetricsError::registration("internal_errors_total", source))?;
 
         // Register all metrics
         registry
             .register(Box::new(requests_total.clone()))
-            .expect("Failed to register requests_total");
+            .map_err(|source| MetricsError::registration("requests_total", source))?;
+        registry
+            .register(Box::new(requests_raw_total.clone()))
+            .map_err(|source| MetricsError::registration("requests_raw_total", source))?;
         registry
             .register(Box::new(redirects_total.clone()))
-            .expect("Failed to register redirects_total");
+            .map_err(|source| MetricsError::registration("redirects_total", source))?;
         registry
             .register(Box::new(blocked_total.clone()))
-            .expect("Failed to register blocked_total");
+            .map_err(|source| MetricsError::registration("blocked_total", source))?;
         registry
             .register(Box::new(days_until_sunset.clone()))
-            .expect("Failed to register days_until_sunset");
+            .map_err(|source| MetricsError::registration("days_until_sunset", source))?;
+        registry
+            .register(Box::new(endpoint_severity.clone()))
+            .map_err(|source| MetricsError::registration("endpoint_severity", source))?;
+        registry
+            .register(Box::new(sunset_extensions_total.clone()))
+            .map_err(|source| MetricsError::registration("sunset_extensions_total", source))?;
+        registry
+            .register(Box::new(traffic_spikes_total.clone()))
+            .map_err(|source| MetricsError::registration("traffic_spikes_total", source))?;
         registry
             .register(Box::new(request_duration_seconds.clone()))
-            .expect("Failed to register request_duration_seconds");
+            .map_err(|source| MetricsError::registration("request_duration_seconds", source))?;
+        registry
+            .register(Box::new(matcher_errors_total.clone()))
+            .map_err(|source| MetricsError::registration("matcher_errors_total", source))?;
+        registry
+            .register(Box::new(replacement_reachable.clone()))
+            .map_err(|source| MetricsError::registration("replacement_reachable", source))?;
+        for (name, gauge) in [
+            ("requests", &requests_cluster_total),
+            ("redirects", &redirects_cluster_total),
+            ("blocked", &blocked_cluster_total),
+        ] {
+            registry
+                .register(Box::new(gauge.clone()))
+                .map_err(|source| {
+                    MetricsError::registration(format!("{}_cluster_total", name), source)
+                })?;
+        }
+        registry
+            .register(Box::new(errors_total.clone()))
+            .map_err(|source| MetricsError::registration("errors_total", source))?;
+        registry
+            .register(Box::new(error_ratio.clone()))
+            .map_err(|source| MetricsError::registration("error_ratio", source))?;
+        registry
+            .register(Box::new(usage_daily_average.clone()))
+            .map_err(|source| MetricsError::registration("usage_daily_average", source))?;
+        registry
+            .register(Box::new(usage_spike_detected_total.clone()))
+            .map_err(|source| MetricsError::registration("usage_spike_detected_total", source))?;
+        registry
+            .register(Box::new(unique_consumers_today.clone()))
+            .map_err(|source| MetricsError::registration("unique_consumers_today", source))?;
+        registry
+            .register(Box::new(unique_consumers_yesterday.clone()))
+            .map_err(|source| MetricsError::registration("unique_consumers_yesterday", source))?;
+        registry
+            .register(Box::new(unconfirmed_rules.clone()))
+            .map_err(|source| MetricsError::registration("unconfirmed_rules", source))?;
+        registry
+            .register(Box::new(config_info.clone()))
+            .map_err(|source| MetricsError::registration("config_info", source))?;
+        registry
+            .register(Box::new(allowed_total.clone()))
+            .map_err(|source| MetricsError::registration("allowed_total", source))?;
+        registry
+            .register(Box::new(header_truncations_total.clone()))
+            .map_err(|source| MetricsError::registration("header_truncations_total", source))?;
+        registry
+            .register(Box::new(deprecated_params_total.clone()))
+            .map_err(|source| MetricsError::registration("deprecated_params_total", source))?;
+        registry
+            .register(Box::new(deprecated_headers_total.clone()))
+            .map_err(|source| MetricsError::registration("deprecated_headers_total", source))?;
+        registry
+            .register(Box::new(deprecated_body_fields_total.clone()))
+            .map_err(|source| MetricsError::registration("deprecated_body_fields_total", source))?;
+        registry
+            .register(Box::new(tombstone_hits_total.clone()))
+            .map_err(|source| MetricsError::registration("tombstone_hits_total", source))?;
+        registry
+            .register(Box::new(internal_errors_total.clone()))
+            .map_err(|source| MetricsError::registration("internal_errors_total", source))?;
 
-        Self {
+        Ok(Self {
             registry,
             requests_total,
+            requests_raw_total,
             redirects_total,
             blocked_total,
             days_until_sunset,
+            endpoint_severity,
+            sunset_extensions_total,
+            traffic_spikes_total,
             request_duration_seconds,
-        }
+            matcher_errors_total,
+            replacement_reachable,
+            requests_cluster_total,
+            redirects_cluster_total,
+            blocked_cluster_total,
+            errors_total,
+            error_ratio,
+            usage_daily_average,
+            usage_spike_detected_total,
+            unique_consumers_today,
+            unique_consumers_yesterday,
+            unconfirmed_rules,
+            config_info,
+            allowed_total,
+            header_truncations_total,
+            deprecated_params_total,
+            deprecated_headers_total,
+            deprecated_body_fields_total,
+            tombstone_hits_total,
+            internal_errors_total,
+        })
     }
 
-    /// Record a request to a deprecated endpoint.
-    pub fn record_request(&self, endpoint_id: &str, path: &str, method: &str, status: &str) {
+    /// Get the Prometheus registry.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl MetricsSink for DeprecationMetrics {
+    fn record_request(&self, endpoint_id: &str, path: &str, method: &str, status: &str) {
         self.requests_total
             .with_label_values(&[endpoint_id, path, method, status])
             .inc();
     }
 
-    /// Record a redirect from a deprecated endpoint.
-    pub fn record_redirect(&self, endpoint_id: &str, from_path: &str, to_path: &str) {
+    fn record_raw_request(&self, endpoint_id: &str, path: &str, method: &str, status: &str) {
+        self.requests_raw_total
+            .with_label_values(&[endpoint_id, path, method, status])
+            .inc();
+    }
+
+    fn record_redirect(&self, endpoint_id: &str, from_path: &str, to_path: &str) {
         self.redirects_total
             .with_label_values(&[endpoint_id, from_path, to_path])
             .inc();
     }
 
-    /// Record a blocked request.
-    pub fn record_blocked(&self, endpoint_id: &str, path: &str, reason: &str) {
+    fn record_blocked(&self, endpoint_id: &str, path: &str, reason: &str) {
         self.blocked_total
             .with_label_values(&[endpoint_id, path, reason])
             .inc();
     }
 
-    /// Update the days until sunset gauge.
-    pub fn set_days_until_sunset(&self, endpoint_id: &str, path: &str, days: i64) {
+    fn set_days_until_sunset(&self, endpoint_id: &str, path: &str, days: i64) {
         self.days_until_sunset
             .with_label_values(&[endpoint_id, path])
             .set(days);
     }
 
-    /// Record request duration.
-    pub fn observe_duration(&self, endpoint_id: &str, duration_secs: f64) {
+    fn clear_days_until_sunset(&self) {
+        self.days_until_sunset.reset();
+    }
+
+    fn record_sunset_extension(&self, endpoint_id: &str) {
+        self.sunset_extensions_total
+            .with_label_values(&[endpoint_id])
+            .inc();
+    }
+
+    fn record_traffic_spike(&self, endpoint_id: &str) {
+        self.traffic_spikes_total
+            .with_label_values(&[endpoint_id])
+            .inc();
+    }
+
+    fn set_endpoint_severity(&self, endpoint_id: &str, severity: Severity) {
+        self.endpoint_severity
+            .with_label_values(&[endpoint_id, severity.as_str()])
+            .set(1);
+    }
+
+    fn clear_endpoint_severity(&self) {
+        self.endpoint_severity.reset();
+    }
+
+    fn record_matcher_error(&self, endpoint_id: &str) {
+        self.matcher_errors_total
+            .with_label_values(&[endpoint_id])
+            .inc();
+    }
+
+    fn set_replacement_reachable(&self, endpoint_id: &str, reachable: bool) {
+        self.replacement_reachable
+            .with_label_values(&[endpoint_id])
+            .set(i64::from(reachable));
+    }
+
+    fn set_cluster_totals(&self, endpoint_id: &str, totals: &ClusterTotals) {
+        self.requests_cluster_total
+            .with_label_values(&[endpoint_id])
+            .set(totals.requests as i64);
+        self.redirects_cluster_total
+            .with_label_values(&[endpoint_id])
+            .set(totals.redirects as i64);
+        self.blocked_cluster_total
+            .with_label_values(&[endpoint_id])
+            .set(totals.blocked as i64);
+    }
+
+    fn record_error(&self, endpoint_id: &str) {
+        self.errors_total.with_label_values(&[endpoint_id]).inc();
+    }
+
+    fn set_error_ratio(&self, endpoint_id: &str, ratio: f64) {
+        self.error_ratio
+            .with_label_values(&[endpoint_id])
+            .set(ratio);
+    }
+
+    fn set_usage_daily_average(&self, endpoint_id: &str, average: f64) {
+        self.usage_daily_average
+            .with_label_values(&[endpoint_id])
+            .set(average);
+    }
+
+    fn record_usage_spike(&self, endpoint_id: &str) {
+        self.usage_spike_detected_total
+            .with_label_values(&[endpoint_id])
+            .inc();
+    }
+
+    fn set_unique_consumers(&self, endpoint_id: &str, consumers: &UniqueConsumers) {
+        self.unique_consumers_today
+            .with_label_values(&[endpoint_id])
+            .set(consumers.today as i64);
+        if let Some(yesterday) = consumers.yesterday {
+            self.unique_consumers_yesterday
+                .with_label_values(&[endpoint_id])
+                .set(yesterday as i64);
+        }
+    }
+
+    fn set_unconfirmed_rules(&self, count: usize) {
+        self.unconfirmed_rules.set(count as i64);
+    }
+
+    fn record_allowed(&self, endpoint_id: &str, reason: &str) {
+        self.allowed_total
+            .with_label_values(&[endpoint_id, reason])
+            .inc();
+    }
+
+    fn record_header_truncation(&self, endpoint_id: &str) {
+        self.header_truncations_total
+            .with_label_values(&[endpoint_id])
+            .inc();
+    }
+
+    fn set_config_info(&self, hash: &str, loaded_at: DateTime<Utc>) {
+        self.config_info.reset();
+        self.config_info
+            .with_label_values(&[
+                hash,
+                &loaded_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            ])
+            .set(1);
+    }
+
+    fn record_deprecated_param(&self, endpoint_id: &str, param: &str, action: &str) {
+        self.deprecated_params_total
+            .with_label_values(&[endpoint_id, param, action])
+            .inc();
+    }
+
+    fn record_deprecated_header(&self, header: &str, action: &str) {
+        self.deprecated_headers_total
+            .with_label_values(&[header, action])
+            .inc();
+    }
+
+    fn record_deprecated_body_field(&self, endpoint_id: &str, pointer: &str, action: &str) {
+        self.deprecated_body_fields_total
+            .with_label_values(&[endpoint_id, pointer, action])
+            .inc();
+    }
+
+    fn record_tombstone_hit(&self, endpoint_id: &str, method: &str, path: &str, client: &str) {
+        self.tombstone_hits_total
+            .with_label_values(&[endpoint_id, method, path, client])
+            .inc();
+    }
+
+    fn record_internal_error(&self) {
+        self.internal_errors_total.inc();
+    }
+
+    fn observe_duration(&self, endpoint_id: &str, duration_secs: f64) {
         self.request_duration_seconds
             .with_label_values(&[endpoint_id])
             .observe(duration_secs);
     }
 
-    /// Get the Prometheus registry.
-    pub fn registry(&self) -> &Registry {
-        &self.registry
+    fn encode(&self) -> String {
+        encode_families(&self.registry.gather())
     }
 
-    /// Encode metrics in Prometheus text format.
-    pub fn encode(&self) -> String {
-        use prometheus::Encoder;
-        let encoder = prometheus::TextEncoder::new();
-        let metric_families = self.registry.gather();
-        let mut buffer = Vec::new();
-        encoder.encode(&metric_families, &mut buffer).unwrap();
-        String::from_utf8(buffer).unwrap()
+    fn encode_filtered(&self, filter: &MetricsFilter) -> String {
+        if filter.is_empty() {
+            return self.encode();
+        }
+
+        // Filter the gathered families, dropping those left without series
+        let families: Vec<_> = self
+            .registry
+            .gather()
+            .into_iter()
+            .filter_map(|mut family| {
+                let metrics: Vec<_> = family
+                    .take_metric()
+                    .into_iter()
+                    .filter(|metric| {
+                        filter.matches(
+                            metric
+                                .get_label()
+                                .iter()
+                                .map(|label| (label.get_name(), label.get_value())),
+                        )
+                    })
+                    .collect();
+                (!metrics.is_empty()).then(|| {
+                    family.set_metric(metrics.into());
+                    family
+                })
+            })
+            .collect();
+        encode_families(&families)
     }
 }
 
+#[cfg(feature = "prometheus")]
+fn encode_families(families: &[prometheus::proto::MetricFamily]) -> String {
+    use prometheus::Encoder;
+    let encoder = prometheus::TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(families, &mut buffer).unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+#[cfg(feature = "prometheus")]
 impl Default for DeprecationMetrics {
     fn default() -> Self {
-        Self::new("zentinel_api_deprecation")
+        Self::new("zentinel_api_deprecation").expect("the default prefix is valid")
     }
 }
 
@@ -166,16 +1025,59 @@ mod tests {
     use super::*;
 
     #[test]
+    fn test_noop_metrics() {
+        let metrics = NoopMetrics;
+        metrics.record_request("legacy-api", "/api/v1/users", "GET", "deprecated");
+        metrics.set_days_until_sunset("legacy-api", "/api/v1/users", 30);
+        assert!(metrics.encode().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
     fn test_metrics_creation() {
-        let metrics = DeprecationMetrics::new("test");
+        let metrics = DeprecationMetrics::new("test").unwrap();
         // Record a value to initialize the metric
         metrics.record_request("test-endpoint", "/test", "GET", "deprecated");
         assert!(!metrics.encode().is_empty());
     }
 
     #[test]
+    fn test_validate_prefix() {
+        assert!(validate_prefix("zentinel_api_deprecation").is_ok());
+        assert!(validate_prefix("_edge:v2").is_ok());
+        for prefix in [
+            "",
+            "2fast",
+            "api-deprecation",
+            "api deprecation",
+            "api.deprecation",
+        ] {
+            assert!(
+                matches!(
+                    validate_prefix(prefix),
+                    Err(MetricsError::InvalidPrefix { .. })
+                ),
+                "{}",
+                prefix
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
+    fn test_invalid_prefix_fails_gracefully() {
+        let err = DeprecationMetrics::new("api-deprecation").err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid metrics prefix 'api-deprecation': must start with a letter, '_' or ':' \
+             followed by letters, digits, '_' or ':'"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
     fn test_record_request() {
-        let metrics = DeprecationMetrics::new("test");
+        let metrics = DeprecationMetrics::new("test").unwrap();
         metrics.record_request("legacy-api", "/api/v1/users", "GET", "deprecated");
 
         let output = metrics.encode();
@@ -184,8 +1086,9 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "prometheus")]
     fn test_record_redirect() {
-        let metrics = DeprecationMetrics::new("test");
+        let metrics = DeprecationMetrics::new("test").unwrap();
         metrics.record_redirect("legacy-api", "/api/v1/users", "/api/v2/users");
 
         let output = metrics.encode();
@@ -193,12 +1096,56 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "prometheus")]
     fn test_days_until_sunset() {
-        let metrics = DeprecationMetrics::new("test");
+        let metrics = DeprecationMetrics::new("test").unwrap();
         metrics.set_days_until_sunset("legacy-api", "/api/v1/users", 30);
 
         let output = metrics.encode();
         assert!(output.contains("test_days_until_sunset"));
         assert!(output.contains("30"));
     }
+
+    #[test]
+    fn test_metrics_filter_from_query() {
+        let filter = MetricsFilter::from_query(
+            "endpoint_id=users-v1&endpoint_id=orders&prefix=%2Fapi%2Fv1&x=1",
+        );
+        assert_eq!(filter.endpoint_ids, ["users-v1", "orders"]);
+        assert_eq!(filter.path_prefix.as_deref(), Some("/api/v1"));
+        assert!(MetricsFilter::from_query("").is_empty());
+        assert!(MetricsFilter::from_query("endpoint_id=&debug").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "prometheus")]
+    fn test_encode_filtered() {
+        let metrics = DeprecationMetrics::new("test").unwrap();
+        metrics.record_request("users-v1", "/api/v1/users", "GET", "deprecated");
+        metrics.record_request("orders-v1", "/api/v1/orders", "GET", "deprecated");
+        metrics.set_days_until_sunset("users-v1", "/api/v1/users", 30);
+        metrics.set_days_until_sunset("legacy", "/legacy/users", 10);
+        metrics.record_internal_error();
+
+        let output = metrics.encode_filtered(&MetricsFilter::from_query("endpoint_id=users-v1"));
+        let series: Vec<_> = output
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect();
+        assert_eq!(series.len(), 2, "{}", output);
+        assert!(series
+            .iter()
+            .all(|line| line.contains(r#"endpoint_id="users-v1""#)));
+        assert!(!output.contains("test_internal_errors_total"));
+
+        let output = metrics.encode_filtered(&MetricsFilter::from_query("prefix=/api/v1"));
+        assert!(output.contains("orders-v1"));
+        assert!(output.contains("users-v1"));
+        assert!(!output.contains("legacy"));
+
+        assert_eq!(
+            metrics.encode_filtered(&MetricsFilter::default()),
+            metrics.encode()
+        );
+    }
 }
@@ -0,0 +1,99 @@
+//! systemd service notifications (`sd_notify`).
+//!
+//! Built with the `systemd` feature, the agent reports its state to the
+//! socket systemd passes in `NOTIFY_SOCKET`: `READY=1` once the UDS listener
+//! accepts connections, `STATUS=` lines on configuration reloads and drain,
+//! `WATCHDOG=1` pings while `WatchdogSec=` is set, and `STOPPING=1` on
+//! shutdown. Without the feature, or when not started by systemd, every call
+//! is a no-op. Failures to reach the socket are logged and otherwise ignored.
+
+use std::time::Duration;
+
+/// Whether notifications are sent at all: built with `systemd` and started
+/// with `NOTIFY_SOCKET` set.
+pub fn enabled() -> bool {
+    cfg!(all(feature = "systemd", unix)) && std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// Send newline-separated `KEY=value` assignments to systemd.
+pub fn notify(state: &str) {
+    #[cfg(all(feature = "systemd", unix))]
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(err) = send(&socket, state) {
+            tracing::debug!(error = %err, "Failed to notify systemd");
+        }
+    }
+    #[cfg(not(all(feature = "systemd", unix)))]
+    let _ = state;
+}
+
+/// Send a `STATUS=` line, flattened to one line.
+pub fn status(status: &str) {
+    notify(&format!("STATUS={}", status.replace('\n', " ")));
+}
+
+/// Interval between watchdog pings: half of `WATCHDOG_USEC`, if systemd set
+/// it for this process (`WATCHDOG_PID`, when present, must match).
+pub fn watchdog_interval() -> Option<Duration> {
+    if !enabled() {
+        return None;
+    }
+    if let Some(pid) = std::env::var_os("WATCHDOG_PID") {
+        if pid.to_str()?.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec / 2))
+}
+
+/// Send a datagram to a notify socket: a path, or an abstract socket name
+/// prefixed with `@`.
+#[cfg(all(feature = "systemd", unix))]
+fn send(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let datagram = UnixDatagram::unbound()?;
+    match socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            datagram.send_to_addr(state.as_bytes(), &address)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract notify sockets are only supported on Linux",
+            ));
+        }
+        None => {
+            datagram.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(test, feature = "systemd", unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_send_to_path_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1\nSTATUS=Serving").unwrap();
+
+        let mut buffer = [0u8; 64];
+        let read = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"READY=1\nSTATUS=Serving");
+
+        drop(receiver);
+        assert!(send(path.as_os_str(), "WATCHDOG=1").is_err());
+    }
+}
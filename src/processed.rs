@@ -0,0 +1,422 @@
+//! Precomputed per-endpoint state.
+//!
+//! Everything that only depends on the configuration (header sets, response
+//! bodies, the configured action) is built once when the configuration is
+//! loaded, so the request path only clones `Arc`s instead of re-formatting
+//! strings and re-serializing JSON on every match.
+//!
+//! A `ProcessedConfig` is immutable once built; configuration updates build a
+//! new one and swap it in atomically. The optional match cache belongs to the
+//! `ProcessedConfig`, so a swap also drops every cached lookup. Lookups are
+//! cached per period between two `active_from`/`active_until` boundaries, as
+//! the set of active rules only changes at those.
+
+use crate::config::{
+    ApiDeprecationConfig, DeprecatedEndpoint, DeprecationAction, DeprecationStatus, GlobalSettings,
+    HITS_PLACEHOLDER,
+};
+use crate::headers::{gone_response_body_with, DeprecationHeaders};
+use crate::manifest::Manifest;
+use crate::matcher::EndpointIndex;
+use crate::trace::MatchTrace;
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use std::collections::{HashMap, HashSet};
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Recent lookups keyed by window period, method and path; `None` records a
+/// miss.
+type MatchCache = Mutex<LruCache<(usize, String), Option<usize>>>;
+
+/// Configuration together with the precomputed state for each endpoint.
+pub(crate) struct ProcessedConfig {
+    pub config: ApiDeprecationConfig,
+    /// Precomputed state, parallel to `config.endpoints`
+    pub endpoints: Vec<ProcessedEndpoint>,
+    /// Index for fast endpoint lookup
+    index: EndpointIndex,
+    /// Recent lookups, when `settings.match_cache_size` is set
+    match_cache: Option<MatchCache>,
+    /// Sorted `active_from`/`active_until` instants of all endpoints
+    window_boundaries: Vec<DateTime<Utc>>,
+    /// Deprecation catalog served at `settings.manifest_path`
+    pub manifest: Option<Manifest>,
+    /// When this configuration was loaded
+    pub loaded_at: DateTime<Utc>,
+    /// [`ApiDeprecationConfig::hash`] of the compiled configuration
+    pub hash: String,
+    /// Rules (or global header entries) whose shadowed blocks were logged
+    shadow_logged: Mutex<HashSet<String>>,
+    /// Rules whose headers were logged as trimmed for a request
+    trim_logged: Mutex<HashSet<String>>,
+}
+
+impl ProcessedConfig {
+    /// Compile matchers and precompute the per-endpoint state for a
+    /// configuration loaded at `loaded_at`.
+    pub fn new(mut config: ApiDeprecationConfig, loaded_at: DateTime<Utc>) -> Self {
+        config.compile();
+
+        let endpoints = config
+            .endpoints
+            .iter()
+            .enumerate()
+            .map(|(priority, endpoint)| {
+                ProcessedEndpoint::new(priority, endpoint, &config.settings)
+            })
+            .collect();
+
+        let index = EndpointIndex::new(&config.endpoints);
+        let match_cache = NonZeroUsize::new(config.settings.match_cache_size)
+            .map(|capacity| Mutex::new(LruCache::new(capacity)));
+        let mut window_boundaries: Vec<DateTime<Utc>> = config
+            .endpoints
+            .iter()
+            .flat_map(|endpoint| [endpoint.active_from, endpoint.active_until])
+            .flatten()
+            .collect();
+        window_boundaries.sort_unstable();
+        window_boundaries.dedup();
+        let hash = config.hash();
+        let manifest = Manifest::new(&config, loaded_at);
+
+        Self {
+            config,
+            endpoints,
+            index,
+            match_cache,
+            window_boundaries,
+            manifest,
+            loaded_at,
+            hash,
+            shadow_logged: Mutex::default(),
+            trim_logged: Mutex::default(),
+        }
+    }
+
+    /// Whether a block held back by an unconfirmed rule should be logged:
+    /// the first time for each rule (keyed by `id`) and configuration, so a
+    /// busy shadowed rule doesn't log every request.
+    pub fn first_shadowed(&self, id: &str) -> bool {
+        let mut logged = self
+            .shadow_logged
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        !logged.contains(id) && logged.insert(id.to_string())
+    }
+
+    /// Whether headers a request lost to `settings.max_added_header_bytes`
+    /// should be logged: the first time for each rule and configuration.
+    pub fn first_trimmed(&self, id: &str) -> bool {
+        let mut logged = self
+            .trim_logged
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        !logged.contains(id) && logged.insert(id.to_string())
+    }
+
+    /// Endpoints whose glob pattern failed to compile and therefore never match.
+    pub fn unmatchable_endpoints(&self) -> impl Iterator<Item = &DeprecatedEndpoint> {
+        self.config
+            .endpoints
+            .iter()
+            .filter(|endpoint| endpoint.has_invalid_pattern())
+    }
+
+    /// Find the endpoint matching a request at `now` along with its
+    /// precomputed state. Rules outside their active window are skipped.
+    pub fn find(
+        &self,
+        path: &str,
+        method: &str,
+        now: DateTime<Utc>,
+    ) -> Option<(&DeprecatedEndpoint, &ProcessedEndpoint)> {
+        let index = match &self.match_cache {
+            Some(cache) => self.find_cached(cache, path, method, now),
+            None => self
+                .index
+                .find_at(&self.config.endpoints, path, method, now),
+        }?;
+        Some((&self.config.endpoints[index], &self.endpoints[index]))
+    }
+
+    /// Explain how every rule fared against a request at `now`, with the
+    /// selected rule taken from this configuration's index.
+    pub fn trace(&self, path: &str, method: &str, now: DateTime<Utc>) -> MatchTrace {
+        MatchTrace::with_index(&self.config, &self.index, path, method, now)
+    }
+
+    /// Look up through the match cache, falling back to the index on a miss.
+    ///
+    /// Rules only match on method and path, so the query string is not part
+    /// of the key. The key starts with the number of window boundaries
+    /// passed, so entries from an earlier period are never reused.
+    fn find_cached(
+        &self,
+        cache: &MatchCache,
+        path: &str,
+        method: &str,
+        now: DateTime<Utc>,
+    ) -> Option<usize> {
+        let period = self
+            .window_boundaries
+            .partition_point(|boundary| *boundary <= now);
+        let key = (period, format!("{} {}", method, path));
+        if let Some(cached) = cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+        {
+            return *cached;
+        }
+
+        let result = self
+            .index
+            .find_at(&self.config.endpoints, path, method, now);
+        cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .put(key, result);
+        result
+    }
+}
+
+/// Static, per-endpoint data reused by every matching request.
+pub(crate) struct ProcessedEndpoint {
+    /// Identifier used as the `endpoint_id` metrics label
+    pub metrics_id: Arc<str>,
+    /// Status label used in metrics
+    pub status_label: &'static str,
+    /// Deprecation headers added to responses
+    pub headers: Arc<HashMap<String, String>>,
+    /// Headers used instead of `headers` while a scheduled endpoint's
+    /// `deprecated_at` is still in the future
+    pub scheduled_headers: Option<Arc<HashMap<String, String>>>,
+    /// Whether the notice contains the `{{hits}}` placeholder
+    pub hits_template: bool,
+    /// JSON body for blocked requests
+    pub gone_body: Arc<str>,
+    /// Headers dropped to stay within `settings.max_added_header_bytes`
+    pub trimmed_headers: Vec<String>,
+    /// `computed_headers` templates (global ones overridden by the
+    /// endpoint's), sorted by name and rendered per request
+    pub computed_headers: Vec<(String, String)>,
+    /// Action configured on the endpoint (before sunset policies apply)
+    pub action: DeprecationActionResult,
+}
+
+/// Debug header naming the rule that won the match.
+pub(crate) const MATCHED_PRIORITY_HEADER: &str = "X-Deprecation-Matched-Priority";
+
+impl ProcessedEndpoint {
+    /// `priority` is the rule's position in the configuration; the first
+    /// matching rule wins, so lower values take precedence.
+    fn new(priority: usize, endpoint: &DeprecatedEndpoint, settings: &GlobalSettings) -> Self {
+        let status_label = endpoint.status.as_str();
+
+        let gone_body: Arc<str> = Arc::from(gone_response_body_with(endpoint, settings));
+        let action = match &endpoint.action {
+            DeprecationAction::Warn => DeprecationActionResult::Warn,
+            DeprecationAction::Redirect { status_code } => DeprecationActionResult::Redirect {
+                status_code: *status_code,
+            },
+            DeprecationAction::Block { status_code } => DeprecationActionResult::Block {
+                status_code: *status_code,
+            },
+            DeprecationAction::Custom {
+                status_code,
+                body,
+                content_type,
+            } => DeprecationActionResult::Custom {
+                status_code: *status_code,
+                body: match body {
+                    Some(body) => Arc::from(body.as_str()),
+                    None => Arc::clone(&gone_body),
+                },
+                content_type: Arc::from(content_type.as_str()),
+            },
+        };
+
+        let debug_header = |mut headers: HashMap<String, String>| {
+            if settings.debug_headers {
+                headers.insert(
+                    MATCHED_PRIORITY_HEADER.to_string(),
+                    format!("{}; id={}", priority, endpoint.id),
+                );
+            }
+            Arc::new(headers)
+        };
+
+        // Header sets over settings.max_added_header_bytes lose their
+        // lowest-priority headers
+        let mut trimmed_headers = Vec::new();
+        let mut trim = |mut headers: DeprecationHeaders| {
+            trimmed_headers.extend(headers.trim(endpoint, settings));
+            headers.build()
+        };
+
+        let headers = debug_header(trim(DeprecationHeaders::for_endpoint(endpoint, settings)));
+        let scheduled_headers = (matches!(endpoint.status, DeprecationStatus::Scheduled)
+            && endpoint.deprecated_at.is_some())
+        .then(|| debug_header(trim(DeprecationHeaders::for_scheduled(endpoint, settings))));
+        trimmed_headers.sort_unstable();
+        trimmed_headers.dedup();
+
+        let mut computed_headers: Vec<(String, String)> = settings
+            .computed_headers
+            .iter()
+            .chain(&endpoint.computed_headers)
+            .map(|(name, template)| (name.clone(), template.clone()))
+            .collect::<HashMap<_, _>>()
+            .into_iter()
+            .collect();
+        computed_headers.sort_unstable();
+
+        Self {
+            metrics_id: Arc::from(endpoint.metrics_id()),
+            status_label,
+            headers,
+            scheduled_headers,
+            hits_template: endpoint
+                .message
+                .as_deref()
+                .is_some_and(|message| message.contains(HITS_PLACEHOLDER)),
+            gone_body,
+            trimmed_headers,
+            computed_headers,
+            action,
+        }
+    }
+}
+
+/// Action result after processing.
+#[derive(Debug, Clone)]
+pub(crate) enum DeprecationActionResult {
+    Warn,
+    Redirect {
+        status_code: u16,
+    },
+    Block {
+        status_code: u16,
+    },
+    Custom {
+        status_code: u16,
+        body: Arc<str>,
+        content_type: Arc<str>,
+    },
+}
+
+impl DeprecationActionResult {
+    /// Whether the action answers the request instead of the upstream.
+    /// Redirects don't count: the client is sent on to the replacement.
+    pub fn blocks(&self) -> bool {
+        matches!(self, Self::Block { .. } | Self::Custom { .. })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cached_config(yaml: &str) -> ProcessedConfig {
+        ProcessedConfig::new(ApiDeprecationConfig::from_yaml(yaml).unwrap(), Utc::now())
+    }
+
+    fn cache_len(state: &ProcessedConfig) -> usize {
+        state.match_cache.as_ref().unwrap().lock().unwrap().len()
+    }
+
+    #[test]
+    fn test_match_cache_records_hits_and_misses() {
+        let state = cached_config(
+            r#"
+settings:
+  match_cache_size: 2
+endpoints:
+  - id: users
+    path: /api/v1/users
+    methods: [GET]
+"#,
+        );
+
+        for _ in 0..3 {
+            assert_eq!(
+                state.find("/api/v1/users", "GET", Utc::now()).unwrap().0.id,
+                "users"
+            );
+            assert!(state.find("/api/v1/users", "POST", Utc::now()).is_none());
+        }
+        assert_eq!(cache_len(&state), 2);
+
+        // Bounded: a third key evicts the least recently used one
+        assert!(state.find("/api/v2/users", "GET", Utc::now()).is_none());
+        assert_eq!(cache_len(&state), 2);
+        assert_eq!(
+            state.find("/api/v1/users", "GET", Utc::now()).unwrap().0.id,
+            "users"
+        );
+    }
+
+    #[test]
+    fn test_match_cache_follows_windows() {
+        let state = cached_config(
+            r#"
+settings:
+  match_cache_size: 8
+endpoints:
+  - id: tax-season
+    path: /api/v1/filings
+    active_from: 2025-01-15
+    active_until: 2025-04-16
+"#,
+        );
+        let find = |at: &str| {
+            state
+                .find("/api/v1/filings", "GET", at.parse().unwrap())
+                .map(|(endpoint, _)| endpoint.id.as_str())
+        };
+
+        assert_eq!(find("2025-01-01T00:00:00Z"), None);
+        assert_eq!(find("2025-02-01T00:00:00Z"), Some("tax-season"));
+        assert_eq!(find("2025-02-01T00:00:00Z"), Some("tax-season"));
+        assert_eq!(find("2025-05-01T00:00:00Z"), None);
+        assert_eq!(cache_len(&state), 3);
+    }
+
+    #[test]
+    fn test_matched_priority_header() {
+        let state = cached_config(
+            r#"
+settings:
+  debug_headers: true
+endpoints:
+  - id: users
+    path: /api/v1/users
+  - id: all-v1
+    path: /api/v1/*
+"#,
+        );
+
+        // Both rules match; the earlier one wins
+        let (_, users) = state.find("/api/v1/users", "GET", Utc::now()).unwrap();
+        assert_eq!(users.headers[MATCHED_PRIORITY_HEADER], "0; id=users");
+
+        let (_, all) = state.find("/api/v1/posts", "GET", Utc::now()).unwrap();
+        assert_eq!(all.headers[MATCHED_PRIORITY_HEADER], "1; id=all-v1");
+    }
+
+    #[test]
+    fn test_matched_priority_header_off_by_default() {
+        let state = cached_config("endpoints:\n  - id: users\n    path: /api/v1/users\n");
+        let (_, users) = state.find("/api/v1/users", "GET", Utc::now()).unwrap();
+        assert!(!users.headers.contains_key(MATCHED_PRIORITY_HEADER));
+    }
+
+    #[test]
+    fn test_match_cache_disabled_by_default() {
+        let state = cached_config("endpoints:\n  - id: users\n    path: /api/v1/users\n");
+        assert!(state.match_cache.is_none());
+        assert!(state.find("/api/v1/users", "GET", Utc::now()).is_some());
+    }
+}
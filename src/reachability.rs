@@ -0,0 +1,182 @@
+//! Reachability checks for replacement URLs.
+//!
+//! A typo in an absolute `replacement.path` sends redirected clients into the
+//! void. When `settings.check_replacements` is enabled, each absolute
+//! replacement URL gets a `HEAD` request and the result is exported as the
+//! `<prefix>_replacement_reachable{endpoint_id}` gauge. Relative replacements
+//! are served by the same proxy and are not checked.
+
+use crate::config::ApiDeprecationConfig;
+use crate::metrics::MetricsSink;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// Timeout for a single replacement check.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Outcome of checking one replacement URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplacementCheck {
+    /// Metrics identifier of the deprecated endpoint
+    pub endpoint_id: String,
+    /// Replacement URL that was checked
+    pub url: String,
+    /// Whether the URL answered with a success or redirect status
+    pub reachable: bool,
+}
+
+/// Build the HTTP client used for replacement checks.
+///
+/// Fails if the TLS backend or system configuration can't be initialized.
+pub fn try_client() -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder().timeout(CHECK_TIMEOUT).build()
+}
+
+/// Build the HTTP client used for replacement checks, panicking on failure.
+#[deprecated(note = "panics if the client can't be built; use `try_client`")]
+pub fn client() -> reqwest::Client {
+    try_client().expect("Failed to build replacement check client")
+}
+
+/// Check every absolute replacement URL in the configuration.
+pub async fn check_replacements(
+    config: &ApiDeprecationConfig,
+    client: &reqwest::Client,
+) -> Vec<ReplacementCheck> {
+    let mut checks = Vec::new();
+
+    for endpoint in &config.endpoints {
+        let Some(replacement) = &endpoint.replacement else {
+            continue;
+        };
+        if !is_absolute(&replacement.path) {
+            continue;
+        }
+
+        let reachable = match client.head(&replacement.path).send().await {
+            Ok(response) => {
+                let status = response.status();
+                debug!(endpoint_id = %endpoint.id, url = %replacement.path, %status, "Checked replacement");
+                status.is_success() || status.is_redirection()
+            }
+            Err(err) => {
+                debug!(endpoint_id = %endpoint.id, url = %replacement.path, error = %err, "Replacement check failed");
+                false
+            }
+        };
+
+        if !reachable {
+            warn!(
+                endpoint_id = %endpoint.id,
+                url = %replacement.path,
+                "Replacement URL is not reachable"
+            );
+        }
+
+        checks.push(ReplacementCheck {
+            endpoint_id: endpoint.metrics_id().to_string(),
+            url: replacement.path.clone(),
+            reachable,
+        });
+    }
+
+    checks
+}
+
+/// Check the replacements and record the results into a metrics sink.
+pub async fn record_replacement_checks(
+    config: &ApiDeprecationConfig,
+    client: &reqwest::Client,
+    metrics: &dyn MetricsSink,
+) {
+    for check in check_replacements(config, client).await {
+        metrics.set_replacement_reachable(&check.endpoint_id, check.reachable);
+    }
+}
+
+fn is_absolute(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP server answering 200 for `/ok` and 404 for anything else.
+    async fn mock_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut socket, _)) = listener.accept().await else {
+                    return;
+                };
+                let mut buf = vec![0; 1024];
+                let n = socket.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let status = if request.starts_with("HEAD /ok ") {
+                    "200 OK"
+                } else {
+                    "404 Not Found"
+                };
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    async fn test_reachable_and_missing_replacements() {
+        let base = mock_server().await;
+        let yaml = format!(
+            r#"
+endpoints:
+  - id: users
+    path: /api/v1/users
+    replacement:
+      path: {base}/ok
+  - id: posts
+    path: /api/v1/posts
+    metrics_id: posts-series
+    replacement:
+      path: {base}/typo
+  - id: orders
+    path: /api/v1/orders
+    replacement:
+      path: /api/v2/orders
+"#
+        );
+        let config = ApiDeprecationConfig::from_yaml(&yaml).unwrap();
+
+        let checks = check_replacements(&config, &try_client().unwrap()).await;
+
+        assert_eq!(checks.len(), 2, "relative replacements are skipped");
+        assert_eq!(checks[0].endpoint_id, "users");
+        assert!(checks[0].reachable);
+        assert_eq!(checks[1].endpoint_id, "posts-series");
+        assert!(!checks[1].reachable);
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[tokio::test]
+    async fn test_replacement_gauge() {
+        let base = mock_server().await;
+        let yaml = format!(
+            "endpoints:\n  - id: users\n    path: /api/v1/users\n    replacement:\n      path: {base}/ok\n  - id: posts\n    path: /api/v1/posts\n    replacement:\n      path: {base}/missing\n"
+        );
+        let config = ApiDeprecationConfig::from_yaml(&yaml).unwrap();
+        let metrics = crate::metrics::DeprecationMetrics::new("test").unwrap();
+
+        record_replacement_checks(&config, &try_client().unwrap(), &metrics).await;
+
+        let output = metrics.encode();
+        assert!(output.contains("test_replacement_reachable{endpoint_id=\"users\"} 1"));
+        assert!(output.contains("test_replacement_reachable{endpoint_id=\"posts\"} 0"));
+    }
+}
@@ -0,0 +1,92 @@
+//! Redaction of sensitive request header values.
+//!
+//! Anything the agent records about a request's headers (match trace samples,
+//! logs, payloads sent elsewhere) goes through [`redact_headers`], so a header
+//! listed in `settings.redact_headers` never leaves the agent with its value.
+
+use std::collections::BTreeMap;
+
+/// Replacement for the value of a redacted header.
+pub const REDACTED: &str = "***";
+
+/// Headers redacted unless `settings.redact_headers` says otherwise.
+pub const DEFAULT_REDACT_HEADERS: &[&str] = &[
+    "Authorization",
+    "Proxy-Authorization",
+    "Cookie",
+    "Set-Cookie",
+];
+
+/// Whether a header's value must be redacted (names compare case-insensitively).
+pub fn is_redacted(name: &str, redact: &[String]) -> bool {
+    redact
+        .iter()
+        .any(|redacted| redacted.eq_ignore_ascii_case(name))
+}
+
+/// Copy headers for recording, with the values of redacted headers replaced
+/// by [`REDACTED`]. Names are lowercased; repeated headers are joined with
+/// `", "`.
+pub fn redact_headers<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+    redact: &[String],
+) -> BTreeMap<String, String> {
+    let mut recorded = BTreeMap::<String, String>::new();
+    for (name, value) in headers {
+        let value = if is_redacted(name, redact) {
+            REDACTED
+        } else {
+            value
+        };
+        recorded
+            .entry(name.to_ascii_lowercase())
+            .and_modify(|existing| {
+                if existing != REDACTED {
+                    existing.push_str(", ");
+                    existing.push_str(value);
+                }
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    recorded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn defaults() -> Vec<String> {
+        DEFAULT_REDACT_HEADERS
+            .iter()
+            .map(|h| h.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_redacts_listed_headers_case_insensitively() {
+        let recorded = redact_headers(
+            [
+                ("authorization", "Bearer secret"),
+                ("COOKIE", "session=abc"),
+                ("Accept", "application/json"),
+                ("Accept", "text/plain"),
+            ],
+            &defaults(),
+        );
+
+        assert_eq!(recorded["authorization"], REDACTED);
+        assert_eq!(recorded["cookie"], REDACTED);
+        assert_eq!(recorded["accept"], "application/json, text/plain");
+    }
+
+    #[test]
+    fn test_custom_list_replaces_defaults() {
+        let recorded = redact_headers(
+            [("Authorization", "Bearer secret"), ("X-Api-Key", "k")],
+            &["x-api-key".to_string()],
+        );
+
+        assert_eq!(recorded["authorization"], "Bearer secret");
+        assert_eq!(recorded["x-api-key"], REDACTED);
+    }
+}
@@ -0,0 +1,168 @@
+//! Redis-backed usage counters shared across agent replicas.
+//!
+//! Each replica periodically pushes the growth of its local counters with
+//! `HINCRBY` (one hash per endpoint, keyed `<key_prefix>:usage:<metrics_id>`)
+//! and reads the cluster-wide totals back. Pushes are atomic, and a failed
+//! push is retried with the accumulated delta on the next flush, so an outage
+//! only delays counts: the agent keeps counting locally and reports degraded
+//! health until the store is reachable again.
+
+use crate::config::SharedStoreConfig;
+use crate::usage::{ClusterTotals, UsageTracker};
+use redis::aio::MultiplexedConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Connection to the shared store plus what this replica already pushed.
+pub(crate) struct SharedStore {
+    client: redis::Client,
+    key_prefix: String,
+    connection: Option<MultiplexedConnection>,
+    /// Local totals as of the last successful push
+    flushed: HashMap<Arc<str>, ClusterTotals>,
+}
+
+impl SharedStore {
+    pub fn new(config: &SharedStoreConfig) -> redis::RedisResult<Self> {
+        Ok(Self {
+            client: redis::Client::open(config.url.as_str())?,
+            key_prefix: config.key_prefix.clone(),
+            connection: None,
+            flushed: HashMap::new(),
+        })
+    }
+
+    fn key(&self, metrics_id: &str) -> String {
+        format!("{}:usage:{}", self.key_prefix, metrics_id)
+    }
+
+    /// Push local growth and read back cluster totals for the given endpoints.
+    pub async fn sync(
+        &mut self,
+        usage: &UsageTracker,
+        metrics_ids: &[String],
+    ) -> redis::RedisResult<HashMap<String, ClusterTotals>> {
+        let result = self.try_sync(usage, metrics_ids).await;
+        if result.is_err() {
+            // Reconnect on the next flush
+            self.connection = None;
+        }
+        result
+    }
+
+    async fn try_sync(
+        &mut self,
+        usage: &UsageTracker,
+        metrics_ids: &[String],
+    ) -> redis::RedisResult<HashMap<String, ClusterTotals>> {
+        let mut connection = match &self.connection {
+            Some(connection) => connection.clone(),
+            None => {
+                let connection = self.client.get_multiplexed_async_connection().await?;
+                self.connection = Some(connection.clone());
+                connection
+            }
+        };
+
+        let local = usage.totals();
+        let deltas = deltas(&self.flushed, &local);
+        if !deltas.is_empty() {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            for (metrics_id, delta) in &deltas {
+                let key = self.key(metrics_id);
+                for (field, value) in [
+                    ("requests", delta.requests),
+                    ("redirects", delta.redirects),
+                    ("blocked", delta.blocked),
+                ] {
+                    if value > 0 {
+                        pipe.hincr(&key, field, value).ignore();
+                    }
+                }
+            }
+            pipe.query_async::<()>(&mut connection).await?;
+        }
+        self.flushed = local.into_iter().collect();
+
+        let mut pipe = redis::pipe();
+        for metrics_id in metrics_ids {
+            pipe.hgetall(self.key(metrics_id));
+        }
+        let hashes: Vec<HashMap<String, u64>> = pipe.query_async(&mut connection).await?;
+
+        Ok(metrics_ids
+            .iter()
+            .zip(hashes)
+            .map(|(metrics_id, hash)| {
+                let field = |name: &str| hash.get(name).copied().unwrap_or(0);
+                (
+                    metrics_id.clone(),
+                    ClusterTotals {
+                        requests: field("requests"),
+                        redirects: field("redirects"),
+                        blocked: field("blocked"),
+                    },
+                )
+            })
+            .collect())
+    }
+}
+
+/// Growth of each local counter since the last successful push.
+fn deltas(
+    flushed: &HashMap<Arc<str>, ClusterTotals>,
+    local: &[(Arc<str>, ClusterTotals)],
+) -> Vec<(Arc<str>, ClusterTotals)> {
+    local
+        .iter()
+        .filter_map(|(metrics_id, current)| {
+            let previous = flushed.get(metrics_id).copied().unwrap_or_default();
+            let delta = ClusterTotals {
+                requests: current.requests.saturating_sub(previous.requests),
+                redirects: current.redirects.saturating_sub(previous.redirects),
+                blocked: current.blocked.saturating_sub(previous.blocked),
+            };
+            (delta != ClusterTotals::default()).then(|| (Arc::clone(metrics_id), delta))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn totals(requests: u64, redirects: u64, blocked: u64) -> ClusterTotals {
+        ClusterTotals {
+            requests,
+            redirects,
+            blocked,
+        }
+    }
+
+    #[test]
+    fn test_deltas_since_last_flush() {
+        let users: Arc<str> = Arc::from("users");
+        let posts: Arc<str> = Arc::from("posts");
+        let flushed = HashMap::from([(Arc::clone(&users), totals(5, 1, 0))]);
+        let local = vec![
+            (Arc::clone(&users), totals(8, 1, 2)),
+            (Arc::clone(&posts), totals(3, 0, 0)),
+        ];
+
+        let mut deltas = deltas(&flushed, &local);
+        deltas.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            deltas,
+            vec![(posts, totals(3, 0, 0)), (users, totals(3, 0, 2))]
+        );
+    }
+
+    #[test]
+    fn test_no_deltas_when_unchanged() {
+        let users: Arc<str> = Arc::from("users");
+        let flushed = HashMap::from([(Arc::clone(&users), totals(5, 1, 0))]);
+        assert!(deltas(&flushed, &[(users, totals(5, 1, 0))]).is_empty());
+    }
+}
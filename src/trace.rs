@@ -0,0 +1,263 @@
+//! Explanations of why a request did or didn't match each rule.
+//!
+//! A [`MatchTrace`] evaluates every rule against a request in configuration
+//! order and records the first check each rule failed, or that it matched but
+//! lost to an earlier rule. The selected rule is taken from the
+//! [`EndpointIndex`] the agent matches requests with, so a trace always
+//! names the rule that actually handled the request.
+//!
+//! Traces are produced by the `--trace-match` CLI option and, with
+//! `settings.trace_match` enabled, logged at debug level for requests that
+//! carry the [`TRACE_MATCH_HEADER`], together with their (redacted) headers.
+
+use crate::config::ApiDeprecationConfig;
+use crate::matcher::EndpointIndex;
+use crate::redact::redact_headers;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Request header opting a single request into match tracing.
+pub const TRACE_MATCH_HEADER: &str = "X-Deprecation-Trace";
+
+/// How every rule fared against one request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MatchTrace {
+    pub method: String,
+    pub path: String,
+    /// One entry per rule, in configuration order
+    pub rules: Vec<RuleTrace>,
+    /// Id of the rule that handles the request, if any
+    pub selected: Option<String>,
+    /// Request headers of a traced live request, redacted
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Outcome of evaluating one rule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleTrace {
+    /// Rule identifier
+    pub id: String,
+    /// Position in configuration order (earlier rules win)
+    pub position: usize,
+    #[serde(flatten)]
+    pub outcome: RuleOutcome,
+}
+
+/// Why a rule did or didn't handle the request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum RuleOutcome {
+    /// The rule matched and handles the request
+    Selected,
+    /// The rule matched, but an earlier rule was selected
+    Shadowed { by: String },
+    /// One of the rule's own checks failed
+    Failed { check: MatchCheck },
+    /// The rule matched, but a rule in its `unless` list matched too
+    Suppressed { by: String },
+    /// The rule matched, but the matcher selected no rule
+    Unselected,
+}
+
+/// A check performed on each rule, in evaluation order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchCheck {
+    /// The request falls outside the rule's `active_from`..`active_until`
+    Window,
+    /// The request method isn't one of the rule's `methods`
+    Method,
+    /// No path pattern matches
+    Path,
+    /// An `exclude` pattern matches
+    Exclude,
+}
+
+impl MatchTrace {
+    /// Evaluate every rule of a (compiled) configuration against a request
+    /// made now.
+    #[deprecated(note = "use `MatchTrace::at` with an explicit time")]
+    pub fn new(config: &ApiDeprecationConfig, path: &str, method: &str) -> Self {
+        Self::at(config, path, method, Utc::now())
+    }
+
+    /// Evaluate every rule of a (compiled) configuration against a request
+    /// made at `now`.
+    pub fn at(config: &ApiDeprecationConfig, path: &str, method: &str, now: DateTime<Utc>) -> Self {
+        Self::with_index(
+            config,
+            &EndpointIndex::new(&config.endpoints),
+            path,
+            method,
+            now,
+        )
+    }
+
+    /// Evaluate every rule against a request, taking the selected rule from
+    /// `index`, which must be built from `config.endpoints`.
+    pub fn with_index(
+        config: &ApiDeprecationConfig,
+        index: &EndpointIndex,
+        path: &str,
+        method: &str,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let endpoints = &config.endpoints;
+        let selected = index.find_at(endpoints, path, method, now);
+
+        let rules = endpoints
+            .iter()
+            .enumerate()
+            .map(|(position, endpoint)| {
+                let failed = if !endpoint.is_active_at(now) {
+                    Some(MatchCheck::Window)
+                } else if !endpoint.matches_method(method) {
+                    Some(MatchCheck::Method)
+                } else if !endpoint.matches_path(path) {
+                    Some(MatchCheck::Path)
+                } else if endpoint.is_excluded(path) {
+                    Some(MatchCheck::Exclude)
+                } else {
+                    None
+                };
+
+                let outcome = if selected == Some(position) {
+                    RuleOutcome::Selected
+                } else if let Some(check) = failed {
+                    RuleOutcome::Failed { check }
+                } else if let Some(by) = index.suppressor(endpoints, position, path, method, now) {
+                    RuleOutcome::Suppressed {
+                        by: endpoints[by].id.clone(),
+                    }
+                } else {
+                    match selected {
+                        Some(by) => RuleOutcome::Shadowed {
+                            by: endpoints[by].id.clone(),
+                        },
+                        None => RuleOutcome::Unselected,
+                    }
+                };
+
+                RuleTrace {
+                    id: endpoint.id.clone(),
+                    position,
+                    outcome,
+                }
+            })
+            .collect();
+
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            rules,
+            selected: selected.map(|i| endpoints[i].id.clone()),
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Attach the request's headers, with values in `redact` replaced.
+    pub fn with_headers<'a>(
+        mut self,
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+        redact: &[String],
+    ) -> Self {
+        self.headers = redact_headers(headers, redact);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> ApiDeprecationConfig {
+        let mut config = ApiDeprecationConfig::from_yaml(yaml).unwrap();
+        config.compile();
+        config
+    }
+
+    #[test]
+    fn test_trace_explains_each_rule() {
+        let config = config(
+            r#"
+endpoints:
+  - id: posts
+    path: /api/v1/posts
+  - id: writes
+    path: /api/v1/*
+    methods: [POST]
+  - id: internal
+    path: /api/v1/*
+    exclude: [/api/v1/users/*]
+  - id: user
+    path: /api/v1/users/*
+    unless: [admins]
+  - id: admins
+    path: /api/v1/users/admin
+  - id: all-users
+    path: /api/v1/users
+"#,
+        );
+
+        let trace = MatchTrace::at(&config, "/api/v1/users/admin", "GET", Utc::now());
+        let outcomes: Vec<_> = trace.rules.iter().map(|r| r.outcome.clone()).collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                RuleOutcome::Failed {
+                    check: MatchCheck::Path
+                },
+                RuleOutcome::Failed {
+                    check: MatchCheck::Method
+                },
+                RuleOutcome::Failed {
+                    check: MatchCheck::Exclude
+                },
+                RuleOutcome::Suppressed {
+                    by: "admins".to_string()
+                },
+                RuleOutcome::Selected,
+                RuleOutcome::Shadowed {
+                    by: "admins".to_string()
+                },
+            ]
+        );
+        assert_eq!(trace.selected.as_deref(), Some("admins"));
+
+        for (path, method) in [
+            ("/api/v1/users/admin", "GET"),
+            ("/api/v1/users/7", "GET"),
+            ("/api/v1/orders", "POST"),
+            ("/api/v2/posts", "GET"),
+        ] {
+            assert_eq!(
+                MatchTrace::at(&config, path, method, Utc::now())
+                    .selected
+                    .as_deref(),
+                config.find_endpoint(path, method).map(|e| e.id.as_str()),
+                "{method} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_trace_json() {
+        let config =
+            config("endpoints:\n  - id: users\n    path: /api/v1/users\n    methods: [GET]\n");
+        let trace = MatchTrace::at(&config, "/api/v1/users", "DELETE", Utc::now());
+
+        assert_eq!(
+            serde_json::to_value(&trace).unwrap(),
+            serde_json::json!({
+                "method": "DELETE",
+                "path": "/api/v1/users",
+                "rules": [
+                    {"id": "users", "position": 0, "result": "failed", "check": "method"}
+                ],
+                "selected": null,
+            })
+        );
+    }
+}
@@ -0,0 +1,864 @@
+//! In-process usage counters for deprecated endpoints.
+//!
+//! These mirror the Prometheus counters with plain atomics so embedders can
+//! read current usage through
+//! [`ApiDeprecationAgent::usage_snapshot`](crate::ApiDeprecationAgent::usage_snapshot)
+//! without scraping and re-parsing the text format, and without depending on
+//! the `prometheus` feature. Counters are keyed by `metrics_id`, so they carry
+//! over configuration reloads the same way the metric series do.
+//!
+//! With a shared store configured, the cluster-wide totals last read back from
+//! it are kept alongside the local counters.
+//!
+//! [`DailyHits`] counts today's requests per endpoint and per client for the
+//! `{{hits}}` placeholder in deprecation notices, and [`Sessions`] tells the
+//! first request of a client's session for `metrics.session_window_secs`.
+//!
+//! Each endpoint's requests are also kept per hour for the last
+//! [`TREND_HOURS`], comparing the last day with the average day of the week
+//! before it (see [`UsageCounters::usage_trend`]).
+//!
+//! Identified clients are counted once per endpoint and UTC day, so
+//! migration progress reads as consumers left rather than requests left.
+//! The count is a HyperLogLog estimate in a fixed [`CONSUMER_REGISTERS`]
+//! bytes per endpoint, however many consumers there are.
+
+use crate::bucket::client_hash;
+use crate::client_state::{ClientStateConfig, ClientStore};
+use crate::clock::Clock;
+use crate::config::{DeprecationStatus, Severity};
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+/// Usage of a single deprecated endpoint at the time of the snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EndpointUsage {
+    /// Rule identifier
+    pub id: String,
+    /// Identifier used to label metrics
+    pub metrics_id: String,
+    /// Primary path pattern of the rule
+    pub pattern: String,
+    /// Additional path patterns matched by the rule
+    pub aliases: Vec<String>,
+    /// Deprecation status
+    pub status: DeprecationStatus,
+    /// Sunset date, if any
+    pub sunset_at: Option<DateTime<Utc>>,
+    /// Whether the rule is within its `active_from`..`active_until` window
+    /// (inactive rules match no requests)
+    pub active: bool,
+    /// How much the deprecation matters
+    pub severity: Severity,
+    /// Operator notes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Team owning the deprecation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    /// Who to ask about the deprecation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact: Option<String>,
+    /// Whole days until sunset (negative once past it)
+    pub days_until_sunset: Option<i64>,
+    /// Requests to the endpoint (excluding CORS preflight)
+    pub requests: u64,
+    /// Requests redirected to the replacement
+    pub redirects: u64,
+    /// Requests blocked
+    pub blocked: u64,
+    /// Upstream responses with a 5xx status
+    pub errors: u64,
+    /// Share of upstream responses with a 5xx status over the last refresh
+    /// interval, once computed
+    pub error_ratio: Option<f64>,
+    /// Time of the most recent request
+    pub last_access: Option<DateTime<Utc>>,
+    /// Distinct identified clients since midnight UTC (estimated)
+    pub unique_consumers_today: u64,
+    /// Distinct identified clients over the previous UTC day, once the agent
+    /// has counted through a day boundary
+    pub unique_consumers_yesterday: Option<u64>,
+    /// Totals across all replicas, when a shared store is configured and has
+    /// been read at least once
+    pub cluster: Option<ClusterTotals>,
+}
+
+/// Usage over the lifetime of the process, logged on shutdown.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LifetimeSummary {
+    /// Requests to deprecated endpoints (excluding CORS preflight)
+    pub requests: u64,
+    /// Requests redirected to a replacement
+    pub redirects: u64,
+    /// Requests blocked
+    pub blocked: u64,
+    /// Requests per `metrics_id`, including endpoints that saw none
+    pub endpoints: BTreeMap<String, u64>,
+}
+
+impl LifetimeSummary {
+    /// Sum a usage snapshot, counting endpoints sharing a `metrics_id` once.
+    pub fn from_usage(usage: &[EndpointUsage]) -> Self {
+        let mut summary = Self::default();
+        for endpoint in usage {
+            if summary
+                .endpoints
+                .insert(endpoint.metrics_id.clone(), endpoint.requests)
+                .is_none()
+            {
+                summary.requests += endpoint.requests;
+                summary.redirects += endpoint.redirects;
+                summary.blocked += endpoint.blocked;
+            }
+        }
+        summary
+    }
+}
+
+/// Usage summed across all replicas sharing a store.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ClusterTotals {
+    pub requests: u64,
+    pub redirects: u64,
+    pub blocked: u64,
+}
+
+/// Distinct consumers of an endpoint today and over the previous day.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UniqueConsumers {
+    pub today: u64,
+    /// `None` until the agent has counted through a day boundary
+    pub yesterday: Option<u64>,
+}
+
+/// Counters for one endpoint.
+#[derive(Debug, Default)]
+pub(crate) struct UsageCounters {
+    pub requests: AtomicU64,
+    pub redirects: AtomicU64,
+    pub blocked: AtomicU64,
+    /// Upstream responses to requests that were let through
+    pub responses: AtomicU64,
+    /// Upstream responses with a 5xx status
+    pub errors: AtomicU64,
+    /// Unix timestamp in milliseconds of the last request (0 = never)
+    pub last_access_ms: AtomicI64,
+    /// Response counts at the last error ratio computation
+    error_window: Mutex<ErrorWindow>,
+    /// Request count and moving average at the last rate computation
+    rate_window: Mutex<RateWindow>,
+    /// Requests per hour, for the usage trend
+    hourly: Mutex<HourlyUsage>,
+    /// Requests per UTC day, for windows longer than the hourly history
+    daily: Mutex<DailyUsage>,
+    /// Distinct clients of the current UTC day
+    consumers: Mutex<DailyConsumers>,
+}
+
+/// Bits of a client hash selecting the register of the consumer sketch.
+const CONSUMER_PRECISION: u32 = 12;
+
+/// Registers (bytes) of each endpoint's consumer sketch: a standard error of
+/// about 1.6%, and exact in practice for the handful of consumers left at the
+/// end of a migration.
+pub const CONSUMER_REGISTERS: usize = 1 << CONSUMER_PRECISION;
+
+/// HyperLogLog sketch of the clients seen on one UTC day.
+#[derive(Debug)]
+struct DailyConsumers {
+    /// Day being counted (`None` before the first client)
+    day: Option<NaiveDate>,
+    /// Highest rank seen per register
+    registers: Box<[u8]>,
+    /// Final estimate of the day before `day`
+    yesterday: Option<u64>,
+}
+
+impl Default for DailyConsumers {
+    fn default() -> Self {
+        Self {
+            day: None,
+            registers: vec![0; CONSUMER_REGISTERS].into_boxed_slice(),
+            yesterday: None,
+        }
+    }
+}
+
+impl DailyConsumers {
+    /// Start counting `today`, keeping the final count of the day before it.
+    /// A clock going backwards keeps counting the later day.
+    fn roll(&mut self, today: NaiveDate) {
+        match self.day {
+            Some(day) if day >= today => return,
+            Some(day) if day.succ_opt() == Some(today) => self.yesterday = Some(self.estimate()),
+            // Nobody was counted on the day before today
+            Some(_) => self.yesterday = Some(0),
+            None => {}
+        }
+        self.day = Some(today);
+        self.registers.fill(0);
+    }
+
+    fn record(&mut self, today: NaiveDate, hash: u64) {
+        self.roll(today);
+        let register = (hash >> (64 - CONSUMER_PRECISION)) as usize;
+        // The sentinel bit caps the rank once the remaining bits are all zero
+        let rest = (hash << CONSUMER_PRECISION) | (1 << (CONSUMER_PRECISION - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        let slot = &mut self.registers[register];
+        *slot = (*slot).max(rank);
+    }
+
+    /// Estimated distinct clients of `day`, with linear counting while
+    /// registers are still empty.
+    fn estimate(&self) -> u64 {
+        let m = CONSUMER_REGISTERS as f64;
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&rank| 2f64.powi(-i32::from(rank)))
+            .sum();
+        let raw = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+
+        let empty = self.registers.iter().filter(|&&rank| rank == 0).count();
+        let estimate = if raw <= 2.5 * m && empty > 0 {
+            m * (m / empty as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+/// Hours of history kept per endpoint for the usage trend: the last day and
+/// the seven before it.
+pub const TREND_HOURS: usize = 8 * 24;
+
+/// Requests per hour over the last [`TREND_HOURS`].
+#[derive(Debug)]
+struct HourlyUsage {
+    /// Requests, indexed by hour since the epoch modulo [`TREND_HOURS`]
+    counts: Vec<u64>,
+    /// Hour each slot counts, telling stale slots apart
+    hours: Vec<i64>,
+    /// First hour recorded
+    since: Option<i64>,
+    /// Whether the last trend computation was a spike, so a spike is
+    /// reported once
+    spiking: bool,
+}
+
+impl Default for HourlyUsage {
+    fn default() -> Self {
+        Self {
+            counts: vec![0; TREND_HOURS],
+            hours: vec![i64::MIN; TREND_HOURS],
+            since: None,
+            spiking: false,
+        }
+    }
+}
+
+impl HourlyUsage {
+    fn record(&mut self, hour: i64) {
+        let slot = hour.rem_euclid(TREND_HOURS as i64) as usize;
+        if self.hours[slot] != hour {
+            self.hours[slot] = hour;
+            self.counts[slot] = 0;
+        }
+        self.counts[slot] += 1;
+        self.since.get_or_insert(hour);
+    }
+
+    /// Requests in the hours `from..to`.
+    fn sum(&self, from: i64, to: i64) -> u64 {
+        self.hours
+            .iter()
+            .zip(&self.counts)
+            .filter(|(hour, _)| (from..to).contains(*hour))
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// Days of request history kept per endpoint, for windowed exports longer
+/// than [`TREND_HOURS`].
+pub const HISTORY_DAYS: usize = 31;
+
+/// Requests per UTC day over the last [`HISTORY_DAYS`].
+#[derive(Debug)]
+struct DailyUsage {
+    /// Requests, indexed by day since the epoch modulo [`HISTORY_DAYS`]
+    counts: [u64; HISTORY_DAYS],
+    /// Day each slot counts, telling stale slots apart
+    days: [i64; HISTORY_DAYS],
+}
+
+impl Default for DailyUsage {
+    fn default() -> Self {
+        Self {
+            counts: [0; HISTORY_DAYS],
+            days: [i64::MIN; HISTORY_DAYS],
+        }
+    }
+}
+
+impl DailyUsage {
+    fn record(&mut self, day: i64) {
+        let slot = day.rem_euclid(HISTORY_DAYS as i64) as usize;
+        if self.days[slot] != day {
+            self.days[slot] = day;
+            self.counts[slot] = 0;
+        }
+        self.counts[slot] += 1;
+    }
+
+    /// Requests in the days `from..to`.
+    fn sum(&self, from: i64, to: i64) -> u64 {
+        self.days
+            .iter()
+            .zip(&self.counts)
+            .filter(|(day, _)| (from..to).contains(*day))
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// Usage of the last 24 hours against the days before them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct UsageTrend {
+    /// Requests in the last 24 hours
+    pub last_day: u64,
+    /// Average requests per day over up to seven days before the last 24
+    /// hours (`None` until a full day of them has been recorded)
+    pub daily_average: Option<f64>,
+    /// Whether the last day rose above the spike factor with this
+    /// computation (false while it stays above it)
+    pub spike_started: bool,
+}
+
+/// State of the windowed error ratio computation.
+#[derive(Debug, Default, Clone, Copy)]
+struct ErrorWindow {
+    responses: u64,
+    errors: u64,
+    ratio: Option<f64>,
+}
+
+/// State of the request rate moving average.
+#[derive(Debug, Default, Clone, Copy)]
+struct RateWindow {
+    requests: u64,
+    /// Exponentially weighted moving average in requests per second, once
+    /// one interval has been seen
+    ewma: Option<f64>,
+    /// Whether the last interval was a spike, so a spike is reported once
+    spiking: bool,
+}
+
+/// Weight of the latest interval in the request rate moving average.
+pub(crate) const RATE_EWMA_ALPHA: f64 = 0.3;
+
+/// Request rate over one refresh interval, against the moving average of the
+/// intervals before it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct RequestRate {
+    /// Requests per second over the interval
+    pub rate: f64,
+    /// Moving average before this interval (`None` for the first one)
+    pub average: Option<f64>,
+    /// Requests in the interval
+    pub requests: u64,
+    /// Whether the rate rose above the spike threshold with this interval
+    /// (false while it stays above it)
+    pub spike_started: bool,
+}
+
+/// Error ratio over one refresh interval.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ErrorRatio {
+    /// Share of responses with a 5xx status (0 without responses)
+    pub ratio: f64,
+    /// Responses in the interval
+    pub responses: u64,
+}
+
+impl UsageCounters {
+    /// Error ratio computed at the last refresh.
+    pub fn error_ratio(&self) -> Option<f64> {
+        self.error_window
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .ratio
+    }
+
+    /// Compute the error ratio since the previous call and start a new window.
+    pub fn roll_error_window(&self) -> ErrorRatio {
+        let responses = self.responses.load(Ordering::Relaxed);
+        let errors = self.errors.load(Ordering::Relaxed);
+        let mut window = self
+            .error_window
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let window_responses = responses.saturating_sub(window.responses);
+        let window_errors = errors.saturating_sub(window.errors);
+        let ratio = if window_responses == 0 {
+            0.0
+        } else {
+            window_errors as f64 / window_responses as f64
+        };
+
+        *window = ErrorWindow {
+            responses,
+            errors,
+            ratio: Some(ratio),
+        };
+        ErrorRatio {
+            ratio,
+            responses: window_responses,
+        }
+    }
+
+    /// Compute the request rate since the previous call, over an interval of
+    /// `interval_secs`, and fold it into the moving average. The rate is a
+    /// spike when above `threshold` times the previous average and at least
+    /// `min_requests` requests were seen.
+    pub fn roll_rate_window(
+        &self,
+        interval_secs: u64,
+        threshold: Option<f64>,
+        min_requests: u64,
+    ) -> RequestRate {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let mut window = self
+            .rate_window
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let window_requests = requests.saturating_sub(window.requests);
+        let rate = window_requests as f64 / interval_secs.max(1) as f64;
+        let average = window.ewma;
+
+        let spiking = match (threshold, average) {
+            (Some(threshold), Some(average)) => {
+                window_requests >= min_requests && rate > average * threshold
+            }
+            _ => false,
+        };
+        let spike_started = spiking && !window.spiking;
+
+        *window = RateWindow {
+            requests,
+            ewma: Some(match average {
+                Some(average) => RATE_EWMA_ALPHA * rate + (1.0 - RATE_EWMA_ALPHA) * average,
+                None => rate,
+            }),
+            spiking,
+        };
+        RequestRate {
+            rate,
+            average,
+            requests: window_requests,
+            spike_started,
+        }
+    }
+
+    /// Compare the last 24 hours before `now` with the daily average of up
+    /// to seven days before them. The last day is a spike when above
+    /// `factor` times an average of at least `min_daily_average` requests,
+    /// so endpoints with a near-zero baseline stay quiet.
+    pub fn usage_trend(
+        &self,
+        now: DateTime<Utc>,
+        factor: Option<f64>,
+        min_daily_average: f64,
+    ) -> UsageTrend {
+        let hour = now.timestamp().div_euclid(3600);
+        let day_start = hour - 23;
+        let mut hourly = self.hourly.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let last_day = hourly.sum(day_start, hour + 1);
+        let baseline_start = day_start - (TREND_HOURS as i64 - 24);
+        let daily_average = hourly.since.and_then(|since| {
+            let from = since.max(baseline_start);
+            let hours = day_start - from;
+            (hours >= 24).then(|| hourly.sum(from, day_start) as f64 * 24.0 / hours as f64)
+        });
+
+        let spiking = match (factor, daily_average) {
+            (Some(factor), Some(average)) => {
+                average >= min_daily_average && last_day as f64 > average * factor
+            }
+            _ => false,
+        };
+        let spike_started = spiking && !hourly.spiking;
+        hourly.spiking = spiking;
+
+        UsageTrend {
+            last_day,
+            daily_average,
+            spike_started,
+        }
+    }
+
+    /// Requests in the `hours` hours up to and including the hour of `now`.
+    /// Windows longer than [`TREND_HOURS`] are counted in whole UTC days up
+    /// to and including the day of `now`, as far as the last
+    /// [`HISTORY_DAYS`] reach.
+    pub fn recent_requests(&self, hours: usize, now: DateTime<Utc>) -> u64 {
+        if hours > TREND_HOURS {
+            let day = now.timestamp().div_euclid(86_400);
+            let from = day + 1 - hours.div_ceil(24).min(HISTORY_DAYS) as i64;
+            return self
+                .daily
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .sum(from, day + 1);
+        }
+        let hour = now.timestamp().div_euclid(3600);
+        let from = hour + 1 - hours as i64;
+        self.hourly
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .sum(from, hour + 1)
+    }
+
+    /// Distinct consumers of the UTC day of `now` and of the day before,
+    /// moving on to a new day first if `now` is past the one being counted.
+    pub fn unique_consumers(&self, now: DateTime<Utc>) -> UniqueConsumers {
+        let mut consumers = self
+            .consumers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let today = now.date_naive();
+        if consumers.day.is_some() {
+            consumers.roll(today);
+        }
+        UniqueConsumers {
+            today: if consumers.day == Some(today) {
+                consumers.estimate()
+            } else {
+                0
+            },
+            yesterday: consumers.yesterday,
+        }
+    }
+
+    pub fn last_access(&self) -> Option<DateTime<Utc>> {
+        match self.last_access_ms.load(Ordering::Relaxed) {
+            0 => None,
+            ms => DateTime::from_timestamp_millis(ms),
+        }
+    }
+}
+
+/// Usage counters for all endpoints, keyed by `metrics_id`.
+#[derive(Debug, Default)]
+pub(crate) struct UsageTracker {
+    counters: RwLock<HashMap<Arc<str>, Arc<UsageCounters>>>,
+}
+
+impl UsageTracker {
+    /// Counters for an endpoint, created on first use.
+    pub fn counters(&self, metrics_id: &Arc<str>) -> Arc<UsageCounters> {
+        if let Some(counters) = self
+            .counters
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(metrics_id)
+        {
+            return Arc::clone(counters);
+        }
+
+        let mut counters = self
+            .counters
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(counters.entry(Arc::clone(metrics_id)).or_default())
+    }
+
+    /// Counters for an endpoint, if it has seen any traffic.
+    pub fn get(&self, metrics_id: &str) -> Option<Arc<UsageCounters>> {
+        self.counters
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(metrics_id)
+            .cloned()
+    }
+
+    /// Current local totals for every endpoint that has seen traffic.
+    #[cfg_attr(not(feature = "redis-store"), allow(dead_code))]
+    pub fn totals(&self) -> Vec<(Arc<str>, ClusterTotals)> {
+        self.counters
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(metrics_id, counters)| {
+                (
+                    Arc::clone(metrics_id),
+                    ClusterTotals {
+                        requests: counters.requests.load(Ordering::Relaxed),
+                        redirects: counters.redirects.load(Ordering::Relaxed),
+                        blocked: counters.blocked.load(Ordering::Relaxed),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn record_request(&self, metrics_id: &Arc<str>, now: DateTime<Utc>) {
+        let counters = self.counters(metrics_id);
+        counters.requests.fetch_add(1, Ordering::Relaxed);
+        counters
+            .last_access_ms
+            .store(now.timestamp_millis(), Ordering::Relaxed);
+        counters
+            .hourly
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(now.timestamp().div_euclid(3600));
+        counters
+            .daily
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(now.timestamp().div_euclid(86_400));
+    }
+
+    /// Count an identified client of an endpoint for the UTC day of `now`.
+    pub fn record_consumer(&self, metrics_id: &Arc<str>, client: &str, now: DateTime<Utc>) {
+        self.counters(metrics_id)
+            .consumers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(now.date_naive(), client_hash(client, 0));
+    }
+
+    pub fn record_redirect(&self, metrics_id: &Arc<str>) {
+        self.counters(metrics_id)
+            .redirects
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_blocked(&self, metrics_id: &Arc<str>) {
+        self.counters(metrics_id)
+            .blocked
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record an upstream response and return whether it was an error (5xx).
+    pub fn record_response(&self, metrics_id: &Arc<str>, status: u16) -> bool {
+        let counters = self.counters(metrics_id);
+        counters.responses.fetch_add(1, Ordering::Relaxed);
+        let error = status >= 500;
+        if error {
+            counters.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        error
+    }
+}
+
+/// Cluster-wide totals and the state of the shared store.
+#[derive(Debug, Default)]
+pub(crate) struct ClusterUsage {
+    totals: RwLock<HashMap<String, ClusterTotals>>,
+    /// Last shared store error; cleared by the next successful sync
+    error: ArcSwapOption<String>,
+}
+
+impl ClusterUsage {
+    pub fn get(&self, metrics_id: &str) -> Option<ClusterTotals> {
+        self.totals
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(metrics_id)
+            .copied()
+    }
+
+    pub fn error(&self) -> Option<Arc<String>> {
+        self.error.load_full()
+    }
+
+    #[cfg_attr(not(feature = "redis-store"), allow(dead_code))]
+    pub fn update(&self, totals: HashMap<String, ClusterTotals>) {
+        *self.totals.write().unwrap_or_else(PoisonError::into_inner) = totals;
+        self.error.store(None);
+    }
+
+    #[cfg_attr(not(feature = "redis-store"), allow(dead_code))]
+    pub fn set_error(&self, error: String) {
+        self.error.store(Some(Arc::new(error)));
+    }
+}
+
+/// Requests since midnight UTC per endpoint, and per client when the request
+/// identifies one.
+pub(crate) struct DailyHits {
+    store: ClientStore<(NaiveDate, u64)>,
+}
+
+impl DailyHits {
+    pub fn new(config: &ClientStateConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            store: ClientStore::with_clock(config, clock),
+        }
+    }
+
+    /// Count a request and return today's count for the client, or for the
+    /// endpoint as a whole when there is no client.
+    pub fn record(&self, metrics_id: &str, client: Option<&str>, now: DateTime<Utc>) -> u64 {
+        let today = now.date_naive();
+        let count = |key: &str| {
+            self.store.update(
+                key,
+                || (today, 0),
+                |(day, hits)| {
+                    if *day != today {
+                        *day = today;
+                        *hits = 0;
+                    }
+                    *hits += 1;
+                    *hits
+                },
+            )
+        };
+
+        let total = count(metrics_id);
+        match client {
+            Some(client) => count(&format!("{metrics_id}\n{client}")),
+            None => total,
+        }
+    }
+
+    /// Today's count without recording a request.
+    pub fn get(&self, metrics_id: &str, client: Option<&str>, now: DateTime<Utc>) -> u64 {
+        let key = match client {
+            Some(client) => format!("{metrics_id}\n{client}"),
+            None => metrics_id.to_string(),
+        };
+        match self.store.get(&key) {
+            Some((day, hits)) if day == now.date_naive() => hits,
+            _ => 0,
+        }
+    }
+}
+
+/// Start of each client's current session per endpoint, for counting a
+/// client once per `metrics.session_window_secs`.
+pub(crate) struct Sessions {
+    store: ClientStore<Option<DateTime<Utc>>>,
+}
+
+impl Sessions {
+    pub fn new(config: &ClientStateConfig, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            store: ClientStore::with_clock(config, clock),
+        }
+    }
+
+    /// Whether a request starts a new session of the client, `window_secs`
+    /// after the last started (or once the client's state was evicted).
+    pub fn start(
+        &self,
+        metrics_id: &str,
+        client: &str,
+        window_secs: u64,
+        now: DateTime<Utc>,
+    ) -> bool {
+        self.store.update(
+            &format!("{metrics_id}\n{client}"),
+            || None,
+            |started| match started {
+                Some(start)
+                    if u64::try_from((now - *start).num_seconds())
+                        .is_ok_and(|elapsed| elapsed < window_secs) =>
+                {
+                    false
+                }
+                _ => {
+                    *started = Some(now);
+                    true
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn test_consumer_sketch_is_bounded() {
+        let mut consumers = DailyConsumers::default();
+        for i in 0..200_000 {
+            consumers.record(day("2030-01-01"), client_hash(&format!("client-{i}"), 0));
+        }
+        assert_eq!(consumers.registers.len(), CONSUMER_REGISTERS);
+
+        let estimate = consumers.estimate() as f64;
+        assert!(
+            (estimate - 200_000.0).abs() < 200_000.0 * 0.05,
+            "estimated {estimate} of 200000"
+        );
+    }
+
+    #[test]
+    fn test_few_consumers_are_counted_exactly() {
+        let mut consumers = DailyConsumers::default();
+        for count in 1..=20u64 {
+            // Repeat visits don't count twice
+            for _ in 0..3 {
+                consumers.record(
+                    day("2030-01-01"),
+                    client_hash(&format!("client-{count}"), 0),
+                );
+            }
+            assert_eq!(consumers.estimate(), count);
+        }
+    }
+
+    #[test]
+    fn test_consumers_roll_over_at_midnight() {
+        let counters = UsageCounters::default();
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+        let record = |client: &str, time: &str| {
+            counters
+                .consumers
+                .lock()
+                .unwrap()
+                .record(at(time).date_naive(), client_hash(client, 0));
+        };
+
+        assert_eq!(
+            counters.unique_consumers(at("2030-01-01T00:00:00Z")),
+            UniqueConsumers::default()
+        );
+
+        record("a", "2030-01-01T08:00:00Z");
+        record("b", "2030-01-01T23:59:59Z");
+        let first_day = counters.unique_consumers(at("2030-01-01T23:59:59Z"));
+        assert_eq!((first_day.today, first_day.yesterday), (2, None));
+
+        // Reading after midnight moves on without a request
+        let second_day = counters.unique_consumers(at("2030-01-02T00:00:00Z"));
+        assert_eq!((second_day.today, second_day.yesterday), (0, Some(2)));
+
+        record("a", "2030-01-02T10:00:00Z");
+        let second_day = counters.unique_consumers(at("2030-01-02T10:00:00Z"));
+        assert_eq!((second_day.today, second_day.yesterday), (1, Some(2)));
+
+        // A day without consumers in between
+        let fourth_day = counters.unique_consumers(at("2030-01-04T01:00:00Z"));
+        assert_eq!((fourth_day.today, fourth_day.yesterday), (0, Some(0)));
+    }
+}
@@ -0,0 +1,304 @@
+//! Shared fixture for integration tests.
+//!
+//! Drives the agent only through its public surface: construction from YAML,
+//! configuration pushed the way the proxy sends it (`Agent::on_configure`),
+//! traffic through the request, body and response handlers, and the v2
+//! handler's capabilities, health, metrics, drain and shutdown. New protocol
+//! features (config push variants, cancellation) can add helpers here and
+//! reuse them from any test file with `mod common;`.
+
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use zentinel_agent_api_deprecation::ApiDeprecationAgent;
+use zentinel_agent_protocol::v2::{
+    AgentCapabilities, AgentHandlerV2, DrainReason, GaugeMetric, HealthState, MetricsReport,
+    ShutdownReason,
+};
+use zentinel_agent_protocol::{
+    Decision as ProtocolDecision, HeaderOp, RequestHeadersEvent, RequestMetadata,
+    ResponseHeadersEvent,
+};
+use zentinel_agent_sdk::{Agent, Decision, Request, Response};
+
+/// An agent under test.
+pub struct Harness {
+    pub agent: ApiDeprecationAgent,
+}
+
+impl Harness {
+    /// Start an agent from a YAML configuration.
+    pub fn from_yaml(yaml: &str) -> Self {
+        Self {
+            agent: ApiDeprecationAgent::from_yaml(yaml).expect("valid test configuration"),
+        }
+    }
+
+    /// Push a configuration as the proxy does: YAML converted to the JSON
+    /// value carried by the protocol.
+    pub async fn push_config(&self, yaml: &str) -> Result<(), String> {
+        let value: serde_json::Value = serde_yaml::from_str(yaml).expect("valid YAML");
+        Agent::on_configure(&self.agent, value).await
+    }
+
+    /// Send a request's headers through `on_request`.
+    pub async fn send(&self, request: &Request) -> Outcome {
+        Outcome::from(Agent::on_request(&self.agent, request).await)
+    }
+
+    /// Send a request with its body through `on_request_body`.
+    pub async fn send_body(&self, request: &Request) -> Outcome {
+        Outcome::from(Agent::on_request_body(&self.agent, request).await)
+    }
+
+    /// Send the upstream's response to a request through `on_response`.
+    pub async fn respond(&self, request: &Request, response: &Response) -> Outcome {
+        Outcome::from(Agent::on_response(&self.agent, request, response).await)
+    }
+
+    /// Ids of the active rules, in configuration order.
+    pub fn rule_ids(&self) -> Vec<String> {
+        self.agent
+            .usage_snapshot()
+            .into_iter()
+            .map(|usage| usage.id)
+            .collect()
+    }
+
+    pub fn capabilities(&self) -> AgentCapabilities {
+        self.agent.capabilities()
+    }
+
+    pub async fn drain(&self) {
+        self.agent.on_drain(30_000, DrainReason::ConfigReload).await;
+    }
+
+    pub async fn shutdown(&self) {
+        self.agent.on_shutdown(ShutdownReason::Graceful, 1000).await;
+    }
+
+    pub fn assert_healthy(&self) {
+        let health = self.agent.health_status();
+        assert!(
+            health.is_healthy(),
+            "expected a healthy agent, got {health:?}"
+        );
+    }
+
+    pub fn assert_degraded(&self) {
+        let health = self.agent.health_status();
+        assert!(
+            !health.is_healthy(),
+            "expected a degraded agent, got {health:?}"
+        );
+    }
+
+    /// Assert the agent is degraded with `feature` reported as disabled.
+    pub fn assert_degraded_feature(&self, feature: &str) {
+        let health = self.agent.health_status();
+        match &health.state {
+            HealthState::Degraded {
+                disabled_features, ..
+            } => assert!(
+                disabled_features.iter().any(|disabled| disabled == feature),
+                "expected {feature} to be disabled, got {health:?}"
+            ),
+            _ => panic!("expected a degraded agent, got {health:?}"),
+        }
+    }
+
+    /// The metrics report, which must be present.
+    pub fn metrics_report(&self) -> MetricsReport {
+        self.agent
+            .metrics_report()
+            .expect("metrics report while metrics are enabled")
+    }
+
+    /// A gauge from the metrics report, optionally for one endpoint.
+    pub fn gauge(&self, name: &str, endpoint_id: Option<&str>) -> Option<GaugeMetric> {
+        self.metrics_report().gauges.into_iter().find(|gauge| {
+            gauge.name == name
+                && endpoint_id.is_none_or(|id| {
+                    gauge.labels.get("endpoint_id").map(String::as_str) == Some(id)
+                })
+        })
+    }
+
+    /// A counter's value from the metrics report, optionally for one
+    /// endpoint.
+    pub fn counter(&self, name: &str, endpoint_id: Option<&str>) -> Option<u64> {
+        self.metrics_report()
+            .counters
+            .into_iter()
+            .find(|counter| {
+                counter.name == name
+                    && endpoint_id.is_none_or(|id| {
+                        counter.labels.get("endpoint_id").map(String::as_str) == Some(id)
+                    })
+            })
+            .map(|counter| counter.value)
+    }
+}
+
+/// A request as the proxy sends it, with `uri` including any query string.
+pub fn request(method: &str, uri: &str, headers: &[(&str, &str)]) -> Request {
+    let mut header_map: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, value) in headers {
+        header_map
+            .entry(name.to_string())
+            .or_default()
+            .push(value.to_string());
+    }
+    Request::from_headers_event(RequestHeadersEvent {
+        metadata: RequestMetadata {
+            correlation_id: "test".to_string(),
+            request_id: "test".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            client_port: 40000,
+            server_name: None,
+            protocol: "HTTP/1.1".to_string(),
+            tls_version: None,
+            tls_cipher: None,
+            route_id: None,
+            upstream_id: None,
+            timestamp: "2030-01-01T00:00:00Z".to_string(),
+            traceparent: None,
+        },
+        method: method.to_string(),
+        uri: uri.to_string(),
+        headers: header_map,
+    })
+}
+
+/// A request carrying a JSON body, as sent to `on_request_body`.
+pub fn json_request(method: &str, uri: &str, body: &serde_json::Value) -> Request {
+    request(method, uri, &[("Content-Type", "application/json")])
+        .with_body(serde_json::to_vec(body).unwrap())
+}
+
+/// An upstream response.
+pub fn response(status: u16, headers: &[(&str, &str)]) -> Response {
+    let mut header_map: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, value) in headers {
+        header_map
+            .entry(name.to_string())
+            .or_default()
+            .push(value.to_string());
+    }
+    Response::from_headers_event(ResponseHeadersEvent {
+        correlation_id: "test".to_string(),
+        status,
+        headers: header_map,
+    })
+}
+
+/// A decision flattened into what the proxy acts on.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Status of the agent's own response, for blocked requests
+    pub blocked: Option<u16>,
+    /// Body of the agent's own response
+    pub body: Option<String>,
+    /// Headers of the agent's own response
+    pub block_headers: HashMap<String, String>,
+    /// Target and status of a redirect
+    pub redirect: Option<(String, u16)>,
+    /// Headers set or added on the request sent upstream
+    pub request_headers: Vec<(String, String)>,
+    /// Headers removed from the request sent upstream
+    pub removed_request_headers: Vec<String>,
+    /// Headers set or added on the response sent to the client
+    pub response_headers: Vec<(String, String)>,
+    /// Headers removed from the response sent to the client
+    pub removed_response_headers: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+impl Outcome {
+    pub fn allowed(&self) -> bool {
+        self.blocked.is_none() && self.redirect.is_none()
+    }
+
+    /// Value of a header added to the response, ignoring case.
+    pub fn response_header(&self, name: &str) -> Option<&str> {
+        find_header(&self.response_headers, name)
+    }
+
+    /// Value of a header added to the upstream request, ignoring case.
+    pub fn request_header(&self, name: &str) -> Option<&str> {
+        find_header(&self.request_headers, name)
+    }
+
+    /// Value of a header on the agent's own response, ignoring case.
+    pub fn block_header(&self, name: &str) -> Option<&str> {
+        self.block_headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Whether `name` is removed from the response, ignoring case.
+    pub fn removes_response_header(&self, name: &str) -> bool {
+        self.removed_response_headers
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name))
+    }
+
+    /// Whether `name` is removed from the upstream request, ignoring case.
+    pub fn removes_request_header(&self, name: &str) -> bool {
+        self.removed_request_headers
+            .iter()
+            .any(|header| header.eq_ignore_ascii_case(name))
+    }
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn split_ops(ops: Vec<HeaderOp>) -> (Vec<(String, String)>, Vec<String>) {
+    let mut set = Vec::new();
+    let mut removed = Vec::new();
+    for op in ops {
+        match op {
+            HeaderOp::Set { name, value } | HeaderOp::Add { name, value } => {
+                set.push((name, value))
+            }
+            HeaderOp::Remove { name } => removed.push(name),
+        }
+    }
+    (set, removed)
+}
+
+impl From<Decision> for Outcome {
+    fn from(decision: Decision) -> Self {
+        let response = decision.build();
+        let mut outcome = Outcome::default();
+        match response.decision {
+            ProtocolDecision::Block {
+                status,
+                body,
+                headers,
+            } => {
+                outcome.blocked = Some(status);
+                outcome.body = body;
+                outcome.block_headers = headers.unwrap_or_default();
+            }
+            ProtocolDecision::Redirect { url, status } => outcome.redirect = Some((url, status)),
+            _ => {}
+        }
+        (outcome.request_headers, outcome.removed_request_headers) =
+            split_ops(response.request_headers);
+        (outcome.response_headers, outcome.removed_response_headers) =
+            split_ops(response.response_headers);
+        outcome.tags = response.audit.tags;
+        outcome
+    }
+}
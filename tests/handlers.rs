@@ -0,0 +1,589 @@
+//! Integration tests for request handling through the proxy-facing handlers:
+//! `on_request`, `on_request_body` and `on_response`.
+
+mod common;
+
+use common::{json_request, request, response, Harness};
+use serde_json::json;
+use zentinel_agent_api_deprecation::ApiDeprecationConfig;
+
+#[tokio::test]
+async fn test_body_fields_keep_endpoint_deprecation() {
+    let harness = Harness::from_yaml(
+        r#"
+endpoints:
+  - id: orders-legacy-fields
+    path: /api/v2/orders
+    sunset_at: 2030-01-01
+    body_field_deprecated: [/legacy_id]
+"#,
+    );
+
+    // The headers phase decides on the endpoint as for any rule
+    let headers = harness.send(&request("POST", "/api/v2/orders", &[])).await;
+    assert!(headers.allowed());
+    assert!(headers.response_header("Deprecation").is_some());
+    assert!(headers.response_header("Sunset").is_some());
+
+    // The body phase only names the fields it found
+    let body = harness
+        .send_body(&json_request(
+            "POST",
+            "/api/v2/orders",
+            &json!({"legacy_id": 7}),
+        ))
+        .await;
+    assert!(body.allowed());
+    assert_eq!(
+        body.response_header("X-Deprecated-Body-Fields"),
+        Some("/legacy_id")
+    );
+    assert!(body.response_header("Deprecation").is_none());
+
+    let body = harness
+        .send_body(&json_request("POST", "/api/v2/orders", &json!({"id": 7})))
+        .await;
+    assert!(body.allowed());
+    assert!(body.response_headers.is_empty());
+}
+
+#[tokio::test]
+async fn test_body_field_limits_and_blocks() {
+    let harness = Harness::from_yaml(
+        r#"
+settings:
+  max_body_inspection_bytes: 64
+  body_inspection_fail_open: false
+endpoints:
+  - id: users-fields
+    path: /api/v2/users
+    confirmed: true
+    deprecated_body_fields:
+      - pointer: /user_name
+        replacement: /username
+      - pointer: /legacy_role
+        action: block
+"#,
+    );
+    let harness = &harness;
+    let send = |body: serde_json::Value| {
+        let request = json_request("POST", "/api/v2/users", &body);
+        async move { harness.send_body(&request).await }
+    };
+
+    let outcome = send(json!({"user_name": "ada"})).await;
+    assert!(outcome.allowed());
+    assert_eq!(
+        outcome.response_header("X-Deprecated-Body-Fields"),
+        Some("/user_name")
+    );
+
+    // A blocking field rejects the request with a problem body naming it
+    let outcome = send(json!({"user_name": "ada", "legacy_role": "admin"})).await;
+    assert_eq!(outcome.blocked, Some(400));
+    assert_eq!(
+        outcome.block_header("Content-Type"),
+        Some("application/problem+json")
+    );
+    let problem: serde_json::Value =
+        serde_json::from_str(outcome.body.as_deref().unwrap()).unwrap();
+    assert_eq!(problem["fields"][0]["pointer"], "/legacy_role");
+
+    // Failing closed, oversized and non-JSON bodies are rejected too
+    let outcome = send(json!({"username": "x".repeat(64)})).await;
+    assert_eq!(outcome.blocked, Some(400));
+    let form = request("POST", "/api/v2/users", &[]).with_body(b"user_name=ada".to_vec());
+    assert_eq!(harness.send_body(&form).await.blocked, Some(400));
+
+    // A request without a body has nothing to inspect
+    let empty = request("POST", "/api/v2/users", &[]).with_body(Vec::new());
+    assert!(harness.send_body(&empty).await.allowed());
+}
+
+#[tokio::test]
+async fn test_body_field_limits_fail_open() {
+    let harness = Harness::from_yaml(
+        r#"
+settings:
+  max_body_inspection_bytes: 64
+endpoints:
+  - id: users-fields
+    path: /api/v2/users
+    confirmed: true
+    deprecated_body_fields:
+      - pointer: /legacy_role
+        action: block
+"#,
+    );
+
+    let oversized = json_request(
+        "POST",
+        "/api/v2/users",
+        &json!({"legacy_role": "x".repeat(64)}),
+    );
+    let outcome = harness.send_body(&oversized).await;
+    assert!(outcome.allowed());
+    assert!(outcome.response_headers.is_empty());
+
+    let form = request("POST", "/api/v2/users", &[]).with_body(b"legacy_role=admin".to_vec());
+    assert!(harness.send_body(&form).await.allowed());
+}
+
+#[tokio::test]
+async fn test_original_path_header_cannot_bypass_block() {
+    let harness = Harness::from_yaml(
+        r#"
+settings:
+  original_path_header: X-Original-URI
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+  - id: removed-posts
+    path: /api/v1/posts
+    status: removed
+    confirmed: true
+"#,
+    );
+
+    // An upstream rewrite is matched on the path the client asked for
+    let rewritten = harness
+        .send(&request(
+            "GET",
+            "/internal/users",
+            &[("X-Original-URI", "/api/v1/users?page=2")],
+        ))
+        .await;
+    assert!(rewritten.allowed());
+    assert!(rewritten.response_header("Deprecation").is_some());
+
+    // A client-set value doesn't move a request away from its own rule
+    let spoofed = harness
+        .send(&request(
+            "GET",
+            "/api/v1/posts",
+            &[("X-Original-URI", "/api/v2/posts")],
+        ))
+        .await;
+    assert_eq!(spoofed.blocked, Some(410));
+}
+
+#[tokio::test]
+async fn test_param_and_header_rules_keep_endpoint_deprecation() {
+    let harness = Harness::from_yaml(
+        r#"
+endpoints:
+  - id: users-params
+    path: /api/v2/users
+    sunset_at: 2030-01-01
+    deprecated_params:
+      - name: include_deleted
+  - id: orders-headers
+    path: /api/v2/orders
+    sunset_at: 2030-01-01
+    deprecated_request_headers:
+      - name: X-Api-Version
+"#,
+    );
+
+    for uri in ["/api/v2/users", "/api/v2/users?include_deleted=1"] {
+        let outcome = harness.send(&request("GET", uri, &[])).await;
+        assert!(outcome.allowed());
+        assert!(outcome.response_header("Sunset").is_some(), "{uri}");
+    }
+
+    let outcome = harness.send(&request("GET", "/api/v2/orders", &[])).await;
+    assert!(outcome.response_header("Sunset").is_some());
+    let outcome = harness
+        .send(&request("GET", "/api/v2/orders", &[("X-Api-Version", "1")]))
+        .await;
+    assert!(outcome.response_header("Sunset").is_some());
+    assert_eq!(
+        outcome.response_header("X-Deprecated-Header-Used"),
+        Some("X-Api-Version")
+    );
+}
+
+#[tokio::test]
+async fn test_global_header_block_needs_confirmation() {
+    let harness = Harness::from_yaml(
+        r#"
+settings:
+  deprecated_request_headers:
+    - name: X-Legacy-Tenant
+      action: block
+    - name: X-Session-Key
+      action: block
+      confirmed: false
+endpoints: []
+"#,
+    );
+
+    let outcome = harness
+        .send(&request(
+            "GET",
+            "/api/v1/orders",
+            &[("X-Legacy-Tenant", "a")],
+        ))
+        .await;
+    assert_eq!(outcome.blocked, Some(400));
+
+    let outcome = harness
+        .send(&request("GET", "/api/v1/orders", &[("X-Session-Key", "a")]))
+        .await;
+    assert!(outcome.allowed());
+    assert!(outcome.has_tag("would_block"));
+    assert_eq!(
+        outcome.response_header("X-Deprecated-Header-Used"),
+        Some("X-Session-Key")
+    );
+}
+
+#[tokio::test]
+async fn test_upstream_header_drops_spoofed_value() {
+    let harness = Harness::from_yaml(
+        r#"
+settings:
+  upstream_header: X-Zentinel-Deprecated
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+"#,
+    );
+    let spoofed = [("X-Zentinel-Deprecated", "forged")];
+
+    // Unmatched requests lose the client's value
+    let outcome = harness
+        .send(&request("GET", "/api/v2/users", &spoofed))
+        .await;
+    assert!(outcome.removes_request_header("X-Zentinel-Deprecated"));
+    assert!(outcome.request_header("X-Zentinel-Deprecated").is_none());
+
+    // Matched requests get it replaced by the rule's
+    let outcome = harness
+        .send(&request("GET", "/api/v1/users", &spoofed))
+        .await;
+    assert!(outcome.removes_request_header("X-Zentinel-Deprecated"));
+    assert_eq!(
+        outcome.request_header("X-Zentinel-Deprecated"),
+        Some("legacy-users")
+    );
+}
+
+#[tokio::test]
+async fn test_error_responses_lose_only_agent_headers() {
+    let harness = Harness::from_yaml(
+        r#"
+settings:
+  strip_headers_on_not_modified: true
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: 2030-06-01
+    documentation_url: https://docs.example.com/migration
+    warn_once:
+      cookie_name: users_v1_seen
+"#,
+    );
+    let users = request("GET", "/api/v1/users", &[]);
+    let warned = harness.send(&users).await;
+    assert!(warned.response_header("Deprecation").is_some());
+    assert!(warned
+        .response_header("Set-Cookie")
+        .is_some_and(|cookie| cookie.starts_with("users_v1_seen=")));
+
+    let upstream = [
+        ("Link", "</docs>; rel=\"help\""),
+        ("Set-Cookie", "session=abc"),
+        ("Cache-Control", "no-store"),
+    ];
+
+    // Error responses lose the notice and the marker cookie, but keep the
+    // upstream's own values of the same headers
+    let outcome = harness.respond(&users, &response(503, &upstream)).await;
+    for name in ["Deprecation", "Sunset", "Link", "Set-Cookie"] {
+        assert!(outcome.removes_response_header(name), "{name}");
+    }
+    assert_eq!(
+        outcome.response_headers,
+        [
+            ("Link".to_string(), "</docs>; rel=\"help\"".to_string()),
+            ("Set-Cookie".to_string(), "session=abc".to_string()),
+        ]
+    );
+    assert!(!outcome.removes_response_header("Cache-Control"));
+
+    // A 404 is left as it is
+    let outcome = harness.respond(&users, &response(404, &upstream)).await;
+    assert!(outcome.removed_response_headers.is_empty());
+    assert!(outcome.response_headers.is_empty());
+
+    // A 304 loses what it can't carry, the marker cookie included
+    let outcome = harness.respond(&users, &response(304, &upstream)).await;
+    assert!(outcome.removes_response_header("Deprecation"));
+    assert!(outcome.removes_response_header("Set-Cookie"));
+    assert_eq!(outcome.response_header("Set-Cookie"), Some("session=abc"));
+
+    // Successful responses keep everything
+    let outcome = harness.respond(&users, &response(200, &upstream)).await;
+    assert!(outcome.removed_response_headers.is_empty());
+}
+
+#[tokio::test]
+async fn test_error_responses_keep_upstream_deprecation() {
+    let harness = Harness::from_yaml(
+        r#"
+endpoints:
+  - id: users-v1
+    path: /api/v1/users
+    sunset_at: 2030-06-01
+"#,
+    );
+    let users = request("GET", "/api/v1/users", &[]);
+    assert!(harness
+        .send(&users)
+        .await
+        .response_header("Deprecation")
+        .is_some());
+
+    // The upstream deprecates the endpoint itself as well
+    let upstream = [("Deprecation", "@1700000000")];
+    for status in [200, 404] {
+        let outcome = harness.respond(&users, &response(status, &upstream)).await;
+        assert!(outcome.removed_response_headers.is_empty(), "{status}");
+    }
+
+    let outcome = harness.respond(&users, &response(503, &upstream)).await;
+    assert!(outcome.removes_response_header("Deprecation"));
+    assert!(outcome.removes_response_header("Sunset"));
+    assert_eq!(outcome.response_header("Deprecation"), Some("@1700000000"));
+    assert!(outcome.response_header("Sunset").is_none());
+
+    // The access is still counted, as an error
+    let usage = harness.agent.usage_snapshot();
+    assert_eq!((usage[0].requests, usage[0].errors), (1, 1));
+}
+
+#[tokio::test]
+async fn test_close_connection_on_block() {
+    let harness = Harness::from_yaml(
+        r#"
+settings:
+  close_connection_on_block: true
+endpoints:
+  - id: posts
+    path: /api/v1/posts
+    status: removed
+  - id: users
+    path: /api/v1/users
+    sunset_at: 2099-01-01
+    confirmed: true
+    deprecated_params:
+      - name: include_deleted
+        action: block
+    deprecated_request_headers:
+      - name: X-Api-Version
+        action: block
+    deprecated_body_fields:
+      - pointer: /legacy_role
+        action: block
+"#,
+    );
+
+    // Every kind of block asks the proxy to close the connection, without
+    // a hop-by-hop header on the response
+    let blocks = [
+        harness.send(&request("GET", "/api/v1/posts", &[])).await,
+        harness
+            .send(&request("GET", "/api/v1/users?include_deleted=1", &[]))
+            .await,
+        harness
+            .send(&request("GET", "/api/v1/users", &[("X-Api-Version", "1")]))
+            .await,
+        harness
+            .send_body(&json_request(
+                "POST",
+                "/api/v1/users",
+                &json!({"legacy_role": "admin"}),
+            ))
+            .await,
+    ];
+    for outcome in &blocks {
+        assert!(outcome.blocked.is_some(), "{outcome:?}");
+        assert!(outcome.has_tag("close_connection"), "{outcome:?}");
+        assert!(outcome.block_header("Connection").is_none(), "{outcome:?}");
+    }
+
+    // Warned clients keep their connection
+    let warned = harness.send(&request("GET", "/api/v1/users", &[])).await;
+    assert!(warned.allowed());
+    assert!(!warned.has_tag("close_connection"));
+
+    // And so do clients of a block degraded to a warning
+    harness.agent.set_enforcement(false);
+    let unenforced = harness.send(&request("GET", "/api/v1/posts", &[])).await;
+    assert!(unenforced.allowed());
+    assert!(!unenforced.has_tag("close_connection"));
+}
+
+#[tokio::test]
+async fn test_head_blocks_have_no_body() {
+    let harness = Harness::from_yaml(
+        r#"
+endpoints:
+  - id: posts
+    path: /api/v1/posts
+    status: removed
+  - id: reports
+    path: /api/v1/reports
+    confirmed: true
+    action:
+      type: custom
+      status_code: 410
+      body: '{"error": "gone"}'
+  - id: users
+    path: /api/v1/users
+    sunset_at: 2099-01-01
+    confirmed: true
+    deprecated_params:
+      - name: include_deleted
+        action: block
+    deprecated_request_headers:
+      - name: X-Api-Version
+        action: block
+"#,
+    );
+
+    // Each block answers HEAD with the GET response's status and length,
+    // but no body
+    let cases = [
+        ("/api/v1/posts", vec![]),
+        ("/api/v1/reports", vec![]),
+        ("/api/v1/users?include_deleted=1", vec![]),
+        ("/api/v1/users", vec![("X-Api-Version", "1")]),
+    ];
+    for (uri, headers) in cases {
+        let get = harness.send(&request("GET", uri, &headers)).await;
+        let head = harness.send(&request("HEAD", uri, &headers)).await;
+        let body = get.body.clone().unwrap_or_default();
+        assert!(!body.is_empty(), "{uri}");
+        assert_eq!(head.blocked, get.blocked, "{uri}");
+        assert_eq!(head.body.as_deref().unwrap_or_default(), "", "{uri}");
+        assert_eq!(
+            head.block_header("Content-Length"),
+            Some(body.len().to_string().as_str()),
+            "{uri}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_header_budget_counts_per_request_headers() {
+    let harness = Harness::from_yaml(&format!(
+        r#"
+settings:
+  max_added_header_bytes: 300
+  sunset_reminder: true
+endpoints:
+  - id: audited
+    path: /api/v1/users
+    sunset_at: 2099-01-01
+    computed_headers:
+      X-Audit: "{}"
+  - id: plain
+    path: /api/v1/posts
+    sunset_at: 2099-01-01
+"#,
+        "x".repeat(400)
+    ));
+
+    // The computed header only exists per request, yet counts: the notice
+    // and then the computed header go, the countdown stays
+    let outcome = harness.send(&request("GET", "/api/v1/users", &[])).await;
+    assert!(outcome.allowed());
+    assert!(outcome.response_header("X-Audit").is_none());
+    assert!(outcome.response_header("X-Deprecation-Notice").is_none());
+    assert!(outcome.response_header("Deprecation").is_some());
+    assert!(outcome.response_header("Sunset").is_some());
+    assert!(outcome.response_header("X-Days-Until-Sunset").is_some());
+
+    let outcome = harness.send(&request("GET", "/api/v1/posts", &[])).await;
+    assert!(outcome.response_header("X-Deprecation-Notice").is_some());
+}
+
+#[tokio::test]
+async fn test_bodies_by_status_code() {
+    let bodies = r#"
+    bodies:
+      410: {body: "<h1>Gone</h1>", content_type: text/html}
+      405:
+        body: '{"code":"METHOD_RETIRED"}'
+"#;
+    let harness = Harness::from_yaml(&format!(
+        r#"
+endpoints:
+  - id: posts-v1
+    path: /api/v1/posts
+    status: removed
+    confirmed: true{bodies}
+  - id: comments-v1
+    path: /api/v1/comments
+    confirmed: true
+    action:
+      type: block
+      status_code: 405{bodies}
+  - id: tags-v1
+    path: /api/v1/tags
+    confirmed: true
+    action:
+      type: block
+      status_code: 403{bodies}
+"#
+    ));
+
+    let removed = harness.send(&request("DELETE", "/api/v1/posts", &[])).await;
+    assert_eq!(removed.blocked, Some(410));
+    assert_eq!(removed.body.as_deref(), Some("<h1>Gone</h1>"));
+    assert_eq!(removed.block_header("Content-Type"), Some("text/html"));
+
+    let blocked = harness
+        .send(&request("POST", "/api/v1/comments", &[]))
+        .await;
+    assert_eq!(blocked.blocked, Some(405));
+    assert_eq!(
+        blocked.body.as_deref(),
+        Some(r#"{"code":"METHOD_RETIRED"}"#)
+    );
+    assert_eq!(
+        blocked.block_header("Content-Type"),
+        Some("application/json")
+    );
+
+    // No body for 403: the generated one
+    let generated = harness.send(&request("GET", "/api/v1/tags", &[])).await;
+    assert_eq!(generated.blocked, Some(403));
+    assert!(generated
+        .body
+        .as_deref()
+        .unwrap()
+        .contains("endpoint_removed"));
+    assert_eq!(
+        generated.block_header("Content-Type"),
+        Some("application/json")
+    );
+
+    let invalid = ApiDeprecationConfig::from_yaml(
+        r#"
+endpoints:
+  - id: posts-v1
+    path: /api/v1/posts
+    bodies:
+      200: {body: ok}
+"#,
+    )
+    .unwrap();
+    assert!(invalid
+        .validate()
+        .unwrap_err()
+        .to_string()
+        .contains("200 is not an error status code"));
+}
@@ -0,0 +1,158 @@
+//! Integration tests for the agent's protocol-facing surface: configuration
+//! pushed by the proxy, capabilities, health reporting, drain and shutdown,
+//! and metrics reports.
+
+mod common;
+
+use common::{request, Harness};
+use zentinel_agent_protocol::EventType;
+
+const CONFIG: &str = r#"
+settings:
+  reload_failure_mode: degrade
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    sunset_at: "2099-01-01T00:00:00Z"
+  - id: removed-posts
+    path: /api/v1/posts
+    status: removed
+    confirmed: true
+"#;
+
+#[tokio::test]
+async fn test_config_push_replaces_rules() {
+    let harness = Harness::from_yaml(CONFIG);
+    assert_eq!(harness.rule_ids(), ["legacy-users", "removed-posts"]);
+
+    harness
+        .push_config("endpoints:\n  - id: legacy-orders\n    path: /api/v1/orders\n")
+        .await
+        .unwrap();
+    assert_eq!(harness.rule_ids(), ["legacy-orders"]);
+    harness.assert_healthy();
+}
+
+#[tokio::test]
+async fn test_rejected_push_degrades_until_next_success() {
+    let harness = Harness::from_yaml(CONFIG);
+
+    assert!(harness
+        .push_config("endpoints:\n  - id: broken\n    path: \"\"\n")
+        .await
+        .is_err());
+    harness.assert_degraded();
+    assert_eq!(harness.rule_ids(), ["legacy-users", "removed-posts"]);
+
+    harness.push_config(CONFIG).await.unwrap();
+    harness.assert_healthy();
+}
+
+#[tokio::test]
+async fn test_metrics_report() {
+    let harness = Harness::from_yaml(CONFIG);
+
+    let endpoints = harness
+        .gauge("api_deprecation_endpoints_total", None)
+        .unwrap();
+    assert_eq!(endpoints.value, 2.0);
+
+    let days = harness
+        .gauge("api_deprecation_days_until_sunset", Some("legacy-users"))
+        .unwrap();
+    assert!(days.value > 0.0);
+    assert!(harness
+        .gauge("api_deprecation_days_until_sunset", Some("removed-posts"))
+        .is_none());
+    assert_eq!(
+        harness.counter("api_deprecation_internal_errors_total", None),
+        Some(0)
+    );
+
+    // A pushed configuration is reflected in the next report
+    harness
+        .push_config("endpoints:\n  - id: legacy-users\n    path: /api/v1/users\n")
+        .await
+        .unwrap();
+    let endpoints = harness
+        .gauge("api_deprecation_endpoints_total", None)
+        .unwrap();
+    assert_eq!(endpoints.value, 1.0);
+
+    // Disabling metrics through a push stops the reports
+    harness
+        .push_config(&format!("{CONFIG}metrics:\n  enabled: false\n"))
+        .await
+        .unwrap();
+    assert!(zentinel_agent_protocol::v2::AgentHandlerV2::metrics_report(&harness.agent).is_none());
+}
+
+#[tokio::test]
+async fn test_capabilities() {
+    let harness = Harness::from_yaml(CONFIG);
+    let capabilities = harness.capabilities();
+    assert_eq!(
+        capabilities.supported_events,
+        [EventType::RequestHeaders, EventType::ResponseHeaders]
+    );
+    assert!(capabilities.features.config_push);
+    assert!(capabilities.features.health_reporting);
+    assert!(capabilities.features.metrics_export);
+    assert!(!capabilities.features.streaming_body);
+    assert_eq!(capabilities.features.concurrent_requests, 100);
+
+    // Bodies are only requested once a rule inspects them
+    harness
+        .push_config(
+            r#"
+settings:
+  max_concurrent_requests: 2
+endpoints:
+  - id: legacy-users
+    path: /api/v1/users
+    body_field_deprecated: [/legacy_id]
+"#,
+        )
+        .await
+        .unwrap();
+    let capabilities = harness.capabilities();
+    assert!(capabilities.features.streaming_body);
+    assert!(capabilities
+        .supported_events
+        .contains(&EventType::RequestBodyChunk));
+
+    // The limit is advertised for the proxy to apply; the agent itself
+    // processes every request it is sent
+    assert_eq!(capabilities.features.concurrent_requests, 2);
+    let users = request("GET", "/api/v1/users", &[]);
+    let outcomes = tokio::join!(
+        harness.send(&users),
+        harness.send(&users),
+        harness.send(&users),
+    );
+    for outcome in [outcomes.0, outcomes.1, outcomes.2] {
+        assert!(outcome.response_header("Deprecation").is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_health_degrades_on_drain() {
+    let harness = Harness::from_yaml(CONFIG);
+    harness.assert_healthy();
+
+    harness.drain().await;
+    harness.assert_degraded_feature("new_requests");
+
+    // Rules keep being applied while draining
+    let outcome = harness.send(&request("GET", "/api/v1/posts", &[])).await;
+    assert_eq!(outcome.blocked, Some(410));
+}
+
+#[tokio::test]
+async fn test_health_degrades_on_shutdown() {
+    let harness = Harness::from_yaml(CONFIG);
+    harness.assert_healthy();
+
+    harness.shutdown().await;
+    harness.assert_degraded_feature("new_requests");
+}